@@ -1,26 +1,267 @@
+use engine::{
+    ecs::{MeshRenderer, Phase, World},
+    nalgebra_glm as glm,
+    picking::{Pick, PickRequest},
+    scene_graph::{Node, NodeId, Transform},
+};
+
 fn main() {
     if let Err(error) = engine::launch(App::default()) {
         eprintln!("Failed to launch app: {error}");
     }
 }
 
+/// The built in triangle is always the first mesh
+const TRIANGLE_MESH: usize = 0;
+const GRID_SIZE: usize = 100;
+const GRID_EXTENT: f32 = 2.5;
+const RING_SIZE: usize = 12;
+
+/// Turns an entity around the Y axis, in radians per second
+struct Spin(f32);
+
+fn spin_system(world: &mut World, delta_time: f32) {
+    for (transform, spin) in world.query_mut::<(&mut Transform, &Spin)>() {
+        let rotation = glm::quat_angle_axis(spin.0 * delta_time, &glm::Vec3::y());
+        transform.translation = glm::quat_rotate_vec3(&rotation, &transform.translation);
+        transform.rotation = rotation * transform.rotation;
+    }
+}
+
+struct Orbits {
+    sun: NodeId,
+    planet: NodeId,
+}
+
+impl Orbits {
+    fn add(context: &mut engine::Context) -> Self {
+        let scale = |scale: f32| Transform {
+            scale: glm::vec3(scale, scale, scale),
+            ..Default::default()
+        };
+        let graph = context.scene_graph_mut();
+        let sun = graph.add(
+            Node::new("Sun")
+                .with_mesh(TRIANGLE_MESH)
+                .with_transform(scale(0.5)),
+        );
+        let planet = graph
+            .add_child(
+                sun,
+                Node::new("Planet")
+                    .with_mesh(TRIANGLE_MESH)
+                    .with_transform(Transform {
+                        translation: glm::vec3(3.0, 0.0, 0.0),
+                        ..scale(0.4)
+                    }),
+            )
+            .expect("the sun was just added");
+        graph
+            .add_child(
+                planet,
+                Node::new("Moon")
+                    .with_mesh(TRIANGLE_MESH)
+                    .with_transform(Transform {
+                        translation: glm::vec3(2.5, 0.0, 0.0),
+                        ..scale(0.4)
+                    }),
+            )
+            .expect("the planet was just added");
+        Self { sun, planet }
+    }
+
+    fn update(&self, context: &mut engine::Context, elapsed: f32) {
+        let graph = context.scene_graph_mut();
+        for (node, speed) in [(self.sun, 0.5), (self.planet, 2.0)] {
+            if let Some(transform) = graph.transform_mut(node) {
+                transform.rotation = glm::quat_angle_axis(elapsed * speed, &glm::Vec3::z());
+            }
+        }
+    }
+}
+
 #[derive(Default)]
-pub struct App {}
+pub struct App {
+    instanced: bool,
+    /// The fountain emitter, added the first time it is enabled
+    fountain: Option<usize>,
+    fountain_enabled: bool,
+    fountain_settings: engine::particles::ParticleEmitter,
+    debug_shapes: bool,
+    /// A small triangle orbiting a larger one, with a moon orbiting the small one
+    orbits: Option<Orbits>,
+    /// Triangle entities circling the origin, moved by a fixed update system
+    ring: Vec<engine::ecs::Entity>,
+    start_time: Option<engine::Instant>,
+    /// The pick of the last click, until it arrives
+    pick: Option<PickRequest>,
+    selection: Option<Pick>,
+}
 
 impl engine::State for App {
-    fn initialize(&mut self, _context: &mut engine::Context) {
+    fn initialize(&mut self, context: &mut engine::Context) {
         engine::log::info!("App initialized!");
+        context.add_system(Phase::FixedUpdate, spin_system);
+        self.start_time = Some(engine::Instant::now());
     }
 
     fn receive_event(
         &mut self,
-        _context: &mut engine::Context,
-        _event: &engine::winit::event::WindowEvent,
+        context: &mut engine::Context,
+        event: &engine::winit::event::WindowEvent,
     ) {
-        engine::log::info!("Event received: {:?}", _event);
+        engine::log::info!("Event received: {:?}", event);
+        if let engine::winit::event::WindowEvent::MouseInput {
+            state: engine::winit::event::ElementState::Pressed,
+            button: engine::winit::event::MouseButton::Left,
+            ..
+        } = event
+        {
+            if let Some(position) = context.cursor_position() {
+                self.pick = Some(context.pick(position.x, position.y));
+            }
+        }
     }
 
-    fn update(&mut self, _context: &mut engine::Context) {}
+    fn update(&mut self, context: &mut engine::Context) {
+        if let Some(selection) = self.pick.as_mut().and_then(PickRequest::try_read) {
+            self.selection = selection;
+            self.pick = None;
+        }
+        if let Some(selection) = &self.selection {
+            context
+                .debug_draw()
+                .sphere(selection.position, 0.05, [1.0, 1.0, 1.0, 1.0])
+                .on_top();
+        }
+
+        if self.fountain_enabled && self.fountain.is_none() {
+            self.fountain = Some(context.add_emitter(self.fountain_settings.clone()));
+        }
+        if let Some(emitter) = self
+            .fountain
+            .and_then(|fountain| context.emitter_mut(fountain))
+        {
+            *emitter = self.fountain_settings.clone();
+            // Particles already spawned live out their lifetime
+            if !self.fountain_enabled {
+                emitter.spawn_rate = 0.0;
+            }
+        }
 
-    fn ui(&mut self, _context: &mut engine::Context, _ui: &engine::egui::Context) {}
+        if self.debug_shapes {
+            let debug = context.debug_draw();
+            debug.grid(glm::vec3(0.0, -1.0, 0.0), 4.0, 8, [0.5, 0.5, 0.5, 1.0]);
+            debug.axes(&glm::Mat4::identity(), 0.5).on_top();
+            debug.aabb(
+                glm::vec3(-1.0, -1.0, -0.1),
+                glm::vec3(1.0, 1.0, 0.1),
+                [1.0, 1.0, 0.0, 1.0],
+            );
+            debug.sphere(glm::vec3(0.0, 0.0, 0.0), 1.2, [0.0, 1.0, 1.0, 1.0]);
+        }
+
+        let elapsed = self
+            .start_time
+            .map(|start_time| start_time.elapsed().as_secs_f32())
+            .unwrap_or_default();
+        if let Some(orbits) = &self.orbits {
+            orbits.update(context, elapsed);
+        }
+
+        if !self.instanced {
+            return;
+        }
+        let Some(instances) = context.instances_mut(TRIANGLE_MESH) else {
+            return;
+        };
+        instances.clear();
+        instances.extend((0..GRID_SIZE * GRID_SIZE).map(|index| {
+            let (column, row) = (index % GRID_SIZE, index / GRID_SIZE);
+            let u = column as f32 / (GRID_SIZE - 1) as f32;
+            let v = row as f32 / (GRID_SIZE - 1) as f32;
+            let position = glm::vec3(
+                (u * 2.0 - 1.0) * GRID_EXTENT,
+                (v * 2.0 - 1.0) * GRID_EXTENT,
+                0.0,
+            );
+            let speed = 1.0 + (index % 7) as f32 * 0.5;
+            let transform = glm::translation(&position)
+                * glm::rotation(elapsed * speed, &glm::Vec3::z())
+                * glm::scaling(&glm::vec3(0.04, 0.04, 0.04));
+            engine::mesh::Instance {
+                transform,
+                color: [u, v, 1.0 - u, 1.0],
+                ..Default::default()
+            }
+        }));
+    }
+
+    fn ui(&mut self, context: &mut engine::Context, ui: &engine::egui::Context) {
+        engine::egui::Window::new("Triangle").show(ui, |ui| {
+            let label = format!("Instanced demo ({} triangles)", GRID_SIZE * GRID_SIZE);
+            if ui.checkbox(&mut self.instanced, label).changed() && !self.instanced {
+                if let Some(instances) = context.instances_mut(TRIANGLE_MESH) {
+                    *instances = vec![engine::mesh::Instance::default()];
+                }
+            }
+            let mut orbits = self.orbits.is_some();
+            if ui.checkbox(&mut orbits, "Orbiting triangles").changed() {
+                match self.orbits.take() {
+                    Some(existing) => {
+                        // Removing the sun removes the planet and moon along with it
+                        context.scene_graph_mut().remove(existing.sun);
+                    }
+                    None => self.orbits = Some(Orbits::add(context)),
+                }
+            }
+            let mut ring = !self.ring.is_empty();
+            if ui.checkbox(&mut ring, "Entity ring").changed() {
+                let world = context.world_mut();
+                if ring {
+                    self.ring = (0..RING_SIZE)
+                        .map(|index| {
+                            let angle = index as f32 / RING_SIZE as f32 * std::f32::consts::TAU;
+                            let transform = Transform {
+                                translation: glm::vec3(angle.cos(), -0.5, angle.sin()) * 1.5,
+                                scale: glm::vec3(0.2, 0.2, 0.2),
+                                ..Default::default()
+                            };
+                            let renderer = MeshRenderer {
+                                color: [1.0, 0.6, 0.2, 1.0],
+                                ..MeshRenderer::new(TRIANGLE_MESH)
+                            };
+                            world.spawn((transform, renderer, Spin(1.0)))
+                        })
+                        .collect();
+                } else {
+                    for entity in self.ring.drain(..) {
+                        let _ = world.despawn(entity);
+                    }
+                }
+            }
+            let overlays = context.overlay_settings_mut();
+            ui.checkbox(&mut overlays.grid.enabled, "Ground grid");
+            ui.checkbox(&mut overlays.view_gizmo, "View gizmo");
+            ui.checkbox(&mut self.debug_shapes, "Debug shapes");
+            ui.checkbox(&mut self.fountain_enabled, "Particle fountain");
+            if self.fountain_enabled {
+                ui.collapsing("Emitter", |ui| self.fountain_settings.editor(ui));
+            }
+            let mut gpu_driven = context.gpu_driven();
+            if ui.checkbox(&mut gpu_driven, "GPU driven drawing").changed() {
+                context.set_gpu_driven(gpu_driven);
+            }
+            let stats = context.cull_stats();
+            ui.label(format!("Drawn {}, culled {}", stats.drawn, stats.culled));
+            match &self.selection {
+                Some(selection) => ui.label(format!(
+                    "Selected {:?} at {:.2?}",
+                    selection.target,
+                    selection.position.as_slice()
+                )),
+                None => ui.label("Click the scene to select"),
+            };
+        });
+    }
 }
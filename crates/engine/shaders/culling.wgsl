@@ -0,0 +1,98 @@
+#include "instancing.wgsl"
+
+struct Culling {
+    // Left, right, bottom, top, near and far, pointing inwards
+    planes: array<vec4<f32>, 6>,
+    // x: the number of instances, y: the number of draws
+    counts: vec4<u32>,
+};
+
+// The bounds of the mesh of one draw, in the mesh's local space
+struct CullMesh {
+    // xyz: center, w: radius
+    sphere: vec4<f32>,
+    aabb_min: vec4<f32>,
+    aabb_max: vec4<f32>,
+    // x: where the instances of the draw start, in both instance buffers, y: how many there are
+    offsets: vec4<u32>,
+};
+
+struct DrawArgs {
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+};
+
+@group(0) @binding(0)
+var<uniform> culling: Culling;
+
+@group(0) @binding(1)
+var<storage, read> meshes: array<CullMesh>;
+
+@group(0) @binding(2)
+var<storage, read> instances: array<Instance>;
+
+@group(0) @binding(3)
+var<storage, read_write> visible_instances: array<Instance>;
+
+@group(0) @binding(4)
+var<storage, read_write> draws: array<DrawArgs>;
+
+// The draws are ordered by their first instance, so an instance belongs to the last draw starting at or before it
+fn find_draw(index: u32) -> u32 {
+    var low = 0u;
+    var high = culling.counts.y;
+    while high - low > 1u {
+        let middle = (low + high) / 2u;
+        if meshes[middle].offsets.x <= index {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    return low;
+}
+
+// Matches `Frustum::intersects` on the cpu: the sphere rejects most instances, the box the rest
+fn is_visible(mesh: CullMesh, transform: mat4x4<f32>) -> bool {
+    let scale = max(length(transform[0].xyz), max(length(transform[1].xyz), length(transform[2].xyz)));
+    let sphere_center = (transform * vec4<f32>(mesh.sphere.xyz, 1.0)).xyz;
+    let sphere_radius = mesh.sphere.w * scale;
+
+    let linear = mat3x3<f32>(abs(transform[0].xyz), abs(transform[1].xyz), abs(transform[2].xyz));
+    let box_center = (transform * vec4<f32>((mesh.aabb_min.xyz + mesh.aabb_max.xyz) * 0.5, 1.0)).xyz;
+    let box_half_extents = linear * ((mesh.aabb_max.xyz - mesh.aabb_min.xyz) * 0.5);
+
+    for (var index = 0u; index < 6u; index++) {
+        let plane = culling.planes[index];
+        if dot(plane.xyz, sphere_center) + plane.w < -sphere_radius {
+            return false;
+        }
+        if dot(plane.xyz, box_center) + plane.w < -dot(abs(plane.xyz), box_half_extents) {
+            return false;
+        }
+    }
+    return true;
+}
+
+@compute @workgroup_size(64)
+fn cull(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if index >= culling.counts.x {
+        return;
+    }
+    let instance = instances[index];
+    let draw = find_draw(index);
+    let mesh = meshes[draw];
+    // Meshes without draws, such as empty ones, leave their instances outside every draw's range
+    if index < mesh.offsets.x || index >= mesh.offsets.x + mesh.offsets.y {
+        return;
+    }
+    if !is_visible(mesh, instance.transform) {
+        return;
+    }
+    let slot = atomicAdd(&draws[draw].instance_count, 1u);
+    visible_instances[mesh.offsets.x + slot] = instance;
+}
@@ -0,0 +1,176 @@
+const PI: f32 = 3.14159265359;
+const SAMPLE_COUNT: u32 = 256u;
+
+struct Bake {
+    face: u32,
+    roughness: f32,
+    source_size: f32,
+    padding: f32,
+};
+
+@group(0) @binding(0)
+var<uniform> bake: Bake;
+
+@group(0) @binding(1)
+var source_equirectangular: texture_2d<f32>;
+
+@group(0) @binding(3)
+var source_cube: texture_cube<f32>;
+
+@group(0) @binding(2)
+var source_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vertex_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    out.uv = vec2<f32>(uv.x, 1.0 - uv.y);
+    return out;
+}
+
+// Maps a texel on a cube face to the direction a cube texture samples it with
+fn face_direction(face: u32, uv: vec2<f32>) -> vec3<f32> {
+    let st = uv * 2.0 - 1.0;
+    switch face {
+        case 0u: { return normalize(vec3<f32>(1.0, -st.y, -st.x)); }
+        case 1u: { return normalize(vec3<f32>(-1.0, -st.y, st.x)); }
+        case 2u: { return normalize(vec3<f32>(st.x, 1.0, st.y)); }
+        case 3u: { return normalize(vec3<f32>(st.x, -1.0, -st.y)); }
+        case 4u: { return normalize(vec3<f32>(st.x, -st.y, 1.0)); }
+        default: { return normalize(vec3<f32>(-st.x, -st.y, -1.0)); }
+    }
+}
+
+fn tangent_frame(normal: vec3<f32>) -> mat3x3<f32> {
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    if abs(normal.y) > 0.999 {
+        up = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    let tangent = normalize(cross(up, normal));
+    let bitangent = cross(normal, tangent);
+    return mat3x3<f32>(tangent, bitangent, normal);
+}
+
+fn radical_inverse(index: u32) -> f32 {
+    var bits = index;
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return f32(bits) * 2.3283064365386963e-10;
+}
+
+fn hammersley(index: u32, count: u32) -> vec2<f32> {
+    return vec2<f32>(f32(index) / f32(count), radical_inverse(index));
+}
+
+fn importance_sample_ggx(xi: vec2<f32>, roughness: f32) -> vec3<f32> {
+    let alpha = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+}
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let alpha = roughness * roughness;
+    let alpha_squared = alpha * alpha;
+    let denominator = n_dot_h * n_dot_h * (alpha_squared - 1.0) + 1.0;
+    return alpha_squared / (PI * denominator * denominator);
+}
+
+@fragment
+fn equirectangular_to_cube(in: VertexOutput) -> @location(0) vec4<f32> {
+    let direction = face_direction(bake.face, in.uv);
+    let uv = vec2<f32>(
+        atan2(direction.z, direction.x) / (2.0 * PI) + 0.5,
+        acos(clamp(direction.y, -1.0, 1.0)) / PI,
+    );
+    return vec4<f32>(textureSampleLevel(source_equirectangular, source_sampler, uv, 0.0).rgb, 1.0);
+}
+
+@fragment
+fn downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let direction = face_direction(bake.face, in.uv);
+    return vec4<f32>(textureSampleLevel(source_cube, source_sampler, direction, 0.0).rgb, 1.0);
+}
+
+@fragment
+fn irradiance(in: VertexOutput) -> @location(0) vec4<f32> {
+    let normal = face_direction(bake.face, in.uv);
+    let frame = tangent_frame(normal);
+    let sample_delta = 0.05;
+    var sum = vec3<f32>(0.0);
+    var count = 0.0;
+    for (var phi = 0.0; phi < 2.0 * PI; phi += sample_delta) {
+        for (var theta = 0.0; theta < 0.5 * PI; theta += sample_delta) {
+            let local = vec3<f32>(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            let direction = frame * local;
+            sum += textureSampleLevel(source_cube, source_sampler, direction, 4.0).rgb
+                * cos(theta) * sin(theta);
+            count += 1.0;
+        }
+    }
+    return vec4<f32>(PI * sum / count, 1.0);
+}
+
+@fragment
+fn prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    let normal = face_direction(bake.face, in.uv);
+    let frame = tangent_frame(normal);
+    let roughness = max(bake.roughness, 0.001);
+    let texel_solid_angle = 4.0 * PI / (6.0 * bake.source_size * bake.source_size);
+    var sum = vec3<f32>(0.0);
+    var weight = 0.0;
+    for (var index = 0u; index < SAMPLE_COUNT; index++) {
+        let halfway = frame * importance_sample_ggx(hammersley(index, SAMPLE_COUNT), roughness);
+        let light = normalize(2.0 * dot(normal, halfway) * halfway - normal);
+        let n_dot_l = dot(normal, light);
+        if n_dot_l > 0.0 {
+            // Sampling a blurrier mip for less likely directions avoids bright speckles
+            let n_dot_h = max(dot(normal, halfway), 0.0);
+            let pdf = distribution_ggx(n_dot_h, roughness) / 4.0 + 1e-4;
+            let sample_solid_angle = 1.0 / (f32(SAMPLE_COUNT) * pdf);
+            let mip = select(0.5 * log2(sample_solid_angle / texel_solid_angle) + 1.0, 0.0, bake.roughness == 0.0);
+            sum += textureSampleLevel(source_cube, source_sampler, light, mip).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+    return vec4<f32>(sum / max(weight, 1e-4), 1.0);
+}
+
+fn geometry_schlick_ibl(n_dot: f32, roughness: f32) -> f32 {
+    let k = roughness * roughness / 2.0;
+    return n_dot / (n_dot * (1.0 - k) + k);
+}
+
+@fragment
+fn brdf_lut(in: VertexOutput) -> @location(0) vec4<f32> {
+    let n_dot_v = max(in.uv.x, 1e-3);
+    let roughness = 1.0 - in.uv.y;
+    let view = vec3<f32>(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var index = 0u; index < SAMPLE_COUNT; index++) {
+        let halfway = importance_sample_ggx(hammersley(index, SAMPLE_COUNT), roughness);
+        let light = normalize(2.0 * dot(view, halfway) * halfway - view);
+        let n_dot_l = max(light.z, 0.0);
+        let n_dot_h = max(halfway.z, 0.0);
+        let v_dot_h = max(dot(view, halfway), 0.0);
+        if n_dot_l > 0.0 {
+            let geometry = geometry_schlick_ibl(n_dot_v, roughness) * geometry_schlick_ibl(n_dot_l, roughness);
+            let visibility = geometry * v_dot_h / (n_dot_h * n_dot_v);
+            let fresnel = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fresnel) * visibility;
+            bias += fresnel * visibility;
+        }
+    }
+    return vec4<f32>(scale / f32(SAMPLE_COUNT), bias / f32(SAMPLE_COUNT), 0.0, 1.0);
+}
@@ -0,0 +1,47 @@
+struct Environment {
+    settings: vec4<f32>,
+};
+
+@group(3) @binding(0)
+var<uniform> environment: Environment;
+
+@group(3) @binding(1)
+var irradiance_map: texture_cube<f32>;
+
+@group(3) @binding(2)
+var prefiltered_map: texture_cube<f32>;
+
+@group(3) @binding(3)
+var brdf_lut: texture_2d<f32>;
+
+@group(3) @binding(4)
+var environment_sampler: sampler;
+
+fn ambient_lighting(
+    normal: vec3<f32>,
+    view_direction: vec3<f32>,
+    base_color: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    f0: vec3<f32>,
+    flat_ambient: vec3<f32>,
+) -> vec3<f32> {
+#ifndef ENVIRONMENT
+    return flat_ambient * base_color;
+#else
+    let n_dot_v = max(dot(normal, view_direction), 1e-4);
+    let fresnel = f0 + (max(vec3<f32>(1.0 - roughness), f0) - f0) * pow(clamp(1.0 - n_dot_v, 0.0, 1.0), 5.0);
+    let diffuse_weight = (1.0 - fresnel) * (1.0 - metallic);
+    let irradiance = textureSampleLevel(irradiance_map, environment_sampler, normal, 0.0).rgb;
+    let reflection = reflect(-view_direction, normal);
+    let prefiltered = textureSampleLevel(
+        prefiltered_map,
+        environment_sampler,
+        reflection,
+        roughness * environment.settings.z,
+    ).rgb;
+    let brdf = textureSampleLevel(brdf_lut, environment_sampler, vec2<f32>(n_dot_v, roughness), 0.0).rg;
+    let specular = prefiltered * (fresnel * brdf.x + brdf.y);
+    return (diffuse_weight * irradiance * base_color + specular) * environment.settings.y;
+#endif
+}
@@ -0,0 +1,82 @@
+struct Grid {
+    inverse_view_projection: mat4x4<f32>,
+    view_projection: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    // x: cell size, y: cells per major line, z: fade distance
+    settings: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> grid: Grid;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) clip_position: vec2<f32>,
+};
+
+// A single triangle covering the screen
+@vertex
+fn vertex_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    let clip_position = uv * 2.0 - 1.0;
+    out.position = vec4<f32>(clip_position, 0.0, 1.0);
+    out.clip_position = clip_position;
+    return out;
+}
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+};
+
+fn unproject(clip_position: vec2<f32>, depth: f32) -> vec3<f32> {
+    let world = grid.inverse_view_projection * vec4<f32>(clip_position, depth, 1.0);
+    return world.xyz / world.w;
+}
+
+// Coverage of lines every `spacing` units, about one pixel wide
+fn grid_lines(coordinates: vec2<f32>, spacing: f32) -> f32 {
+    let scaled = coordinates / spacing;
+    let width = fwidth(scaled);
+    let distance = abs(fract(scaled - 0.5) - 0.5) / width;
+    return 1.0 - min(min(distance.x, distance.y), 1.0);
+}
+
+@fragment
+fn fragment_main(in: VertexOutput) -> FragmentOutput {
+    var out: FragmentOutput;
+    // The ray through this pixel, intersected with the ground plane
+    let near = unproject(in.clip_position, 0.0);
+    let far = unproject(in.clip_position, 1.0);
+    let t = -near.y / (far.y - near.y);
+    // Also rejects the NaN of rays parallel to the plane
+    if !(t > 0.0 && t <= 1.0) {
+        discard;
+    }
+    let position = near + (far - near) * t;
+    let coordinates = position.xz;
+
+    let cell_size = grid.settings.x;
+    let minor = grid_lines(coordinates, cell_size);
+    let major = grid_lines(coordinates, cell_size * grid.settings.y);
+    var color = vec4<f32>(0.5, 0.5, 0.5, max(minor * 0.3, major * 0.6));
+
+    // The X axis runs along z = 0 and the Z axis along x = 0
+    let axis_width = fwidth(coordinates);
+    if abs(coordinates.y) < axis_width.y {
+        color = vec4<f32>(0.9, 0.2, 0.2, 1.0);
+    }
+    if abs(coordinates.x) < axis_width.x {
+        color = vec4<f32>(0.2, 0.4, 0.9, 1.0);
+    }
+
+    let distance = length(position - grid.camera_position.xyz);
+    let fade_distance = grid.settings.z;
+    color.a *= 1.0 - smoothstep(fade_distance * 0.5, fade_distance, distance);
+
+    let clip = grid.view_projection * vec4<f32>(position, 1.0);
+    out.depth = clip.z / clip.w;
+    out.color = color;
+    return out;
+}
@@ -0,0 +1,24 @@
+struct InstanceInput {
+    @location(4) transform_0: vec4<f32>,
+    @location(5) transform_1: vec4<f32>,
+    @location(6) transform_2: vec4<f32>,
+    @location(7) transform_3: vec4<f32>,
+    @location(8) color: vec4<f32>,
+    @location(9) custom: vec4<f32>,
+};
+
+// An instance as stored in buffers, laid out like `mesh::Instance`
+struct Instance {
+    transform: mat4x4<f32>,
+    color: vec4<f32>,
+    custom: vec4<f32>,
+};
+
+fn instance_transform(instance: InstanceInput) -> mat4x4<f32> {
+    return mat4x4<f32>(
+        instance.transform_0,
+        instance.transform_1,
+        instance.transform_2,
+        instance.transform_3,
+    );
+}
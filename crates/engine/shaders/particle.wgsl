@@ -0,0 +1,52 @@
+// Shared by the particle simulation and rendering shaders,
+// which each declare the `emitter` uniform in their own bind group
+
+struct Particle {
+    position_age: vec4<f32>,
+    velocity_lifetime: vec4<f32>,
+};
+
+struct Emitter {
+    // xyz: position, w: spawn radius
+    origin: vec4<f32>,
+    // xyz: initial velocity, w: random spread
+    velocity: vec4<f32>,
+    // x: lifetime, y: lifetime variation, z: delta time
+    lifetime: vec4<f32>,
+    // x: height, y: restitution, z: friction, w: 1.0 when particles collide with the ground
+    ground: vec4<f32>,
+    // x: first spawned index, y: spawn count, z: seed, w: capacity
+    spawn: vec4<u32>,
+    // x: the first particle of the emitter in the particle buffer
+    range: vec4<u32>,
+    color: array<vec4<f32>, CURVE_SAMPLES>,
+    // xyz: acceleration
+    acceleration: array<vec4<f32>, CURVE_SAMPLES>,
+    // x: size, y: speed
+    scale: array<vec4<f32>, CURVE_SAMPLES>,
+};
+
+// The sample before `life` in x and the blend factor towards the next one in y
+fn curve_position(life: f32) -> vec2<f32> {
+    let position = clamp(life, 0.0, 1.0) * f32(CURVE_SAMPLES - 1u);
+    let index = min(floor(position), f32(CURVE_SAMPLES - 2u));
+    return vec2<f32>(index, position - index);
+}
+
+fn sample_color(life: f32) -> vec4<f32> {
+    let position = curve_position(life);
+    let index = u32(position.x);
+    return mix(emitter.color[index], emitter.color[index + 1u], position.y);
+}
+
+fn sample_acceleration(life: f32) -> vec3<f32> {
+    let position = curve_position(life);
+    let index = u32(position.x);
+    return mix(emitter.acceleration[index], emitter.acceleration[index + 1u], position.y).xyz;
+}
+
+fn sample_scale(life: f32) -> vec4<f32> {
+    let position = curve_position(life);
+    let index = u32(position.x);
+    return mix(emitter.scale[index], emitter.scale[index + 1u], position.y);
+}
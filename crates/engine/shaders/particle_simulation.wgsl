@@ -0,0 +1,70 @@
+#include "particle.wgsl"
+
+const TAU: f32 = 6.28318530718;
+
+@group(0) @binding(0)
+var<uniform> emitter: Emitter;
+
+@group(0) @binding(1)
+var<storage, read_write> particles: array<Particle>;
+
+// PCG hash, see "Hash Functions for GPU Rendering" by Jarzynski and Olano
+fn hash(value: u32) -> u32 {
+    let state = value * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn random(state: ptr<function, u32>) -> f32 {
+    *state = hash(*state);
+    return f32(*state) / 4294967295.0;
+}
+
+// Uniformly distributed inside the unit sphere
+fn random_in_sphere(state: ptr<function, u32>) -> vec3<f32> {
+    let z = random(state) * 2.0 - 1.0;
+    let angle = random(state) * TAU;
+    let radius = sqrt(1.0 - z * z);
+    let direction = vec3<f32>(radius * cos(angle), radius * sin(angle), z);
+    return direction * pow(random(state), 1.0 / 3.0);
+}
+
+@compute @workgroup_size(64)
+fn simulate(@builtin(global_invocation_id) id: vec3<u32>) {
+    let capacity = emitter.spawn.w;
+    let index = id.x;
+    if index >= capacity {
+        return;
+    }
+    let slot = emitter.range.x + index;
+    var particle = particles[slot];
+
+    // Spawned particles replace the oldest ones in a ring
+    let spawn_offset = (index + capacity - emitter.spawn.x) % capacity;
+    if spawn_offset < emitter.spawn.y {
+        var state = hash(index ^ hash(emitter.spawn.z));
+        let position = emitter.origin.xyz + random_in_sphere(&state) * emitter.origin.w;
+        let velocity = emitter.velocity.xyz + random_in_sphere(&state) * emitter.velocity.w;
+        let variation = (random(&state) * 2.0 - 1.0) * emitter.lifetime.y;
+        let lifetime = max(emitter.lifetime.x + variation, 0.001);
+        particle.position_age = vec4<f32>(position, 0.0);
+        particle.velocity_lifetime = vec4<f32>(velocity, lifetime);
+    } else if particle.position_age.w < particle.velocity_lifetime.w {
+        let delta_time = emitter.lifetime.z;
+        let life = particle.position_age.w / particle.velocity_lifetime.w;
+        var velocity = particle.velocity_lifetime.xyz + sample_acceleration(life) * delta_time;
+        var position = particle.position_age.xyz + velocity * sample_scale(life).y * delta_time;
+        if emitter.ground.w > 0.0 && position.y < emitter.ground.x {
+            position.y = emitter.ground.x;
+            if velocity.y < 0.0 {
+                velocity.y = -velocity.y * emitter.ground.y;
+            }
+            velocity.x *= 1.0 - emitter.ground.z;
+            velocity.z *= 1.0 - emitter.ground.z;
+        }
+        particle.position_age = vec4<f32>(position, particle.position_age.w + delta_time);
+        particle.velocity_lifetime = vec4<f32>(velocity, particle.velocity_lifetime.w);
+    }
+
+    particles[slot] = particle;
+}
@@ -0,0 +1,54 @@
+#include "particle.wgsl"
+#include "scene_uniform.wgsl"
+
+@group(1) @binding(0)
+var<uniform> emitter: Emitter;
+
+struct ParticleInput {
+    @location(0) position_age: vec4<f32>,
+    @location(1) velocity_lifetime: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+    @location(1) offset: vec2<f32>,
+};
+
+// Each particle is an instance of a camera facing quad
+@vertex
+fn vertex_main(@builtin(vertex_index) vertex_index: u32, particle: ParticleInput) -> VertexOutput {
+    var out: VertexOutput;
+    let age = particle.position_age.w;
+    let lifetime = particle.velocity_lifetime.w;
+    // Dead particles collapse to a point and produce no fragments
+    if age >= lifetime {
+        return out;
+    }
+
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(-1.0, 1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(1.0, -1.0),
+    );
+    let corner = corners[vertex_index];
+    let life = age / lifetime;
+    let right = vec3<f32>(ubo.view[0].x, ubo.view[1].x, ubo.view[2].x);
+    let up = vec3<f32>(ubo.view[0].y, ubo.view[1].y, ubo.view[2].y);
+    let half_size = sample_scale(life).x * 0.5;
+    let world_position = particle.position_age.xyz + (right * corner.x + up * corner.y) * half_size;
+
+    out.position = ubo.view_projection * vec4<f32>(world_position, 1.0);
+    out.color = sample_color(life);
+    out.offset = corner;
+    return out;
+}
+
+@fragment
+fn fragment_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let falloff = 1.0 - smoothstep(0.5, 1.0, length(in.offset));
+    return vec4<f32>(in.color.rgb, in.color.a * falloff);
+}
@@ -0,0 +1,36 @@
+#include "instancing.wgsl"
+
+struct Picking {
+    view_projection: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> picking: Picking;
+
+struct VertexInput {
+    @location(0) position: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) @interpolate(flat) id: u32,
+};
+
+struct FragmentOutput {
+    @location(0) id: u32,
+    @location(1) depth: u32,
+};
+
+@vertex
+fn vertex_main(vert: VertexInput, instance: InstanceInput, @location(10) id: u32) -> VertexOutput {
+    var out: VertexOutput;
+    out.position = picking.view_projection * instance_transform(instance) * vert.position;
+    out.id = id;
+    return out;
+}
+
+// The depth is stored as bits, as integer targets can be rendered to on every backend
+@fragment
+fn fragment_main(in: VertexOutput) -> FragmentOutput {
+    return FragmentOutput(in.id, bitcast<u32>(in.position.z));
+}
@@ -0,0 +1,202 @@
+#include "instancing.wgsl"
+#include "scene_uniform.wgsl"
+#include "shadow_sampling.wgsl"
+#include "environment_sampling.wgsl"
+
+const PI: f32 = 3.14159265359;
+
+struct Light {
+    position: vec4<f32>,
+    direction: vec4<f32>,
+    color: vec4<f32>,
+    cone: vec4<f32>,
+};
+
+struct Lights {
+    ambient: vec4<f32>,
+    count: vec4<u32>,
+    lights: array<Light, MAX_LIGHTS>,
+};
+
+struct Material {
+    base_color_factor: vec4<f32>,
+    emissive_factor: vec4<f32>,
+    metallic_factor: f32,
+    roughness_factor: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+};
+
+@group(0) @binding(1)
+var<uniform> lights: Lights;
+
+@group(1) @binding(0)
+var<uniform> material: Material;
+
+@group(1) @binding(1)
+var base_color_texture: texture_2d<f32>;
+
+@group(1) @binding(2)
+var metallic_roughness_texture: texture_2d<f32>;
+
+@group(1) @binding(3)
+var normal_texture: texture_2d<f32>;
+
+@group(1) @binding(4)
+var occlusion_texture: texture_2d<f32>;
+
+@group(1) @binding(5)
+var emissive_texture: texture_2d<f32>;
+
+@group(1) @binding(6)
+var material_sampler: sampler;
+
+struct VertexInput {
+    @location(0) position: vec4<f32>,
+    @location(1) normal: vec4<f32>,
+    @location(2) color: vec4<f32>,
+    @location(3) tex_coords: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) color: vec4<f32>,
+    @location(3) tex_coords: vec2<f32>,
+    @location(4) view_depth: f32,
+};
+
+@vertex
+fn vertex_main(vert: VertexInput, instance: InstanceInput) -> VertexOutput {
+    var out: VertexOutput;
+    let model = instance_transform(instance);
+    let world_position = model * vert.position;
+    out.world_position = world_position.xyz;
+    out.normal = (model * vec4<f32>(vert.normal.xyz, 0.0)).xyz;
+    out.color = vert.color * instance.color;
+    out.tex_coords = vert.tex_coords;
+    out.view_depth = (ubo.view * world_position).z;
+    out.position = ubo.view_projection * world_position;
+    return out;
+};
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let alpha = roughness * roughness;
+    let alpha_squared = alpha * alpha;
+    let denominator = n_dot_h * n_dot_h * (alpha_squared - 1.0) + 1.0;
+    return alpha_squared / (PI * denominator * denominator);
+}
+
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    let view = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let light = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return view * light;
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Builds a tangent frame from screen space derivatives so meshes need no tangent attribute
+fn perturb_normal(normal: vec3<f32>, world_position: vec3<f32>, tex_coords: vec2<f32>, sampled: vec3<f32>) -> vec3<f32> {
+    let dp1 = dpdx(world_position);
+    let dp2 = dpdy(world_position);
+    let duv1 = dpdx(tex_coords);
+    let duv2 = dpdy(tex_coords);
+    let dp2_perp = cross(dp2, normal);
+    let dp1_perp = cross(normal, dp1);
+    let tangent = dp2_perp * duv1.x + dp1_perp * duv2.x;
+    let bitangent = dp2_perp * duv1.y + dp1_perp * duv2.y;
+    let scale = inverseSqrt(max(max(dot(tangent, tangent), dot(bitangent, bitangent)), 1e-8));
+    let tbn = mat3x3<f32>(tangent * scale, bitangent * scale, normal);
+    let mapped = vec3<f32>(sampled.xy * material.normal_scale, sampled.z);
+    return normalize(tbn * mapped);
+}
+
+fn light_radiance(light: Light, world_position: vec3<f32>) -> vec4<f32> {
+    let kind = u32(light.position.w);
+    if kind == 0u {
+        return vec4<f32>(-normalize(light.direction.xyz), 1.0);
+    }
+
+    let to_light = light.position.xyz - world_position;
+    let distance = length(to_light);
+    let direction = to_light / max(distance, 1e-4);
+    let range = light.direction.w;
+    let falloff = clamp(1.0 - pow(distance / range, 4.0), 0.0, 1.0);
+    var attenuation = falloff * falloff / max(distance * distance, 1e-4);
+
+    if kind == 2u {
+        let cos_angle = dot(normalize(light.direction.xyz), -direction);
+        attenuation *= smoothstep(light.cone.y, light.cone.x, cos_angle);
+    }
+
+    return vec4<f32>(direction, attenuation);
+}
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    return pow(color, vec3<f32>(1.0 / 2.2));
+}
+
+@fragment
+fn fragment_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let base_color = material.base_color_factor * in.color
+        * textureSample(base_color_texture, material_sampler, in.tex_coords);
+    let metallic_roughness = textureSample(metallic_roughness_texture, material_sampler, in.tex_coords);
+    let metallic = clamp(material.metallic_factor * metallic_roughness.b, 0.0, 1.0);
+    let roughness = clamp(material.roughness_factor * metallic_roughness.g, 0.04, 1.0);
+    let occlusion = mix(1.0, textureSample(occlusion_texture, material_sampler, in.tex_coords).r, material.occlusion_strength);
+    let emissive = material.emissive_factor.rgb
+        * textureSample(emissive_texture, material_sampler, in.tex_coords).rgb;
+    let sampled_normal = textureSample(normal_texture, material_sampler, in.tex_coords).xyz * 2.0 - 1.0;
+
+    let view_direction = normalize(ubo.camera_position.xyz - in.world_position);
+    var geometric_normal = normalize(in.normal);
+    // Lights both sides of a surface, since meshes are drawn without culling
+    if dot(geometric_normal, view_direction) < 0.0 {
+        geometric_normal = -geometric_normal;
+    }
+    let normal = perturb_normal(geometric_normal, in.world_position, in.tex_coords, sampled_normal);
+
+    let f0 = mix(vec3<f32>(0.04), base_color.rgb, metallic);
+    let n_dot_v = max(dot(normal, view_direction), 1e-4);
+
+    var color = vec3<f32>(0.0);
+    for (var index = 0u; index < min(lights.count.x, MAX_LIGHTS); index++) {
+        let light = lights.lights[index];
+        let radiance = light_radiance(light, in.world_position);
+        let light_direction = radiance.xyz;
+        let halfway = normalize(view_direction + light_direction);
+        let n_dot_l = max(dot(normal, light_direction), 0.0);
+        let n_dot_h = max(dot(normal, halfway), 0.0);
+
+        let fresnel = fresnel_schlick(max(dot(halfway, view_direction), 0.0), f0);
+        let specular = distribution_ggx(n_dot_h, roughness) * geometry_smith(n_dot_v, n_dot_l, roughness) * fresnel
+            / max(4.0 * n_dot_v * n_dot_l, 1e-4);
+        let diffuse = (1.0 - fresnel) * (1.0 - metallic) * base_color.rgb / PI;
+
+#ifdef SHADOWS
+        let shadow = shadow_factor(light, in.world_position, geometric_normal, in.view_depth);
+#else
+        let shadow = 1.0;
+#endif
+        color += (diffuse + specular) * light.color.rgb * light.color.w * radiance.w * n_dot_l * shadow;
+    }
+
+    color += ambient_lighting(
+        normal,
+        view_direction,
+        base_color.rgb,
+        metallic,
+        roughness,
+        f0,
+        lights.ambient.rgb,
+    ) * occlusion;
+    color += emissive;
+
+    // Reinhard tonemapping, then encoding for the non-srgb surface
+    color = color / (color + vec3<f32>(1.0));
+    return vec4<f32>(linear_to_srgb(color), base_color.a);
+}
@@ -0,0 +1,9 @@
+// The camera uniform of the scene, shared by every shader drawn into the scene pass
+struct Uniform {
+    view_projection: mat4x4<f32>,
+    view: mat4x4<f32>,
+    camera_position: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> ubo: Uniform;
@@ -0,0 +1,17 @@
+#include "instancing.wgsl"
+
+struct ShadowPass {
+    view_projection: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> pass_uniform: ShadowPass;
+
+struct VertexInput {
+    @location(0) position: vec4<f32>,
+};
+
+@vertex
+fn vertex_main(vert: VertexInput, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    return pass_uniform.view_projection * instance_transform(instance) * vert.position;
+}
@@ -0,0 +1,79 @@
+struct Shadows {
+    layer_view_projections: array<mat4x4<f32>, SHADOW_LAYER_COUNT>,
+    cascade_splits: vec4<f32>,
+    settings: vec4<f32>,
+};
+
+@group(2) @binding(0)
+var<uniform> shadows: Shadows;
+
+@group(2) @binding(1)
+var shadow_map: texture_depth_2d_array;
+
+@group(2) @binding(2)
+var shadow_sampler: sampler_comparison;
+
+fn sample_shadow_layer(layer: u32, world_position: vec3<f32>) -> f32 {
+    let clip = shadows.layer_view_projections[layer] * vec4<f32>(world_position, 1.0);
+    let ndc = clip.xyz / clip.w;
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + vec2<f32>(0.5);
+    if any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0 {
+        return 1.0;
+    }
+
+    let depth = ndc.z - shadows.settings.x;
+    let radius = i32(shadows.settings.z);
+    let texel_size = shadows.settings.w;
+    var lit = 0.0;
+    for (var y = -radius; y <= radius; y++) {
+        for (var x = -radius; x <= radius; x++) {
+            let offset = vec2<f32>(f32(x), f32(y)) * texel_size;
+            lit += textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, layer, depth);
+        }
+    }
+    let size = f32(radius * 2 + 1);
+    return lit / (size * size);
+}
+
+fn cube_face(direction: vec3<f32>) -> u32 {
+    let magnitude = abs(direction);
+    if magnitude.x >= magnitude.y && magnitude.x >= magnitude.z {
+        return select(1u, 0u, direction.x > 0.0);
+    }
+    if magnitude.y >= magnitude.z {
+        return select(3u, 2u, direction.y > 0.0);
+    }
+    return select(5u, 4u, direction.z > 0.0);
+}
+
+// Returns how lit a surface is by a light, from 0.0 (fully shadowed) to 1.0
+fn shadow_factor(light: Light, world_position: vec3<f32>, normal: vec3<f32>, view_depth: f32) -> f32 {
+    let first_layer = i32(light.cone.z);
+    if first_layer < 0 {
+        return 1.0;
+    }
+    let kind = u32(light.position.w);
+    var layer = u32(first_layer);
+    var texel_world_size = shadows.settings.w;
+    if kind == 0u {
+        var cascade = 0u;
+        for (var index = 0u; index < 3u; index++) {
+            if view_depth > shadows.cascade_splits[index] {
+                cascade = index + 1u;
+            }
+        }
+        if view_depth > shadows.cascade_splits[cascade] {
+            return 1.0;
+        }
+        layer += cascade;
+        texel_world_size *= shadows.cascade_splits[cascade];
+    } else if kind == 1u {
+        let to_fragment = world_position - light.position.xyz;
+        layer += cube_face(to_fragment);
+        texel_world_size *= length(to_fragment);
+    } else {
+        texel_world_size *= length(world_position - light.position.xyz);
+    }
+    let offset_position = world_position + normal * shadows.settings.y * texel_world_size;
+    return sample_shadow_layer(layer, offset_position);
+}
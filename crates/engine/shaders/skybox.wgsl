@@ -0,0 +1,38 @@
+struct Skybox {
+    inverse_view_projection: mat4x4<f32>,
+    intensity: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> skybox: Skybox;
+
+@group(0) @binding(1)
+var environment_map: texture_cube<f32>;
+
+@group(0) @binding(2)
+var environment_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) clip_position: vec2<f32>,
+};
+
+@vertex
+fn vertex_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    let clip_position = uv * 2.0 - 1.0;
+    var out: VertexOutput;
+    out.position = vec4<f32>(clip_position, 1.0, 1.0);
+    out.clip_position = clip_position;
+    return out;
+}
+
+@fragment
+fn fragment_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let world = skybox.inverse_view_projection * vec4<f32>(in.clip_position, 1.0, 1.0);
+    let direction = normalize(world.xyz / world.w);
+    var color = textureSampleLevel(environment_map, environment_sampler, direction, 0.0).rgb
+        * skybox.intensity.x;
+    color = color / (color + vec3<f32>(1.0));
+    return vec4<f32>(pow(color, vec3<f32>(1.0 / 2.2)), 1.0);
+}
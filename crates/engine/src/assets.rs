@@ -0,0 +1,1159 @@
+use std::{
+    any::{Any, TypeId},
+    collections::{HashMap, HashSet},
+    marker::PhantomData,
+    sync::{mpsc, Arc, Mutex, Weak},
+};
+
+use crate::{
+    material::{Image, Material, MaterialFile},
+    mesh::{parse_obj, parse_stl, ImportOptions, Mesh},
+    shader::ShaderReflection,
+    vfs::Vfs,
+};
+
+pub use pack::ASSET_DIRECTORY;
+
+pub type AssetId = u64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssetError {
+    Read { path: String, message: String },
+    UnsupportedFormat { path: String },
+    Decode { path: String, message: String },
+    Upload { path: String, message: String },
+}
+
+impl std::fmt::Display for AssetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Read { path, message } => write!(f, "Failed to read {path:?}: {message}"),
+            Self::UnsupportedFormat { path } => {
+                write!(f, "The format of {path:?} is not supported")
+            }
+            Self::Decode { path, message } => write!(f, "Failed to decode {path:?}: {message}"),
+            Self::Upload { path, message } => write!(f, "Failed to upload {path:?}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for AssetError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadState {
+    Loading,
+    Loaded,
+    Failed(AssetError),
+}
+
+#[derive(Debug)]
+struct HandleInner {
+    id: AssetId,
+    path: String,
+}
+
+/// Keeps an asset alive. The asset is released once its last handle is dropped.
+pub struct Handle<T> {
+    inner: Arc<HandleInner>,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    pub fn id(&self) -> AssetId {
+        self.inner.id
+    }
+
+    pub fn path(&self) -> &str {
+        &self.inner.path
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Handle")
+            .field("id", &self.inner.id)
+            .field("path", &self.inner.path)
+            .finish()
+    }
+}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.inner.id == other.inner.id
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> std::hash::Hash for Handle<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.inner.id.hash(state);
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub type AssetFuture<T> = futures::future::BoxFuture<'static, Result<T, AssetError>>;
+
+/// Fetches are not `Send` on the web, where loads run on the main thread
+#[cfg(target_arch = "wasm32")]
+pub type AssetFuture<T> = futures::future::LocalBoxFuture<'static, Result<T, AssetError>>;
+
+/// Reads the files an asset is made of
+#[derive(Debug, Clone)]
+pub struct LoadContext {
+    vfs: Arc<Vfs>,
+    path: String,
+    /// Every path read so far, which reload the asset when they change
+    reads: Arc<Mutex<Vec<String>>>,
+}
+
+impl LoadContext {
+    /// The path of the asset being loaded
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The lowercase file extension of the asset
+    pub fn extension(&self) -> String {
+        std::path::Path::new(&self.path)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+            .unwrap_or_default()
+    }
+
+    /// Resolves a path relative to the directory of the asset
+    pub fn relative(&self, path: &str) -> String {
+        match self.path.rsplit_once('/') {
+            Some((directory, _)) => format!("{directory}/{path}"),
+            None => path.to_string(),
+        }
+    }
+
+    pub async fn read(&self, path: &str) -> Result<Vec<u8>, AssetError> {
+        // Missing files are recorded too, so that creating them retries the load
+        self.reads
+            .lock()
+            .expect("Reads are recorded without panicking")
+            .push(path.to_string());
+        self.vfs.read(path).await.map_err(|error| AssetError::Read {
+            path: path.to_string(),
+            message: error.to_string(),
+        })
+    }
+
+    pub async fn read_string(&self, path: &str) -> Result<String, AssetError> {
+        String::from_utf8(self.read(path).await?).map_err(|error| AssetError::Decode {
+            path: path.to_string(),
+            message: error.to_string(),
+        })
+    }
+
+    fn decode_error(&self, error: impl std::fmt::Display) -> AssetError {
+        AssetError::Decode {
+            path: self.path.clone(),
+            message: error.to_string(),
+        }
+    }
+}
+
+/// Where assets are uploaded once loaded, implemented by the renderer
+pub trait RenderResources {
+    fn device(&self) -> &wgpu::Device;
+    fn queue(&self) -> &wgpu::Queue;
+    fn add_mesh(&mut self, mesh: &Mesh, material: usize) -> usize;
+    fn replace_mesh(&mut self, index: usize, mesh: &Mesh);
+    fn remove_mesh(&mut self, mesh: usize);
+    fn instances_mut(&mut self, mesh: usize) -> Option<&mut Vec<crate::mesh::Instance>>;
+    fn add_material(&mut self, material: &Material) -> usize;
+    fn replace_material(&mut self, index: usize, material: &Material);
+    fn remove_material(&mut self, material: usize);
+}
+
+/// A type loaded from files by `Assets`
+pub trait Asset: Sized + Send + 'static {
+    /// What the asset becomes once uploaded, kept until its last handle is dropped
+    type Prepared: 'static;
+
+    /// Decodes the asset, on a worker thread on native
+    fn load(context: LoadContext) -> AssetFuture<Self>;
+
+    /// Uploads the decoded asset, on the main thread
+    fn prepare(
+        self,
+        resources: &mut dyn RenderResources,
+        path: &str,
+    ) -> Result<Self::Prepared, AssetError>;
+
+    /// Uploads a changed version of the asset over the one it was prepared into before,
+    /// on the main thread. By default the new version is prepared and the old one released.
+    fn reload(
+        self,
+        prepared: &mut Self::Prepared,
+        resources: &mut dyn RenderResources,
+        path: &str,
+    ) -> Result<(), AssetError> {
+        let previous = std::mem::replace(prepared, self.prepare(resources, path)?);
+        Self::release(previous, resources);
+        Ok(())
+    }
+
+    /// Frees what `prepare` registered with the renderer.
+    /// Gpu resources owned by `Prepared` are freed by dropping it.
+    fn release(_prepared: Self::Prepared, _resources: &mut dyn RenderResources) {}
+}
+
+/// Meshes are loaded from `.obj` and `.stl` files, merging every object of an `.obj` file.
+/// Prepared meshes are scene meshes without instances, drawn by entities holding their handle.
+/// Reloads replace the buffers of the scene mesh, so its index stays the same.
+impl Asset for Mesh {
+    type Prepared = usize;
+
+    fn load(context: LoadContext) -> AssetFuture<Self> {
+        Box::pin(async move {
+            match context.extension().as_str() {
+                "obj" => {
+                    let source = context.read_string(context.path()).await?;
+                    // Material libraries are read up front, since the parser asks for them synchronously
+                    let mut libraries = HashMap::new();
+                    for library in source
+                        .lines()
+                        .filter_map(|line| line.trim().strip_prefix("mtllib "))
+                    {
+                        let library = library.trim();
+                        if let Ok(contents) = context.read_string(&context.relative(library)).await
+                        {
+                            libraries.insert(library.to_string(), contents);
+                        }
+                    }
+                    let model = parse_obj(
+                        &source,
+                        |library| libraries.get(library).cloned(),
+                        ImportOptions::default(),
+                    )
+                    .map_err(|error| context.decode_error(error))?;
+                    Ok(merge_meshes(model.meshes.into_iter().map(|mesh| mesh.mesh)))
+                }
+                "stl" => parse_stl(
+                    &context.read(context.path()).await?,
+                    ImportOptions::default(),
+                )
+                .map_err(|error| context.decode_error(error)),
+                _ => Err(AssetError::UnsupportedFormat {
+                    path: context.path().to_string(),
+                }),
+            }
+        })
+    }
+
+    fn prepare(
+        self,
+        resources: &mut dyn RenderResources,
+        _path: &str,
+    ) -> Result<usize, AssetError> {
+        let mesh = resources.add_mesh(&self, 0);
+        if let Some(instances) = resources.instances_mut(mesh) {
+            instances.clear();
+        }
+        Ok(mesh)
+    }
+
+    fn reload(
+        self,
+        mesh: &mut usize,
+        resources: &mut dyn RenderResources,
+        _path: &str,
+    ) -> Result<(), AssetError> {
+        resources.replace_mesh(*mesh, &self);
+        Ok(())
+    }
+
+    fn release(mesh: usize, resources: &mut dyn RenderResources) {
+        resources.remove_mesh(mesh);
+    }
+}
+
+fn merge_meshes(meshes: impl IntoIterator<Item = Mesh>) -> Mesh {
+    let mut merged = Mesh::default();
+    for mesh in meshes {
+        let offset = merged.vertices.len() as u32;
+        merged.vertices.extend(mesh.vertices);
+        merged
+            .indices
+            .extend(mesh.indices.into_iter().map(|index| index + offset));
+    }
+    merged
+}
+
+/// Materials are loaded from RON `MaterialFile`s, along with the textures they name.
+/// Prepared materials are the index meshes refer to them by, which reloads keep.
+impl Asset for Material {
+    type Prepared = usize;
+
+    fn load(context: LoadContext) -> AssetFuture<Self> {
+        Box::pin(async move {
+            let source = context.read_string(context.path()).await?;
+            let file: MaterialFile =
+                ron::from_str(&source).map_err(|error| context.decode_error(error))?;
+            let mut textures: [Option<Image>; 5] = Default::default();
+            for (texture, path) in textures.iter_mut().zip(file.textures()) {
+                if let Some(path) = path {
+                    let path = context.relative(path);
+                    let bytes = context.read(&path).await?;
+                    *texture =
+                        Some(
+                            Image::from_bytes(&bytes).map_err(|error| AssetError::Decode {
+                                path,
+                                message: error.to_string(),
+                            })?,
+                        );
+                }
+            }
+            Ok(file.into_material(textures))
+        })
+    }
+
+    fn prepare(
+        self,
+        resources: &mut dyn RenderResources,
+        _path: &str,
+    ) -> Result<usize, AssetError> {
+        Ok(resources.add_material(&self))
+    }
+
+    fn reload(
+        self,
+        material: &mut usize,
+        resources: &mut dyn RenderResources,
+        _path: &str,
+    ) -> Result<(), AssetError> {
+        resources.replace_material(*material, &self);
+        Ok(())
+    }
+
+    fn release(material: usize, resources: &mut dyn RenderResources) {
+        resources.remove_material(material);
+    }
+}
+
+/// A texture uploaded from an image asset
+#[derive(Debug)]
+pub struct Texture {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+}
+
+/// Images are decoded from png, jpeg and hdr files into srgb textures.
+/// A reload creates a new texture, so views taken from the previous one go stale.
+impl Asset for Image {
+    type Prepared = Texture;
+
+    fn load(context: LoadContext) -> AssetFuture<Self> {
+        Box::pin(async move {
+            let bytes = context.read(context.path()).await?;
+            Image::from_bytes(&bytes).map_err(|error| context.decode_error(error))
+        })
+    }
+
+    fn prepare(
+        self,
+        resources: &mut dyn RenderResources,
+        _path: &str,
+    ) -> Result<Texture, AssetError> {
+        let texture = crate::renderer::create_texture(
+            resources.device(),
+            resources.queue(),
+            &self,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+        );
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Ok(Texture { texture, view })
+    }
+}
+
+/// WGSL source, validated when loaded and compiled into a shader module.
+/// Unlike the engine's shaders, it is not preprocessed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShaderSource {
+    pub source: String,
+}
+
+impl Asset for ShaderSource {
+    type Prepared = wgpu::ShaderModule;
+
+    fn load(context: LoadContext) -> AssetFuture<Self> {
+        Box::pin(async move {
+            let source = context.read_string(context.path()).await?;
+            ShaderReflection::new(&source).map_err(|error| context.decode_error(error))?;
+            Ok(ShaderSource { source })
+        })
+    }
+
+    fn prepare(
+        self,
+        resources: &mut dyn RenderResources,
+        path: &str,
+    ) -> Result<wgpu::ShaderModule, AssetError> {
+        crate::shader::validated(resources.device(), || {
+            resources
+                .device()
+                .create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some(path),
+                    source: wgpu::ShaderSource::Wgsl(self.source.into()),
+                })
+        })
+        .map_err(|message| AssetError::Upload {
+            path: path.to_string(),
+            message,
+        })
+    }
+}
+
+enum Slot<T: Asset> {
+    Loading,
+    Loaded(T::Prepared),
+    Failed(AssetError),
+}
+
+struct Entry<T: Asset> {
+    path: String,
+    handle: Weak<HandleInner>,
+    slot: Slot<T>,
+    /// The files the asset was loaded from
+    dependencies: Vec<String>,
+}
+
+struct Storage<T: Asset> {
+    entries: HashMap<AssetId, Entry<T>>,
+    ids: HashMap<String, AssetId>,
+}
+
+impl<T: Asset> Default for Storage<T> {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+            ids: HashMap::new(),
+        }
+    }
+}
+
+impl<T: Asset> Storage<T> {
+    /// A handle to the asset at `path`, revived if its handles were dropped but it was not released yet
+    fn existing(&mut self, path: &str) -> Option<Handle<T>> {
+        let entry = self.entries.get_mut(self.ids.get(path)?)?;
+        let inner = entry.handle.upgrade().unwrap_or_else(|| {
+            let inner = Arc::new(HandleInner {
+                id: self.ids[path],
+                path: path.to_string(),
+            });
+            entry.handle = Arc::downgrade(&inner);
+            inner
+        });
+        Some(Handle {
+            inner,
+            marker: PhantomData,
+        })
+    }
+
+    fn insert(&mut self, id: AssetId, path: String) -> Handle<T> {
+        let inner = Arc::new(HandleInner {
+            id,
+            path: path.clone(),
+        });
+        self.ids.insert(path.clone(), id);
+        self.entries.insert(
+            id,
+            Entry {
+                path,
+                handle: Arc::downgrade(&inner),
+                slot: Slot::Loading,
+                dependencies: Vec::new(),
+            },
+        );
+        Handle {
+            inner,
+            marker: PhantomData,
+        }
+    }
+}
+
+type LoadResult = Result<Box<dyn Any + Send>, AssetError>;
+
+struct Completed {
+    asset_type: TypeId,
+    id: AssetId,
+    result: LoadResult,
+    dependencies: Vec<String>,
+}
+
+trait AnyStorage {
+    fn complete(&mut self, completed: Completed, resources: &mut dyn RenderResources);
+    /// Loads the assets made from any of the `changed` files again
+    fn reload(&self, changed: &HashSet<String>, loader: &Loader);
+    /// Releases the assets whose handles were all dropped
+    fn collect(&mut self, resources: &mut dyn RenderResources);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Asset> AnyStorage for Storage<T> {
+    fn complete(&mut self, completed: Completed, resources: &mut dyn RenderResources) {
+        // The asset was released while it was loading
+        let Some(entry) = self.entries.get_mut(&completed.id) else {
+            return;
+        };
+        entry.dependencies = completed.dependencies;
+        let prepared = completed.result.and_then(|asset| {
+            let asset = asset
+                .downcast::<T>()
+                .expect("Loads complete with the type they were started with");
+            // Reloads swap the new version in, so handles keep referring to the same resources
+            match &mut entry.slot {
+                Slot::Loaded(prepared) => {
+                    asset.reload(prepared, resources, &entry.path).map(|_| None)
+                }
+                _ => asset.prepare(resources, &entry.path).map(Some),
+            }
+        });
+        match prepared {
+            Ok(Some(prepared)) => entry.slot = Slot::Loaded(prepared),
+            Ok(None) => {}
+            Err(error) => {
+                log::error!("{error}");
+                // A failed reload keeps the version that loaded before
+                if !matches!(entry.slot, Slot::Loaded(_)) {
+                    entry.slot = Slot::Failed(error);
+                }
+            }
+        }
+    }
+
+    fn reload(&self, changed: &HashSet<String>, loader: &Loader) {
+        for (id, entry) in &self.entries {
+            if entry
+                .dependencies
+                .iter()
+                .any(|dependency| changed.contains(dependency))
+            {
+                log::info!("Reloading asset {}", entry.path);
+                loader.spawn::<T>(*id, entry.path.clone());
+            }
+        }
+    }
+
+    fn collect(&mut self, resources: &mut dyn RenderResources) {
+        let released = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.handle.strong_count() == 0)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in released {
+            let Some(entry) = self.entries.remove(&id) else {
+                continue;
+            };
+            self.ids.remove(&entry.path);
+            if let Slot::Loaded(prepared) = entry.slot {
+                T::release(prepared, resources);
+            }
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Starts loads in the background, which send back what they decoded
+struct Loader {
+    vfs: Arc<Vfs>,
+    sender: mpsc::Sender<Completed>,
+    #[cfg(not(target_arch = "wasm32"))]
+    pool: rayon::ThreadPool,
+}
+
+impl Loader {
+    fn complete<T: Asset>(&self, id: AssetId, result: LoadResult, dependencies: Vec<String>) {
+        // The receiver lives as long as the sender it is paired with
+        let _ = self.sender.send(Completed {
+            asset_type: TypeId::of::<T>(),
+            id,
+            result,
+            dependencies,
+        });
+    }
+
+    fn spawn<T: Asset>(&self, id: AssetId, path: String) {
+        let context = LoadContext {
+            vfs: self.vfs.clone(),
+            path,
+            reads: Arc::default(),
+        };
+        let reads = context.reads.clone();
+        let sender = self.sender.clone();
+        let load = async move {
+            let result = T::load(context)
+                .await
+                .map(|asset| Box::new(asset) as Box<dyn Any + Send>);
+            let dependencies =
+                std::mem::take(&mut *reads.lock().expect("Reads are recorded without panicking"));
+            let _ = sender.send(Completed {
+                asset_type: TypeId::of::<T>(),
+                id,
+                result,
+                dependencies,
+            });
+        };
+
+        #[cfg(not(target_arch = "wasm32"))]
+        self.pool.spawn(move || futures::executor::block_on(load));
+
+        #[cfg(target_arch = "wasm32")]
+        wasm_bindgen_futures::spawn_local(load);
+    }
+}
+
+/// Watches the asset directory, so that assets are reloaded when their files change
+#[cfg(not(target_arch = "wasm32"))]
+struct AssetWatcher {
+    /// The watched directories and the mount points they are attached to
+    roots: Vec<(String, std::path::PathBuf)>,
+    _watcher: notify::RecommendedWatcher,
+    events: mpsc::Receiver<notify::Result<notify::Event>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl AssetWatcher {
+    fn new(vfs: &Vfs) -> Option<Self> {
+        use notify::Watcher;
+
+        // Applications without assets have nothing to watch
+        let roots = vfs
+            .directories()
+            .filter_map(|(point, directory)| {
+                Some((point.to_string(), std::fs::canonicalize(directory).ok()?))
+            })
+            .collect::<Vec<_>>();
+        if roots.is_empty() {
+            return None;
+        }
+        let (sender, events) = mpsc::channel();
+        let watcher = notify::recommended_watcher(move |event| {
+            let _ = sender.send(event);
+        })
+        .and_then(|mut watcher| {
+            for (_, root) in &roots {
+                watcher.watch(root, notify::RecursiveMode::Recursive)?;
+            }
+            Ok(watcher)
+        });
+        match watcher {
+            Ok(watcher) => Some(Self {
+                roots,
+                _watcher: watcher,
+                events,
+            }),
+            Err(error) => {
+                log::warn!(
+                    "Failed to watch the asset directories, hot reloading is disabled: {error}"
+                );
+                None
+            }
+        }
+    }
+
+    /// The virtual paths of the files that were written since the last poll
+    fn poll_changes(&self) -> HashSet<String> {
+        self.events
+            .try_iter()
+            .filter_map(Result::ok)
+            .filter(|event| event.kind.is_modify() || event.kind.is_create())
+            .flat_map(|event| event.paths)
+            // Editors often truncate before writing
+            .filter(|path| std::fs::metadata(path).is_ok_and(|metadata| metadata.len() > 0))
+            .filter_map(|path| {
+                let (point, relative) = self
+                    .roots
+                    .iter()
+                    .find_map(|(point, root)| Some((point, path.strip_prefix(root).ok()?)))?;
+                Some(
+                    point
+                        .split('/')
+                        .filter(|component| !component.is_empty())
+                        .map(std::borrow::Cow::Borrowed)
+                        .chain(
+                            relative
+                                .components()
+                                .map(|component| component.as_os_str().to_string_lossy()),
+                        )
+                        .collect::<Vec<_>>()
+                        .join("/"),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Loads meshes, materials, textures and shaders into typed handles.
+/// Each path is loaded once while any handle to it is alive, and
+/// loaded assets are uploaded by `update` on the main thread.
+/// Native builds reload assets in place when the files they were read from change.
+pub struct Assets {
+    storages: HashMap<TypeId, Box<dyn AnyStorage>>,
+    next_id: AssetId,
+    loader: Loader,
+    receiver: mpsc::Receiver<Completed>,
+    #[cfg(not(target_arch = "wasm32"))]
+    watcher: Option<AssetWatcher>,
+}
+
+/// Native builds read the asset directory. Web builds read the asset pack,
+/// and fetch the files missing from it one by one.
+impl Default for Assets {
+    fn default() -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        let vfs = Vfs::directory(ASSET_DIRECTORY);
+
+        #[cfg(target_arch = "wasm32")]
+        let vfs = Vfs::directory(ASSET_DIRECTORY)
+            .with_mount("", crate::vfs::PackMount::open(crate::vfs::ASSET_PACK));
+
+        Self::with_vfs(vfs)
+    }
+}
+
+impl Assets {
+    /// Reads assets relative to `root`, a directory on native and a url on the web
+    pub fn new(root: impl Into<String>) -> Self {
+        Self::with_vfs(Vfs::directory(root))
+    }
+
+    /// Reads assets from the mounts of `vfs`
+    pub fn with_vfs(vfs: Vfs) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            storages: HashMap::new(),
+            next_id: 0,
+            #[cfg(not(target_arch = "wasm32"))]
+            watcher: AssetWatcher::new(&vfs),
+            loader: Loader {
+                vfs: Arc::new(vfs),
+                sender,
+                #[cfg(not(target_arch = "wasm32"))]
+                pool: rayon::ThreadPoolBuilder::new()
+                    .thread_name(|index| format!("Asset Loader {index}"))
+                    .build()
+                    .expect("Failed to start the asset loading threads"),
+            },
+            receiver,
+        }
+    }
+
+    /// Starts loading the asset at `path`, or returns a handle to it if it is already loaded
+    pub fn load<T: Asset>(&mut self, path: impl Into<String>) -> Handle<T> {
+        let path = path.into();
+        if let Some(handle) = self.storage_mut::<T>().existing(&path) {
+            return handle;
+        }
+        let id = self.next_id();
+        let handle = self.storage_mut::<T>().insert(id, path.clone());
+        self.loader.spawn::<T>(id, path);
+        handle
+    }
+
+    /// Adds an asset that was created in code, uploaded by the next `update`.
+    /// If `path` is already loaded, its handles switch over to the new asset.
+    pub fn add<T: Asset>(&mut self, path: impl Into<String>, asset: T) -> Handle<T> {
+        let path = path.into();
+        let handle = match self.storage_mut::<T>().existing(&path) {
+            Some(handle) => handle,
+            None => {
+                let id = self.next_id();
+                self.storage_mut::<T>().insert(id, path)
+            }
+        };
+        // Assets made in code are not reloaded from files
+        self.loader
+            .complete::<T>(handle.id(), Ok(Box::new(asset)), Vec::new());
+        handle
+    }
+
+    pub fn load_state<T: Asset>(&self, handle: &Handle<T>) -> LoadState {
+        match self.entry(handle).map(|entry| &entry.slot) {
+            Some(Slot::Loaded(_)) => LoadState::Loaded,
+            Some(Slot::Failed(error)) => LoadState::Failed(error.clone()),
+            Some(Slot::Loading) | None => LoadState::Loading,
+        }
+    }
+
+    /// The uploaded asset, once it finished loading
+    pub fn get<T: Asset>(&self, handle: &Handle<T>) -> Option<&T::Prepared> {
+        match &self.entry(handle)?.slot {
+            Slot::Loaded(prepared) => Some(prepared),
+            _ => None,
+        }
+    }
+
+    /// Reloads the assets whose files changed, uploads the assets
+    /// that finished loading and releases those without handles
+    pub fn update(&mut self, resources: &mut dyn RenderResources) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(changed) = self.watcher.as_ref().map(AssetWatcher::poll_changes) {
+            self.reload(&changed);
+        }
+        while let Ok(completed) = self.receiver.try_recv() {
+            if let Some(storage) = self.storages.get_mut(&completed.asset_type) {
+                storage.complete(completed, resources);
+            }
+        }
+        for storage in self.storages.values_mut() {
+            storage.collect(resources);
+        }
+    }
+
+    /// Loads the assets read from any of the `changed` paths again.
+    /// The previous versions stay in use until the new ones are uploaded.
+    fn reload(&self, changed: &HashSet<String>) {
+        if changed.is_empty() {
+            return;
+        }
+        for storage in self.storages.values() {
+            storage.reload(changed, &self.loader);
+        }
+    }
+
+    fn entry<T: Asset>(&self, handle: &Handle<T>) -> Option<&Entry<T>> {
+        self.storages
+            .get(&TypeId::of::<T>())?
+            .as_any()
+            .downcast_ref::<Storage<T>>()?
+            .entries
+            .get(&handle.id())
+    }
+
+    fn storage_mut<T: Asset>(&mut self) -> &mut Storage<T> {
+        self.storages
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(Storage::<T>::default()))
+            .as_any_mut()
+            .downcast_mut()
+            .expect("Storages are keyed by their asset type")
+    }
+
+    fn next_id(&mut self) -> AssetId {
+        self.next_id += 1;
+        self.next_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Counts what reaches the renderer, without a gpu
+    #[derive(Default)]
+    struct Resources {
+        added: usize,
+        removed: Vec<usize>,
+        /// The index and triangle count of each replaced mesh
+        replaced: Vec<(usize, usize)>,
+        instances: Vec<crate::mesh::Instance>,
+        /// The base colors of the added materials, or of those they were replaced with
+        materials: Vec<[f32; 4]>,
+    }
+
+    impl RenderResources for Resources {
+        fn device(&self) -> &wgpu::Device {
+            unreachable!("No test asset uses the device")
+        }
+
+        fn queue(&self) -> &wgpu::Queue {
+            unreachable!("No test asset uses the queue")
+        }
+
+        fn add_mesh(&mut self, _mesh: &Mesh, _material: usize) -> usize {
+            self.added += 1;
+            self.added
+        }
+
+        fn replace_mesh(&mut self, index: usize, mesh: &Mesh) {
+            self.replaced.push((index, mesh.triangle_count()));
+        }
+
+        fn remove_mesh(&mut self, mesh: usize) {
+            self.removed.push(mesh);
+        }
+
+        fn instances_mut(&mut self, _mesh: usize) -> Option<&mut Vec<crate::mesh::Instance>> {
+            Some(&mut self.instances)
+        }
+
+        fn add_material(&mut self, material: &Material) -> usize {
+            self.materials.push(material.base_color_factor);
+            self.materials.len() - 1
+        }
+
+        fn replace_material(&mut self, index: usize, material: &Material) {
+            self.materials[index] = material.base_color_factor;
+        }
+
+        fn remove_material(&mut self, _material: usize) {}
+    }
+
+    /// Writes files into a directory that is removed when dropped
+    struct TempDirectory(std::path::PathBuf);
+
+    impl TempDirectory {
+        fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("engine_assets_{name}_{}", std::process::id()));
+            std::fs::create_dir_all(&path).unwrap();
+            for (file, contents) in files {
+                std::fs::write(path.join(file), contents).unwrap();
+            }
+            Self(path)
+        }
+
+        fn assets(&self) -> Assets {
+            Assets::new(self.0.to_string_lossy())
+        }
+    }
+
+    impl Drop for TempDirectory {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    const QUAD: &str = "o first\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3\no second\nf 1 3 4\n";
+    const TRIANGLE: &str = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n";
+
+    fn wait<T: Asset>(
+        assets: &mut Assets,
+        resources: &mut Resources,
+        handle: &Handle<T>,
+    ) -> LoadState {
+        let start = std::time::Instant::now();
+        loop {
+            assets.update(resources);
+            let state = assets.load_state(handle);
+            if state != LoadState::Loading || start.elapsed().as_secs() > 10 {
+                return state;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn loads_are_deduplicated_by_path() {
+        let directory =
+            TempDirectory::new("dedup", &[("quad.obj", QUAD), ("triangle.obj", TRIANGLE)]);
+        let mut assets = directory.assets();
+        let mut resources = Resources::default();
+
+        let quad = assets.load::<Mesh>("quad.obj");
+        let again = assets.load::<Mesh>("quad.obj");
+        let triangle = assets.load::<Mesh>("triangle.obj");
+        assert_eq!(quad, again);
+        assert_ne!(quad, triangle);
+
+        assert_eq!(wait(&mut assets, &mut resources, &quad), LoadState::Loaded);
+        assert_eq!(
+            wait(&mut assets, &mut resources, &triangle),
+            LoadState::Loaded
+        );
+        assert_eq!(resources.added, 2);
+        assert_ne!(assets.get(&quad), assets.get(&triangle));
+    }
+
+    #[test]
+    fn missing_and_unsupported_files_fail() {
+        let directory = TempDirectory::new("failures", &[("notes.txt", "")]);
+        let mut assets = directory.assets();
+        let mut resources = Resources::default();
+
+        let missing = assets.load::<Mesh>("missing.obj");
+        assert!(matches!(
+            wait(&mut assets, &mut resources, &missing),
+            LoadState::Failed(AssetError::Read { .. })
+        ));
+        let unsupported = assets.load::<Mesh>("notes.txt");
+        assert_eq!(
+            wait(&mut assets, &mut resources, &unsupported),
+            LoadState::Failed(AssetError::UnsupportedFormat {
+                path: "notes.txt".into()
+            })
+        );
+        assert!(assets.get(&missing).is_none());
+    }
+
+    #[test]
+    fn assets_are_released_with_their_last_handle() {
+        let directory = TempDirectory::new("release", &[("quad.obj", QUAD)]);
+        let mut assets = directory.assets();
+        let mut resources = Resources::default();
+
+        let quad = assets.load::<Mesh>("quad.obj");
+        assert_eq!(wait(&mut assets, &mut resources, &quad), LoadState::Loaded);
+        let mesh = *assets.get(&quad).unwrap();
+
+        let copy = quad.clone();
+        drop(quad);
+        assets.update(&mut resources);
+        assert!(resources.removed.is_empty());
+        assert_eq!(assets.get(&copy), Some(&mesh));
+
+        drop(copy);
+        assets.update(&mut resources);
+        assert_eq!(resources.removed, [mesh]);
+
+        // Loading the path again starts over
+        let reloaded = assets.load::<Mesh>("quad.obj");
+        assert_eq!(assets.load_state(&reloaded), LoadState::Loading);
+        assert_eq!(
+            wait(&mut assets, &mut resources, &reloaded),
+            LoadState::Loaded
+        );
+        assert_eq!(resources.added, 2);
+    }
+
+    #[test]
+    fn added_assets_replace_the_loaded_version() {
+        let directory = TempDirectory::new("replace", &[]);
+        let mut assets = directory.assets();
+        let mut resources = Resources::default();
+
+        let handle = assets.add("generated", Mesh::default());
+        assert_eq!(
+            wait(&mut assets, &mut resources, &handle),
+            LoadState::Loaded
+        );
+        let first = *assets.get(&handle).unwrap();
+
+        let same = assets.add("generated", Mesh::default());
+        assets.update(&mut resources);
+        assert_eq!(same, handle);
+        // The scene mesh is replaced in place rather than added anew
+        assert_eq!(assets.get(&handle), Some(&first));
+        assert_eq!(resources.replaced, [(first, 0)]);
+        assert!(resources.removed.is_empty());
+    }
+
+    fn wait_until(
+        assets: &mut Assets,
+        resources: &mut Resources,
+        done: impl Fn(&Resources) -> bool,
+    ) -> bool {
+        let start = std::time::Instant::now();
+        while !done(resources) {
+            if start.elapsed().as_secs() > 10 {
+                return false;
+            }
+            assets.update(resources);
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        true
+    }
+
+    #[test]
+    fn materials_reload_when_their_files_change() {
+        let directory = TempDirectory::new(
+            "reload",
+            &[(
+                "red.ron",
+                "(base_color_factor: (1, 0, 0, 1), base_color_texture: Some(\"red.png\"))",
+            )],
+        );
+        let mut assets = directory.assets();
+        let mut resources = Resources::default();
+
+        let material = assets.load::<Material>("red.ron");
+        assert!(matches!(
+            wait(&mut assets, &mut resources, &material),
+            LoadState::Failed(AssetError::Read { path, .. }) if path == "red.png"
+        ));
+
+        // Creating the missing texture retries the load
+        let mut png = std::io::Cursor::new(Vec::new());
+        image::RgbaImage::from_pixel(1, 1, image::Rgba([255, 0, 0, 255]))
+            .write_to(&mut png, image::ImageFormat::Png)
+            .unwrap();
+        std::fs::write(directory.0.join("red.png"), png.into_inner()).unwrap();
+        assets.reload(&HashSet::from(["red.png".to_string()]));
+        assert!(wait_until(&mut assets, &mut resources, |resources| {
+            !resources.materials.is_empty()
+        }));
+        assert_eq!(assets.load_state(&material), LoadState::Loaded);
+        let index = *assets.get(&material).unwrap();
+
+        // Edits are swapped in at the same index
+        std::fs::write(
+            directory.0.join("red.ron"),
+            "(base_color_factor: (0.5, 0, 0, 1), base_color_texture: Some(\"red.png\"))",
+        )
+        .unwrap();
+        assets.reload(&HashSet::from(["unrelated.obj".to_string()]));
+        assets.reload(&HashSet::from(["red.ron".to_string()]));
+        assert!(wait_until(&mut assets, &mut resources, |resources| {
+            resources.materials[index] == [0.5, 0.0, 0.0, 1.0]
+        }));
+        assert_eq!(assets.get(&material), Some(&index));
+        assert_eq!(resources.materials.len(), 1);
+
+        // A broken edit keeps the version that loaded before
+        std::fs::write(directory.0.join("red.ron"), "(base_color_factor: ").unwrap();
+        assets.reload(&HashSet::from(["red.ron".to_string()]));
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assets.update(&mut resources);
+        assert_eq!(assets.load_state(&material), LoadState::Loaded);
+    }
+
+    #[test]
+    fn written_files_are_reloaded() {
+        let directory = TempDirectory::new("watch", &[("model.obj", TRIANGLE)]);
+        let mut assets = directory.assets();
+        assert!(
+            assets.watcher.is_some(),
+            "The asset directory can not be watched"
+        );
+        let mut resources = Resources::default();
+
+        let model = assets.load::<Mesh>("model.obj");
+        assert_eq!(wait(&mut assets, &mut resources, &model), LoadState::Loaded);
+        let mesh = *assets.get(&model).unwrap();
+
+        std::fs::write(directory.0.join("model.obj"), QUAD).unwrap();
+        assert!(wait_until(&mut assets, &mut resources, |resources| {
+            resources.replaced.last() == Some(&(mesh, 2))
+        }));
+        assert_eq!(assets.get(&model), Some(&mesh));
+    }
+
+    #[test]
+    fn obj_objects_are_merged_into_one_mesh() {
+        let directory = TempDirectory::new("merge", &[("quad.obj", QUAD)]);
+        let mesh = futures::executor::block_on(Mesh::load(LoadContext {
+            vfs: Arc::new(Vfs::directory(directory.0.to_string_lossy())),
+            path: "quad.obj".into(),
+            reads: Arc::default(),
+        }))
+        .unwrap();
+        assert_eq!(mesh.triangle_count(), 2);
+        assert!(mesh
+            .indices
+            .iter()
+            .all(|index| (*index as usize) < mesh.vertices.len()));
+    }
+}
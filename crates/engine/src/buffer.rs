@@ -0,0 +1,437 @@
+use std::marker::PhantomData;
+
+/// A type that can be copied into a buffer and read by WGSL as is.
+///
+/// `bytemuck::Pod` only guarantees a `#[repr(C)]` layout, which differs from the WGSL one
+/// whenever a member is less aligned in Rust than in WGSL, like a `vec3` followed by another
+/// `vec3`, or an array whose elements are not padded to their alignment. Implementations
+/// describe the WGSL alignment, and the buffers below refuse to compile with types that
+/// break the layout rules of their address space.
+///
+/// Structs implement it through [`shader_type!`](crate::shader_type). Fixed size arrays
+/// are supported for vectors, matrices and structs, since `[f32; 4]` already means `vec4<f32>`.
+/// For the same reason padding is written as scalars or `vec4`s, as `[f32; 3]` is a `vec3<f32>`.
+pub trait ShaderType: bytemuck::Pod {
+    /// The alignment of the type in storage buffers
+    const ALIGNMENT: usize;
+    /// The alignment of the type in uniform buffers, where structs and arrays align to 16 bytes
+    const UNIFORM_ALIGNMENT: usize = Self::ALIGNMENT;
+    /// Why the Rust layout does not match the WGSL one in storage buffers, if it does not
+    const STORAGE_LAYOUT_ERROR: Option<&'static str> = None;
+    /// Why the Rust layout does not match the WGSL one in uniform buffers, if it does not
+    const UNIFORM_LAYOUT_ERROR: Option<&'static str> = None;
+}
+
+/// Types that can be the elements of a fixed size array in a shader type
+pub trait ArrayElement: ShaderType {}
+
+macro_rules! impl_shader_type {
+    ($alignment:literal => $($ty:ty),*) => {
+        $(impl ShaderType for $ty {
+            const ALIGNMENT: usize = $alignment;
+        })*
+    };
+}
+
+impl_shader_type!(4 => f32, u32, i32);
+impl_shader_type!(8 => [f32; 2], [u32; 2], [i32; 2], nalgebra_glm::Vec2);
+impl_shader_type!(16 => [f32; 3], [u32; 3], [i32; 3], nalgebra_glm::Vec3);
+impl_shader_type!(16 => [f32; 4], [u32; 4], [i32; 4], nalgebra_glm::Vec4, nalgebra_glm::Mat4);
+
+impl ArrayElement for [f32; 2] {}
+impl ArrayElement for [u32; 2] {}
+impl ArrayElement for [i32; 2] {}
+impl ArrayElement for nalgebra_glm::Vec2 {}
+impl ArrayElement for [f32; 3] {}
+impl ArrayElement for [u32; 3] {}
+impl ArrayElement for [i32; 3] {}
+impl ArrayElement for nalgebra_glm::Vec3 {}
+impl ArrayElement for [f32; 4] {}
+impl ArrayElement for [u32; 4] {}
+impl ArrayElement for [i32; 4] {}
+impl ArrayElement for nalgebra_glm::Vec4 {}
+impl ArrayElement for nalgebra_glm::Mat4 {}
+
+impl<T: ArrayElement, const N: usize> ShaderType for [T; N]
+where
+    [T; N]: bytemuck::Pod,
+{
+    const ALIGNMENT: usize = T::ALIGNMENT;
+    const UNIFORM_ALIGNMENT: usize = T::UNIFORM_ALIGNMENT.next_multiple_of(16);
+    const STORAGE_LAYOUT_ERROR: Option<&'static str> = if T::STORAGE_LAYOUT_ERROR.is_some() {
+        T::STORAGE_LAYOUT_ERROR
+    } else if !std::mem::size_of::<T>().is_multiple_of(T::ALIGNMENT) {
+        Some("array elements need a size that is a multiple of their alignment, since WGSL pads them")
+    } else {
+        None
+    };
+    const UNIFORM_LAYOUT_ERROR: Option<&'static str> = if T::UNIFORM_LAYOUT_ERROR.is_some() {
+        T::UNIFORM_LAYOUT_ERROR
+    } else if !std::mem::size_of::<T>().is_multiple_of(16) {
+        Some("array elements in uniform buffers need a size that is a multiple of 16 bytes")
+    } else {
+        None
+    };
+}
+
+/// Declares a `#[repr(C)]` struct and implements [`ShaderType`] for it, checking that
+/// every field sits at the offset WGSL expects and that the struct is padded to its alignment.
+/// Layout errors are reported when the struct is used in a [`UniformBuffer`] or [`StorageBuffer`].
+#[macro_export]
+macro_rules! shader_type {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $($(#[$field_meta:meta])* $field_vis:vis $field:ident: $ty:ty),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $($(#[$field_meta])* $field_vis $field: $ty),*
+        }
+
+        impl $crate::buffer::ShaderType for $name {
+            const ALIGNMENT: usize = $crate::buffer::max_alignment(&[
+                $(<$ty as $crate::buffer::ShaderType>::ALIGNMENT),*
+            ]);
+            const UNIFORM_ALIGNMENT: usize = $crate::buffer::max_alignment(&[
+                16,
+                $(<$ty as $crate::buffer::ShaderType>::UNIFORM_ALIGNMENT),*
+            ]);
+            const STORAGE_LAYOUT_ERROR: Option<&'static str> = {
+                let mut error = None;
+                $(
+                    if error.is_none() {
+                        error = <$ty as $crate::buffer::ShaderType>::STORAGE_LAYOUT_ERROR;
+                    }
+                    if error.is_none()
+                        && !::core::mem::offset_of!($name, $field)
+                            .is_multiple_of(<$ty as $crate::buffer::ShaderType>::ALIGNMENT)
+                    {
+                        error = Some(concat!(
+                            stringify!($name), "::", stringify!($field),
+                            " is not at its WGSL offset, add padding before it"
+                        ));
+                    }
+                )*
+                if error.is_none() && !::core::mem::size_of::<$name>().is_multiple_of(Self::ALIGNMENT) {
+                    error = Some(concat!(
+                        stringify!($name),
+                        " is not padded to its WGSL alignment, add padding at the end"
+                    ));
+                }
+                error
+            };
+            const UNIFORM_LAYOUT_ERROR: Option<&'static str> = {
+                let mut error = None;
+                $(
+                    if error.is_none() {
+                        error = <$ty as $crate::buffer::ShaderType>::UNIFORM_LAYOUT_ERROR;
+                    }
+                    if error.is_none()
+                        && !::core::mem::offset_of!($name, $field)
+                            .is_multiple_of(<$ty as $crate::buffer::ShaderType>::UNIFORM_ALIGNMENT)
+                    {
+                        error = Some(concat!(
+                            stringify!($name), "::", stringify!($field),
+                            " is not at its WGSL uniform offset, add padding before it"
+                        ));
+                    }
+                )*
+                if error.is_none()
+                    && !::core::mem::size_of::<$name>().is_multiple_of(Self::UNIFORM_ALIGNMENT)
+                {
+                    error = Some(concat!(
+                        stringify!($name),
+                        " is not padded to 16 bytes for uniform buffers, add padding at the end"
+                    ));
+                }
+                error
+            };
+        }
+
+        impl $crate::buffer::ArrayElement for $name {}
+    };
+}
+
+#[doc(hidden)]
+pub const fn max_alignment(alignments: &[usize]) -> usize {
+    let mut max = 1;
+    let mut index = 0;
+    while index < alignments.len() {
+        if alignments[index] > max {
+            max = alignments[index];
+        }
+        index += 1;
+    }
+    max
+}
+
+/// A uniform buffer holding one or more values of a shader type.
+/// Buffers with several values place each one at an offset usable as a dynamic offset,
+/// so per-object data can share one buffer and one bind group.
+pub struct UniformBuffer<T> {
+    buffer: wgpu::Buffer,
+    stride: wgpu::BufferAddress,
+    count: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: ShaderType> UniformBuffer<T> {
+    const LAYOUT_CHECK: () = if let Some(error) = T::UNIFORM_LAYOUT_ERROR {
+        panic!("{}", error)
+    };
+
+    pub fn new(device: &wgpu::Device, label: &str, value: &T) -> Self {
+        let () = Self::LAYOUT_CHECK;
+        let buffer = wgpu::util::DeviceExt::create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents: bytemuck::bytes_of(value),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            },
+        );
+        Self {
+            buffer,
+            stride: std::mem::size_of::<T>() as _,
+            count: 1,
+            _marker: PhantomData,
+        }
+    }
+
+    /// A buffer with room for `count` zeroed values, bound with dynamic offsets
+    pub fn new_dynamic(device: &wgpu::Device, label: &str, count: usize) -> Self {
+        let () = Self::LAYOUT_CHECK;
+        let stride = (std::mem::size_of::<T>() as wgpu::BufferAddress)
+            .next_multiple_of(device.limits().min_uniform_buffer_offset_alignment as _);
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: stride * count.max(1) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self {
+            buffer,
+            stride,
+            count,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn write(&self, queue: &wgpu::Queue, value: &T) {
+        self.write_at(queue, 0, value);
+    }
+
+    pub fn write_at(&self, queue: &wgpu::Queue, index: usize, value: &T) {
+        assert!(
+            index < self.count,
+            "Uniform buffer index {index} is out of bounds, the buffer holds {} values",
+            self.count
+        );
+        queue.write_buffer(
+            &self.buffer,
+            self.stride * index as wgpu::BufferAddress,
+            bytemuck::bytes_of(value),
+        );
+    }
+
+    /// The offset to bind the value at `index` with
+    pub fn dynamic_offset(&self, index: usize) -> wgpu::DynamicOffset {
+        (self.stride * index as wgpu::BufferAddress) as _
+    }
+
+    /// Binds a single value, the first one unless a dynamic offset is given
+    pub fn binding(&self) -> wgpu::BindingResource<'_> {
+        wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+            buffer: &self.buffer,
+            offset: 0,
+            size: wgpu::BufferSize::new(std::mem::size_of::<T>() as _),
+        })
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+}
+
+/// A storage buffer holding an array of a shader type
+pub struct StorageBuffer<T> {
+    buffer: wgpu::Buffer,
+    len: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: ShaderType> StorageBuffer<T> {
+    const LAYOUT_CHECK: () = if let Some(error) = T::STORAGE_LAYOUT_ERROR {
+        panic!("{}", error)
+    } else if !std::mem::size_of::<T>().is_multiple_of(T::ALIGNMENT) {
+        panic!("storage buffer elements need a size that is a multiple of their alignment")
+    };
+
+    /// `usage` is added to `STORAGE | COPY_DST`, e.g. `COPY_SRC` to read the buffer back
+    pub fn new(
+        device: &wgpu::Device,
+        label: &str,
+        values: &[T],
+        usage: wgpu::BufferUsages,
+    ) -> Self {
+        let () = Self::LAYOUT_CHECK;
+        // Empty bindings are invalid, so empty buffers still hold one value
+        let contents = if values.is_empty() {
+            vec![0; std::mem::size_of::<T>()]
+        } else {
+            bytemuck::cast_slice(values).to_vec()
+        };
+        let buffer = wgpu::util::DeviceExt::create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents: &contents,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | usage,
+            },
+        );
+        Self {
+            buffer,
+            len: values.len(),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn zeroed(
+        device: &wgpu::Device,
+        label: &str,
+        len: usize,
+        usage: wgpu::BufferUsages,
+    ) -> Self {
+        let () = Self::LAYOUT_CHECK;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: (std::mem::size_of::<T>() * len.max(1)) as _,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | usage,
+            mapped_at_creation: false,
+        });
+        Self {
+            buffer,
+            len,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Overwrites the values starting at `start`
+    pub fn write(&self, queue: &wgpu::Queue, start: usize, values: &[T]) {
+        assert!(
+            start + values.len() <= self.len,
+            "Writing {} values at {start} overflows a storage buffer of {} values",
+            values.len(),
+            self.len
+        );
+        queue.write_buffer(
+            &self.buffer,
+            (start * std::mem::size_of::<T>()) as _,
+            bytemuck::cast_slice(values),
+        );
+    }
+
+    pub fn binding(&self) -> wgpu::BindingResource<'_> {
+        self.buffer.as_entire_binding()
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    shader_type! {
+        #[repr(C)]
+        #[derive(Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+        struct Light {
+            position: [f32; 3],
+            range: f32,
+            color: [f32; 4],
+        }
+    }
+
+    shader_type! {
+        #[repr(C)]
+        #[derive(Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+        struct Lights {
+            lights: [Light; 4],
+            view_projection: nalgebra_glm::Mat4,
+            /// x: number of lights, padded to a `vec4` like in the shader
+            count: [u32; 4],
+        }
+    }
+
+    shader_type! {
+        #[repr(C)]
+        #[derive(Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+        struct Unpadded {
+            position: [f32; 3],
+            normal: [f32; 3],
+        }
+    }
+
+    shader_type! {
+        #[repr(C)]
+        #[derive(Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+        struct Point {
+            position: [f32; 2],
+        }
+    }
+
+    shader_type! {
+        #[repr(C)]
+        #[derive(Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+        struct Points {
+            points: [Point; 2],
+        }
+    }
+
+    #[test]
+    fn padded_vec3_matches_wgsl() {
+        assert_eq!(Light::ALIGNMENT, 16);
+        assert_eq!(Light::STORAGE_LAYOUT_ERROR, None);
+        assert_eq!(Light::UNIFORM_LAYOUT_ERROR, None);
+        assert_eq!(Lights::STORAGE_LAYOUT_ERROR, None);
+        assert_eq!(Lights::UNIFORM_LAYOUT_ERROR, None);
+    }
+
+    #[test]
+    fn consecutive_vec3_need_padding() {
+        assert_eq!(
+            Unpadded::STORAGE_LAYOUT_ERROR,
+            Some("Unpadded::normal is not at its WGSL offset, add padding before it")
+        );
+    }
+
+    #[test]
+    fn uniform_arrays_need_16_byte_strides() {
+        assert_eq!(Point::ALIGNMENT, 8);
+        assert_eq!(Points::STORAGE_LAYOUT_ERROR, None);
+        assert_eq!(
+            Points::UNIFORM_LAYOUT_ERROR,
+            Some("Point is not padded to 16 bytes for uniform buffers, add padding at the end")
+        );
+        assert_eq!(
+            <[[f32; 2]; 4]>::UNIFORM_LAYOUT_ERROR,
+            Some("array elements in uniform buffers need a size that is a multiple of 16 bytes")
+        );
+        assert_eq!(
+            <[[f32; 3]; 4]>::STORAGE_LAYOUT_ERROR,
+            Some("array elements need a size that is a multiple of their alignment, since WGSL pads them")
+        );
+    }
+}
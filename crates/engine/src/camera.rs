@@ -0,0 +1,99 @@
+use nalgebra_glm::{Mat4, Vec3};
+
+/// Pitch stops at looking straight down or up
+const MAX_PITCH: f32 = std::f32::consts::FRAC_PI_2;
+
+/// A camera circling a target point. At zero yaw and pitch it looks from +Z towards the target.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct OrbitCamera {
+    pub target: Vec3,
+    /// Rotation around the Y axis in radians, turning towards +X
+    pub yaw: f32,
+    /// Elevation above the XZ plane in radians
+    pub pitch: f32,
+    pub distance: f32,
+}
+
+impl Default for OrbitCamera {
+    fn default() -> Self {
+        Self {
+            target: Vec3::zeros(),
+            yaw: 0.0,
+            pitch: 0.0,
+            distance: 3.0,
+        }
+    }
+}
+
+impl OrbitCamera {
+    /// The unit vector from the target towards the camera
+    pub fn direction(&self) -> Vec3 {
+        let pitch = self.pitch.clamp(-MAX_PITCH, MAX_PITCH);
+        nalgebra_glm::vec3(
+            self.yaw.sin() * pitch.cos(),
+            pitch.sin(),
+            self.yaw.cos() * pitch.cos(),
+        )
+    }
+
+    pub fn position(&self) -> Vec3 {
+        self.target + self.direction() * self.distance
+    }
+
+    /// Perpendicular to the view direction, so straight down and up views stay well defined
+    pub fn up(&self) -> Vec3 {
+        let pitch = self.pitch.clamp(-MAX_PITCH, MAX_PITCH);
+        nalgebra_glm::vec3(
+            -pitch.sin() * self.yaw.sin(),
+            pitch.cos(),
+            -pitch.sin() * self.yaw.cos(),
+        )
+    }
+
+    pub fn view(&self) -> Mat4 {
+        nalgebra_glm::look_at_lh(&self.position(), &self.target, &self.up())
+    }
+
+    /// Moves the camera around the target so it looks back along `direction`,
+    /// keeping its yaw when looking straight up or down
+    pub fn look_from(&mut self, direction: &Vec3) {
+        let direction = direction.normalize();
+        self.pitch = direction.y.clamp(-1.0, 1.0).asin();
+        if direction.xz().norm() > 1e-4 {
+            self.yaw = direction.x.atan2(direction.z);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(first: Vec3, second: Vec3) {
+        assert!((first - second).norm() < 1e-5, "{first:?} != {second:?}");
+    }
+
+    #[test]
+    fn default_camera_looks_from_positive_z() {
+        let camera = OrbitCamera::default();
+        assert_near(camera.position(), nalgebra_glm::vec3(0.0, 0.0, 3.0));
+        assert_near(camera.up(), Vec3::y());
+    }
+
+    #[test]
+    fn looking_from_an_axis_places_the_camera_on_it() {
+        let mut camera = OrbitCamera::default();
+        for axis in [Vec3::x(), -Vec3::x(), -Vec3::z(), Vec3::z()] {
+            camera.look_from(&axis);
+            assert_near(camera.direction(), axis);
+            assert_near(camera.up(), Vec3::y());
+        }
+
+        // Looking down keeps the yaw, so the screen stays oriented like the side view before it
+        camera.look_from(&Vec3::x());
+        camera.look_from(&Vec3::y());
+        assert_near(camera.direction(), Vec3::y());
+        assert_near(camera.up(), -Vec3::x());
+        assert!(camera.view().iter().all(|value| value.is_finite()));
+    }
+}
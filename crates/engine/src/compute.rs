@@ -0,0 +1,406 @@
+use std::{marker::PhantomData, sync::Arc};
+
+use futures::channel::oneshot;
+
+use crate::{
+    buffer::{ShaderType, StorageBuffer},
+    shader::{validated, PipelineError, ShaderReflection},
+};
+
+/// A compute pipeline compiled from a WGSL entry point.
+/// Its bind group layouts are derived from the shader.
+pub struct ComputePipeline {
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl ComputePipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        label: &str,
+        source: &str,
+        entry_point: &str,
+    ) -> Result<Self, PipelineError> {
+        // Parsing up front reports shader errors on the web too, where error scopes are skipped
+        ShaderReflection::new(source)?;
+        let pipeline = validated(device, || {
+            let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(label),
+                source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(source)),
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: None,
+                module: &module,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        })
+        .map_err(PipelineError::Validation)?;
+        Ok(Self { pipeline })
+    }
+
+    /// Creates a bind group for `group`, binding each resource at its index
+    pub fn bind_group(
+        &self,
+        device: &wgpu::Device,
+        group: u32,
+        resources: &[wgpu::BindingResource],
+    ) -> wgpu::BindGroup {
+        let entries = resources
+            .iter()
+            .enumerate()
+            .map(|(binding, resource)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource: resource.clone(),
+            })
+            .collect::<Vec<_>>();
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.pipeline.get_bind_group_layout(group),
+            entries: &entries,
+        })
+    }
+
+    /// Records a dispatch of `workgroups` into the encoder, with the bind groups set in order
+    pub fn dispatch(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        bind_groups: &[&wgpu::BindGroup],
+        workgroups: [u32; 3],
+    ) {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        for (index, bind_group) in bind_groups.iter().enumerate() {
+            pass.set_bind_group(index as u32, *bind_group, &[]);
+        }
+        let [x, y, z] = workgroups;
+        pass.dispatch_workgroups(x, y, z);
+    }
+
+    /// Dispatches right away in a submission of its own, outside of any frame
+    pub fn dispatch_once(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bind_groups: &[&wgpu::BindGroup],
+        workgroups: [u32; 3],
+    ) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Compute Encoder"),
+        });
+        self.dispatch(&mut encoder, bind_groups, workgroups);
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    pub fn pipeline(&self) -> &wgpu::ComputePipeline {
+        &self.pipeline
+    }
+}
+
+/// The number of workgroups needed to cover `count` invocations
+pub fn workgroup_count(count: u32, workgroup_size: u32) -> u32 {
+    count.div_ceil(workgroup_size)
+}
+
+/// A texture compute shaders write to and other passes sample
+pub struct StorageTexture {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+}
+
+impl StorageTexture {
+    pub fn new(
+        device: &wgpu::Device,
+        label: &str,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Self { texture, view }
+    }
+
+    pub fn binding(&self) -> wgpu::BindingResource<'_> {
+        wgpu::BindingResource::TextureView(&self.view)
+    }
+}
+
+/// The contents of a buffer on their way back from the gpu.
+/// The copy is mapped once the commands recording it have been submitted.
+pub struct Readback<T> {
+    staging: Arc<wgpu::Buffer>,
+    receiver: oneshot::Receiver<Result<(), wgpu::BufferAsyncError>>,
+    _marker: PhantomData<T>,
+}
+
+/// A staging buffer that can be mapped once its copy was submitted
+pub(crate) struct PendingReadback {
+    staging: Arc<wgpu::Buffer>,
+    sender: oneshot::Sender<Result<(), wgpu::BufferAsyncError>>,
+}
+
+impl<T: bytemuck::Pod> Readback<T> {
+    /// Records a copy of `source`, which needs `COPY_SRC` usage, into a staging buffer
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        source: &wgpu::Buffer,
+    ) -> (Self, PendingReadback) {
+        let staging = Self::create_staging(device, source.size());
+        encoder.copy_buffer_to_buffer(source, 0, &staging, 0, source.size());
+        Self::pending(staging)
+    }
+
+    /// Records copies of single texels, one after the other. Every texture needs `COPY_SRC`
+    /// usage and a format whose texels have the size of `T`.
+    pub(crate) fn texels(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texels: &[wgpu::ImageCopyTexture],
+    ) -> (Self, PendingReadback) {
+        let texel_size = std::mem::size_of::<T>() as wgpu::BufferAddress;
+        let staging =
+            Self::create_staging(device, texel_size * texels.len() as wgpu::BufferAddress);
+        for (index, texel) in texels.iter().enumerate() {
+            encoder.copy_texture_to_buffer(
+                *texel,
+                wgpu::ImageCopyBuffer {
+                    buffer: &staging,
+                    layout: wgpu::ImageDataLayout {
+                        offset: texel_size * index as wgpu::BufferAddress,
+                        bytes_per_row: None,
+                        rows_per_image: None,
+                    },
+                },
+                wgpu::Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+        Self::pending(staging)
+    }
+
+    fn create_staging(device: &wgpu::Device, size: wgpu::BufferAddress) -> Arc<wgpu::Buffer> {
+        Arc::new(device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }))
+    }
+
+    fn pending(staging: Arc<wgpu::Buffer>) -> (Self, PendingReadback) {
+        let (sender, receiver) = oneshot::channel();
+        (
+            Self {
+                staging: staging.clone(),
+                receiver,
+                _marker: PhantomData,
+            },
+            PendingReadback { staging, sender },
+        )
+    }
+
+    /// Returns the contents once they arrived, without blocking
+    pub fn try_read(&mut self) -> Option<Result<Vec<T>, wgpu::BufferAsyncError>> {
+        match self.receiver.try_recv() {
+            Ok(Some(result)) => Some(result.map(|_| Self::contents(&self.staging))),
+            Ok(None) => None,
+            Err(oneshot::Canceled) => Some(Err(wgpu::BufferAsyncError)),
+        }
+    }
+
+    /// Waits for the contents. On native the device has to be polled meanwhile,
+    /// which the renderer does every frame.
+    pub async fn read(self) -> Result<Vec<T>, wgpu::BufferAsyncError> {
+        let Self {
+            staging, receiver, ..
+        } = self;
+        receiver.await.unwrap_or(Err(wgpu::BufferAsyncError))?;
+        Ok(Self::contents(&staging))
+    }
+
+    fn contents(staging: &wgpu::Buffer) -> Vec<T> {
+        let contents = bytemuck::pod_collect_to_vec(&staging.slice(..).get_mapped_range());
+        staging.unmap();
+        contents
+    }
+}
+
+impl PendingReadback {
+    /// Requests the mapping, which completes after the copy ran on the gpu
+    pub(crate) fn map(self) {
+        let Self { staging, sender } = self;
+        staging
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = sender.send(result);
+            });
+    }
+}
+
+/// Copies a buffer back to the cpu outside of any frame, waiting for the gpu
+pub async fn read_buffer<T: bytemuck::Pod>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    buffer: &wgpu::Buffer,
+) -> Result<Vec<T>, wgpu::BufferAsyncError> {
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+    let (readback, pending) = Readback::new(device, &mut encoder, buffer);
+    queue.submit(std::iter::once(encoder.finish()));
+    pending.map();
+    #[cfg(not(target_arch = "wasm32"))]
+    device.poll(wgpu::Maintain::Wait);
+    readback.read().await
+}
+
+impl<T: ShaderType> StorageBuffer<T> {
+    /// Reads the values back outside of any frame. The buffer needs `COPY_SRC` usage.
+    pub async fn read(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<Vec<T>, wgpu::BufferAsyncError> {
+        let mut values = read_buffer(device, queue, self.buffer()).await?;
+        values.truncate(self.len());
+        Ok(values)
+    }
+}
+
+/// A device without a window, for running compute work and tests
+pub struct HeadlessGpu {
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+    pub adapter_info: wgpu::AdapterInfo,
+}
+
+impl HeadlessGpu {
+    /// Returns `None` when there is no adapter that can run compute shaders.
+    /// The fallback adapter is a software rasterizer, where the platform provides one.
+    pub async fn new(force_fallback_adapter: bool) -> Option<Self> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::util::backend_bits_from_env().unwrap_or_else(wgpu::Backends::all),
+            ..Default::default()
+        });
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: None,
+                force_fallback_adapter,
+            })
+            .await?;
+        if !adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+        {
+            return None;
+        }
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: Some("Headless Device"),
+                    required_features: wgpu::Features::empty(),
+                    required_limits: wgpu::Limits::downlevel_defaults(),
+                    memory_hints: wgpu::MemoryHints::default(),
+                },
+                None,
+            )
+            .await
+            .ok()?;
+        Some(Self {
+            device,
+            queue,
+            adapter_info: adapter.get_info(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An inclusive Hillis-Steele scan of one workgroup
+    const PREFIX_SUM: &str = "
+const SIZE: u32 = 256u;
+
+@group(0) @binding(0)
+var<storage, read_write> values: array<u32>;
+
+var<workgroup> scratch: array<u32, SIZE>;
+
+@compute @workgroup_size(256)
+fn prefix_sum(@builtin(local_invocation_index) index: u32) {
+    scratch[index] = values[index];
+    workgroupBarrier();
+    for (var offset = 1u; offset < SIZE; offset *= 2u) {
+        var sum = scratch[index];
+        if index >= offset {
+            sum += scratch[index - offset];
+        }
+        workgroupBarrier();
+        scratch[index] = sum;
+        workgroupBarrier();
+    }
+    values[index] = scratch[index];
+}
+";
+
+    #[test]
+    #[ignore = "needs a fallback adapter, run with `just test-gpu`"]
+    fn prefix_sum_on_fallback_adapter() {
+        let gpu = pollster::block_on(HeadlessGpu::new(true)).expect("There is no fallback adapter");
+        let input = (1..=256).collect::<Vec<u32>>();
+        let values = StorageBuffer::new(
+            &gpu.device,
+            "Prefix Sum Values",
+            &input,
+            wgpu::BufferUsages::COPY_SRC,
+        );
+        let pipeline =
+            ComputePipeline::new(&gpu.device, "Prefix Sum", PREFIX_SUM, "prefix_sum").unwrap();
+        let bind_group = pipeline.bind_group(&gpu.device, 0, &[values.binding()]);
+        pipeline.dispatch_once(&gpu.device, &gpu.queue, &[&bind_group], [1, 1, 1]);
+
+        let output = pollster::block_on(values.read(&gpu.device, &gpu.queue)).unwrap();
+        let expected = input
+            .iter()
+            .scan(0, |sum, value| {
+                *sum += value;
+                Some(*sum)
+            })
+            .collect::<Vec<u32>>();
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn workgroup_count_rounds_up() {
+        assert_eq!(workgroup_count(0, 64), 0);
+        assert_eq!(workgroup_count(64, 64), 1);
+        assert_eq!(workgroup_count(65, 64), 2);
+    }
+}
@@ -0,0 +1,282 @@
+use nalgebra_glm::{Mat4, Vec3, Vec4};
+
+use crate::mesh::Instance;
+
+/// An axis aligned bounding box
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// The smallest box around the points, or an empty box at the origin without any
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        let mut points = points.into_iter();
+        let Some(first) = points.next() else {
+            return Self {
+                min: Vec3::zeros(),
+                max: Vec3::zeros(),
+            };
+        };
+        points.fold(
+            Self {
+                min: first,
+                max: first,
+            },
+            |aabb, point| Self {
+                min: aabb.min.inf(&point),
+                max: aabb.max.sup(&point),
+            },
+        )
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    /// Half the size of the box along each axis
+    pub fn half_extents(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    /// The box around the transformed box
+    pub fn transformed(&self, transform: &Mat4) -> Self {
+        let center = (transform * self.center().push(1.0)).xyz();
+        let linear = transform.fixed_view::<3, 3>(0, 0).abs();
+        let half_extents = linear * self.half_extents();
+        Self {
+            min: center - half_extents,
+            max: center + half_extents,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// A sphere around the points, centered on their bounding box
+    pub fn from_points(points: impl IntoIterator<Item = Vec3> + Clone) -> Self {
+        let center = Aabb::from_points(points.clone()).center();
+        let radius = points
+            .into_iter()
+            .map(|point| nalgebra_glm::distance(&point, &center))
+            .fold(0.0, f32::max);
+        Self { center, radius }
+    }
+
+    /// The sphere around the transformed sphere, scaled by the largest axis scale
+    pub fn transformed(&self, transform: &Mat4) -> Self {
+        let scale = (0..3)
+            .map(|axis| transform.fixed_view::<3, 1>(0, axis).norm())
+            .fold(0.0, f32::max);
+        Self {
+            center: (transform * self.center.push(1.0)).xyz(),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+/// The bounding volumes of a mesh in its local space
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Bounds {
+    pub aabb: Aabb,
+    pub sphere: BoundingSphere,
+}
+
+impl Bounds {
+    pub fn from_points(points: impl IntoIterator<Item = Vec3> + Clone) -> Self {
+        Self {
+            aabb: Aabb::from_points(points.clone()),
+            sphere: BoundingSphere::from_points(points),
+        }
+    }
+}
+
+/// The six planes bounding what a camera sees, pointing inwards
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Frustum {
+    /// Left, right, bottom, top, near and far, as normals followed by their distance
+    pub planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes of a view projection with a 0 to 1 depth range
+    pub fn from_view_projection(view_projection: &Mat4) -> Self {
+        let row = |index: usize| view_projection.row(index).transpose();
+        let planes = [
+            row(3) + row(0),
+            row(3) - row(0),
+            row(3) + row(1),
+            row(3) - row(1),
+            row(2),
+            row(3) - row(2),
+        ]
+        .map(|plane| plane / plane.xyz().norm().max(f32::EPSILON));
+        Self { planes }
+    }
+
+    fn distance(plane: &Vec4, point: &Vec3) -> f32 {
+        plane.xyz().dot(point) + plane.w
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| Self::distance(plane, &sphere.center) >= -sphere.radius)
+    }
+
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the plane normal is the last to leave the frustum
+            let corner = Vec3::from_fn(|axis, _| {
+                if plane[axis] >= 0.0 {
+                    aabb.max[axis]
+                } else {
+                    aabb.min[axis]
+                }
+            });
+            Self::distance(plane, &corner) >= 0.0
+        })
+    }
+
+    /// Whether local `bounds` placed by `transform` may be visible.
+    /// The sphere rejects most objects cheaply, the box catches the rest.
+    pub fn intersects(&self, bounds: &Bounds, transform: &Mat4) -> bool {
+        self.intersects_sphere(&bounds.sphere.transformed(transform))
+            && self.intersects_aabb(&bounds.aabb.transformed(transform))
+    }
+}
+
+/// How many instances the last frame drew and skipped
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct CullStats {
+    pub drawn: u32,
+    pub culled: u32,
+}
+
+/// Moves the instances of a mesh that may be visible to the front, keeping their order,
+/// and returns how many there are. The culled instances behind them end up in any order.
+pub fn partition_visible(instances: &mut [Instance], bounds: &Bounds, frustum: &Frustum) -> usize {
+    let mut visible = 0;
+    for index in 0..instances.len() {
+        if frustum.intersects(bounds, &instances[index].transform) {
+            instances.swap(visible, index);
+            visible += 1;
+        }
+    }
+    visible
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nalgebra_glm::vec3;
+
+    fn cube() -> Vec<Vec3> {
+        [-1.0, 1.0]
+            .into_iter()
+            .flat_map(|x| [-1.0, 1.0].map(|y| (x, y)))
+            .flat_map(|(x, y)| [-1.0, 1.0].map(|z| vec3(x, y, z)))
+            .collect()
+    }
+
+    /// Looks down +Z from the origin, like the scene camera
+    fn frustum() -> Frustum {
+        let projection = nalgebra_glm::perspective_lh_zo(1.0, 90f32.to_radians(), 0.1, 100.0);
+        Frustum::from_view_projection(&projection)
+    }
+
+    fn at(position: Vec3) -> Mat4 {
+        nalgebra_glm::translation(&position)
+    }
+
+    #[test]
+    fn bounds_enclose_the_points() {
+        let mut points = cube();
+        points.push(vec3(3.0, 0.0, 0.0));
+        let bounds = Bounds::from_points(points.clone());
+        assert_eq!(bounds.aabb.min, vec3(-1.0, -1.0, -1.0));
+        assert_eq!(bounds.aabb.max, vec3(3.0, 1.0, 1.0));
+        assert_eq!(bounds.sphere.center, vec3(1.0, 0.0, 0.0));
+        assert!(points.iter().all(|point| {
+            nalgebra_glm::distance(point, &bounds.sphere.center) <= bounds.sphere.radius + 1e-5
+        }));
+
+        let empty = Bounds::from_points(Vec::new());
+        assert_eq!(empty.aabb.min, empty.aabb.max);
+        assert_eq!(empty.sphere.radius, 0.0);
+    }
+
+    #[test]
+    fn transformed_bounds_follow_the_transform() {
+        let bounds = Bounds::from_points(cube());
+        let transform = at(vec3(5.0, 0.0, 0.0))
+            * nalgebra_glm::rotation(45f32.to_radians(), &Vec3::y())
+            * nalgebra_glm::scaling(&vec3(2.0, 1.0, 1.0));
+
+        let aabb = bounds.aabb.transformed(&transform);
+        assert!((aabb.center() - vec3(5.0, 0.0, 0.0)).norm() < 1e-5);
+        // A rotated box needs a larger box around it
+        assert!(aabb.half_extents().x > 2.0 && (aabb.half_extents().y - 1.0).abs() < 1e-5);
+
+        let sphere = bounds.sphere.transformed(&transform);
+        assert!((sphere.radius - bounds.sphere.radius * 2.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn objects_outside_the_frustum_are_culled() {
+        let frustum = frustum();
+        let bounds = Bounds::from_points(cube());
+        for (position, visible) in [
+            (vec3(0.0, 0.0, 10.0), true),
+            // Straddling the near plane and the side planes
+            (vec3(0.0, 0.0, 0.0), true),
+            (vec3(10.5, 0.0, 10.0), true),
+            (vec3(0.0, 0.0, -10.0), false),
+            (vec3(20.0, 0.0, 10.0), false),
+            (vec3(0.0, -20.0, 10.0), false),
+            (vec3(0.0, 0.0, 102.0), false),
+        ] {
+            assert_eq!(
+                frustum.intersects(&bounds, &at(position)),
+                visible,
+                "{position:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn the_box_rejects_what_the_sphere_misses() {
+        let frustum = frustum();
+        // A long thin box just behind the camera, whose sphere reaches into the frustum
+        let bounds = Bounds::from_points([vec3(-10.0, -0.1, -0.1), vec3(10.0, 0.1, 0.1)]);
+        let transform = at(vec3(0.0, 0.0, -0.5));
+        let sphere = bounds.sphere.transformed(&transform);
+        assert!(frustum.intersects_sphere(&sphere));
+        assert!(!frustum.intersects(&bounds, &transform));
+    }
+
+    #[test]
+    fn visible_instances_are_moved_to_the_front() {
+        let bounds = Bounds::from_points(cube());
+        let mut instances = [
+            vec3(0.0, 0.0, -10.0),
+            vec3(0.0, 0.0, 10.0),
+            vec3(0.0, 50.0, 10.0),
+            vec3(1.0, 0.0, 20.0),
+        ]
+        .map(|position| Instance::new(at(position)));
+
+        let visible = partition_visible(&mut instances, &bounds, &frustum());
+        assert_eq!(visible, 2);
+        let positions = instances.map(|instance| instance.transform.column(3).xyz());
+        assert_eq!(positions[0], vec3(0.0, 0.0, 10.0));
+        assert_eq!(positions[1], vec3(1.0, 0.0, 20.0));
+    }
+}
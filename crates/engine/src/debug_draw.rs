@@ -0,0 +1,445 @@
+use std::sync::Arc;
+
+use nalgebra_glm::{Mat4, Vec3};
+
+use crate::{
+    pipeline::{Blend, PipelineBuilder, PipelineCache},
+    renderer::Gpu,
+    shader::{PipelineError, ShaderDefines, ShaderLibrary, ShaderReflection, Sources},
+};
+
+const SPHERE_SEGMENTS: usize = 32;
+const ARROW_HEAD_LENGTH: f32 = 0.2;
+
+const RED: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+const GREEN: [f32; 4] = [0.0, 1.0, 0.0, 1.0];
+const BLUE: [f32; 4] = [0.0, 0.0, 1.0, 1.0];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct DebugLine {
+    start: Vec3,
+    end: Vec3,
+    color: [f32; 4],
+    /// Seconds the line is drawn for after the current frame
+    remaining: f32,
+    depth_test: bool,
+}
+
+/// Lines and shapes drawn over the scene for debugging. Everything drawn
+/// is shown for a single frame and depth tested, unless told otherwise through
+/// the returned `DebugShape`. Colors are linear RGBA.
+#[derive(Debug, Default)]
+pub struct DebugDraw {
+    lines: Vec<DebugLine>,
+}
+
+/// The lines of the shape that was just drawn
+pub struct DebugShape<'a> {
+    lines: &'a mut [DebugLine],
+}
+
+impl DebugShape<'_> {
+    /// Keeps drawing the shape for this many seconds instead of a single frame
+    pub fn duration(self, seconds: f32) -> Self {
+        for line in self.lines.iter_mut() {
+            line.remaining = seconds;
+        }
+        self
+    }
+
+    /// Draws the shape on top of the scene instead of hiding it behind geometry
+    pub fn on_top(self) -> Self {
+        for line in self.lines.iter_mut() {
+            line.depth_test = false;
+        }
+        self
+    }
+}
+
+impl DebugDraw {
+    pub fn line(&mut self, start: Vec3, end: Vec3, color: [f32; 4]) -> DebugShape<'_> {
+        self.push([(start, end)], color)
+    }
+
+    /// A line with a head at `end`, sized relative to its length
+    pub fn arrow(&mut self, start: Vec3, end: Vec3, color: [f32; 4]) -> DebugShape<'_> {
+        self.push(arrow_segments(start, end), color)
+    }
+
+    /// An axis aligned box between two opposite corners
+    pub fn aabb(&mut self, min: Vec3, max: Vec3, color: [f32; 4]) -> DebugShape<'_> {
+        let corners = std::array::from_fn(|index| {
+            nalgebra_glm::vec3(
+                if index & 1 == 0 { min.x } else { max.x },
+                if index & 2 == 0 { min.y } else { max.y },
+                if index & 4 == 0 { min.z } else { max.z },
+            )
+        });
+        self.push(box_segments(&corners), color)
+    }
+
+    /// Three circles around the center, one in each axis plane
+    pub fn sphere(&mut self, center: Vec3, radius: f32, color: [f32; 4]) -> DebugShape<'_> {
+        let planes = [
+            (Vec3::x(), Vec3::y()),
+            (Vec3::y(), Vec3::z()),
+            (Vec3::z(), Vec3::x()),
+        ];
+        let segments = planes.into_iter().flat_map(|(first, second)| {
+            let point = move |index: usize| {
+                let angle = index as f32 / SPHERE_SEGMENTS as f32 * std::f32::consts::TAU;
+                center + (first * angle.cos() + second * angle.sin()) * radius
+            };
+            (0..SPHERE_SEGMENTS).map(move |index| (point(index), point(index + 1)))
+        });
+        self.push(segments, color)
+    }
+
+    /// The volume a camera sees, given its projection multiplied by its view
+    pub fn frustum(&mut self, view_projection: &Mat4, color: [f32; 4]) -> DebugShape<'_> {
+        let inverse = view_projection.try_inverse().unwrap_or_else(Mat4::identity);
+        // Clip space depth goes from zero to one
+        let corners = std::array::from_fn(|index| {
+            let corner = inverse
+                * nalgebra_glm::vec4(
+                    if index & 1 == 0 { -1.0 } else { 1.0 },
+                    if index & 2 == 0 { -1.0 } else { 1.0 },
+                    if index & 4 == 0 { 0.0 } else { 1.0 },
+                    1.0,
+                );
+            corner.xyz() / corner.w
+        });
+        self.push(box_segments(&corners), color)
+    }
+
+    /// A square grid on the XZ plane, `size` wide with `divisions` cells along each side
+    pub fn grid(
+        &mut self,
+        center: Vec3,
+        size: f32,
+        divisions: u32,
+        color: [f32; 4],
+    ) -> DebugShape<'_> {
+        let divisions = divisions.max(1);
+        let half = size * 0.5;
+        let segments = (0..=divisions).flat_map(|index| {
+            let offset = index as f32 / divisions as f32 * size - half;
+            [
+                (
+                    center + nalgebra_glm::vec3(offset, 0.0, -half),
+                    center + nalgebra_glm::vec3(offset, 0.0, half),
+                ),
+                (
+                    center + nalgebra_glm::vec3(-half, 0.0, offset),
+                    center + nalgebra_glm::vec3(half, 0.0, offset),
+                ),
+            ]
+        });
+        self.push(segments, color)
+    }
+
+    /// Red, green and blue arrows along the X, Y and Z axes of a transform
+    pub fn axes(&mut self, transform: &Mat4, length: f32) -> DebugShape<'_> {
+        let first = self.lines.len();
+        let origin = (transform * nalgebra_glm::vec4(0.0, 0.0, 0.0, 1.0)).xyz();
+        for (axis, color) in [(Vec3::x(), RED), (Vec3::y(), GREEN), (Vec3::z(), BLUE)] {
+            let end = (transform * (axis * length).push(1.0)).xyz();
+            self.push(arrow_segments(origin, end), color);
+        }
+        DebugShape {
+            lines: &mut self.lines[first..],
+        }
+    }
+
+    /// Removes everything drawn, including shapes that have time left
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    fn push(
+        &mut self,
+        segments: impl IntoIterator<Item = (Vec3, Vec3)>,
+        color: [f32; 4],
+    ) -> DebugShape<'_> {
+        let first = self.lines.len();
+        self.lines
+            .extend(segments.into_iter().map(|(start, end)| DebugLine {
+                start,
+                end,
+                color,
+                remaining: 0.0,
+                depth_test: true,
+            }));
+        DebugShape {
+            lines: &mut self.lines[first..],
+        }
+    }
+
+    /// Drops the lines whose time ran out, after a frame that took `delta_time`
+    fn advance(&mut self, delta_time: f32) {
+        self.lines.retain_mut(|line| {
+            line.remaining -= delta_time;
+            line.remaining > 0.0
+        });
+    }
+
+    /// Depth tested lines come first, followed by the ones drawn on top
+    fn vertices(&self) -> (Vec<DebugVertex>, u32) {
+        let vertices = |depth_test: bool| {
+            self.lines
+                .iter()
+                .filter(move |line| line.depth_test == depth_test)
+                .flat_map(|line| {
+                    [line.start, line.end].map(|position| DebugVertex {
+                        position: [position.x, position.y, position.z, 1.0],
+                        color: line.color,
+                    })
+                })
+        };
+        let mut all = vertices(true).collect::<Vec<_>>();
+        let depth_tested = all.len() as u32;
+        all.extend(vertices(false));
+        (all, depth_tested)
+    }
+}
+
+fn arrow_segments(start: Vec3, end: Vec3) -> Vec<(Vec3, Vec3)> {
+    let mut segments = vec![(start, end)];
+    let direction = end - start;
+    let length = direction.norm();
+    if length <= f32::EPSILON {
+        return segments;
+    }
+    let direction = direction / length;
+    // Any axis that is not parallel to the arrow gives a perpendicular frame
+    let reference = if direction.y.abs() < 0.99 {
+        Vec3::y()
+    } else {
+        Vec3::x()
+    };
+    let side = direction.cross(&reference).normalize();
+    let up = side.cross(&direction);
+    let head_length = length * ARROW_HEAD_LENGTH;
+    let base = end - direction * head_length;
+    for offset in [side, -side, up, -up] {
+        segments.push((end, base + offset * head_length * 0.5));
+    }
+    segments
+}
+
+/// The twelve edges of a box whose corners are indexed by their X, Y and Z bits
+fn box_segments(corners: &[Vec3; 8]) -> Vec<(Vec3, Vec3)> {
+    (0..8)
+        .flat_map(|corner| {
+            [1, 2, 4]
+                .into_iter()
+                .filter(move |bit| corner & bit == 0)
+                .map(move |bit| (corners[corner], corners[corner | bit]))
+        })
+        .collect()
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct DebugVertex {
+    position: [f32; 4],
+    color: [f32; 4],
+}
+
+impl DebugVertex {
+    fn vertex_attributes() -> Vec<wgpu::VertexAttribute> {
+        wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4].to_vec()
+    }
+
+    fn description(attributes: &[wgpu::VertexAttribute]) -> wgpu::VertexBufferLayout<'_> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<DebugVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes,
+        }
+    }
+}
+
+/// Draws the lines of a `DebugDraw` as one line list, split into a
+/// depth tested range and a range drawn on top of everything
+pub struct DebugLines {
+    pub draw: DebugDraw,
+    /// The depth tested pipeline followed by the one drawing on top
+    pipelines: [Arc<wgpu::RenderPipeline>; 2],
+    buffer: wgpu::Buffer,
+    capacity: usize,
+    depth_tested_count: u32,
+    vertex_count: u32,
+}
+
+impl DebugLines {
+    /// The debug line shader along with every file it includes
+    pub const SHADERS: [&str; 2] = ["debug_lines.wgsl", "scene_uniform.wgsl"];
+
+    pub fn new(
+        gpu: &Gpu,
+        shaders: &mut ShaderLibrary,
+        pipelines: &PipelineCache,
+        scene_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let device = &gpu.device;
+        let render_pipelines = shaders.build(device, "Debug Lines", |sources| {
+            Self::create_pipelines(
+                device,
+                gpu.surface_format,
+                scene_bind_group_layout,
+                sources,
+                pipelines,
+            )
+        });
+        Self {
+            draw: DebugDraw::default(),
+            pipelines: render_pipelines,
+            buffer: Self::create_buffer(device, 1),
+            capacity: 1,
+            depth_tested_count: 0,
+            vertex_count: 0,
+        }
+    }
+
+    pub fn reload_shaders(
+        &mut self,
+        changed: &[&str],
+        device: &wgpu::Device,
+        surface_format: wgpu::TextureFormat,
+        shaders: &mut ShaderLibrary,
+        pipelines: &PipelineCache,
+        scene_bind_group_layout: &wgpu::BindGroupLayout,
+    ) {
+        if !changed.iter().any(|name| Self::SHADERS.contains(name)) {
+            return;
+        }
+        if let Some(render_pipelines) = shaders.rebuild(device, "Debug Lines", |sources| {
+            Self::create_pipelines(
+                device,
+                surface_format,
+                scene_bind_group_layout,
+                sources,
+                pipelines,
+            )
+        }) {
+            self.pipelines = render_pipelines;
+        }
+    }
+
+    /// Uploads this frame's lines, then lets the time of every line run down by `delta_time`
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, delta_time: f32) {
+        let (vertices, depth_tested_count) = self.draw.vertices();
+        if vertices.len() > self.capacity {
+            self.capacity = vertices.len().next_power_of_two();
+            self.buffer = Self::create_buffer(device, self.capacity);
+        }
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&vertices));
+        self.depth_tested_count = depth_tested_count;
+        self.vertex_count = vertices.len() as u32;
+        self.draw.advance(delta_time);
+    }
+
+    pub fn render<'rpass>(
+        &'rpass self,
+        render_pass: &mut wgpu::RenderPass<'rpass>,
+        scene_bind_group: &'rpass wgpu::BindGroup,
+    ) {
+        if self.vertex_count == 0 {
+            return;
+        }
+        render_pass.set_bind_group(0, scene_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.buffer.slice(..));
+        let ranges = [
+            0..self.depth_tested_count,
+            self.depth_tested_count..self.vertex_count,
+        ];
+        for (pipeline, range) in self.pipelines.iter().zip(ranges) {
+            if range.is_empty() {
+                continue;
+            }
+            render_pass.set_pipeline(pipeline);
+            render_pass.draw(range, 0..1);
+        }
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        surface_format: wgpu::TextureFormat,
+        scene_bind_group_layout: &wgpu::BindGroupLayout,
+        sources: Sources,
+        pipelines: &PipelineCache,
+    ) -> Result<[Arc<wgpu::RenderPipeline>; 2], PipelineError> {
+        let source = sources.preprocess("debug_lines.wgsl", &ShaderDefines::new())?;
+        let attributes = DebugVertex::vertex_attributes();
+        let buffers = [DebugVertex::description(&attributes)];
+        ShaderReflection::new(&source)?.validate_vertex_buffers("vertex_main", &buffers)?;
+
+        let layouts = [scene_bind_group_layout];
+        let create = |compare: wgpu::CompareFunction| {
+            PipelineBuilder::new("Debug Lines", &source, surface_format)
+                .bind_group_layouts(&layouts)
+                .vertex_buffers(&buffers)
+                .topology(wgpu::PrimitiveTopology::LineList)
+                .blend(Blend::Alpha)
+                .depth_test(compare, false)
+                .build(device, pipelines)
+        };
+        Ok([
+            create(wgpu::CompareFunction::LessEqual)?,
+            create(wgpu::CompareFunction::Always)?,
+        ])
+    }
+
+    fn create_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Debug Line Buffer"),
+            size: (capacity.max(1) * std::mem::size_of::<DebugVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shapes_produce_their_edges() {
+        let mut draw = DebugDraw::default();
+        draw.aabb(Vec3::zeros(), nalgebra_glm::vec3(1.0, 1.0, 1.0), RED);
+        assert_eq!(draw.lines.len(), 12);
+        assert!(draw
+            .lines
+            .iter()
+            .all(|line| (line.end - line.start).norm() == 1.0));
+
+        draw.clear();
+        draw.frustum(&Mat4::identity(), RED);
+        assert_eq!(draw.lines.len(), 12);
+
+        draw.clear();
+        draw.axes(&Mat4::identity(), 1.0);
+        assert_eq!(draw.lines.len(), 15);
+        assert_eq!(draw.lines[0].end, Vec3::x());
+    }
+
+    #[test]
+    fn lines_expire_after_their_duration() {
+        let mut draw = DebugDraw::default();
+        draw.line(Vec3::zeros(), Vec3::x(), RED);
+        draw.sphere(Vec3::zeros(), 1.0, GREEN)
+            .duration(0.25)
+            .on_top();
+
+        let (vertices, depth_tested) = draw.vertices();
+        assert_eq!(vertices.len(), 2 + SPHERE_SEGMENTS * 3 * 2);
+        assert_eq!(depth_tested, 2);
+
+        draw.advance(0.1);
+        assert_eq!(draw.lines.len(), SPHERE_SEGMENTS * 3);
+        draw.advance(0.2);
+        assert!(draw.lines.is_empty());
+    }
+}
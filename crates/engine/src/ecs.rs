@@ -0,0 +1,282 @@
+use nalgebra_glm::Mat4;
+
+pub use hecs::{Entity, World};
+
+use crate::{
+    assets::{Assets, Handle},
+    light::Light,
+    mesh::{Instance, Mesh},
+};
+
+pub use crate::scene_graph::Transform;
+
+/// The most fixed updates run in one frame. A frame that took longer
+/// drops the remaining steps instead of falling further behind.
+const MAX_FIXED_STEPS: u32 = 8;
+
+/// Draws a mesh added with `Context::add_mesh` at the entity's `Transform`
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct MeshRenderer {
+    pub mesh: usize,
+    /// Multiplied with the material base color
+    pub color: [f32; 4],
+}
+
+impl MeshRenderer {
+    pub fn new(mesh: usize) -> Self {
+        Self {
+            mesh,
+            color: [1.0; 4],
+        }
+    }
+}
+
+/// Renders the scene from the entity's `Transform`, looking along its +Z axis.
+/// Takes over from the orbit camera while such an entity exists.
+#[derive(Debug, Default, Copy, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Camera {
+    /// Among several cameras, the one with the highest priority is used
+    pub priority: i32,
+}
+
+/// When a system runs within a frame
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Phase {
+    /// Once per frame, right after `State::update`
+    Update,
+    /// At a fixed rate, after `State::fixed_update`, however long frames take
+    FixedUpdate,
+}
+
+/// A system is handed the world and the seconds it advances by:
+/// the frame time for `Phase::Update`, the timestep for `Phase::FixedUpdate`.
+pub type System = Box<dyn FnMut(&mut World, f32)>;
+
+/// The entity world on `Context`, along with the systems that run on it.
+/// Entities with a `Transform` and a `MeshRenderer`, `Handle<Mesh>`, `Light` or `Camera`
+/// are rendered without the application touching the renderer.
+pub struct Ecs {
+    pub world: World,
+    update_systems: Vec<System>,
+    fixed_update_systems: Vec<System>,
+    /// Seconds between two fixed updates
+    pub fixed_timestep: f32,
+    /// Time that has passed but was not simulated by a fixed update yet
+    accumulator: f32,
+}
+
+impl Default for Ecs {
+    fn default() -> Self {
+        Self {
+            world: World::new(),
+            update_systems: Vec::new(),
+            fixed_update_systems: Vec::new(),
+            fixed_timestep: 1.0 / 60.0,
+            accumulator: 0.0,
+        }
+    }
+}
+
+impl Ecs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Systems of a phase run in the order they were added
+    pub fn add_system(&mut self, phase: Phase, system: impl FnMut(&mut World, f32) + 'static) {
+        let systems = match phase {
+            Phase::Update => &mut self.update_systems,
+            Phase::FixedUpdate => &mut self.fixed_update_systems,
+        };
+        systems.push(Box::new(system));
+    }
+
+    /// Adds a frame's time to the accumulator, returning how many fixed updates it covers
+    pub fn fixed_steps(&mut self, delta_time: f32) -> u32 {
+        let timestep = self.fixed_timestep.max(f32::EPSILON);
+        self.accumulator += delta_time;
+        let steps = (self.accumulator / timestep).floor() as u32;
+        self.accumulator -= steps as f32 * timestep;
+        if steps > MAX_FIXED_STEPS {
+            log::warn!("Skipping {} fixed updates", steps - MAX_FIXED_STEPS);
+        }
+        steps.min(MAX_FIXED_STEPS)
+    }
+
+    /// How far the simulation is between the last fixed update and the next, from 0 to 1
+    pub fn fixed_interpolation(&self) -> f32 {
+        (self.accumulator / self.fixed_timestep.max(f32::EPSILON)).clamp(0.0, 1.0)
+    }
+
+    pub fn run_fixed_update(&mut self) {
+        for system in self.fixed_update_systems.iter_mut() {
+            system(&mut self.world, self.fixed_timestep);
+        }
+    }
+
+    pub fn run_update(&mut self, delta_time: f32) {
+        for system in self.update_systems.iter_mut() {
+            system(&mut self.world, delta_time);
+        }
+    }
+}
+
+/// What the renderer draws from the world this frame
+#[derive(Debug, Default)]
+pub(crate) struct WorldExtract {
+    /// Instances indexed by mesh
+    pub instances: Vec<Vec<Instance>>,
+    /// The entity each instance was extracted from, indexed like `instances`
+    pub entities: Vec<Vec<Entity>>,
+    pub lights: Vec<Light>,
+    /// The world transform of the active camera
+    pub camera: Option<Mat4>,
+}
+
+impl WorldExtract {
+    /// Gathers the rendered components, reusing the allocations of the previous frame.
+    /// Entities holding a `Handle<Mesh>` are drawn once the mesh has loaded.
+    pub fn extract(&mut self, world: &World, assets: &Assets) {
+        self.instances.iter_mut().for_each(Vec::clear);
+        self.entities.iter_mut().for_each(Vec::clear);
+        for (entity, transform, renderer) in
+            world.query::<(Entity, &Transform, &MeshRenderer)>().iter()
+        {
+            self.push(entity, renderer.mesh, transform, renderer.color);
+        }
+        for (entity, transform, mesh) in world.query::<(Entity, &Transform, &Handle<Mesh>)>().iter()
+        {
+            if let Some(mesh) = assets.get(mesh) {
+                self.push(entity, *mesh, transform, [1.0; 4]);
+            }
+        }
+
+        self.lights.clear();
+        self.lights.extend(
+            world
+                .query::<(&Transform, &Light)>()
+                .iter()
+                .map(|(transform, light)| light.transformed(&transform.matrix())),
+        );
+
+        self.camera = world
+            .query::<(&Transform, &Camera)>()
+            .iter()
+            .max_by_key(|(_, camera)| camera.priority)
+            .map(|(transform, _)| transform.matrix());
+    }
+
+    fn push(&mut self, entity: Entity, mesh: usize, transform: &Transform, color: [f32; 4]) {
+        if self.instances.len() <= mesh {
+            self.instances.resize_with(mesh + 1, Vec::new);
+            self.entities.resize_with(mesh + 1, Vec::new);
+        }
+        self.entities[mesh].push(entity);
+        self.instances[mesh].push(Instance {
+            transform: transform.matrix(),
+            color,
+            ..Default::default()
+        });
+    }
+
+    pub fn instances(&self, mesh: usize) -> &[Instance] {
+        self.instances.get(mesh).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn entities(&self, mesh: usize) -> &[Entity] {
+        self.entities.get(mesh).map_or(&[], Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn fixed_steps_follow_the_accumulated_time() {
+        let mut ecs = Ecs {
+            fixed_timestep: 0.25,
+            ..Default::default()
+        };
+        assert_eq!(ecs.fixed_steps(0.1), 0);
+        assert_eq!(ecs.fixed_steps(0.2), 1);
+        assert!((ecs.fixed_interpolation() - 0.2).abs() < 1e-5);
+        assert_eq!(ecs.fixed_steps(0.45), 2);
+        // A long stall only catches up by the step limit
+        assert_eq!(ecs.fixed_steps(10.0), MAX_FIXED_STEPS);
+    }
+
+    #[test]
+    fn systems_run_in_their_phase_and_order() {
+        let mut ecs = Ecs::new();
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        for (phase, name) in [
+            (Phase::Update, "first"),
+            (Phase::FixedUpdate, "fixed"),
+            (Phase::Update, "second"),
+        ] {
+            let calls = calls.clone();
+            ecs.add_system(phase, move |_, delta_time| {
+                calls.borrow_mut().push((name, delta_time))
+            });
+        }
+
+        ecs.run_fixed_update();
+        ecs.run_update(0.5);
+        assert_eq!(
+            *calls.borrow(),
+            [
+                ("fixed", ecs.fixed_timestep),
+                ("first", 0.5),
+                ("second", 0.5)
+            ]
+        );
+    }
+
+    #[test]
+    fn rendered_components_are_extracted() {
+        let mut world = World::new();
+        let position = nalgebra_glm::vec3(1.0, 2.0, 3.0);
+        let first = world.spawn((Transform::from_translation(position), MeshRenderer::new(2)));
+        let second = world.spawn((Transform::default(), MeshRenderer::new(2)));
+        // Without a transform there is nowhere to draw the mesh
+        world.spawn((MeshRenderer::new(0),));
+        world.spawn((
+            Transform::from_translation(position),
+            Light::Point {
+                position: nalgebra_glm::Vec3::zeros(),
+                color: nalgebra_glm::vec3(1.0, 1.0, 1.0),
+                intensity: 1.0,
+                range: 10.0,
+            },
+        ));
+        world.spawn((Transform::default(), Camera { priority: 0 }));
+        world.spawn((
+            Transform::from_translation(position),
+            Camera { priority: 1 },
+        ));
+
+        let assets = Assets::default();
+        let mut extract = WorldExtract::default();
+        extract.extract(&world, &assets);
+        assert!(extract.instances(0).is_empty());
+        assert_eq!(extract.instances(2).len(), 2);
+        assert_eq!(extract.entities(2), [first, second]);
+        assert!(extract.instances(5).is_empty());
+        assert!(matches!(
+            extract.lights.as_slice(),
+            [Light::Point { position: light_position, .. }] if *light_position == position
+        ));
+        assert_eq!(
+            extract.camera.map(|camera| camera.column(3).xyz()),
+            Some(position)
+        );
+
+        world.clear();
+        extract.extract(&world, &assets);
+        assert!(extract.instances(2).is_empty());
+        assert!(extract.lights.is_empty() && extract.camera.is_none());
+    }
+}
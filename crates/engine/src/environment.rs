@@ -0,0 +1,635 @@
+use std::sync::Arc;
+
+use crate::{
+    buffer::UniformBuffer,
+    pipeline::{PipelineBuilder, PipelineCache},
+    renderer::Gpu,
+    shader::{PipelineError, ShaderDefines, ShaderLibrary, Sources},
+    shader_type,
+};
+
+const ENVIRONMENT_SIZE: u32 = 512;
+const IRRADIANCE_SIZE: u32 = 32;
+const PREFILTERED_SIZE: u32 = 128;
+const PREFILTERED_MIP_LEVELS: u32 = 5;
+const BRDF_LUT_SIZE: u32 = 256;
+const CUBE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const BRDF_LUT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg16Float;
+
+/// Image based lighting baked from an equirectangular hdr image.
+/// Until an environment is loaded, 1x1 black placeholders are bound
+/// and the scene falls back to its flat ambient color.
+pub struct Environment {
+    pub intensity: f32,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+    uniform_buffer: UniformBuffer<EnvironmentUniform>,
+    pub skybox_pipeline: Arc<wgpu::RenderPipeline>,
+    skybox_buffer: UniformBuffer<SkyboxUniform>,
+    pub skybox_bind_group: Option<wgpu::BindGroup>,
+    /// The baked environment cubemap the skybox samples
+    environment_view: Option<wgpu::TextureView>,
+    sampler: wgpu::Sampler,
+    color_format: wgpu::TextureFormat,
+    depth_format: wgpu::TextureFormat,
+}
+
+impl Environment {
+    pub const SKYBOX_SHADER: &str = "skybox.wgsl";
+    const BAKE_SHADER: &str = "environment_bake.wgsl";
+
+    /// Takes the environment bind group layout reflected from the scene shader
+    pub fn new(
+        gpu: &Gpu,
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        layout_entries: &[wgpu::BindGroupLayoutEntry],
+        shaders: &mut ShaderLibrary,
+        pipelines: &PipelineCache,
+    ) -> Self {
+        let device = &gpu.device;
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Environment Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let uniform_buffer = UniformBuffer::new(
+            device,
+            "Environment Uniform Buffer",
+            &EnvironmentUniform::default(),
+        );
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: layout_entries,
+            label: Some("environment_bind_group_layout"),
+        });
+
+        let placeholder_cube = create_cube_texture(device, 1, 1, "Environment Placeholder");
+        let placeholder_lut =
+            create_render_texture(device, 1, BRDF_LUT_FORMAT, "BRDF LUT Placeholder");
+        let bind_group = create_bind_group(
+            device,
+            &bind_group_layout,
+            &uniform_buffer,
+            &cube_view(&placeholder_cube),
+            &cube_view(&placeholder_cube),
+            &placeholder_lut.create_view(&wgpu::TextureViewDescriptor::default()),
+            &sampler,
+        );
+
+        let skybox_buffer =
+            UniformBuffer::new(device, "Skybox Uniform Buffer", &SkyboxUniform::default());
+        let skybox_pipeline = shaders.build(device, "Skybox", |sources| {
+            create_skybox_pipeline(device, color_format, depth_format, sources, pipelines)
+        });
+
+        Self {
+            intensity: 1.0,
+            bind_group_layout,
+            bind_group,
+            uniform_buffer,
+            skybox_pipeline,
+            skybox_buffer,
+            skybox_bind_group: None,
+            environment_view: None,
+            sampler,
+            color_format,
+            depth_format,
+        }
+    }
+
+    pub fn create_skybox_pipeline(
+        &self,
+        device: &wgpu::Device,
+        sources: Sources,
+        pipelines: &PipelineCache,
+    ) -> Result<Arc<wgpu::RenderPipeline>, PipelineError> {
+        create_skybox_pipeline(
+            device,
+            self.color_format,
+            self.depth_format,
+            sources,
+            pipelines,
+        )
+    }
+
+    /// Swaps in a rebuilt skybox pipeline. Its bind group layout is derived from the shader,
+    /// so the skybox bind group is recreated along with it.
+    pub fn set_skybox_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        pipeline: Arc<wgpu::RenderPipeline>,
+    ) {
+        self.skybox_pipeline = pipeline;
+        self.skybox_bind_group = self
+            .environment_view
+            .as_ref()
+            .map(|view| self.create_skybox_bind_group(device, view));
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.skybox_bind_group.is_some()
+    }
+
+    /// Decodes a radiance `.hdr` image and bakes the skybox, irradiance,
+    /// and prefiltered specular cubemaps, along with the brdf lookup table.
+    pub fn load(
+        &mut self,
+        gpu: &Gpu,
+        hdr_bytes: &[u8],
+        shaders: &mut ShaderLibrary,
+        pipelines: &PipelineCache,
+    ) -> Result<(), image::ImageError> {
+        let image = image::load_from_memory_with_format(hdr_bytes, image::ImageFormat::Hdr)?;
+        let max_dimension = gpu.device.limits().max_texture_dimension_2d;
+        let image = if image.width() > max_dimension || image.height() > max_dimension {
+            image.resize(
+                max_dimension,
+                max_dimension,
+                image::imageops::FilterType::Triangle,
+            )
+        } else {
+            image
+        };
+        let image = image.to_rgba32f();
+        let pixels: Vec<half::f16> = image
+            .as_raw()
+            .iter()
+            .map(|component| half::f16::from_f32(*component))
+            .collect();
+
+        let device = &gpu.device;
+        let equirectangular = wgpu::util::DeviceExt::create_texture_with_data(
+            device,
+            &gpu.queue,
+            &wgpu::TextureDescriptor {
+                label: Some("Equirectangular Texture"),
+                size: wgpu::Extent3d {
+                    width: image.width(),
+                    height: image.height(),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: CUBE_FORMAT,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            bytemuck::cast_slice(&pixels),
+        );
+
+        let environment_mip_levels = ENVIRONMENT_SIZE.ilog2() + 1;
+        let environment = create_cube_texture(
+            device,
+            ENVIRONMENT_SIZE,
+            environment_mip_levels,
+            "Environment Cubemap",
+        );
+        let irradiance = create_cube_texture(device, IRRADIANCE_SIZE, 1, "Irradiance Cubemap");
+        let prefiltered = create_cube_texture(
+            device,
+            PREFILTERED_SIZE,
+            PREFILTERED_MIP_LEVELS,
+            "Prefiltered Cubemap",
+        );
+        let brdf_lut = create_render_texture(device, BRDF_LUT_SIZE, BRDF_LUT_FORMAT, "BRDF LUT");
+
+        let baker = shaders.build(device, "Environment Bake", |sources| {
+            Baker::new(device, &self.sampler, sources, pipelines)
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Environment Bake Encoder"),
+        });
+
+        let equirectangular_view =
+            equirectangular.create_view(&wgpu::TextureViewDescriptor::default());
+        for face in 0..6 {
+            baker.draw(
+                device,
+                &mut encoder,
+                &baker.equirectangular_pipeline,
+                BakeSource::Equirectangular(&equirectangular_view),
+                &face_view(&environment, face, 0),
+                BakeUniform {
+                    face,
+                    ..Default::default()
+                },
+            );
+        }
+
+        // The mip chain lets the convolutions read prefiltered radiance instead of aliasing
+        for mip in 1..environment_mip_levels {
+            let source = environment.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("Environment Mip Source"),
+                dimension: Some(wgpu::TextureViewDimension::Cube),
+                base_mip_level: mip - 1,
+                mip_level_count: Some(1),
+                ..Default::default()
+            });
+            for face in 0..6 {
+                baker.draw(
+                    device,
+                    &mut encoder,
+                    &baker.downsample_pipeline,
+                    BakeSource::Cube(&source),
+                    &face_view(&environment, face, mip),
+                    BakeUniform {
+                        face,
+                        ..Default::default()
+                    },
+                );
+            }
+        }
+
+        let environment_view = cube_view(&environment);
+        for face in 0..6 {
+            baker.draw(
+                device,
+                &mut encoder,
+                &baker.irradiance_pipeline,
+                BakeSource::Cube(&environment_view),
+                &face_view(&irradiance, face, 0),
+                BakeUniform {
+                    face,
+                    source_size: ENVIRONMENT_SIZE as f32,
+                    ..Default::default()
+                },
+            );
+        }
+
+        for mip in 0..PREFILTERED_MIP_LEVELS {
+            for face in 0..6 {
+                baker.draw(
+                    device,
+                    &mut encoder,
+                    &baker.prefilter_pipeline,
+                    BakeSource::Cube(&environment_view),
+                    &face_view(&prefiltered, face, mip),
+                    BakeUniform {
+                        face,
+                        roughness: mip as f32 / (PREFILTERED_MIP_LEVELS - 1) as f32,
+                        source_size: ENVIRONMENT_SIZE as f32,
+                        ..Default::default()
+                    },
+                );
+            }
+        }
+
+        let brdf_lut_view = brdf_lut.create_view(&wgpu::TextureViewDescriptor::default());
+        baker.draw_brdf_lut(&mut encoder, &brdf_lut_view);
+
+        gpu.queue.submit(std::iter::once(encoder.finish()));
+
+        self.bind_group = create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.uniform_buffer,
+            &cube_view(&irradiance),
+            &cube_view(&prefiltered),
+            &brdf_lut_view,
+            &self.sampler,
+        );
+        self.skybox_bind_group = Some(self.create_skybox_bind_group(device, &environment_view));
+        self.environment_view = Some(environment_view);
+
+        Ok(())
+    }
+
+    fn create_skybox_bind_group(
+        &self,
+        device: &wgpu::Device,
+        environment_view: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.skybox_pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.skybox_buffer.binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(environment_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+            label: Some("skybox_bind_group"),
+        })
+    }
+
+    /// Unbinds the environment, returning the scene to flat ambient lighting
+    /// and the clear color background
+    pub fn clear(&mut self, gpu: &Gpu) {
+        let placeholder_cube = create_cube_texture(&gpu.device, 1, 1, "Environment Placeholder");
+        let placeholder_lut =
+            create_render_texture(&gpu.device, 1, BRDF_LUT_FORMAT, "BRDF LUT Placeholder");
+        self.bind_group = create_bind_group(
+            &gpu.device,
+            &self.bind_group_layout,
+            &self.uniform_buffer,
+            &cube_view(&placeholder_cube),
+            &cube_view(&placeholder_cube),
+            &placeholder_lut.create_view(&wgpu::TextureViewDescriptor::default()),
+            &self.sampler,
+        );
+        self.skybox_bind_group = None;
+        self.environment_view = None;
+    }
+
+    pub fn update(
+        &self,
+        queue: &wgpu::Queue,
+        view: &nalgebra_glm::Mat4,
+        projection: &nalgebra_glm::Mat4,
+    ) {
+        self.uniform_buffer.write(
+            queue,
+            &EnvironmentUniform {
+                settings: [
+                    if self.is_loaded() { 1.0 } else { 0.0 },
+                    self.intensity,
+                    (PREFILTERED_MIP_LEVELS - 1) as f32,
+                    0.0,
+                ],
+            },
+        );
+
+        // The skybox follows the camera rotation but never its translation
+        let mut rotation = *view;
+        rotation.fixed_view_mut::<3, 1>(0, 3).fill(0.0);
+        self.skybox_buffer.write(
+            queue,
+            &SkyboxUniform {
+                inverse_view_projection: nalgebra_glm::inverse(&(projection * rotation)),
+                intensity: [self.intensity, 0.0, 0.0, 0.0],
+            },
+        );
+    }
+
+    pub fn render_skybox<'rpass>(&'rpass self, renderpass: &mut wgpu::RenderPass<'rpass>) {
+        let Some(bind_group) = self.skybox_bind_group.as_ref() else {
+            return;
+        };
+        renderpass.set_pipeline(&self.skybox_pipeline);
+        renderpass.set_bind_group(0, bind_group, &[]);
+        renderpass.draw(0..3, 0..1);
+    }
+}
+
+struct Baker<'a> {
+    equirectangular_pipeline: Arc<wgpu::RenderPipeline>,
+    downsample_pipeline: Arc<wgpu::RenderPipeline>,
+    irradiance_pipeline: Arc<wgpu::RenderPipeline>,
+    prefilter_pipeline: Arc<wgpu::RenderPipeline>,
+    brdf_lut_pipeline: Arc<wgpu::RenderPipeline>,
+    sampler: &'a wgpu::Sampler,
+}
+
+enum BakeSource<'a> {
+    Equirectangular(&'a wgpu::TextureView),
+    Cube(&'a wgpu::TextureView),
+}
+
+impl<'a> Baker<'a> {
+    fn new(
+        device: &wgpu::Device,
+        sampler: &'a wgpu::Sampler,
+        sources: Sources,
+        pipelines: &PipelineCache,
+    ) -> Result<Self, PipelineError> {
+        let source = sources.preprocess(Environment::BAKE_SHADER, &ShaderDefines::new())?;
+        let pipeline = |entry_point: &str, format: wgpu::TextureFormat| {
+            PipelineBuilder::new(entry_point, &source, format)
+                .fragment_entry(entry_point)
+                .no_depth()
+                .build(device, pipelines)
+        };
+        Ok(Self {
+            equirectangular_pipeline: pipeline("equirectangular_to_cube", CUBE_FORMAT)?,
+            downsample_pipeline: pipeline("downsample", CUBE_FORMAT)?,
+            irradiance_pipeline: pipeline("irradiance", CUBE_FORMAT)?,
+            prefilter_pipeline: pipeline("prefilter", CUBE_FORMAT)?,
+            brdf_lut_pipeline: pipeline("brdf_lut", BRDF_LUT_FORMAT)?,
+            sampler,
+        })
+    }
+
+    fn draw(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::RenderPipeline,
+        source: BakeSource,
+        target: &wgpu::TextureView,
+        uniform: BakeUniform,
+    ) {
+        let (source_binding, source) = match source {
+            BakeSource::Equirectangular(view) => (1, view),
+            BakeSource::Cube(view) => (3, view),
+        };
+        let buffer = UniformBuffer::new(device, "Bake Uniform Buffer", &uniform);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: source_binding,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(self.sampler),
+                },
+            ],
+            label: Some("bake_bind_group"),
+        });
+        let mut render_pass = Self::begin_pass(encoder, target);
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    fn draw_brdf_lut(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        let mut render_pass = Self::begin_pass(encoder, target);
+        render_pass.set_pipeline(&self.brdf_lut_pipeline);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    fn begin_pass<'encoder>(
+        encoder: &'encoder mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+    ) -> wgpu::RenderPass<'encoder> {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Environment Bake Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        })
+    }
+}
+
+fn create_cube_texture(
+    device: &wgpu::Device,
+    size: u32,
+    mip_level_count: u32,
+    label: &str,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 6,
+        },
+        mip_level_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: CUBE_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })
+}
+
+fn create_render_texture(
+    device: &wgpu::Device,
+    size: u32,
+    format: wgpu::TextureFormat,
+    label: &str,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })
+}
+
+fn cube_view(texture: &wgpu::Texture) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        label: Some("Cube View"),
+        dimension: Some(wgpu::TextureViewDimension::Cube),
+        ..Default::default()
+    })
+}
+
+fn face_view(texture: &wgpu::Texture, face: u32, mip: u32) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        label: Some("Cube Face View"),
+        dimension: Some(wgpu::TextureViewDimension::D2),
+        base_mip_level: mip,
+        mip_level_count: Some(1),
+        base_array_layer: face,
+        array_layer_count: Some(1),
+        ..Default::default()
+    })
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    uniform_buffer: &UniformBuffer<EnvironmentUniform>,
+    irradiance: &wgpu::TextureView,
+    prefiltered: &wgpu::TextureView,
+    brdf_lut: &wgpu::TextureView,
+    sampler: &wgpu::Sampler,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(irradiance),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(prefiltered),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(brdf_lut),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+        ],
+        label: Some("environment_bind_group"),
+    })
+}
+
+fn create_skybox_pipeline(
+    device: &wgpu::Device,
+    color_format: wgpu::TextureFormat,
+    depth_format: wgpu::TextureFormat,
+    sources: Sources,
+    pipelines: &PipelineCache,
+) -> Result<Arc<wgpu::RenderPipeline>, PipelineError> {
+    let source = sources.preprocess(Environment::SKYBOX_SHADER, &ShaderDefines::new())?;
+    PipelineBuilder::new("Skybox", &source, color_format)
+        .depth_format(depth_format)
+        .depth_test(wgpu::CompareFunction::Always, false)
+        .build(device, pipelines)
+}
+
+shader_type! {
+    #[repr(C)]
+    #[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+    struct BakeUniform {
+        face: u32,
+        roughness: f32,
+        source_size: f32,
+        padding: f32,
+    }
+}
+
+shader_type! {
+    #[repr(C)]
+    #[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+    struct EnvironmentUniform {
+        /// x: 1.0 when an environment is loaded, y: intensity, z: highest prefiltered mip
+        settings: [f32; 4],
+    }
+}
+
+shader_type! {
+    #[repr(C)]
+    #[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+    struct SkyboxUniform {
+        inverse_view_projection: nalgebra_glm::Mat4,
+        intensity: [f32; 4],
+    }
+}
@@ -0,0 +1,826 @@
+use std::{marker::PhantomData, ops::Range};
+
+use nalgebra_glm::Vec4;
+
+use crate::{
+    buffer::{StorageBuffer, UniformBuffer},
+    compute::{workgroup_count, ComputePipeline, PendingReadback, Readback},
+    culling::{Bounds, CullStats, Frustum},
+    mesh::{Instance, Mesh, Vertex},
+    renderer::{Gpu, InstanceBuffer},
+    shader::{PipelineError, ShaderDefines, ShaderLibrary, Sources},
+    shader_type,
+};
+
+const WORKGROUP_SIZE: u32 = 64;
+
+/// Hands out ranges of a fixed capacity, taking the first free range that fits
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeAllocator {
+    capacity: u32,
+    /// Sorted by start and never touching each other
+    free: Vec<Range<u32>>,
+}
+
+impl RangeAllocator {
+    pub fn new(capacity: u32) -> Self {
+        let mut allocator = Self {
+            capacity: 0,
+            free: Vec::new(),
+        };
+        allocator.grow(capacity);
+        allocator
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// Returns `None` when no free range is large enough
+    pub fn allocate(&mut self, size: u32) -> Option<Range<u32>> {
+        if size == 0 {
+            return Some(0..0);
+        }
+        let index = self
+            .free
+            .iter()
+            .position(|free| free.len() as u32 >= size)?;
+        let free = &mut self.free[index];
+        let range = free.start..free.start + size;
+        free.start += size;
+        if free.start == free.end {
+            self.free.remove(index);
+        }
+        Some(range)
+    }
+
+    /// Returns a range to the free list, merging it with its free neighbours
+    pub fn free(&mut self, range: Range<u32>) {
+        if range.is_empty() {
+            return;
+        }
+        let index = self.free.partition_point(|free| free.start < range.start);
+        let joins_previous = index > 0 && self.free[index - 1].end == range.start;
+        let joins_next = index < self.free.len() && self.free[index].start == range.end;
+        match (joins_previous, joins_next) {
+            (true, true) => {
+                self.free[index - 1].end = self.free[index].end;
+                self.free.remove(index);
+            }
+            (true, false) => self.free[index - 1].end = range.end,
+            (false, true) => self.free[index].start = range.start,
+            (false, false) => self.free.insert(index, range),
+        }
+    }
+
+    /// Adds free space at the end
+    pub fn grow(&mut self, capacity: u32) {
+        assert!(
+            capacity >= self.capacity,
+            "Allocators only grow, from {} to {capacity}",
+            self.capacity
+        );
+        let added = self.capacity..capacity;
+        self.capacity = capacity;
+        self.free(added);
+    }
+}
+
+/// A buffer shared by many arrays of values, which grows when they no longer fit
+struct ArenaBuffer<T> {
+    buffer: wgpu::Buffer,
+    allocator: RangeAllocator,
+    label: &'static str,
+    usage: wgpu::BufferUsages,
+    _marker: PhantomData<T>,
+}
+
+impl<T: bytemuck::Pod> ArenaBuffer<T> {
+    fn new(
+        device: &wgpu::Device,
+        label: &'static str,
+        usage: wgpu::BufferUsages,
+        capacity: u32,
+    ) -> Self {
+        Self {
+            buffer: Self::create_buffer(device, label, usage, capacity),
+            allocator: RangeAllocator::new(capacity),
+            label,
+            usage,
+            _marker: PhantomData,
+        }
+    }
+
+    fn create_buffer(
+        device: &wgpu::Device,
+        label: &str,
+        usage: wgpu::BufferUsages,
+        capacity: u32,
+    ) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: (capacity.max(1) as usize * std::mem::size_of::<T>()) as wgpu::BufferAddress,
+            // Copied from when the arena grows
+            usage: usage | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn insert(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, values: &[T]) -> Range<u32> {
+        let size = values.len() as u32;
+        let range = self.allocator.allocate(size).unwrap_or_else(|| {
+            self.grow(device, queue, self.allocator.capacity() + size);
+            self.allocator
+                .allocate(size)
+                .expect("The arena grew to fit the values")
+        });
+        if !values.is_empty() {
+            queue.write_buffer(
+                &self.buffer,
+                (range.start as usize * std::mem::size_of::<T>()) as wgpu::BufferAddress,
+                bytemuck::cast_slice(values),
+            );
+        }
+        range
+    }
+
+    /// Moves the values into a buffer of at least `capacity` values.
+    /// Writes queued for the old buffer land before the copy, as they run ahead of the submission.
+    fn grow(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, capacity: u32) {
+        let capacity = capacity.next_power_of_two();
+        let buffer = Self::create_buffer(device, self.label, self.usage, capacity);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Arena Growth Encoder"),
+        });
+        encoder.copy_buffer_to_buffer(&self.buffer, 0, &buffer, 0, self.buffer.size());
+        queue.submit(std::iter::once(encoder.finish()));
+        self.buffer = buffer;
+        self.allocator.grow(capacity);
+    }
+}
+
+/// Where a mesh lives in the [`MeshArena`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MeshRange {
+    pub vertices: Range<u32>,
+    pub indices: Range<u32>,
+}
+
+impl MeshRange {
+    /// Indices are stored relative to the first vertex of their mesh
+    pub fn base_vertex(&self) -> i32 {
+        self.vertices.start as i32
+    }
+}
+
+/// One vertex and one index buffer holding every mesh of the scene, so drawing
+/// switches buffers once and indirect draws of many meshes can be issued together
+pub struct MeshArena {
+    vertices: ArenaBuffer<Vertex>,
+    indices: ArenaBuffer<u32>,
+}
+
+impl MeshArena {
+    const INITIAL_VERTICES: u32 = 1 << 12;
+    const INITIAL_INDICES: u32 = 1 << 14;
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            vertices: ArenaBuffer::new(
+                device,
+                "Arena Vertex Buffer",
+                wgpu::BufferUsages::VERTEX,
+                Self::INITIAL_VERTICES,
+            ),
+            indices: ArenaBuffer::new(
+                device,
+                "Arena Index Buffer",
+                wgpu::BufferUsages::INDEX,
+                Self::INITIAL_INDICES,
+            ),
+        }
+    }
+
+    pub fn insert(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, mesh: &Mesh) -> MeshRange {
+        MeshRange {
+            vertices: self.vertices.insert(device, queue, &mesh.vertices),
+            indices: self.indices.insert(device, queue, &mesh.indices),
+        }
+    }
+
+    /// Frees the space of a mesh for meshes inserted later
+    pub fn remove(&mut self, range: MeshRange) {
+        self.vertices.allocator.free(range.vertices);
+        self.indices.allocator.free(range.indices);
+    }
+
+    pub fn vertex_buffer(&self) -> &wgpu::Buffer {
+        &self.vertices.buffer
+    }
+
+    pub fn index_buffer(&self) -> &wgpu::Buffer {
+        &self.indices.buffer
+    }
+}
+
+shader_type! {
+    #[repr(C)]
+    #[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+    struct CullingUniform {
+        /// Left, right, bottom, top, near and far, pointing inwards
+        planes: [Vec4; 6],
+        /// x: instance count, y: draw count
+        counts: [u32; 4],
+    }
+}
+
+shader_type! {
+    #[repr(C)]
+    #[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+    struct CullMesh {
+        /// xyz: center, w: radius
+        sphere: [f32; 4],
+        aabb_min: [f32; 4],
+        aabb_max: [f32; 4],
+        /// x: first instance, y: instance count
+        offsets: [u32; 4],
+    }
+}
+
+shader_type! {
+    /// The layout of `wgpu::util::DrawIndexedIndirectArgs`, written by the culling pass
+    #[repr(C)]
+    #[derive(Default, Debug, Copy, Clone, PartialEq, Eq, bytemuck::Pod, bytemuck::Zeroable)]
+    pub struct DrawArgs {
+        pub index_count: u32,
+        pub instance_count: u32,
+        pub first_index: u32,
+        pub base_vertex: i32,
+        pub first_instance: u32,
+    }
+}
+
+/// A mesh with instances, as handed to [`IndirectDraws::prepare`]
+pub struct IndirectMesh<'a> {
+    pub range: &'a MeshRange,
+    pub bounds: &'a Bounds,
+    pub material: usize,
+    /// Where the instances of the mesh are in the instance buffer
+    pub instances: Range<u32>,
+}
+
+/// Consecutive draws sharing a material
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DrawBatch {
+    pub material: usize,
+    pub draws: Range<u32>,
+}
+
+struct StatsReadback {
+    readback: Readback<DrawArgs>,
+    draw_count: usize,
+    instance_count: u32,
+}
+
+/// Culls instances against the view frustum in a compute pass, which writes
+/// the visible ones into a second instance buffer along with indexed indirect draws.
+/// Draws sharing a material are issued with one multi draw where the device supports it.
+pub struct IndirectDraws {
+    pipeline: ComputePipeline,
+    /// Batches are drawn with `multi_draw_indexed_indirect`
+    multi_draw: bool,
+    /// Draws start at their instances rather than the visible buffer being bound at them
+    first_instance: bool,
+    uniform: UniformBuffer<CullingUniform>,
+    meshes: StorageBuffer<CullMesh>,
+    visible_instances: StorageBuffer<Instance>,
+    draws: StorageBuffer<DrawArgs>,
+    /// Created again whenever a buffer or the pipeline changes
+    bind_group: Option<wgpu::BindGroup>,
+    /// The buffer of the instances culled last, which grows with the scene
+    source_capacity: usize,
+    cull_meshes: Vec<CullMesh>,
+    draw_args: Vec<DrawArgs>,
+    batches: Vec<DrawBatch>,
+    instance_count: u32,
+    stats: CullStats,
+    stats_readback: Option<StatsReadback>,
+}
+
+impl IndirectDraws {
+    /// The culling shader along with every file it includes
+    pub const SHADERS: [&str; 2] = ["culling.wgsl", "instancing.wgsl"];
+
+    /// Returns `None` when the adapter can not run compute shaders or indirect draws
+    pub fn new(gpu: &Gpu, shaders: &mut ShaderLibrary) -> Option<Self> {
+        let required =
+            wgpu::DownlevelFlags::COMPUTE_SHADERS | wgpu::DownlevelFlags::INDIRECT_EXECUTION;
+        if !gpu.downlevel_flags.contains(required) {
+            log::warn!("Gpu driven drawing is disabled, the adapter does not support indirect compute work");
+            return None;
+        }
+        let device = &gpu.device;
+        let pipeline = shaders.build(device, "Culling", |sources| {
+            Self::create_pipeline(device, sources)
+        });
+        Some(Self::with_pipeline(device, pipeline))
+    }
+
+    fn with_pipeline(device: &wgpu::Device, pipeline: ComputePipeline) -> Self {
+        let features = device.features();
+        let first_instance = features.contains(wgpu::Features::INDIRECT_FIRST_INSTANCE);
+        Self {
+            pipeline,
+            multi_draw: first_instance && features.contains(wgpu::Features::MULTI_DRAW_INDIRECT),
+            first_instance,
+            uniform: UniformBuffer::new(device, "Culling Uniform", &CullingUniform::default()),
+            meshes: StorageBuffer::zeroed(device, "Cull Meshes", 0, wgpu::BufferUsages::empty()),
+            visible_instances: Self::create_visible_instances(device, 0),
+            draws: Self::create_draws(device, 0),
+            bind_group: None,
+            source_capacity: 0,
+            cull_meshes: Vec::new(),
+            draw_args: Vec::new(),
+            batches: Vec::new(),
+            instance_count: 0,
+            stats: CullStats::default(),
+            stats_readback: None,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        sources: Sources,
+    ) -> Result<ComputePipeline, PipelineError> {
+        let source = sources.preprocess("culling.wgsl", &ShaderDefines::new())?;
+        ComputePipeline::new(device, "Culling", &source, "cull")
+    }
+
+    fn create_visible_instances(device: &wgpu::Device, len: usize) -> StorageBuffer<Instance> {
+        StorageBuffer::zeroed(
+            device,
+            "Visible Instances",
+            len,
+            wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC,
+        )
+    }
+
+    fn create_draws(device: &wgpu::Device, len: usize) -> StorageBuffer<DrawArgs> {
+        StorageBuffer::zeroed(
+            device,
+            "Indirect Draws",
+            len,
+            wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::COPY_SRC,
+        )
+    }
+
+    pub fn reload_shaders(
+        &mut self,
+        changed: &[&str],
+        device: &wgpu::Device,
+        shaders: &mut ShaderLibrary,
+    ) {
+        if !changed.iter().any(|name| Self::SHADERS.contains(name)) {
+            return;
+        }
+        if let Some(pipeline) = shaders.rebuild(device, "Culling", |sources| {
+            Self::create_pipeline(device, sources)
+        }) {
+            // Bind groups of automatic layouts only fit the pipeline they came from
+            self.pipeline = pipeline;
+            self.bind_group = None;
+        }
+    }
+
+    /// Uploads the draws of this frame. `instances` holds the instances of each mesh
+    /// at the given ranges, which ascend. Meshes sharing a material are drawn as one batch
+    /// when they are consecutive.
+    pub fn prepare<'a>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        meshes: impl IntoIterator<Item = IndirectMesh<'a>>,
+        instances: &InstanceBuffer,
+        frustum: &Frustum,
+    ) {
+        self.poll_stats();
+
+        self.cull_meshes.clear();
+        self.draw_args.clear();
+        self.batches.clear();
+        for mesh in meshes {
+            if mesh.instances.is_empty() || mesh.range.indices.is_empty() {
+                continue;
+            }
+            let draw = self.draw_args.len() as u32;
+            let sphere = &mesh.bounds.sphere;
+            self.cull_meshes.push(CullMesh {
+                sphere: [
+                    sphere.center.x,
+                    sphere.center.y,
+                    sphere.center.z,
+                    sphere.radius,
+                ],
+                aabb_min: mesh.bounds.aabb.min.push(1.0).into(),
+                aabb_max: mesh.bounds.aabb.max.push(1.0).into(),
+                offsets: [mesh.instances.start, mesh.instances.len() as u32, 0, 0],
+            });
+            self.draw_args.push(DrawArgs {
+                index_count: mesh.range.indices.len() as u32,
+                instance_count: 0,
+                first_index: mesh.range.indices.start,
+                base_vertex: mesh.range.base_vertex(),
+                first_instance: if self.first_instance {
+                    mesh.instances.start
+                } else {
+                    0
+                },
+            });
+            match self.batches.last_mut() {
+                Some(batch) if batch.material == mesh.material => batch.draws.end = draw + 1,
+                _ => self.batches.push(DrawBatch {
+                    material: mesh.material,
+                    draws: draw..draw + 1,
+                }),
+            }
+        }
+        self.instance_count = instances.count;
+
+        if self.meshes.len() < self.cull_meshes.len() {
+            self.meshes = StorageBuffer::zeroed(
+                device,
+                "Cull Meshes",
+                self.cull_meshes.len().next_power_of_two(),
+                wgpu::BufferUsages::empty(),
+            );
+            self.bind_group = None;
+        }
+        if self.draws.len() < self.draw_args.len() {
+            self.draws = Self::create_draws(device, self.draw_args.len().next_power_of_two());
+            self.bind_group = None;
+        }
+        // Visible instances mirror where the instances of each mesh are
+        if self.source_capacity != instances.capacity {
+            self.visible_instances = Self::create_visible_instances(device, instances.capacity);
+            self.source_capacity = instances.capacity;
+            self.bind_group = None;
+        }
+        if self.bind_group.is_none() {
+            self.bind_group = Some(self.pipeline.bind_group(
+                device,
+                0,
+                &[
+                    self.uniform.binding(),
+                    self.meshes.binding(),
+                    instances.buffer.as_entire_binding(),
+                    self.visible_instances.binding(),
+                    self.draws.binding(),
+                ],
+            ));
+        }
+
+        self.uniform.write(
+            queue,
+            &CullingUniform {
+                planes: frustum.planes,
+                counts: [self.instance_count, self.draw_args.len() as u32, 0, 0],
+            },
+        );
+        self.meshes.write(queue, 0, &self.cull_meshes);
+        // Also resets the instance counts the culling pass adds to
+        self.draws.write(queue, 0, &self.draw_args);
+    }
+
+    /// Records the culling pass, which has to run before the draws
+    pub fn cull(&self, encoder: &mut wgpu::CommandEncoder) {
+        let Some(bind_group) = self.bind_group.as_ref() else {
+            return;
+        };
+        if self.draw_args.is_empty() {
+            return;
+        }
+        self.pipeline.dispatch(
+            encoder,
+            &[bind_group],
+            [workgroup_count(self.instance_count, WORKGROUP_SIZE), 1, 1],
+        );
+    }
+
+    /// The draw arguments the culling pass writes instance counts into
+    pub fn draw_buffer(&self) -> &wgpu::Buffer {
+        self.draws.buffer()
+    }
+
+    /// The instances the culling pass found visible, drawn in place of the scene's instances
+    pub fn visible_instance_buffer(&self) -> &wgpu::Buffer {
+        self.visible_instances.buffer()
+    }
+
+    pub fn batches(&self) -> &[DrawBatch] {
+        &self.batches
+    }
+
+    /// Draws a batch into a pass with the arena buffers and the batch's material bound
+    pub fn draw<'rpass>(
+        &'rpass self,
+        render_pass: &mut wgpu::RenderPass<'rpass>,
+        batch: &DrawBatch,
+    ) {
+        let stride = std::mem::size_of::<DrawArgs>() as wgpu::BufferAddress;
+        let draws = self.draws.buffer();
+        if self.multi_draw {
+            render_pass.set_vertex_buffer(1, self.visible_instances.buffer().slice(..));
+            render_pass.multi_draw_indexed_indirect(
+                draws,
+                batch.draws.start as wgpu::BufferAddress * stride,
+                batch.draws.len() as u32,
+            );
+            return;
+        }
+        for draw in batch.draws.clone() {
+            // Without first instance support, the visible instances are bound where the draw starts
+            let first_instance = if self.first_instance {
+                0
+            } else {
+                self.cull_meshes[draw as usize].offsets[0]
+            };
+            let offset =
+                (first_instance as usize * std::mem::size_of::<Instance>()) as wgpu::BufferAddress;
+            render_pass.set_vertex_buffer(1, self.visible_instances.buffer().slice(offset..));
+            render_pass.draw_indexed_indirect(draws, draw as wgpu::BufferAddress * stride);
+        }
+    }
+
+    /// What the culling pass drew and skipped a few frames ago, as the counts are read back
+    pub fn stats(&self) -> CullStats {
+        self.stats
+    }
+
+    /// Records a copy of the draws to count the visible instances, unless one is in flight.
+    /// The returned readback is mapped once the encoder was submitted.
+    pub(crate) fn read_stats(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> Option<PendingReadback> {
+        if self.stats_readback.is_some() {
+            return None;
+        }
+        let (readback, pending) = Readback::new(device, encoder, self.draws.buffer());
+        self.stats_readback = Some(StatsReadback {
+            readback,
+            draw_count: self.draw_args.len(),
+            instance_count: self.instance_count,
+        });
+        Some(pending)
+    }
+
+    fn poll_stats(&mut self) {
+        let Some(stats_readback) = self.stats_readback.as_mut() else {
+            return;
+        };
+        let Some(result) = stats_readback.readback.try_read() else {
+            return;
+        };
+        if let Ok(draws) = result {
+            let drawn = draws
+                .iter()
+                .take(stats_readback.draw_count)
+                .map(|draw| draw.instance_count)
+                .sum::<u32>();
+            self.stats = CullStats {
+                drawn,
+                culled: stats_readback.instance_count.saturating_sub(drawn),
+            };
+        }
+        self.stats_readback = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nalgebra_glm::{vec3, Vec3};
+
+    use crate::compute::{read_buffer, HeadlessGpu};
+
+    #[test]
+    fn ranges_are_allocated_first_fit_and_merged_when_freed() {
+        let mut allocator = RangeAllocator::new(10);
+        assert_eq!(allocator.allocate(4), Some(0..4));
+        assert_eq!(allocator.allocate(3), Some(4..7));
+        assert_eq!(allocator.allocate(0), Some(0..0));
+        assert_eq!(allocator.allocate(4), None);
+
+        allocator.free(0..4);
+        assert_eq!(allocator.allocate(2), Some(0..2));
+        allocator.free(4..7);
+        // Freeing the rest joins the ranges on both sides into one
+        allocator.free(0..2);
+        assert_eq!(allocator.allocate(10), Some(0..10));
+    }
+
+    #[test]
+    fn grown_allocators_extend_their_last_free_range() {
+        let mut allocator = RangeAllocator::new(8);
+        assert_eq!(allocator.allocate(6), Some(0..6));
+        assert_eq!(allocator.allocate(4), None);
+        allocator.grow(16);
+        assert_eq!(allocator.capacity(), 16);
+        assert_eq!(allocator.allocate(10), Some(6..16));
+    }
+
+    fn cube() -> Mesh {
+        let corners = [-1.0, 1.0]
+            .into_iter()
+            .flat_map(|x| [-1.0, 1.0].map(|y| (x, y)))
+            .flat_map(|(x, y)| [-1.0, 1.0].map(|z| vec3(x, y, z)));
+        Mesh {
+            vertices: corners
+                .map(|corner: Vec3| Vertex {
+                    position: [corner.x, corner.y, corner.z, 1.0],
+                    ..Default::default()
+                })
+                .collect(),
+            indices: vec![0, 1, 2, 1, 3, 2],
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a fallback adapter, run with `just test-gpu`"]
+    fn culling_on_fallback_adapter_writes_draws_of_visible_instances() {
+        let gpu = pollster::block_on(HeadlessGpu::new(true)).expect("There is no fallback adapter");
+        let device = &gpu.device;
+        let pipeline = IndirectDraws::create_pipeline(device, Sources::Embedded).unwrap();
+        let mut indirect = IndirectDraws::with_pipeline(device, pipeline);
+
+        let mut arena = MeshArena::new(device);
+        let mesh = cube();
+        // The second mesh starts past the first in both buffers
+        let first = arena.insert(device, &gpu.queue, &mesh);
+        let second = arena.insert(device, &gpu.queue, &mesh);
+        let bounds = mesh.bounds();
+
+        // Looks down +Z from the origin
+        let projection = nalgebra_glm::perspective_lh_zo(1.0, 90f32.to_radians(), 0.1, 100.0);
+        let frustum = Frustum::from_view_projection(&projection);
+        let at = |x: f32, z: f32| Instance::new(nalgebra_glm::translation(&vec3(x, 0.0, z)));
+        let instances = [
+            at(0.0, -10.0),
+            at(0.0, 10.0),
+            at(1.0, 20.0),
+            at(50.0, 10.0),
+            at(0.0, 30.0),
+        ];
+        let mut instance_buffer = InstanceBuffer::new(device, 1, wgpu::BufferUsages::STORAGE);
+        instance_buffer.upload(device, &gpu.queue, &instances);
+
+        indirect.prepare(
+            device,
+            &gpu.queue,
+            [
+                IndirectMesh {
+                    range: &first,
+                    bounds: &bounds,
+                    material: 0,
+                    instances: 0..3,
+                },
+                IndirectMesh {
+                    range: &second,
+                    bounds: &bounds,
+                    material: 0,
+                    instances: 3..5,
+                },
+            ],
+            &instance_buffer,
+            &frustum,
+        );
+        assert_eq!(
+            indirect.batches(),
+            [DrawBatch {
+                material: 0,
+                draws: 0..2
+            }]
+        );
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        indirect.cull(&mut encoder);
+        let pending = indirect.read_stats(device, &mut encoder).unwrap();
+        gpu.queue.submit(std::iter::once(encoder.finish()));
+        pending.map();
+        device.poll(wgpu::Maintain::Wait);
+
+        let draws: Vec<DrawArgs> =
+            pollster::block_on(read_buffer(device, &gpu.queue, indirect.draws.buffer())).unwrap();
+        assert_eq!(
+            draws[..2],
+            [
+                DrawArgs {
+                    index_count: 6,
+                    instance_count: 2,
+                    first_index: 0,
+                    base_vertex: 0,
+                    first_instance: 0,
+                },
+                DrawArgs {
+                    index_count: 6,
+                    instance_count: 1,
+                    first_index: 6,
+                    base_vertex: 8,
+                    first_instance: 0,
+                },
+            ]
+        );
+
+        // Visible instances land in their mesh's range, in any order within it
+        let visible: Vec<Instance> = pollster::block_on(read_buffer(
+            device,
+            &gpu.queue,
+            indirect.visible_instances.buffer(),
+        ))
+        .unwrap();
+        let mut first_positions = visible[..2]
+            .iter()
+            .map(|instance| instance.transform.column(3).xyz())
+            .collect::<Vec<_>>();
+        first_positions.sort_by(|a, b| a.z.total_cmp(&b.z));
+        assert_eq!(
+            first_positions,
+            [vec3(0.0, 0.0, 10.0), vec3(1.0, 0.0, 20.0)]
+        );
+        assert_eq!(visible[3].transform.column(3).xyz(), vec3(0.0, 0.0, 30.0));
+
+        indirect.poll_stats();
+        assert_eq!(
+            indirect.stats(),
+            CullStats {
+                drawn: 3,
+                culled: 2
+            }
+        );
+    }
+
+    #[test]
+    #[ignore = "needs a fallback adapter, run with `just test-gpu`"]
+    fn culling_skips_the_instances_of_empty_meshes() {
+        let gpu = pollster::block_on(HeadlessGpu::new(true)).expect("There is no fallback adapter");
+        let device = &gpu.device;
+        let pipeline = IndirectDraws::create_pipeline(device, Sources::Embedded).unwrap();
+        let mut indirect = IndirectDraws::with_pipeline(device, pipeline);
+
+        let mut arena = MeshArena::new(device);
+        let mesh = cube();
+        let first = arena.insert(device, &gpu.queue, &mesh);
+        let empty = arena.insert(device, &gpu.queue, &Mesh::default());
+        let last = arena.insert(device, &gpu.queue, &mesh);
+        let bounds = mesh.bounds();
+
+        let projection = nalgebra_glm::perspective_lh_zo(1.0, 90f32.to_radians(), 0.1, 100.0);
+        let frustum = Frustum::from_view_projection(&projection);
+        let at = |z: f32| Instance::new(nalgebra_glm::translation(&vec3(0.0, 0.0, z)));
+        // Every instance is in view, including the two of the empty mesh
+        let instances = [at(10.0), at(20.0), at(30.0), at(40.0)];
+        let mut instance_buffer = InstanceBuffer::new(device, 1, wgpu::BufferUsages::STORAGE);
+        instance_buffer.upload(device, &gpu.queue, &instances);
+
+        let indirect_mesh = |range, instances| IndirectMesh {
+            range,
+            bounds: &bounds,
+            material: 0,
+            instances,
+        };
+        indirect.prepare(
+            device,
+            &gpu.queue,
+            [
+                indirect_mesh(&first, 0..1),
+                indirect_mesh(&empty, 1..3),
+                indirect_mesh(&last, 3..4),
+            ],
+            &instance_buffer,
+            &frustum,
+        );
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        indirect.cull(&mut encoder);
+        gpu.queue.submit(std::iter::once(encoder.finish()));
+
+        let draws: Vec<DrawArgs> =
+            pollster::block_on(read_buffer(device, &gpu.queue, indirect.draws.buffer())).unwrap();
+        let instance_counts = draws[..2]
+            .iter()
+            .map(|draw| draw.instance_count)
+            .collect::<Vec<_>>();
+        assert_eq!(instance_counts, [1, 1]);
+
+        let visible: Vec<Instance> = pollster::block_on(read_buffer(
+            device,
+            &gpu.queue,
+            indirect.visible_instances.buffer(),
+        ))
+        .unwrap();
+        assert_eq!(visible[0].transform.column(3).xyz(), vec3(0.0, 0.0, 10.0));
+        assert_eq!(visible[3].transform.column(3).xyz(), vec3(0.0, 0.0, 40.0));
+    }
+}
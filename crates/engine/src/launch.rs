@@ -2,7 +2,7 @@ use crate::renderer::Renderer;
 use std::sync::Arc;
 use winit::{
     application::ApplicationHandler,
-    dpi::PhysicalSize,
+    dpi::{PhysicalPosition, PhysicalSize},
     event::WindowEvent,
     event_loop::{ActiveEventLoop, ControlFlow},
     window::{Theme, Window, WindowId},
@@ -24,6 +24,241 @@ use wasm_bindgen::prelude::*;
 pub struct Context {
     renderer: Renderer,
     window: Arc<Window>,
+    ecs: crate::ecs::Ecs,
+    assets: crate::assets::Assets,
+    cursor_position: Option<PhysicalPosition<f64>>,
+}
+
+impl Context {
+    fn new(window: Arc<Window>, renderer: Renderer) -> Self {
+        Self {
+            renderer,
+            window,
+            ecs: crate::ecs::Ecs::new(),
+            assets: crate::assets::Assets::default(),
+            cursor_position: None,
+        }
+    }
+
+    /// Loads an asset from the `assets` directory, or returns the handle of the copy already loaded
+    pub fn load<T: crate::assets::Asset>(&mut self, path: &str) -> crate::assets::Handle<T> {
+        self.assets.load(path)
+    }
+
+    /// Loaded assets, along with their load state
+    pub fn assets(&self) -> &crate::assets::Assets {
+        &self.assets
+    }
+
+    pub fn assets_mut(&mut self) -> &mut crate::assets::Assets {
+        &mut self.assets
+    }
+
+    /// The entities of the application. Those with a `Transform` and a `MeshRenderer`,
+    /// `Light` or `Camera` component are rendered every frame.
+    pub fn world(&self) -> &crate::ecs::World {
+        &self.ecs.world
+    }
+
+    pub fn world_mut(&mut self) -> &mut crate::ecs::World {
+        &mut self.ecs.world
+    }
+
+    /// The world along with its systems and fixed timestep
+    pub fn ecs_mut(&mut self) -> &mut crate::ecs::Ecs {
+        &mut self.ecs
+    }
+
+    /// Runs a system on the world every frame or at the fixed timestep
+    pub fn add_system(
+        &mut self,
+        phase: crate::ecs::Phase,
+        system: impl FnMut(&mut crate::ecs::World, f32) + 'static,
+    ) {
+        self.ecs.add_system(phase, system);
+    }
+
+    /// Uploads a mesh through the renderer and draws it with the scene.
+    /// Mesh zero is the built in triangle.
+    pub fn add_mesh(&mut self, mesh: &crate::mesh::Mesh, material: usize) -> usize {
+        self.renderer.add_mesh(mesh, material)
+    }
+
+    /// Uploads every level of a mesh. Each instance is drawn with the level matching its size on screen.
+    pub fn add_lod_mesh(&mut self, mesh: &crate::lod::LodMesh, material: usize) -> usize {
+        self.renderer.add_lod_mesh(mesh, material)
+    }
+
+    /// The instances a mesh is drawn with. Clearing them hides the mesh.
+    /// Returns `None` if the mesh was removed.
+    pub fn instances_mut(&mut self, mesh: usize) -> Option<&mut Vec<crate::mesh::Instance>> {
+        self.renderer.instances_mut(mesh)
+    }
+
+    /// Uploads a material, returning the index meshes use to refer to it
+    pub fn add_material(&mut self, material: &crate::material::Material) -> usize {
+        self.renderer.add_material(material)
+    }
+
+    pub fn shadow_settings_mut(&mut self) -> &mut crate::ShadowSettings {
+        self.renderer.shadow_settings_mut()
+    }
+
+    /// Loads a radiance `.hdr` environment used for image based lighting and the skybox
+    pub fn load_environment(&mut self, hdr_bytes: &[u8]) -> Result<(), image::ImageError> {
+        self.renderer.load_environment(hdr_bytes)
+    }
+
+    pub fn clear_environment(&mut self) {
+        self.renderer.clear_environment();
+    }
+
+    pub fn set_environment_intensity(&mut self, intensity: f32) {
+        self.renderer.set_environment_intensity(intensity);
+    }
+
+    pub fn update_material(&mut self, index: usize, material: &crate::material::Material) {
+        self.renderer.update_material(index, material);
+    }
+
+    /// How many instances the last frame drew and culled against the view frustum
+    pub fn cull_stats(&self) -> crate::culling::CullStats {
+        self.renderer.cull_stats()
+    }
+
+    /// Culls and draws instances on the gpu where the adapter supports it, for large scenes
+    pub fn set_gpu_driven(&mut self, enabled: bool) {
+        self.renderer.set_gpu_driven(enabled);
+    }
+
+    /// Whether instances are culled and drawn by the gpu this frame
+    pub fn gpu_driven(&self) -> bool {
+        self.renderer.gpu_driven()
+    }
+
+    /// The lights illuminating the scene, uploaded every frame
+    pub fn lights_mut(&mut self) -> &mut Vec<crate::light::Light> {
+        self.renderer.lights_mut()
+    }
+
+    /// The node hierarchy meshes, lights and the camera attach to.
+    /// Nodes with a mesh are drawn in addition to the mesh's own instances.
+    pub fn scene_graph(&self) -> &crate::scene_graph::SceneGraph {
+        self.renderer.scene_graph()
+    }
+
+    /// Saves the scene graph and the entity world. `meshes` gives the asset
+    /// paths of the meshes added so far, in the order they were added.
+    pub fn capture_scene(
+        &self,
+        meshes: &[crate::scene_file::MeshReference],
+        registry: &crate::scene_file::ComponentRegistry,
+    ) -> Result<crate::scene_file::SceneDocument, crate::scene_file::SceneFileError> {
+        crate::scene_file::SceneDocument::capture(
+            self.renderer.scene_graph(),
+            &self.ecs.world,
+            meshes,
+            registry,
+        )
+    }
+
+    /// Adds the nodes and entities of a saved scene to the current one
+    pub fn instantiate_scene(
+        &mut self,
+        document: &crate::scene_file::SceneDocument,
+        registry: &crate::scene_file::ComponentRegistry,
+        resolve_mesh: impl FnMut(&crate::scene_file::MeshReference) -> Option<usize>,
+    ) -> Result<crate::scene_file::SceneInstance, crate::scene_file::SceneFileError> {
+        document.instantiate(
+            self.renderer.scene_graph_mut(),
+            &mut self.ecs.world,
+            registry,
+            resolve_mesh,
+        )
+    }
+
+    pub fn scene_graph_mut(&mut self) -> &mut crate::scene_graph::SceneGraph {
+        self.renderer.scene_graph_mut()
+    }
+
+    /// Pipelines whose shaders failed to compile. They keep rendering with their last good version.
+    pub fn shader_errors(&self) -> &[crate::ShaderError] {
+        self.renderer.shader_errors()
+    }
+
+    /// Adds a particle emitter, returning the index used to edit it
+    pub fn add_emitter(&mut self, emitter: crate::particles::ParticleEmitter) -> usize {
+        self.renderer.add_emitter(emitter)
+    }
+
+    /// The settings of an emitter, uploaded every frame.
+    /// Returns `None` if the emitter was removed.
+    pub fn emitter_mut(&mut self, index: usize) -> Option<&mut crate::particles::ParticleEmitter> {
+        self.renderer.emitter_mut(index)
+    }
+
+    /// Removes an emitter, letting its index be reused by the next emitter added
+    pub fn remove_emitter(&mut self, index: usize) -> Option<crate::particles::ParticleEmitter> {
+        self.renderer.remove_emitter(index)
+    }
+
+    /// The camera the scene is rendered from, which the view gizmo also moves
+    pub fn camera_mut(&mut self) -> &mut crate::camera::OrbitCamera {
+        self.renderer.camera_mut()
+    }
+
+    /// Toggles the ground grid and the view gizmo
+    pub fn overlay_settings_mut(&mut self) -> &mut crate::overlay::OverlaySettings {
+        self.renderer.overlay_settings_mut()
+    }
+
+    /// Lines and shapes drawn over the scene, for the current frame unless given a duration
+    pub fn debug_draw(&mut self) -> &mut crate::debug_draw::DebugDraw {
+        self.renderer.debug_draw()
+    }
+
+    /// Where the cursor is in the window, in physical pixels, while it is over the window
+    pub fn cursor_position(&self) -> Option<PhysicalPosition<f64>> {
+        self.cursor_position
+    }
+
+    /// Reads back the entity, node or instance drawn at `x`, `y` in physical pixels, like
+    /// the position of `WindowEvent::CursorMoved`, along with where it was hit in world space.
+    /// The pick is rendered with the next frame and arrives without stalling it.
+    /// Positions outside the window resolve to nothing.
+    pub fn pick(&mut self, x: f64, y: f64) -> crate::picking::PickRequest {
+        let pixel = |coordinate: f64| {
+            if coordinate >= 0.0 {
+                coordinate as u32
+            } else {
+                u32::MAX
+            }
+        };
+        self.renderer.pick(pixel(x), pixel(y))
+    }
+
+    /// The device, for creating compute pipelines and buffers
+    pub fn device(&self) -> &wgpu::Device {
+        self.renderer.device()
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        self.renderer.queue()
+    }
+
+    /// Records compute work that runs this frame, before the scene is rendered
+    pub fn compute_encoder(&mut self) -> &mut wgpu::CommandEncoder {
+        self.renderer.compute_encoder()
+    }
+
+    /// Reads a buffer back once this frame's compute work ran.
+    /// The buffer needs `COPY_SRC` usage.
+    pub fn read_buffer<T: bytemuck::Pod>(
+        &mut self,
+        buffer: &wgpu::Buffer,
+    ) -> crate::compute::Readback<T> {
+        self.renderer.read_buffer(buffer)
+    }
 }
 
 pub trait State {
@@ -31,6 +266,8 @@ pub trait State {
     fn resize(&mut self, _context: &mut Context, _width: u32, _height: u32) {}
     fn receive_event(&mut self, _context: &mut Context, _event: &WindowEvent) {}
     fn update(&mut self, _context: &mut Context) {}
+    /// Runs at the fixed timestep of `Context::ecs_mut`, zero or more times per frame
+    fn fixed_update(&mut self, _context: &mut Context) {}
     fn ui(&mut self, _context: &mut Context, _ui: &egui::Context) {}
 }
 
@@ -132,10 +369,7 @@ impl ApplicationHandler for App {
                         Renderer::new(renderer_window_handle, width, height).await
                     });
 
-                    let mut context = Context {
-                        window: window_handle.clone(),
-                        renderer,
-                    };
+                    let mut context = Context::new(window_handle.clone(), renderer);
                     if let Some(state) = self.state.as_mut() {
                         state.initialize(&mut context);
                     }
@@ -172,7 +406,7 @@ impl ApplicationHandler for App {
                 if let Ok(renderer) = receiver.try_recv() {
                     let window = self.app_context.as_ref().map(|ctx| ctx.window().clone());
                     if let Some(window) = window {
-                        let context = Context::new(window, renderer);
+                        let mut context = Context::new(window, renderer);
                         if let Some(state) = self.state.as_mut() {
                             state.initialize(&mut context);
                         }
@@ -193,6 +427,13 @@ impl ApplicationHandler for App {
             return;
         };
 
+        // Tracked before the gui sees the event, so the position stays current over windows too
+        match event {
+            WindowEvent::CursorMoved { position, .. } => context.cursor_position = Some(position),
+            WindowEvent::CursorLeft { .. } => context.cursor_position = None,
+            _ => {}
+        }
+
         // Receive gui window event
         if gui_state.on_window_event(&context.window, &event).consumed {
             return;
@@ -227,12 +468,25 @@ impl ApplicationHandler for App {
                 let delta_time = now - *last_render_time;
                 *last_render_time = now;
 
+                context.assets.update(&mut context.renderer);
+                for _ in 0..context.ecs.fixed_steps(delta_time.as_secs_f32()) {
+                    state.fixed_update(context);
+                    context.ecs.run_fixed_update();
+                }
                 state.update(context);
+                context.ecs.run_update(delta_time.as_secs_f32());
+                context
+                    .renderer
+                    .extract_world(&context.ecs.world, &context.assets);
 
                 let gui_input = gui_state.take_egui_input(&context.window);
                 gui_state.egui_ctx().begin_pass(gui_input);
 
                 state.ui(context, gui_state.egui_ctx());
+                if context.renderer.overlay_settings().view_gizmo {
+                    crate::overlay::view_gizmo(gui_state.egui_ctx(), context.renderer.camera_mut());
+                }
+                shader_error_overlay(gui_state.egui_ctx(), context.shader_errors());
 
                 let egui_winit::egui::FullOutput {
                     textures_delta,
@@ -264,3 +518,26 @@ impl ApplicationHandler for App {
         context.window.request_redraw();
     }
 }
+
+/// Shows shader compilation errors on top of the app until the shaders are fixed
+fn shader_error_overlay(ui: &egui::Context, errors: &[crate::ShaderError]) {
+    if errors.is_empty() {
+        return;
+    }
+    egui::Window::new("Shader Errors")
+        .anchor(egui::Align2::CENTER_TOP, [0.0, 8.0])
+        .collapsible(false)
+        .show(ui, |ui| {
+            egui::ScrollArea::vertical()
+                .max_height(400.0)
+                .show(ui, |ui| {
+                    for error in errors {
+                        ui.colored_label(
+                            egui::Color32::LIGHT_RED,
+                            format!("{} pipeline", error.pipeline),
+                        );
+                        ui.monospace(&error.message);
+                    }
+                });
+        });
+}
@@ -1,9 +1,39 @@
+mod environment;
+mod indirect;
 mod renderer;
+mod shader;
+mod shadow;
 
+pub mod assets;
+pub mod buffer;
+pub mod camera;
+pub mod compute;
+pub mod culling;
+pub mod debug_draw;
+pub mod ecs;
 pub mod launch;
+pub mod light;
+pub mod lod;
+pub mod material;
+pub mod mesh;
+pub mod overlay;
+pub mod particles;
+pub mod picking;
+pub mod pipeline;
+pub mod render_graph;
+pub mod scene_file;
+pub mod scene_graph;
+pub mod vfs;
 
 pub use launch::*;
+pub use renderer::MAX_LIGHTS;
+pub use shader::{PipelineError, PreprocessError, ReflectionError, ShaderError};
+pub use shadow::{ShadowSettings, MAX_POINT_SHADOWS, MAX_SHADOW_CASCADES, MAX_SPOT_SHADOWS};
 
+pub use bytemuck;
 pub use egui;
+pub use hecs;
 pub use log;
+pub use nalgebra_glm;
+pub use wgpu;
 pub use winit;
@@ -0,0 +1,85 @@
+use nalgebra_glm::{Mat4, Vec3};
+
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Light {
+    /// Infinitely distant light, such as the sun. `direction` is the way the light travels.
+    Directional {
+        direction: nalgebra_glm::Vec3,
+        color: nalgebra_glm::Vec3,
+        intensity: f32,
+    },
+    /// Omnidirectional light that fades out at `range`
+    Point {
+        position: nalgebra_glm::Vec3,
+        color: nalgebra_glm::Vec3,
+        intensity: f32,
+        range: f32,
+    },
+    /// Cone of light that falls off between the inner and outer angles, in radians
+    Spot {
+        position: nalgebra_glm::Vec3,
+        direction: nalgebra_glm::Vec3,
+        color: nalgebra_glm::Vec3,
+        intensity: f32,
+        range: f32,
+        inner_cone_angle: f32,
+        outer_cone_angle: f32,
+    },
+}
+
+impl Default for Light {
+    fn default() -> Self {
+        Self::Directional {
+            direction: nalgebra_glm::vec3(-0.5, -1.0, -1.0).normalize(),
+            color: nalgebra_glm::vec3(1.0, 1.0, 1.0),
+            intensity: 3.0,
+        }
+    }
+}
+
+impl Light {
+    /// The light with its position and direction moved by `transform`
+    pub fn transformed(&self, transform: &Mat4) -> Self {
+        let point = |position: &Vec3| (transform * position.push(1.0)).xyz();
+        let direction = |direction: &Vec3| (transform * direction.push(0.0)).xyz().normalize();
+        match *self {
+            Self::Directional {
+                direction: light_direction,
+                color,
+                intensity,
+            } => Self::Directional {
+                direction: direction(&light_direction),
+                color,
+                intensity,
+            },
+            Self::Point {
+                position,
+                color,
+                intensity,
+                range,
+            } => Self::Point {
+                position: point(&position),
+                color,
+                intensity,
+                range,
+            },
+            Self::Spot {
+                position,
+                direction: light_direction,
+                color,
+                intensity,
+                range,
+                inner_cone_angle,
+                outer_cone_angle,
+            } => Self::Spot {
+                position: point(&position),
+                direction: direction(&light_direction),
+                color,
+                intensity,
+                range,
+                inner_cone_angle,
+                outer_cone_angle,
+            },
+        }
+    }
+}
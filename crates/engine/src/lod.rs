@@ -0,0 +1,194 @@
+use nalgebra_glm::Vec3;
+
+use crate::{
+    culling::BoundingSphere,
+    mesh::{simplify, Mesh},
+};
+
+/// How far the screen size has to move past a threshold before the level changes,
+/// as a fraction of the threshold, so objects near it do not switch back and forth
+pub const LOD_HYSTERESIS: f32 = 0.1;
+
+/// One version of a mesh, drawn while the mesh covers enough of the screen
+#[derive(Debug, Clone, PartialEq)]
+pub struct LodLevel {
+    pub mesh: Mesh,
+    /// The fraction of the screen height the bounding sphere of the mesh has to cover for the
+    /// level to be drawn, unless a more detailed level is. The last level is drawn at any size.
+    pub min_screen_size: f32,
+}
+
+/// A mesh along with coarser versions of itself, the most detailed first
+#[derive(Debug, Clone, PartialEq)]
+pub struct LodMesh {
+    pub levels: Vec<LodLevel>,
+}
+
+impl LodMesh {
+    /// A single level drawn at any size
+    pub fn new(mesh: Mesh) -> Self {
+        Self {
+            levels: vec![LodLevel {
+                mesh,
+                min_screen_size: 0.0,
+            }],
+        }
+    }
+
+    /// Follows `mesh` with `count` simplified levels, each keeping `ratio` of the triangles
+    /// of the level before. The first level is drawn down to `min_screen_size`,
+    /// and each further level down to half the size of the one before.
+    pub fn generate(mesh: Mesh, count: usize, ratio: f32, min_screen_size: f32) -> Self {
+        let mut levels = vec![LodLevel {
+            mesh,
+            min_screen_size,
+        }];
+        for _ in 0..count {
+            let previous = levels.last().expect("There is always a first level");
+            let target = (previous.mesh.triangle_count() as f32 * ratio) as usize;
+            levels.push(LodLevel {
+                mesh: simplify(&previous.mesh, target),
+                min_screen_size: previous.min_screen_size * 0.5,
+            });
+        }
+        if let Some(last) = levels.last_mut() {
+            last.min_screen_size = 0.0;
+        }
+        Self { levels }
+    }
+
+    pub fn min_screen_sizes(&self) -> Vec<f32> {
+        self.levels
+            .iter()
+            .map(|level| level.min_screen_size)
+            .collect()
+    }
+}
+
+/// The fraction of the screen height covered by a sphere in world space,
+/// seen from `camera_position` with a vertical field of view of `fov_y` radians
+pub fn screen_size(sphere: &BoundingSphere, camera_position: &Vec3, fov_y: f32) -> f32 {
+    let distance = nalgebra_glm::distance(&sphere.center, camera_position);
+    if distance <= sphere.radius {
+        return f32::INFINITY;
+    }
+    sphere.radius / (distance * (fov_y * 0.5).tan())
+}
+
+/// Picks the first level whose minimum screen size is covered. Sizes are in decreasing order.
+/// The `current` level is kept while the size stays within its range widened by `hysteresis`.
+pub fn select_level(
+    min_screen_sizes: &[f32],
+    screen_size: f32,
+    current: Option<usize>,
+    hysteresis: f32,
+) -> usize {
+    let last = min_screen_sizes.len().saturating_sub(1);
+    let target = min_screen_sizes
+        .iter()
+        .position(|min| screen_size >= *min)
+        .unwrap_or(last);
+    let Some(current) = current.filter(|current| *current <= last) else {
+        return target;
+    };
+    let lower = min_screen_sizes[current] * (1.0 - hysteresis);
+    let upper = match current {
+        0 => f32::INFINITY,
+        _ => min_screen_sizes[current - 1] * (1.0 + hysteresis),
+    };
+    if (lower..upper).contains(&screen_size) {
+        current
+    } else {
+        target
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nalgebra_glm::vec3;
+
+    use crate::mesh::Vertex;
+
+    const SIZES: [f32; 3] = [0.4, 0.1, 0.0];
+
+    #[test]
+    fn levels_are_selected_by_screen_size() {
+        assert_eq!(select_level(&SIZES, 1.0, None, LOD_HYSTERESIS), 0);
+        assert_eq!(select_level(&SIZES, 0.4, None, LOD_HYSTERESIS), 0);
+        assert_eq!(select_level(&SIZES, 0.2, None, LOD_HYSTERESIS), 1);
+        assert_eq!(select_level(&SIZES, 0.01, None, LOD_HYSTERESIS), 2);
+        assert_eq!(select_level(&[], 0.5, None, LOD_HYSTERESIS), 0);
+    }
+
+    #[test]
+    fn levels_change_once_the_size_moves_past_the_hysteresis() {
+        // Shrinking just below the threshold keeps the detailed level
+        assert_eq!(select_level(&SIZES, 0.38, Some(0), 0.1), 0);
+        assert_eq!(select_level(&SIZES, 0.35, Some(0), 0.1), 1);
+        // Growing just above it keeps the coarse level
+        assert_eq!(select_level(&SIZES, 0.42, Some(1), 0.1), 1);
+        assert_eq!(select_level(&SIZES, 0.45, Some(1), 0.1), 0);
+        // Jumps skip levels
+        assert_eq!(select_level(&SIZES, 0.01, Some(0), 0.1), 2);
+        // Levels that no longer exist are replaced
+        assert_eq!(select_level(&SIZES, 0.2, Some(5), 0.1), 1);
+    }
+
+    #[test]
+    fn screen_size_shrinks_with_distance() {
+        let sphere = BoundingSphere {
+            center: vec3(0.0, 0.0, 10.0),
+            radius: 1.0,
+        };
+        let fov_y = 90f32.to_radians();
+        let near = screen_size(&sphere, &Vec3::zeros(), fov_y);
+        assert!((near - 0.1).abs() < 1e-5);
+        let far = screen_size(&sphere, &vec3(0.0, 0.0, -10.0), fov_y);
+        assert!((far - near * 0.5).abs() < 1e-5);
+        assert_eq!(
+            screen_size(&sphere, &vec3(0.0, 0.0, 10.5), fov_y),
+            f32::INFINITY
+        );
+    }
+
+    /// A bumpy square of `size` by `size` quads
+    fn terrain(size: u32) -> Mesh {
+        let mut mesh = Mesh::default();
+        for z in 0..=size {
+            for x in 0..=size {
+                let [x, z] = [x as f32, z as f32];
+                mesh.vertices.push(Vertex {
+                    position: [x, (x * 0.7).sin() * (z * 0.3).cos(), z, 1.0],
+                    ..Default::default()
+                });
+            }
+        }
+        for z in 0..size {
+            for x in 0..size {
+                let corner = z * (size + 1) + x;
+                let [a, b, c, d] = [corner, corner + 1, corner + size + 1, corner + size + 2];
+                mesh.indices.extend_from_slice(&[a, c, b, b, c, d]);
+            }
+        }
+        mesh
+    }
+
+    #[test]
+    fn generated_levels_halve_their_triangles_and_screen_sizes() {
+        let lod = LodMesh::generate(terrain(16), 3, 0.5, 0.5);
+        let triangles = lod
+            .levels
+            .iter()
+            .map(|level| level.mesh.triangle_count())
+            .collect::<Vec<_>>();
+        assert_eq!(triangles[0], 512);
+        for (level, count) in triangles.iter().enumerate().skip(1) {
+            let target = triangles[level - 1] / 2;
+            assert!(*count <= target && *count + 2 >= target, "{triangles:?}");
+        }
+        assert_eq!(lod.min_screen_sizes(), [0.5, 0.25, 0.125, 0.0]);
+        assert_eq!(lod, LodMesh::generate(terrain(16), 3, 0.5, 0.5));
+    }
+}
@@ -0,0 +1,169 @@
+use crate::mesh::ObjMaterial;
+
+/// Rgba8 pixel data, decoded on the cpu before upload
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn from_rgba8(width: u32, height: u32, pixels: Vec<u8>) -> Self {
+        assert_eq!(
+            pixels.len(),
+            (width * height * 4) as usize,
+            "Image pixel data does not match its dimensions!"
+        );
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Decodes a png or jpeg file held in memory
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, image::ImageError> {
+        let image = image::load_from_memory(bytes)?.to_rgba8();
+        Ok(Self {
+            width: image.width(),
+            height: image.height(),
+            pixels: image.into_raw(),
+        })
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self, image::ImageError> {
+        let image = image::open(path)?.to_rgba8();
+        Ok(Self {
+            width: image.width(),
+            height: image.height(),
+            pixels: image.into_raw(),
+        })
+    }
+}
+
+/// A metallic-roughness material as described by the glTF 2.0 specification.
+/// Every texture is optional and multiplied by its factor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Material {
+    pub base_color_factor: [f32; 4],
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+    pub emissive_factor: [f32; 3],
+    /// Srgb color in rgb, linear alpha in a
+    pub base_color_texture: Option<Image>,
+    /// Roughness is read from the green channel and metalness from the blue channel
+    pub metallic_roughness_texture: Option<Image>,
+    /// Tangent space normal map
+    pub normal_texture: Option<Image>,
+    /// Ambient occlusion is read from the red channel
+    pub occlusion_texture: Option<Image>,
+    /// Srgb emissive color
+    pub emissive_texture: Option<Image>,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            base_color_factor: [1.0; 4],
+            metallic_factor: 0.0,
+            roughness_factor: 0.5,
+            normal_scale: 1.0,
+            occlusion_strength: 1.0,
+            emissive_factor: [0.0; 3],
+            base_color_texture: None,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            occlusion_texture: None,
+            emissive_texture: None,
+        }
+    }
+}
+
+impl Material {
+    /// Approximates a metallic-roughness material from a Phong `.mtl` material.
+    /// Texture paths are not resolved here, so textures must be assigned separately.
+    pub fn from_obj(material: &ObjMaterial) -> Self {
+        let [r, g, b] = material.diffuse;
+        Self {
+            base_color_factor: [r, g, b, material.dissolve],
+            // Maps the Phong exponent onto a perceptual roughness
+            roughness_factor: (2.0 / (material.shininess + 2.0)).sqrt(),
+            emissive_factor: material.emissive,
+            ..Default::default()
+        }
+    }
+}
+
+/// A material as stored on disk, with its textures referred to by path
+/// relative to the material file. Missing fields take the default material's values.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct MaterialFile {
+    pub base_color_factor: [f32; 4],
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+    pub emissive_factor: [f32; 3],
+    pub base_color_texture: Option<String>,
+    pub metallic_roughness_texture: Option<String>,
+    pub normal_texture: Option<String>,
+    pub occlusion_texture: Option<String>,
+    pub emissive_texture: Option<String>,
+}
+
+impl Default for MaterialFile {
+    fn default() -> Self {
+        let material = Material::default();
+        Self {
+            base_color_factor: material.base_color_factor,
+            metallic_factor: material.metallic_factor,
+            roughness_factor: material.roughness_factor,
+            normal_scale: material.normal_scale,
+            occlusion_strength: material.occlusion_strength,
+            emissive_factor: material.emissive_factor,
+            base_color_texture: None,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            occlusion_texture: None,
+            emissive_texture: None,
+        }
+    }
+}
+
+impl MaterialFile {
+    /// The texture paths in the order of the `Material` fields
+    pub fn textures(&self) -> [Option<&str>; 5] {
+        [
+            &self.base_color_texture,
+            &self.metallic_roughness_texture,
+            &self.normal_texture,
+            &self.occlusion_texture,
+            &self.emissive_texture,
+        ]
+        .map(Option::as_deref)
+    }
+
+    /// Combines the factors with decoded textures, given in the order of `textures`
+    pub fn into_material(self, textures: [Option<Image>; 5]) -> Material {
+        let [base_color_texture, metallic_roughness_texture, normal_texture, occlusion_texture, emissive_texture] =
+            textures;
+        Material {
+            base_color_factor: self.base_color_factor,
+            metallic_factor: self.metallic_factor,
+            roughness_factor: self.roughness_factor,
+            normal_scale: self.normal_scale,
+            occlusion_strength: self.occlusion_strength,
+            emissive_factor: self.emissive_factor,
+            base_color_texture,
+            metallic_roughness_texture,
+            normal_texture,
+            occlusion_texture,
+            emissive_texture,
+        }
+    }
+}
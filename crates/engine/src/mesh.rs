@@ -0,0 +1,243 @@
+mod obj;
+mod simplify;
+mod stl;
+
+pub use obj::*;
+pub use simplify::*;
+pub use stl::*;
+
+use std::collections::HashMap;
+
+use crate::shader_type;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Vertex {
+    pub position: [f32; 4],
+    pub normal: [f32; 4],
+    pub color: [f32; 4],
+    pub tex_coords: [f32; 2],
+}
+
+impl Default for Vertex {
+    fn default() -> Self {
+        Self {
+            position: [0.0, 0.0, 0.0, 1.0],
+            normal: [0.0, 0.0, 0.0, 0.0],
+            color: [1.0, 1.0, 1.0, 1.0],
+            tex_coords: [0.0, 0.0],
+        }
+    }
+}
+
+impl Vertex {
+    pub fn vertex_attributes() -> Vec<wgpu::VertexAttribute> {
+        wgpu::vertex_attr_array![
+            0 => Float32x4,
+            1 => Float32x4,
+            2 => Float32x4,
+            3 => Float32x2
+        ]
+        .to_vec()
+    }
+
+    pub fn description(attributes: &[wgpu::VertexAttribute]) -> wgpu::VertexBufferLayout<'_> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes,
+        }
+    }
+}
+
+shader_type! {
+    /// Per instance data, read from a second vertex buffer that advances once per instance
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+    pub struct Instance {
+        pub transform: nalgebra_glm::Mat4,
+        /// Multiplied with the material base color
+        pub color: [f32; 4],
+        /// Free for custom shaders, ignored by the built in ones
+        pub custom: [f32; 4],
+    }
+}
+
+impl Default for Instance {
+    fn default() -> Self {
+        Self {
+            transform: nalgebra_glm::Mat4::identity(),
+            color: [1.0; 4],
+            custom: [0.0; 4],
+        }
+    }
+}
+
+impl Instance {
+    pub fn new(transform: nalgebra_glm::Mat4) -> Self {
+        Self {
+            transform,
+            ..Default::default()
+        }
+    }
+
+    /// Instance attributes continue after the `Vertex` attributes
+    pub fn vertex_attributes() -> Vec<wgpu::VertexAttribute> {
+        wgpu::vertex_attr_array![
+            4 => Float32x4,
+            5 => Float32x4,
+            6 => Float32x4,
+            7 => Float32x4,
+            8 => Float32x4,
+            9 => Float32x4
+        ]
+        .to_vec()
+    }
+
+    pub fn description(attributes: &[wgpu::VertexAttribute]) -> wgpu::VertexBufferLayout<'_> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Instance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes,
+        }
+    }
+}
+
+/// How normals are generated for meshes whose source data has none
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NormalMode {
+    /// Every triangle gets its own vertices, all facing along the face normal
+    Flat,
+    /// Vertices sharing a position average the normals of their adjacent faces
+    #[default]
+    Smooth,
+}
+
+#[derive(Default, Debug, Copy, Clone)]
+pub struct ImportOptions {
+    pub normals: NormalMode,
+}
+
+/// Indexed triangle list geometry, ready to be uploaded to the gpu
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct Mesh {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+}
+
+impl Mesh {
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    /// The bounding box and sphere of the vertices, in the mesh's local space
+    pub fn bounds(&self) -> crate::culling::Bounds {
+        crate::culling::Bounds::from_points(
+            self.vertices
+                .iter()
+                .map(|vertex| nalgebra_glm::make_vec3(&vertex.position[..3])),
+        )
+    }
+
+    pub fn generate_normals(&mut self, mode: NormalMode) {
+        match mode {
+            NormalMode::Flat => self.generate_flat_normals(),
+            NormalMode::Smooth => self.generate_smooth_normals(),
+        }
+    }
+
+    fn generate_flat_normals(&mut self) {
+        let mut vertices = Vec::with_capacity(self.indices.len());
+        for triangle in self.indices.chunks_exact(3) {
+            let corners = [
+                self.vertices[triangle[0] as usize],
+                self.vertices[triangle[1] as usize],
+                self.vertices[triangle[2] as usize],
+            ];
+            // Degenerate triangles have no direction to face
+            let normal = Some(face_normal(
+                &corners[0].position,
+                &corners[1].position,
+                &corners[2].position,
+            ))
+            .filter(|normal| normal.norm_squared() > f32::EPSILON)
+            .map(|normal| normal.normalize())
+            .unwrap_or_else(nalgebra_glm::Vec3::y);
+            for mut vertex in corners {
+                vertex.normal = [normal.x, normal.y, normal.z, 0.0];
+                vertices.push(vertex);
+            }
+        }
+        self.indices = (0..vertices.len() as u32).collect();
+        self.vertices = vertices;
+    }
+
+    fn generate_smooth_normals(&mut self) {
+        // Vertices are keyed by their exact position so that seams introduced
+        // by texture coordinates or materials still shade continuously
+        let key = |position: &[f32; 4]| {
+            [
+                position[0].to_bits(),
+                position[1].to_bits(),
+                position[2].to_bits(),
+            ]
+        };
+
+        let mut accumulated: HashMap<[u32; 3], nalgebra_glm::Vec3> = HashMap::new();
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [
+                &self.vertices[triangle[0] as usize].position,
+                &self.vertices[triangle[1] as usize].position,
+                &self.vertices[triangle[2] as usize].position,
+            ];
+            // The unnormalized cross product weights each face by its area
+            let normal = face_normal(a, b, c);
+            for position in [a, b, c] {
+                *accumulated
+                    .entry(key(position))
+                    .or_insert_with(nalgebra_glm::Vec3::zeros) += normal;
+            }
+        }
+
+        for vertex in self.vertices.iter_mut() {
+            let normal = accumulated
+                .get(&key(&vertex.position))
+                .filter(|normal| normal.norm_squared() > f32::EPSILON)
+                .map(|normal| normal.normalize())
+                .unwrap_or_else(nalgebra_glm::Vec3::y);
+            vertex.normal = [normal.x, normal.y, normal.z, 0.0];
+        }
+    }
+}
+
+fn face_normal(a: &[f32; 4], b: &[f32; 4], c: &[f32; 4]) -> nalgebra_glm::Vec3 {
+    let a = nalgebra_glm::vec3(a[0], a[1], a[2]);
+    let b = nalgebra_glm::vec3(b[0], b[1], b[2]);
+    let c = nalgebra_glm::vec3(c[0], c[1], c[2]);
+    (b - a).cross(&(c - a))
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Io(std::io::Error),
+    Parse { line: usize, message: String },
+    Truncated,
+}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "Failed to read file: {error}"),
+            Self::Parse { line, message } => write!(f, "Line {line}: {message}"),
+            Self::Truncated => write!(f, "File ended unexpectedly"),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+impl From<std::io::Error> for ImportError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
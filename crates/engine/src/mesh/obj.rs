@@ -0,0 +1,480 @@
+use super::{ImportError, ImportOptions, Mesh, Vertex};
+use std::collections::HashMap;
+
+/// A material parsed from a Wavefront `.mtl` library
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjMaterial {
+    pub name: String,
+    pub ambient: [f32; 3],
+    pub diffuse: [f32; 3],
+    pub specular: [f32; 3],
+    pub emissive: [f32; 3],
+    pub shininess: f32,
+    pub dissolve: f32,
+    pub diffuse_texture: Option<String>,
+    pub normal_texture: Option<String>,
+}
+
+impl Default for ObjMaterial {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            ambient: [0.0; 3],
+            diffuse: [1.0; 3],
+            specular: [0.0; 3],
+            emissive: [0.0; 3],
+            shininess: 0.0,
+            dissolve: 1.0,
+            diffuse_texture: None,
+            normal_texture: None,
+        }
+    }
+}
+
+/// A run of faces that share an object name and material
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjMesh {
+    pub name: String,
+    pub material: Option<usize>,
+    pub mesh: Mesh,
+}
+
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct ObjModel {
+    pub meshes: Vec<ObjMesh>,
+    pub materials: Vec<ObjMaterial>,
+}
+
+/// Loads an `.obj` file and any `.mtl` libraries it references relative to it
+#[cfg(not(target_arch = "wasm32"))]
+pub fn load_obj(
+    path: impl AsRef<std::path::Path>,
+    options: ImportOptions,
+) -> Result<ObjModel, ImportError> {
+    let path = path.as_ref();
+    let source = std::fs::read_to_string(path)?;
+    let directory = path.parent().unwrap_or_else(|| std::path::Path::new(""));
+    parse_obj(
+        &source,
+        |library| std::fs::read_to_string(directory.join(library)).ok(),
+        options,
+    )
+}
+
+/// Parses `.obj` source text. Material libraries named by `mtllib`
+/// are resolved through `load_material_library`, and missing libraries
+/// are skipped so geometry still imports.
+pub fn parse_obj(
+    source: &str,
+    mut load_material_library: impl FnMut(&str) -> Option<String>,
+    options: ImportOptions,
+) -> Result<ObjModel, ImportError> {
+    let mut positions: Vec<[f32; 4]> = Vec::new();
+    let mut colors: Vec<[f32; 4]> = Vec::new();
+    let mut normals: Vec<[f32; 4]> = Vec::new();
+    let mut tex_coords: Vec<[f32; 2]> = Vec::new();
+    let mut model = ObjModel::default();
+    let mut builder = MeshBuilder::new(String::new(), None);
+
+    for (line_index, line) in source.lines().enumerate() {
+        let line_number = line_index + 1;
+        let line = line.split('#').next().unwrap_or_default().trim();
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        let arguments: Vec<&str> = tokens.collect();
+        match keyword {
+            "v" => {
+                let values = parse_floats(&arguments, line_number)?;
+                if values.len() < 3 {
+                    return Err(parse_error(line_number, "vertex needs 3 components"));
+                }
+                positions.push([values[0], values[1], values[2], 1.0]);
+                // Vertex colors are a common extension: `v x y z r g b`
+                colors.push(match values.len() {
+                    6.. => [values[3], values[4], values[5], 1.0],
+                    _ => [1.0; 4],
+                });
+            }
+            "vn" => {
+                let values = parse_floats(&arguments, line_number)?;
+                if values.len() < 3 {
+                    return Err(parse_error(line_number, "normal needs 3 components"));
+                }
+                normals.push([values[0], values[1], values[2], 0.0]);
+            }
+            "vt" => {
+                let values = parse_floats(&arguments, line_number)?;
+                if values.is_empty() {
+                    return Err(parse_error(line_number, "texture coordinate is empty"));
+                }
+                // Obj places the texture origin at the bottom left, wgpu at the top left
+                tex_coords.push([values[0], 1.0 - values.get(1).copied().unwrap_or(0.0)]);
+            }
+            "f" => {
+                if arguments.len() < 3 {
+                    return Err(parse_error(line_number, "face needs at least 3 vertices"));
+                }
+                let corners = arguments
+                    .iter()
+                    .map(|corner| {
+                        parse_corner(
+                            corner,
+                            [positions.len(), tex_coords.len(), normals.len()],
+                            line_number,
+                        )
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let material_color = builder
+                    .material
+                    .map(|index| model.materials[index].diffuse)
+                    .unwrap_or([1.0; 3]);
+                let indices: Vec<u32> = corners
+                    .into_iter()
+                    .map(|corner| {
+                        builder.vertex(corner, || {
+                            let color = colors[corner.position];
+                            Vertex {
+                                position: positions[corner.position],
+                                normal: corner
+                                    .normal
+                                    .map(|index| normals[index])
+                                    .unwrap_or_default(),
+                                color: [
+                                    color[0] * material_color[0],
+                                    color[1] * material_color[1],
+                                    color[2] * material_color[2],
+                                    color[3],
+                                ],
+                                tex_coords: corner
+                                    .tex_coords
+                                    .map(|index| tex_coords[index])
+                                    .unwrap_or_default(),
+                            }
+                        })
+                    })
+                    .collect();
+                // Polygons are triangulated as a fan around their first corner
+                for index in 1..indices.len() - 1 {
+                    builder.mesh.indices.extend_from_slice(&[
+                        indices[0],
+                        indices[index],
+                        indices[index + 1],
+                    ]);
+                }
+            }
+            "o" | "g" => {
+                let name = arguments.join(" ");
+                let material = builder.material;
+                builder.finish(&mut model, options);
+                builder = MeshBuilder::new(name, material);
+            }
+            "usemtl" => {
+                let name = arguments.join(" ");
+                let material = model
+                    .materials
+                    .iter()
+                    .position(|material| material.name == name);
+                if material.is_none() {
+                    log::warn!("Obj line {line_number}: unknown material '{name}'");
+                }
+                if material != builder.material {
+                    let name = builder.name.clone();
+                    builder.finish(&mut model, options);
+                    builder = MeshBuilder::new(name, material);
+                }
+            }
+            "mtllib" => {
+                for library in arguments {
+                    match load_material_library(library) {
+                        Some(source) => model.materials.extend(parse_mtl(&source)?),
+                        None => log::warn!("Obj material library '{library}' was not found"),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    builder.finish(&mut model, options);
+
+    Ok(model)
+}
+
+/// Parses `.mtl` source text into its materials
+pub fn parse_mtl(source: &str) -> Result<Vec<ObjMaterial>, ImportError> {
+    let mut materials: Vec<ObjMaterial> = Vec::new();
+    for (line_index, line) in source.lines().enumerate() {
+        let line_number = line_index + 1;
+        let line = line.split('#').next().unwrap_or_default().trim();
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        let arguments: Vec<&str> = tokens.collect();
+        if keyword == "newmtl" {
+            materials.push(ObjMaterial {
+                name: arguments.join(" "),
+                ..Default::default()
+            });
+            continue;
+        }
+        let Some(material) = materials.last_mut() else {
+            return Err(parse_error(line_number, "statement before 'newmtl'"));
+        };
+        match keyword {
+            "Ka" => material.ambient = parse_color(&arguments, line_number)?,
+            "Kd" => material.diffuse = parse_color(&arguments, line_number)?,
+            "Ks" => material.specular = parse_color(&arguments, line_number)?,
+            "Ke" => material.emissive = parse_color(&arguments, line_number)?,
+            "Ns" => material.shininess = parse_scalar(&arguments, line_number)?,
+            "d" => material.dissolve = parse_scalar(&arguments, line_number)?,
+            "Tr" => material.dissolve = 1.0 - parse_scalar(&arguments, line_number)?,
+            // Texture statements may carry options before the file name
+            "map_Kd" => material.diffuse_texture = arguments.last().map(|s| s.to_string()),
+            "map_Bump" | "map_bump" | "bump" | "norm" => {
+                material.normal_texture = arguments.last().map(|s| s.to_string())
+            }
+            _ => {}
+        }
+    }
+    Ok(materials)
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+struct Corner {
+    position: usize,
+    tex_coords: Option<usize>,
+    normal: Option<usize>,
+}
+
+struct MeshBuilder {
+    name: String,
+    material: Option<usize>,
+    mesh: Mesh,
+    has_normals: bool,
+    vertex_lookup: HashMap<Corner, u32>,
+}
+
+impl MeshBuilder {
+    fn new(name: String, material: Option<usize>) -> Self {
+        Self {
+            name,
+            material,
+            mesh: Mesh::default(),
+            has_normals: true,
+            vertex_lookup: HashMap::new(),
+        }
+    }
+
+    fn vertex(&mut self, corner: Corner, create: impl FnOnce() -> Vertex) -> u32 {
+        self.has_normals &= corner.normal.is_some();
+        *self.vertex_lookup.entry(corner).or_insert_with(|| {
+            self.mesh.vertices.push(create());
+            (self.mesh.vertices.len() - 1) as u32
+        })
+    }
+
+    fn finish(self, model: &mut ObjModel, options: ImportOptions) {
+        let Self {
+            name,
+            material,
+            mut mesh,
+            has_normals,
+            ..
+        } = self;
+        if mesh.indices.is_empty() {
+            return;
+        }
+        if !has_normals {
+            mesh.generate_normals(options.normals);
+        }
+        model.meshes.push(ObjMesh {
+            name,
+            material,
+            mesh,
+        });
+    }
+}
+
+fn parse_corner(
+    corner: &str,
+    [position_count, tex_coord_count, normal_count]: [usize; 3],
+    line_number: usize,
+) -> Result<Corner, ImportError> {
+    let mut parts = corner.split('/');
+    let position = parts
+        .next()
+        .map(|index| resolve_index(index, position_count, line_number))
+        .ok_or_else(|| parse_error(line_number, "face corner has no position"))??;
+    let tex_coords = match parts.next() {
+        Some(index) if !index.is_empty() => {
+            Some(resolve_index(index, tex_coord_count, line_number)?)
+        }
+        _ => None,
+    };
+    let normal = match parts.next() {
+        Some(index) if !index.is_empty() => Some(resolve_index(index, normal_count, line_number)?),
+        _ => None,
+    };
+    Ok(Corner {
+        position,
+        tex_coords,
+        normal,
+    })
+}
+
+/// Converts a one-based, possibly negative (relative) obj index to a zero-based one
+fn resolve_index(index: &str, count: usize, line_number: usize) -> Result<usize, ImportError> {
+    let value: i64 = index
+        .parse()
+        .map_err(|_| parse_error(line_number, format!("invalid index '{index}'")))?;
+    let resolved = match value {
+        1.. => value - 1,
+        ..=-1 => count as i64 + value,
+        0 => -1,
+    };
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(parse_error(
+            line_number,
+            format!("index {value} is out of range"),
+        ));
+    }
+    Ok(resolved as usize)
+}
+
+fn parse_floats(arguments: &[&str], line_number: usize) -> Result<Vec<f32>, ImportError> {
+    arguments
+        .iter()
+        .map(|argument| {
+            argument
+                .parse()
+                .map_err(|_| parse_error(line_number, format!("invalid number '{argument}'")))
+        })
+        .collect()
+}
+
+fn parse_scalar(arguments: &[&str], line_number: usize) -> Result<f32, ImportError> {
+    parse_floats(arguments, line_number)?
+        .first()
+        .copied()
+        .ok_or_else(|| parse_error(line_number, "expected a value"))
+}
+
+fn parse_color(arguments: &[&str], line_number: usize) -> Result<[f32; 3], ImportError> {
+    match parse_floats(arguments, line_number)?.as_slice() {
+        [value] => Ok([*value; 3]),
+        [r, g, b, ..] => Ok([*r, *g, *b]),
+        _ => Err(parse_error(line_number, "expected a color")),
+    }
+}
+
+fn parse_error(line: usize, message: impl Into<String>) -> ImportError {
+    ImportError::Parse {
+        line,
+        message: message.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::NormalMode;
+
+    const CUBE_OBJ: &str = include_str!("../../fixtures/cube.obj");
+    const CUBE_MTL: &str = include_str!("../../fixtures/cube.mtl");
+    const QUAD_OBJ: &str = include_str!("../../fixtures/quad.obj");
+
+    fn load_cube() -> ObjModel {
+        parse_obj(
+            CUBE_OBJ,
+            |library| (library == "cube.mtl").then(|| CUBE_MTL.to_string()),
+            ImportOptions::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn parses_materials() {
+        let materials = parse_mtl(CUBE_MTL).unwrap();
+        assert_eq!(materials.len(), 2);
+        assert_eq!(materials[0].name, "red");
+        assert_eq!(materials[0].diffuse, [1.0, 0.0, 0.0]);
+        assert_eq!(materials[1].name, "textured");
+        assert_eq!(materials[1].diffuse_texture.as_deref(), Some("checker.png"));
+        assert_eq!(materials[1].dissolve, 0.5);
+    }
+
+    #[test]
+    fn splits_meshes_by_material() {
+        let model = load_cube();
+        assert_eq!(model.materials.len(), 2);
+        assert_eq!(model.meshes.len(), 2);
+        assert_eq!(model.meshes[0].material, Some(0));
+        assert_eq!(model.meshes[1].material, Some(1));
+        let triangles: usize = model
+            .meshes
+            .iter()
+            .map(|mesh| mesh.mesh.triangle_count())
+            .sum();
+        assert_eq!(triangles, 12);
+    }
+
+    #[test]
+    fn applies_material_color() {
+        let model = load_cube();
+        assert!(model.meshes[0]
+            .mesh
+            .vertices
+            .iter()
+            .all(|vertex| vertex.color == [1.0, 0.0, 0.0, 1.0]));
+    }
+
+    #[test]
+    fn keeps_file_normals() {
+        let model = load_cube();
+        let back = &model.meshes[0].mesh.vertices[0];
+        assert_eq!(back.normal, [0.0, 0.0, -1.0, 0.0]);
+        // Faces with distinct normals never share vertices
+        assert_eq!(model.meshes[0].mesh.vertices.len(), 12);
+    }
+
+    #[test]
+    fn generates_smooth_normals() {
+        let model = parse_obj(QUAD_OBJ, |_| None, ImportOptions::default()).unwrap();
+        let mesh = &model.meshes[0].mesh;
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.triangle_count(), 2);
+        for vertex in &mesh.vertices {
+            assert_eq!(vertex.normal, [0.0, 0.0, 1.0, 0.0]);
+        }
+    }
+
+    #[test]
+    fn generates_flat_normals() {
+        let options = ImportOptions {
+            normals: NormalMode::Flat,
+        };
+        let model = parse_obj(QUAD_OBJ, |_| None, options).unwrap();
+        let mesh = &model.meshes[0].mesh;
+        assert_eq!(mesh.vertices.len(), 6);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn resolves_negative_indices() {
+        let source = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\n";
+        let model = parse_obj(source, |_| None, ImportOptions::default()).unwrap();
+        assert_eq!(
+            model.meshes[0].mesh.vertices[1].position,
+            [1.0, 0.0, 0.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_range_indices() {
+        let source = "v 0 0 0\nv 1 0 0\nf 1 2 3\n";
+        let error = parse_obj(source, |_| None, ImportOptions::default()).unwrap_err();
+        assert!(matches!(error, ImportError::Parse { line: 3, .. }));
+    }
+}
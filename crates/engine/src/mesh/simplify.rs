@@ -0,0 +1,491 @@
+use std::{cmp::Ordering, collections::BinaryHeap, collections::HashMap};
+
+use nalgebra_glm::DVec3;
+
+use super::{Mesh, Vertex};
+
+/// How strongly open edges, including texture seams, resist moving away from their faces
+const BOUNDARY_WEIGHT: f64 = 1000.0;
+
+/// The squared distance to a set of planes, as the symmetric matrix of Garland and Heckbert
+#[derive(Default, Debug, Copy, Clone)]
+struct Quadric {
+    /// aa, ab, ac, ad, bb, bc, bd, cc, cd, dd
+    terms: [f64; 10],
+}
+
+impl Quadric {
+    fn plane(normal: &DVec3, point: &DVec3, weight: f64) -> Self {
+        let [a, b, c] = [normal.x, normal.y, normal.z];
+        let d = -normal.dot(point);
+        Self {
+            terms: [
+                a * a,
+                a * b,
+                a * c,
+                a * d,
+                b * b,
+                b * c,
+                b * d,
+                c * c,
+                c * d,
+                d * d,
+            ]
+            .map(|term| term * weight),
+        }
+    }
+
+    fn add(&mut self, other: &Self) {
+        for (term, other) in self.terms.iter_mut().zip(other.terms) {
+            *term += other;
+        }
+    }
+
+    fn sum(&self, other: &Self) -> Self {
+        let mut sum = *self;
+        sum.add(other);
+        sum
+    }
+
+    fn error(&self, point: &DVec3) -> f64 {
+        let [aa, ab, ac, ad, bb, bc, bd, cc, cd, dd] = self.terms;
+        let [x, y, z] = [point.x, point.y, point.z];
+        aa * x * x
+            + 2.0 * ab * x * y
+            + 2.0 * ac * x * z
+            + 2.0 * ad * x
+            + bb * y * y
+            + 2.0 * bc * y * z
+            + 2.0 * bd * y
+            + cc * z * z
+            + 2.0 * cd * z
+            + dd
+    }
+
+    /// The point of least error, where it is unique
+    fn minimum(&self) -> Option<DVec3> {
+        let [aa, ab, ac, ad, bb, bc, bd, cc, cd, _] = self.terms;
+        let matrix = nalgebra_glm::DMat3::new(aa, ab, ac, ab, bb, bc, ac, bc, cc);
+        if matrix.determinant().abs() < 1e-12 {
+            return None;
+        }
+        Some(matrix.try_inverse()? * -DVec3::new(ad, bd, cd))
+    }
+}
+
+/// A candidate edge collapse, ordered so the heap pops the cheapest first
+#[derive(Debug, Copy, Clone)]
+struct Collapse {
+    cost: f64,
+    /// The vertex kept, moved to `position`
+    kept: u32,
+    removed: u32,
+    position: DVec3,
+    /// How far along the edge `position` is, to interpolate the attributes
+    factor: f64,
+    /// The versions of both vertices when the collapse was computed
+    versions: [u32; 2],
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    /// Reversed, with the vertices breaking ties so equal costs collapse in the same order every run
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .total_cmp(&self.cost)
+            .then_with(|| other.kept.cmp(&self.kept))
+            .then_with(|| other.removed.cmp(&self.removed))
+    }
+}
+
+struct Simplifier {
+    vertices: Vec<Vertex>,
+    positions: Vec<DVec3>,
+    quadrics: Vec<Quadric>,
+    versions: Vec<u32>,
+    removed_vertices: Vec<bool>,
+    triangles: Vec<[u32; 3]>,
+    removed_triangles: Vec<bool>,
+    /// The triangles around each vertex, including removed ones until they are pruned
+    vertex_triangles: Vec<Vec<usize>>,
+    triangle_count: usize,
+    heap: BinaryHeap<Collapse>,
+}
+
+impl Simplifier {
+    fn new(mesh: &Mesh) -> Self {
+        let positions = mesh
+            .vertices
+            .iter()
+            .map(|vertex| vertex.position)
+            .map(|[x, y, z, _]| DVec3::new(x as f64, y as f64, z as f64))
+            .collect::<Vec<_>>();
+        let triangles = mesh
+            .indices
+            .chunks_exact(3)
+            .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+            .collect::<Vec<_>>();
+
+        let mut simplifier = Self {
+            vertices: mesh.vertices.clone(),
+            quadrics: vec![Quadric::default(); positions.len()],
+            versions: vec![0; positions.len()],
+            removed_vertices: vec![false; positions.len()],
+            removed_triangles: vec![false; triangles.len()],
+            vertex_triangles: vec![Vec::new(); positions.len()],
+            triangle_count: 0,
+            heap: BinaryHeap::new(),
+            positions,
+            triangles,
+        };
+
+        // Edges used by a single triangle are open, keyed by their sorted vertices
+        let mut edge_uses = HashMap::<[u32; 2], u32>::new();
+        for (index, triangle) in simplifier.triangles.iter().enumerate() {
+            if Self::is_degenerate(triangle) {
+                simplifier.removed_triangles[index] = true;
+                continue;
+            }
+            simplifier.triangle_count += 1;
+            for corner in 0..3 {
+                simplifier.vertex_triangles[triangle[corner] as usize].push(index);
+                *edge_uses.entry(Self::edge(triangle, corner)).or_default() += 1;
+            }
+        }
+
+        for index in 0..simplifier.triangles.len() {
+            if simplifier.removed_triangles[index] {
+                continue;
+            }
+            let triangle = simplifier.triangles[index];
+            let normal = simplifier.normal(&triangle);
+            let area = normal.norm() * 0.5;
+            if area <= 0.0 {
+                continue;
+            }
+            let normal = normal.normalize();
+            let face = Quadric::plane(&normal, &simplifier.positions[triangle[0] as usize], area);
+            for corner in 0..3 {
+                simplifier.quadrics[triangle[corner] as usize].add(&face);
+                let edge = Self::edge(&triangle, corner);
+                if edge_uses[&edge] != 1 {
+                    continue;
+                }
+                // A plane through the open edge, perpendicular to its face, keeps the edge in place
+                let [start, end] = edge.map(|vertex| simplifier.positions[vertex as usize]);
+                let along = end - start;
+                let perpendicular = along.cross(&normal);
+                if perpendicular.norm() <= 0.0 {
+                    continue;
+                }
+                let boundary = Quadric::plane(
+                    &perpendicular.normalize(),
+                    &start,
+                    along.norm_squared() * BOUNDARY_WEIGHT,
+                );
+                for vertex in edge {
+                    simplifier.quadrics[vertex as usize].add(&boundary);
+                }
+            }
+        }
+
+        for index in 0..simplifier.triangles.len() {
+            if simplifier.removed_triangles[index] {
+                continue;
+            }
+            let triangle = simplifier.triangles[index];
+            for corner in 0..3 {
+                let [first, second] = Self::edge(&triangle, corner);
+                simplifier.push_collapse(first, second);
+            }
+        }
+        simplifier
+    }
+
+    fn is_degenerate(triangle: &[u32; 3]) -> bool {
+        triangle[0] == triangle[1] || triangle[1] == triangle[2] || triangle[0] == triangle[2]
+    }
+
+    fn edge(triangle: &[u32; 3], corner: usize) -> [u32; 2] {
+        let [start, end] = [triangle[corner], triangle[(corner + 1) % 3]];
+        [start.min(end), start.max(end)]
+    }
+
+    fn normal(&self, triangle: &[u32; 3]) -> DVec3 {
+        let [a, b, c] = triangle.map(|vertex| self.positions[vertex as usize]);
+        (b - a).cross(&(c - a))
+    }
+
+    /// Queues the cheapest way of merging two vertices: at the point of least error,
+    /// or at either end or the middle of their edge when that point is not unique
+    fn push_collapse(&mut self, first: u32, second: u32) {
+        let quadric = self.quadrics[first as usize].sum(&self.quadrics[second as usize]);
+        let start = self.positions[first as usize];
+        let end = self.positions[second as usize];
+        let along = end - start;
+
+        let mut candidates = vec![(start, 0.0), (end, 1.0), ((start + end) * 0.5, 0.5)];
+        if let Some(minimum) = quadric.minimum() {
+            let factor = if along.norm_squared() > 0.0 {
+                ((minimum - start).dot(&along) / along.norm_squared()).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            candidates.insert(0, (minimum, factor));
+        }
+        let (position, factor, cost) = candidates
+            .into_iter()
+            .map(|(position, factor)| (position, factor, quadric.error(&position).max(0.0)))
+            .min_by(|a, b| a.2.total_cmp(&b.2))
+            .expect("There are always candidates");
+
+        self.heap.push(Collapse {
+            cost,
+            kept: first,
+            removed: second,
+            position,
+            factor,
+            versions: [
+                self.versions[first as usize],
+                self.versions[second as usize],
+            ],
+        });
+    }
+
+    fn is_current(&self, collapse: &Collapse) -> bool {
+        let [kept, removed] = [collapse.kept as usize, collapse.removed as usize];
+        !self.removed_vertices[kept]
+            && !self.removed_vertices[removed]
+            && collapse.versions == [self.versions[kept], self.versions[removed]]
+    }
+
+    /// Whether moving the vertices would turn a remaining triangle around
+    fn flips_triangles(&self, collapse: &Collapse) -> bool {
+        [collapse.kept, collapse.removed].iter().any(|&vertex| {
+            self.vertex_triangles[vertex as usize]
+                .iter()
+                .filter(|&&triangle| !self.removed_triangles[triangle])
+                .map(|&triangle| self.triangles[triangle])
+                // Triangles along the edge disappear instead
+                .filter(|triangle| {
+                    !(triangle.contains(&collapse.kept) && triangle.contains(&collapse.removed))
+                })
+                .any(|triangle| {
+                    let before = self.normal(&triangle);
+                    let [a, b, c] = triangle.map(|corner| {
+                        if corner == vertex {
+                            collapse.position
+                        } else {
+                            self.positions[corner as usize]
+                        }
+                    });
+                    let after = (b - a).cross(&(c - a));
+                    before.norm_squared() > 0.0 && before.dot(&after) <= 0.0
+                })
+        })
+    }
+
+    fn collapse(&mut self, collapse: &Collapse) {
+        let [kept, removed] = [collapse.kept as usize, collapse.removed as usize];
+        let factor = collapse.factor as f32;
+        let [start, end] = [self.vertices[kept], self.vertices[removed]];
+        let lerp = |a: f32, b: f32| a + (b - a) * factor;
+        let mut vertex = Vertex {
+            position: std::array::from_fn(|axis| lerp(start.position[axis], end.position[axis])),
+            normal: std::array::from_fn(|axis| lerp(start.normal[axis], end.normal[axis])),
+            color: std::array::from_fn(|channel| lerp(start.color[channel], end.color[channel])),
+            tex_coords: std::array::from_fn(|axis| {
+                lerp(start.tex_coords[axis], end.tex_coords[axis])
+            }),
+        };
+        vertex.position[..3].copy_from_slice(&[
+            collapse.position.x as f32,
+            collapse.position.y as f32,
+            collapse.position.z as f32,
+        ]);
+        let normal = nalgebra_glm::make_vec3(&vertex.normal[..3]);
+        if normal.norm_squared() > 0.0 {
+            vertex.normal[..3].copy_from_slice(normal.normalize().as_slice());
+        }
+        self.vertices[kept] = vertex;
+        self.positions[kept] = collapse.position;
+        let quadric = self.quadrics[removed];
+        self.quadrics[kept].add(&quadric);
+        self.removed_vertices[removed] = true;
+        self.versions[kept] += 1;
+
+        for triangle in std::mem::take(&mut self.vertex_triangles[removed]) {
+            if self.removed_triangles[triangle] {
+                continue;
+            }
+            let corners = &mut self.triangles[triangle];
+            for corner in corners.iter_mut() {
+                if *corner == collapse.removed {
+                    *corner = collapse.kept;
+                }
+            }
+            if Self::is_degenerate(corners) {
+                self.removed_triangles[triangle] = true;
+                self.triangle_count -= 1;
+            } else {
+                self.vertex_triangles[kept].push(triangle);
+            }
+        }
+        let removed_triangles = &self.removed_triangles;
+        self.vertex_triangles[kept].retain(|&triangle| !removed_triangles[triangle]);
+
+        let mut neighbours = self.vertex_triangles[kept]
+            .iter()
+            .flat_map(|&triangle| self.triangles[triangle])
+            .filter(|&vertex| vertex != collapse.kept)
+            .collect::<Vec<_>>();
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        for neighbour in neighbours {
+            self.push_collapse(collapse.kept.min(neighbour), collapse.kept.max(neighbour));
+        }
+    }
+
+    fn run(&mut self, target_triangles: usize) {
+        while self.triangle_count > target_triangles {
+            let Some(collapse) = self.heap.pop() else {
+                break;
+            };
+            if !self.is_current(&collapse) || self.flips_triangles(&collapse) {
+                continue;
+            }
+            self.collapse(&collapse);
+        }
+    }
+
+    /// The remaining triangles, keeping the order of the vertices and triangles left
+    fn into_mesh(self) -> Mesh {
+        let triangles = self
+            .triangles
+            .iter()
+            .zip(&self.removed_triangles)
+            .filter(|(_, removed)| !**removed)
+            .map(|(triangle, _)| triangle);
+
+        let mut used = vec![false; self.vertices.len()];
+        for triangle in triangles.clone() {
+            for &corner in triangle {
+                used[corner as usize] = true;
+            }
+        }
+        let mut remap = vec![0; self.vertices.len()];
+        let mut vertices = Vec::new();
+        for (index, vertex) in self.vertices.iter().enumerate() {
+            if used[index] {
+                remap[index] = vertices.len() as u32;
+                vertices.push(*vertex);
+            }
+        }
+        let indices = triangles
+            .flat_map(|triangle| triangle.map(|corner| remap[corner as usize]))
+            .collect();
+        Mesh { vertices, indices }
+    }
+}
+
+/// Collapses edges by their quadric error until at most `target_triangles` remain, or until
+/// no edge can collapse without turning a triangle around. Open edges and texture seams
+/// are kept in place as long as possible. The result only depends on the input.
+pub fn simplify(mesh: &Mesh, target_triangles: usize) -> Mesh {
+    if mesh.triangle_count() <= target_triangles {
+        return mesh.clone();
+    }
+    let mut simplifier = Simplifier::new(mesh);
+    simplifier.run(target_triangles);
+    simplifier.into_mesh()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A square grid of `size` by `size` quads on the XZ plane, raised by `height`
+    fn grid(size: u32, height: impl Fn(f32, f32) -> f32) -> Mesh {
+        let mut mesh = Mesh::default();
+        for z in 0..=size {
+            for x in 0..=size {
+                let [x, z] = [x as f32 / size as f32, z as f32 / size as f32];
+                mesh.vertices.push(Vertex {
+                    position: [x, height(x, z), z, 1.0],
+                    normal: [0.0, 1.0, 0.0, 0.0],
+                    tex_coords: [x, z],
+                    ..Default::default()
+                });
+            }
+        }
+        for z in 0..size {
+            for x in 0..size {
+                let corner = z * (size + 1) + x;
+                let [a, b, c, d] = [corner, corner + 1, corner + size + 1, corner + size + 2];
+                mesh.indices.extend_from_slice(&[a, c, b, b, c, d]);
+            }
+        }
+        mesh
+    }
+
+    #[test]
+    fn flat_grids_simplify_to_the_target_triangle_count() {
+        let mesh = grid(16, |_, _| 0.0);
+        assert_eq!(mesh.triangle_count(), 512);
+
+        let simplified = simplify(&mesh, 100);
+        // Interior collapses remove two triangles at a time
+        assert!((99..=100).contains(&simplified.triangle_count()));
+        assert!(simplified
+            .vertices
+            .iter()
+            .all(|vertex| vertex.position[1] == 0.0));
+        // The outline stays where it was
+        assert_eq!(simplified.bounds().aabb, mesh.bounds().aabb);
+        assert!(simplified
+            .indices
+            .iter()
+            .all(|&index| (index as usize) < simplified.vertices.len()));
+    }
+
+    #[test]
+    fn triangles_keep_facing_the_same_way() {
+        let mesh = grid(12, |x, z| (x * 6.0).sin() * (z * 4.0).cos() * 0.2);
+        let simplified = simplify(&mesh, 60);
+        assert!(simplified.triangle_count() <= 60);
+        for triangle in simplified.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|corner| simplified.vertices[triangle[corner] as usize]);
+            let normal = super::super::face_normal(&a.position, &b.position, &c.position);
+            assert!(normal.y > 0.0, "{triangle:?} turned around");
+        }
+    }
+
+    #[test]
+    fn simplification_is_deterministic() {
+        let mesh = grid(10, |x, z| (x * z * 7.0).sin() * 0.1);
+        let first = simplify(&mesh, 37);
+        assert_eq!(first, simplify(&mesh, 37));
+        assert!(first.triangle_count() <= 37);
+    }
+
+    #[test]
+    fn meshes_within_the_target_are_unchanged() {
+        let mesh = grid(2, |_, _| 0.0);
+        assert_eq!(simplify(&mesh, 8), mesh);
+        assert_eq!(simplify(&mesh, 100), mesh);
+    }
+}
@@ -0,0 +1,243 @@
+use super::{ImportError, ImportOptions, Mesh, Vertex};
+
+const BINARY_HEADER_SIZE: usize = 84;
+const BINARY_TRIANGLE_SIZE: usize = 50;
+
+/// Loads an ascii or binary `.stl` file
+#[cfg(not(target_arch = "wasm32"))]
+pub fn load_stl(
+    path: impl AsRef<std::path::Path>,
+    options: ImportOptions,
+) -> Result<Mesh, ImportError> {
+    parse_stl(&std::fs::read(path)?, options)
+}
+
+/// Parses stl data, detecting whether it is ascii or binary.
+/// Facet normals are used when present, otherwise they are
+/// generated according to `options`.
+pub fn parse_stl(bytes: &[u8], options: ImportOptions) -> Result<Mesh, ImportError> {
+    // Data that is not valid utf8 can only be binary, even if it is too short to parse
+    let facets = match std::str::from_utf8(bytes) {
+        Ok(source) if !is_binary(bytes) => parse_ascii(source)?,
+        _ => parse_binary(bytes)?,
+    };
+
+    let has_normals = facets
+        .iter()
+        .all(|facet| facet.normal.iter().any(|component| *component != 0.0));
+
+    let mut mesh = Mesh::default();
+    for facet in facets {
+        for position in facet.corners {
+            mesh.indices.push(mesh.vertices.len() as u32);
+            mesh.vertices.push(Vertex {
+                position: [position[0], position[1], position[2], 1.0],
+                normal: [facet.normal[0], facet.normal[1], facet.normal[2], 0.0],
+                ..Default::default()
+            });
+        }
+    }
+
+    if !has_normals {
+        mesh.generate_normals(options.normals);
+    }
+
+    Ok(mesh)
+}
+
+struct Facet {
+    normal: [f32; 3],
+    corners: [[f32; 3]; 3],
+}
+
+/// Ascii files start with "solid", but binary headers may too,
+/// so those are only binary when they hold every triangle they claim
+fn is_binary(bytes: &[u8]) -> bool {
+    if !bytes.trim_ascii_start().starts_with(b"solid") {
+        return true;
+    }
+    binary_size(bytes).is_some_and(|size| bytes.len() >= size)
+}
+
+/// The size of a binary file holding the triangle count stored in its header
+fn binary_size(bytes: &[u8]) -> Option<usize> {
+    let count = bytes.get(80..BINARY_HEADER_SIZE)?;
+    let count = u32::from_le_bytes([count[0], count[1], count[2], count[3]]);
+    usize::try_from(count)
+        .ok()?
+        .checked_mul(BINARY_TRIANGLE_SIZE)?
+        .checked_add(BINARY_HEADER_SIZE)
+}
+
+fn parse_binary(bytes: &[u8]) -> Result<Vec<Facet>, ImportError> {
+    // Trailing bytes past the last triangle are ignored
+    let size = binary_size(bytes).ok_or(ImportError::Truncated)?;
+    let bytes = bytes.get(..size).ok_or(ImportError::Truncated)?;
+
+    let read_vector = |triangle: &[u8], offset: usize| -> [f32; 3] {
+        let component = |index: usize| {
+            let start = offset + index * 4;
+            f32::from_le_bytes([
+                triangle[start],
+                triangle[start + 1],
+                triangle[start + 2],
+                triangle[start + 3],
+            ])
+        };
+        [component(0), component(1), component(2)]
+    };
+
+    let facets = bytes[BINARY_HEADER_SIZE..]
+        .chunks_exact(BINARY_TRIANGLE_SIZE)
+        .map(|triangle| Facet {
+            normal: read_vector(triangle, 0),
+            corners: [
+                read_vector(triangle, 12),
+                read_vector(triangle, 24),
+                read_vector(triangle, 36),
+            ],
+        })
+        .collect();
+    Ok(facets)
+}
+
+fn parse_ascii(source: &str) -> Result<Vec<Facet>, ImportError> {
+    let mut facets = Vec::new();
+    let mut normal = [0.0; 3];
+    let mut corners = Vec::with_capacity(3);
+
+    for (line_index, line) in source.lines().enumerate() {
+        let line_number = line_index + 1;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["facet", "normal", components @ ..] => {
+                normal = parse_vector(components, line_number)?;
+                corners.clear();
+            }
+            ["vertex", components @ ..] => {
+                if corners.len() == 3 {
+                    return Err(ImportError::Parse {
+                        line: line_number,
+                        message: "facet has more than 3 vertices".to_string(),
+                    });
+                }
+                corners.push(parse_vector(components, line_number)?);
+            }
+            ["endfacet", ..] => {
+                let [a, b, c] = corners[..] else {
+                    return Err(ImportError::Parse {
+                        line: line_number,
+                        message: "facet needs exactly 3 vertices".to_string(),
+                    });
+                };
+                facets.push(Facet {
+                    normal,
+                    corners: [a, b, c],
+                });
+            }
+            _ => {}
+        }
+    }
+
+    Ok(facets)
+}
+
+fn parse_vector(components: &[&str], line_number: usize) -> Result<[f32; 3], ImportError> {
+    let parse = |component: &str| {
+        component.parse::<f32>().map_err(|_| ImportError::Parse {
+            line: line_number,
+            message: format!("invalid number '{component}'"),
+        })
+    };
+    match components {
+        [x, y, z] => Ok([parse(x)?, parse(y)?, parse(z)?]),
+        _ => Err(ImportError::Parse {
+            line: line_number,
+            message: "expected 3 components".to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::NormalMode;
+
+    const TETRAHEDRON_ASCII: &[u8] = include_bytes!("../../fixtures/tetrahedron.stl");
+    const TETRAHEDRON_BINARY: &[u8] = include_bytes!("../../fixtures/tetrahedron_binary.stl");
+
+    #[test]
+    fn parses_ascii() {
+        let mesh = parse_stl(TETRAHEDRON_ASCII, ImportOptions::default()).unwrap();
+        assert_eq!(mesh.triangle_count(), 4);
+        assert_eq!(mesh.vertices[0].normal, [0.0, 0.0, -1.0, 0.0]);
+    }
+
+    #[test]
+    fn parses_binary() {
+        let mesh = parse_stl(TETRAHEDRON_BINARY, ImportOptions::default()).unwrap();
+        assert_eq!(mesh.triangle_count(), 4);
+        assert_eq!(mesh.vertices[3].position, [0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn binary_and_ascii_agree() {
+        let ascii = parse_stl(TETRAHEDRON_ASCII, ImportOptions::default()).unwrap();
+        let binary = parse_stl(TETRAHEDRON_BINARY, ImportOptions::default()).unwrap();
+        let positions = |mesh: &Mesh| {
+            mesh.vertices
+                .iter()
+                .map(|vertex| vertex.position)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(positions(&ascii), positions(&binary));
+    }
+
+    #[test]
+    fn generates_missing_normals() {
+        // The binary fixture stores zeroed facet normals
+        let mesh = parse_stl(
+            TETRAHEDRON_BINARY,
+            ImportOptions {
+                normals: NormalMode::Flat,
+            },
+        )
+        .unwrap();
+        assert_eq!(mesh.vertices[0].normal, [0.0, 0.0, -1.0, 0.0]);
+    }
+
+    #[test]
+    fn degenerate_facets_get_finite_normals() {
+        let source = b"solid degenerate\nfacet normal 0 0 0\nouter loop\nvertex 0 0 0\nvertex 1 0 0\nvertex 2 0 0\nendloop\nendfacet\nendsolid\n";
+        for normals in [NormalMode::Flat, NormalMode::Smooth] {
+            let mesh = parse_stl(source, ImportOptions { normals }).unwrap();
+            assert!(mesh
+                .vertices
+                .iter()
+                .all(|vertex| vertex.normal.iter().all(|component| component.is_finite())));
+        }
+    }
+
+    #[test]
+    fn ignores_trailing_bytes_in_binary() {
+        let mut padded = TETRAHEDRON_BINARY.to_vec();
+        padded.extend_from_slice(&[0; 16]);
+        let mesh = parse_stl(&padded, ImportOptions::default()).unwrap();
+        assert_eq!(mesh.triangle_count(), 4);
+    }
+
+    #[test]
+    fn rejects_truncated_binary() {
+        let truncated = &TETRAHEDRON_BINARY[..TETRAHEDRON_BINARY.len() - 10];
+        assert!(matches!(
+            parse_stl(truncated, ImportOptions::default()),
+            Err(ImportError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_facets() {
+        let source = b"solid broken\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 0 0\nendloop\nendfacet\nendsolid\n";
+        assert!(parse_stl(source, ImportOptions::default()).is_err());
+    }
+}
@@ -0,0 +1,248 @@
+use std::sync::Arc;
+
+use nalgebra_glm::{Mat4, Vec3};
+
+use crate::{
+    buffer::UniformBuffer,
+    camera::OrbitCamera,
+    pipeline::{Blend, PipelineBuilder, PipelineCache},
+    renderer::Gpu,
+    shader::{PipelineError, ShaderDefines, ShaderLibrary, ShaderReflection, Sources},
+    shader_type,
+};
+
+const GIZMO_SIZE: f32 = 96.0;
+const GIZMO_MARGIN: f32 = 8.0;
+/// Distance from the gizmo center to the axis handles
+const GIZMO_AXIS_LENGTH: f32 = 34.0;
+const GIZMO_HANDLE_RADIUS: f32 = 9.0;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GridSettings {
+    pub enabled: bool,
+    /// Distance between minor lines in world units
+    pub cell_size: f32,
+    /// Minor cells between two major lines
+    pub major_line_every: u32,
+    /// Distance from the camera at which the grid has faded out
+    pub fade_distance: f32,
+}
+
+impl Default for GridSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            cell_size: 0.5,
+            major_line_every: 10,
+            fade_distance: 50.0,
+        }
+    }
+}
+
+/// Editor helpers drawn on top of the scene
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct OverlaySettings {
+    /// An infinite grid on the XZ plane with the X axis in red and the Z axis in blue
+    pub grid: GridSettings,
+    /// Shows the camera orientation in the top right corner.
+    /// Clicking an axis moves the camera to look along it.
+    pub view_gizmo: bool,
+}
+
+shader_type! {
+    #[repr(C)]
+    #[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+    struct GridUniform {
+        inverse_view_projection: nalgebra_glm::Mat4,
+        view_projection: nalgebra_glm::Mat4,
+        camera_position: [f32; 4],
+        /// x: cell size, y: cells per major line, z: fade distance
+        settings: [f32; 4],
+    }
+}
+
+/// Draws the infinite grid by intersecting the view rays of a fullscreen triangle with the ground
+pub struct Grid {
+    pub pipeline: Arc<wgpu::RenderPipeline>,
+    uniform_buffer: UniformBuffer<GridUniform>,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl Grid {
+    pub const SHADERS: [&str; 1] = ["grid.wgsl"];
+
+    pub fn new(gpu: &Gpu, shaders: &mut ShaderLibrary, pipelines: &PipelineCache) -> Self {
+        let device = &gpu.device;
+        let layout_entries = shaders.build(device, "Grid Reflection", |sources| {
+            let source = sources.preprocess("grid.wgsl", &ShaderDefines::new())?;
+            Ok(ShaderReflection::new(&source)?.bind_group_layout_entries(0)?)
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &layout_entries,
+            label: Some("grid_bind_group_layout"),
+        });
+        let uniform_buffer = UniformBuffer::new(device, "Grid Buffer", &GridUniform::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.binding(),
+            }],
+            label: Some("grid_bind_group"),
+        });
+        let pipeline = shaders.build(device, "Grid", |sources| {
+            Self::create_pipeline(
+                device,
+                gpu.surface_format,
+                &bind_group_layout,
+                sources,
+                pipelines,
+            )
+        });
+        Self {
+            pipeline,
+            uniform_buffer,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    pub fn update(
+        &self,
+        queue: &wgpu::Queue,
+        settings: &GridSettings,
+        camera_position: &Vec3,
+        view_projection: &Mat4,
+    ) {
+        self.uniform_buffer.write(
+            queue,
+            &GridUniform {
+                inverse_view_projection: view_projection
+                    .try_inverse()
+                    .unwrap_or_else(Mat4::identity),
+                view_projection: *view_projection,
+                camera_position: [camera_position.x, camera_position.y, camera_position.z, 1.0],
+                settings: [
+                    settings.cell_size.max(f32::EPSILON),
+                    settings.major_line_every.max(1) as f32,
+                    settings.fade_distance,
+                    0.0,
+                ],
+            },
+        );
+    }
+
+    pub fn render<'rpass>(&'rpass self, render_pass: &mut wgpu::RenderPass<'rpass>) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    pub fn create_pipeline(
+        device: &wgpu::Device,
+        surface_format: wgpu::TextureFormat,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        sources: Sources,
+        pipelines: &PipelineCache,
+    ) -> Result<Arc<wgpu::RenderPipeline>, PipelineError> {
+        let source = sources.preprocess("grid.wgsl", &ShaderDefines::new())?;
+        let layouts = [bind_group_layout];
+        PipelineBuilder::new("Grid", &source, surface_format)
+            .bind_group_layouts(&layouts)
+            .blend(Blend::Alpha)
+            // Scene geometry hides the grid, but the grid never hides what is drawn after it
+            .depth_test(wgpu::CompareFunction::LessEqual, false)
+            .build(device, pipelines)
+    }
+}
+
+struct GizmoHandle {
+    /// From the target towards the camera when this handle is clicked
+    direction: Vec3,
+    color: egui::Color32,
+    /// Only the positive axes are labeled
+    label: Option<&'static str>,
+    position: egui::Pos2,
+    depth: f32,
+}
+
+/// Draws the view orientation gizmo, snapping the camera to the axis whose handle is clicked
+pub(crate) fn view_gizmo(ui: &egui::Context, camera: &mut OrbitCamera) {
+    egui::Area::new(egui::Id::new("view_gizmo"))
+        .anchor(egui::Align2::RIGHT_TOP, [-GIZMO_MARGIN, GIZMO_MARGIN])
+        .show(ui, |ui| {
+            let (response, painter) =
+                ui.allocate_painter(egui::Vec2::splat(GIZMO_SIZE), egui::Sense::click());
+            let center = response.rect.center();
+            if response.hovered() {
+                painter.circle_filled(
+                    center,
+                    GIZMO_SIZE * 0.5,
+                    egui::Color32::from_black_alpha(80),
+                );
+            }
+
+            let view = camera.view();
+            let axes = [
+                (Vec3::x(), egui::Color32::from_rgb(230, 60, 60), "X"),
+                (Vec3::y(), egui::Color32::from_rgb(90, 200, 70), "Y"),
+                (Vec3::z(), egui::Color32::from_rgb(60, 110, 230), "Z"),
+            ];
+            let mut handles = axes
+                .into_iter()
+                .flat_map(|(axis, color, label)| {
+                    [
+                        (axis, color, Some(label)),
+                        (-axis, color.gamma_multiply(0.5), None),
+                    ]
+                })
+                .map(|(direction, color, label)| {
+                    let view_direction = (view * direction.push(0.0)).xyz();
+                    GizmoHandle {
+                        direction,
+                        color,
+                        label,
+                        position: center
+                            + egui::vec2(view_direction.x, -view_direction.y) * GIZMO_AXIS_LENGTH,
+                        depth: view_direction.z,
+                    }
+                })
+                .collect::<Vec<_>>();
+            // Handles further away are drawn first, so the nearer ones cover them
+            handles.sort_by(|first, second| second.depth.total_cmp(&first.depth));
+
+            for handle in handles.iter() {
+                if handle.label.is_some() {
+                    painter.line_segment(
+                        [center, handle.position],
+                        egui::Stroke::new(2.0, handle.color),
+                    );
+                }
+                painter.circle_filled(handle.position, GIZMO_HANDLE_RADIUS, handle.color);
+                if let Some(label) = handle.label {
+                    painter.text(
+                        handle.position,
+                        egui::Align2::CENTER_CENTER,
+                        label,
+                        egui::FontId::proportional(11.0),
+                        egui::Color32::BLACK,
+                    );
+                }
+            }
+
+            let Some(pointer) = response
+                .interact_pointer_pos()
+                .filter(|_| response.clicked())
+            else {
+                return;
+            };
+            if let Some(handle) = handles
+                .iter()
+                .rev()
+                .find(|handle| handle.position.distance(pointer) <= GIZMO_HANDLE_RADIUS)
+            {
+                camera.look_from(&handle.direction);
+            }
+        });
+}
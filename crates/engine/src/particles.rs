@@ -0,0 +1,858 @@
+use std::{
+    ops::{Add, Mul},
+    sync::Arc,
+};
+
+use nalgebra_glm::{Vec3, Vec4};
+
+use crate::{
+    buffer::{StorageBuffer, UniformBuffer},
+    compute::{workgroup_count, ComputePipeline},
+    pipeline::{Blend, PipelineBuilder, PipelineCache},
+    renderer::{Gpu, Slots},
+    shader::{PipelineError, ShaderDefines, ShaderLibrary, ShaderReflection, Sources},
+    shader_type,
+};
+
+/// Samples taken from each curve for the gpu, which interpolates linearly between them
+pub const CURVE_SAMPLES: usize = 16;
+
+const WORKGROUP_SIZE: u32 = 64;
+
+/// A value that changes over the life of a particle, from 0.0 at birth to 1.0 at death
+#[derive(Debug, Clone, PartialEq)]
+pub struct Curve<T> {
+    /// Pairs of normalized life and value, sorted by life.
+    /// A curve without keys samples as the default value.
+    pub keys: Vec<(f32, T)>,
+}
+
+impl<T> Curve<T>
+where
+    T: Copy + Default + Add<Output = T> + Mul<f32, Output = T>,
+{
+    pub fn constant(value: T) -> Self {
+        Self {
+            keys: vec![(0.0, value)],
+        }
+    }
+
+    pub fn linear(start: T, end: T) -> Self {
+        Self {
+            keys: vec![(0.0, start), (1.0, end)],
+        }
+    }
+
+    /// Interpolates linearly between the keys around `life`,
+    /// holding the first and last values beyond them
+    pub fn sample(&self, life: f32) -> T {
+        let Some((_, first)) = self.keys.first() else {
+            return T::default();
+        };
+        let next = self.keys.partition_point(|(time, _)| *time <= life);
+        if next == 0 {
+            return *first;
+        }
+        if next == self.keys.len() {
+            return self.keys[next - 1].1;
+        }
+        let (start_time, start) = self.keys[next - 1];
+        let (end_time, end) = self.keys[next];
+        let factor = (life - start_time) / (end_time - start_time);
+        start * (1.0 - factor) + end * factor
+    }
+
+    /// Evenly spaced samples over the whole life, as uploaded to the gpu
+    fn samples(&self) -> [T; CURVE_SAMPLES] {
+        std::array::from_fn(|index| self.sample(index as f32 / (CURVE_SAMPLES - 1) as f32))
+    }
+
+    fn sort(&mut self) {
+        self.keys
+            .sort_by(|(first, _), (second, _)| first.total_cmp(second));
+    }
+}
+
+/// Particles bounce off a horizontal plane
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GroundCollision {
+    pub height: f32,
+    /// The fraction of vertical speed kept when bouncing
+    pub restitution: f32,
+    /// The fraction of horizontal speed lost when touching the ground
+    pub friction: f32,
+}
+
+impl Default for GroundCollision {
+    fn default() -> Self {
+        Self {
+            height: -1.0,
+            restitution: 0.4,
+            friction: 0.2,
+        }
+    }
+}
+
+/// How particles are blended into the scene. They are drawn unsorted in both cases.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum ParticleBlend {
+    /// Brightens what is behind, for fire, sparks and magic
+    #[default]
+    Additive,
+    /// Covers what is behind by the particle alpha, for smoke and dust
+    Alpha,
+}
+
+impl ParticleBlend {
+    fn blend(self) -> Blend {
+        match self {
+            Self::Additive => Blend::Additive,
+            Self::Alpha => Blend::Alpha,
+        }
+    }
+}
+
+/// Spawns particles that are simulated in a compute shader
+/// and drawn as camera facing quads
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParticleEmitter {
+    pub position: Vec3,
+    /// Particles spawned per second
+    pub spawn_rate: f32,
+    /// The most particles alive at once. Once reached, new particles replace the oldest.
+    pub capacity: u32,
+    /// Radius of the sphere around the position that particles spawn in
+    pub spawn_radius: f32,
+    /// Seconds a particle lives
+    pub lifetime: f32,
+    /// Each particle lives up to this many seconds longer or shorter
+    pub lifetime_variation: f32,
+    pub velocity: Vec3,
+    /// Radius of the random offset added to the initial velocity of each particle
+    pub velocity_spread: f32,
+    /// Scales how fast particles move along their velocity
+    pub speed_over_life: Curve<f32>,
+    pub acceleration_over_life: Curve<Vec3>,
+    /// Linear RGBA, with alpha used for blending
+    pub color_over_life: Curve<Vec4>,
+    /// Width of the quad in world units
+    pub size_over_life: Curve<f32>,
+    pub collision: Option<GroundCollision>,
+    pub blend: ParticleBlend,
+}
+
+impl Default for ParticleEmitter {
+    fn default() -> Self {
+        Self {
+            position: Vec3::zeros(),
+            spawn_rate: 200.0,
+            capacity: 4096,
+            spawn_radius: 0.05,
+            lifetime: 2.0,
+            lifetime_variation: 0.5,
+            velocity: nalgebra_glm::vec3(0.0, 3.0, 0.0),
+            velocity_spread: 1.0,
+            speed_over_life: Curve::constant(1.0),
+            acceleration_over_life: Curve::constant(nalgebra_glm::vec3(0.0, -9.81, 0.0)),
+            color_over_life: Curve::linear(
+                nalgebra_glm::vec4(1.0, 0.6, 0.2, 1.0),
+                nalgebra_glm::vec4(1.0, 0.1, 0.0, 0.0),
+            ),
+            size_over_life: Curve::linear(0.1, 0.02),
+            collision: Some(GroundCollision::default()),
+            blend: ParticleBlend::default(),
+        }
+    }
+}
+
+impl ParticleEmitter {
+    /// Widgets for tuning the emitter live. Wrap the call in `ui.push_id`
+    /// when editing several emitters in the same ui.
+    pub fn editor(&mut self, ui: &mut egui::Ui) {
+        egui::Grid::new("particle_emitter")
+            .num_columns(2)
+            .show(ui, |ui| {
+                ui.label("Position");
+                vec3_editor(ui, &mut self.position);
+                ui.end_row();
+
+                ui.label("Spawn rate");
+                ui.add(
+                    egui::DragValue::new(&mut self.spawn_rate)
+                        .range(0.0..=100_000.0)
+                        .suffix(" /s"),
+                );
+                ui.end_row();
+
+                ui.label("Capacity");
+                ui.add(egui::DragValue::new(&mut self.capacity).range(0..=1_000_000));
+                ui.end_row();
+
+                ui.label("Spawn radius");
+                ui.add(
+                    egui::DragValue::new(&mut self.spawn_radius)
+                        .range(0.0..=f32::MAX)
+                        .speed(0.01),
+                );
+                ui.end_row();
+
+                ui.label("Lifetime");
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::DragValue::new(&mut self.lifetime)
+                            .range(0.01..=f32::MAX)
+                            .speed(0.01)
+                            .suffix(" s"),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut self.lifetime_variation)
+                            .range(0.0..=f32::MAX)
+                            .speed(0.01)
+                            .prefix("± ")
+                            .suffix(" s"),
+                    );
+                });
+                ui.end_row();
+
+                ui.label("Velocity");
+                vec3_editor(ui, &mut self.velocity);
+                ui.end_row();
+
+                ui.label("Velocity spread");
+                ui.add(
+                    egui::DragValue::new(&mut self.velocity_spread)
+                        .range(0.0..=f32::MAX)
+                        .speed(0.01),
+                );
+                ui.end_row();
+
+                ui.label("Blending");
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut self.blend, ParticleBlend::Additive, "Additive");
+                    ui.selectable_value(&mut self.blend, ParticleBlend::Alpha, "Alpha");
+                });
+                ui.end_row();
+            });
+
+        ui.collapsing("Speed over life", |ui| {
+            curve_editor(ui, &mut self.speed_over_life, |ui, speed| {
+                ui.add(egui::DragValue::new(speed).speed(0.01));
+            });
+        });
+        ui.collapsing("Acceleration over life", |ui| {
+            curve_editor(ui, &mut self.acceleration_over_life, vec3_editor);
+        });
+        ui.collapsing("Color over life", |ui| {
+            curve_editor(ui, &mut self.color_over_life, |ui, color| {
+                let mut rgba = [color.x, color.y, color.z, color.w];
+                if ui.color_edit_button_rgba_unmultiplied(&mut rgba).changed() {
+                    *color = Vec4::from(rgba);
+                }
+            });
+        });
+        ui.collapsing("Size over life", |ui| {
+            curve_editor(ui, &mut self.size_over_life, |ui, size| {
+                ui.add(
+                    egui::DragValue::new(size)
+                        .range(0.0..=f32::MAX)
+                        .speed(0.005),
+                );
+            });
+        });
+
+        let mut collides = self.collision.is_some();
+        if ui.checkbox(&mut collides, "Ground collision").changed() {
+            self.collision = collides.then(GroundCollision::default);
+        }
+        if let Some(collision) = self.collision.as_mut() {
+            egui::Grid::new("particle_collision")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    ui.label("Height");
+                    ui.add(egui::DragValue::new(&mut collision.height).speed(0.01));
+                    ui.end_row();
+
+                    ui.label("Restitution");
+                    ui.add(egui::Slider::new(&mut collision.restitution, 0.0..=1.0));
+                    ui.end_row();
+
+                    ui.label("Friction");
+                    ui.add(egui::Slider::new(&mut collision.friction, 0.0..=1.0));
+                    ui.end_row();
+                });
+        }
+    }
+}
+
+fn vec3_editor(ui: &mut egui::Ui, value: &mut Vec3) {
+    ui.horizontal(|ui| {
+        for (axis, component) in ["x ", "y ", "z "].into_iter().zip(value.iter_mut()) {
+            ui.add(egui::DragValue::new(component).speed(0.01).prefix(axis));
+        }
+    });
+}
+
+/// One row per key with its life, its value and a button removing it.
+/// The last key cannot be removed.
+fn curve_editor<T>(
+    ui: &mut egui::Ui,
+    curve: &mut Curve<T>,
+    mut value_editor: impl FnMut(&mut egui::Ui, &mut T),
+) where
+    T: Copy + Default + Add<Output = T> + Mul<f32, Output = T>,
+{
+    let mut removed = None;
+    let removable = curve.keys.len() > 1;
+    for (index, (life, value)) in curve.keys.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::DragValue::new(life)
+                    .range(0.0..=1.0)
+                    .speed(0.01)
+                    .prefix("life "),
+            );
+            value_editor(ui, value);
+            if ui.add_enabled(removable, egui::Button::new("🗑")).clicked() {
+                removed = Some(index);
+            }
+        });
+    }
+    if let Some(index) = removed {
+        curve.keys.remove(index);
+    }
+    if ui.button("Add key").clicked() {
+        // Halves the widest gap, so the curve keeps its shape
+        let mut times = std::iter::once(0.0)
+            .chain(curve.keys.iter().map(|(life, _)| *life))
+            .chain(std::iter::once(1.0))
+            .collect::<Vec<_>>();
+        times.sort_by(f32::total_cmp);
+        let life = times
+            .windows(2)
+            .max_by(|first, second| (first[1] - first[0]).total_cmp(&(second[1] - second[0])))
+            .map_or(0.5, |gap| (gap[0] + gap[1]) * 0.5);
+        curve.keys.push((life, curve.sample(life)));
+    }
+    curve.sort();
+}
+
+shader_type! {
+    #[repr(C)]
+    #[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+    struct Particle {
+        /// xyz: position, w: seconds since spawning
+        position_age: [f32; 4],
+        /// xyz: velocity, w: seconds the particle lives
+        velocity_lifetime: [f32; 4],
+    }
+}
+
+impl Particle {
+    fn vertex_attributes() -> Vec<wgpu::VertexAttribute> {
+        wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4].to_vec()
+    }
+
+    /// The particle buffer is read as per instance vertex data when drawing
+    fn description(attributes: &[wgpu::VertexAttribute]) -> wgpu::VertexBufferLayout<'_> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Particle>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes,
+        }
+    }
+}
+
+shader_type! {
+    #[repr(C)]
+    #[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+    struct EmitterUniform {
+        /// xyz: position, w: spawn radius
+        origin: [f32; 4],
+        /// xyz: initial velocity, w: random spread
+        velocity: [f32; 4],
+        /// x: lifetime, y: lifetime variation, z: delta time
+        lifetime: [f32; 4],
+        /// x: height, y: restitution, z: friction, w: 1.0 when particles collide with the ground
+        ground: [f32; 4],
+        /// x: first spawned index, y: spawn count, z: seed, w: capacity
+        spawn: [u32; 4],
+        /// x: the first particle of the emitter in the particle buffer
+        range: [u32; 4],
+        color: [[f32; 4]; CURVE_SAMPLES],
+        /// xyz: acceleration
+        acceleration: [[f32; 4]; CURVE_SAMPLES],
+        /// x: size, y: speed
+        scale: [[f32; 4]; CURVE_SAMPLES],
+    }
+}
+
+impl EmitterUniform {
+    fn new(emitter: &ParticleEmitter, first: u32, spawn: &Spawn, delta_time: f32) -> Self {
+        let speed = emitter.speed_over_life.samples();
+        let size = emitter.size_over_life.samples();
+        let ground = emitter.collision.map_or([0.0; 4], |collision| {
+            [
+                collision.height,
+                collision.restitution,
+                collision.friction,
+                1.0,
+            ]
+        });
+        Self {
+            origin: [
+                emitter.position.x,
+                emitter.position.y,
+                emitter.position.z,
+                emitter.spawn_radius,
+            ],
+            velocity: [
+                emitter.velocity.x,
+                emitter.velocity.y,
+                emitter.velocity.z,
+                emitter.velocity_spread,
+            ],
+            lifetime: [
+                emitter.lifetime,
+                emitter.lifetime_variation,
+                delta_time,
+                0.0,
+            ],
+            ground,
+            spawn: [spawn.first, spawn.count, spawn.seed, emitter.capacity],
+            range: [first, 0, 0, 0],
+            color: emitter.color_over_life.samples().map(Into::into),
+            acceleration: emitter
+                .acceleration_over_life
+                .samples()
+                .map(|acceleration| [acceleration.x, acceleration.y, acceleration.z, 0.0]),
+            scale: std::array::from_fn(|index| [size[index], speed[index], 0.0, 0.0]),
+        }
+    }
+}
+
+/// The particles an emitter spawns in one frame, as a range of its ring of particles
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+struct Spawn {
+    first: u32,
+    count: u32,
+    seed: u32,
+}
+
+/// Turns the spawn rate into whole particles, carrying fractions over to later frames
+#[derive(Debug, Default)]
+struct Spawner {
+    cursor: u32,
+    remainder: f32,
+    frame: u32,
+}
+
+impl Spawner {
+    fn advance(&mut self, spawn_rate: f32, capacity: u32, delta_time: f32) -> Spawn {
+        let due = self.remainder + spawn_rate.max(0.0) * delta_time;
+        let count = (due.floor() as u32).min(capacity);
+        self.remainder = due.fract();
+        let spawn = Spawn {
+            first: self.cursor,
+            count,
+            seed: self.frame,
+        };
+        if capacity > 0 {
+            self.cursor = (self.cursor + count) % capacity;
+        }
+        self.frame = self.frame.wrapping_add(1);
+        spawn
+    }
+}
+
+struct EmitterBuffers {
+    /// The range of the particle buffer the emitter simulates and draws
+    first: u32,
+    capacity: u32,
+    uniform: UniformBuffer<EmitterUniform>,
+    simulation_bind_group: wgpu::BindGroup,
+    render_bind_group: wgpu::BindGroup,
+}
+
+struct Emitter {
+    settings: ParticleEmitter,
+    spawner: Spawner,
+    /// Created on the first update, and again whenever the particle buffer is laid out
+    buffers: Option<EmitterBuffers>,
+}
+
+struct ParticlePipelines {
+    simulation: ComputePipeline,
+    /// Indexed by `ParticleBlend`
+    render: [Arc<wgpu::RenderPipeline>; 2],
+    emitter_bind_group_layout: wgpu::BindGroupLayout,
+}
+
+/// Simulates every emitter in a compute pass ahead of the scene and draws them after it.
+/// Adapters without compute shaders keep the emitters but never draw them.
+pub struct ParticleSystem {
+    emitters: Slots<Emitter>,
+    /// The particles of every emitter, each in its own range.
+    /// Laid out again, restarting every emitter, when an emitter is added or resized.
+    particles: StorageBuffer<Particle>,
+    pipelines: Option<ParticlePipelines>,
+}
+
+impl ParticleSystem {
+    /// The particle shaders along with every file they include
+    pub const SHADERS: [&str; 4] = [
+        "particle.wgsl",
+        "particle_simulation.wgsl",
+        "particles.wgsl",
+        "scene_uniform.wgsl",
+    ];
+
+    pub fn new(
+        gpu: &Gpu,
+        shaders: &mut ShaderLibrary,
+        pipelines: &PipelineCache,
+        scene_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        if !gpu
+            .downlevel_flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+        {
+            log::warn!("Particles are disabled, the adapter does not support compute shaders");
+            return Self {
+                emitters: Slots::default(),
+                particles: Self::create_particle_buffer(&gpu.device, 0),
+                pipelines: None,
+            };
+        }
+        let device = &gpu.device;
+
+        let layout_entries = shaders.build(device, "Particle Reflection", |sources| {
+            let source = sources.preprocess("particles.wgsl", &Self::shader_defines())?;
+            Ok(ShaderReflection::new(&source)?.bind_group_layout_entries(1)?)
+        });
+        let emitter_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &layout_entries,
+                label: Some("particle_emitter_bind_group_layout"),
+            });
+        let simulation = shaders.build(device, "Particle Simulation", |sources| {
+            Self::create_simulation_pipeline(device, sources)
+        });
+        let layouts = [scene_bind_group_layout, &emitter_bind_group_layout];
+        let render = shaders.build(device, "Particles", |sources| {
+            Self::create_render_pipelines(device, gpu.surface_format, &layouts, sources, pipelines)
+        });
+
+        Self {
+            emitters: Slots::default(),
+            particles: Self::create_particle_buffer(device, 0),
+            pipelines: Some(ParticlePipelines {
+                simulation,
+                render,
+                emitter_bind_group_layout,
+            }),
+        }
+    }
+
+    pub fn add_emitter(&mut self, emitter: ParticleEmitter) -> usize {
+        self.emitters.insert(Emitter {
+            settings: emitter,
+            spawner: Spawner::default(),
+            buffers: None,
+        })
+    }
+
+    /// Returns `None` if the emitter was removed
+    pub fn emitter_mut(&mut self, index: usize) -> Option<&mut ParticleEmitter> {
+        self.emitters
+            .get_mut(index)
+            .map(|emitter| &mut emitter.settings)
+    }
+
+    /// Removes an emitter along with its particles. Its index may be reused by an emitter added later.
+    pub fn remove_emitter(&mut self, index: usize) -> Option<ParticleEmitter> {
+        self.emitters.remove(index).map(|emitter| emitter.settings)
+    }
+
+    /// Holds the particles of every emitter, written by `simulate` and read by `render`
+    pub fn particle_buffer(&self) -> &wgpu::Buffer {
+        self.particles.buffer()
+    }
+
+    /// Rebuilds the pipelines whose shaders changed. Particles stay alive.
+    pub fn reload_shaders(
+        &mut self,
+        changed: &[&str],
+        device: &wgpu::Device,
+        surface_format: wgpu::TextureFormat,
+        shaders: &mut ShaderLibrary,
+        pipelines: &PipelineCache,
+        scene_bind_group_layout: &wgpu::BindGroupLayout,
+    ) {
+        let Some(particle_pipelines) = self.pipelines.as_mut() else {
+            return;
+        };
+        let touches =
+            |shader: &str| changed.contains(&"particle.wgsl") || changed.contains(&shader);
+
+        if touches("particle_simulation.wgsl") {
+            if let Some(simulation) = shaders.rebuild(device, "Particle Simulation", |sources| {
+                Self::create_simulation_pipeline(device, sources)
+            }) {
+                // Bind groups of automatic layouts only fit the pipeline they came from
+                for (_, emitter) in self.emitters.iter_mut() {
+                    if let Some(buffers) = emitter.buffers.as_mut() {
+                        buffers.simulation_bind_group = Self::create_simulation_bind_group(
+                            device,
+                            &simulation,
+                            &buffers.uniform,
+                            &self.particles,
+                        );
+                    }
+                }
+                particle_pipelines.simulation = simulation;
+            }
+        }
+
+        if touches("particles.wgsl") || changed.contains(&"scene_uniform.wgsl") {
+            let layouts = [
+                scene_bind_group_layout,
+                &particle_pipelines.emitter_bind_group_layout,
+            ];
+            if let Some(render) = shaders.rebuild(device, "Particles", |sources| {
+                Self::create_render_pipelines(device, surface_format, &layouts, sources, pipelines)
+            }) {
+                particle_pipelines.render = render;
+            }
+        }
+    }
+
+    /// Spawns this frame's particles and uploads the emitter settings
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, delta_time: f32) {
+        if self.pipelines.is_none() {
+            return;
+        }
+        let resized = self.emitters.iter().any(|(_, emitter)| {
+            emitter
+                .buffers
+                .as_ref()
+                .is_none_or(|buffers| buffers.capacity != emitter.settings.capacity)
+        });
+        if resized {
+            self.layout_particles(device);
+        }
+
+        for (_, emitter) in self.emitters.iter_mut() {
+            let Some(buffers) = emitter.buffers.as_ref() else {
+                continue;
+            };
+            let spawn =
+                emitter
+                    .spawner
+                    .advance(emitter.settings.spawn_rate, buffers.capacity, delta_time);
+            buffers.uniform.write(
+                queue,
+                &EmitterUniform::new(&emitter.settings, buffers.first, &spawn, delta_time),
+            );
+        }
+    }
+
+    /// Packs every emitter into a new particle buffer.
+    /// The ranges of removed emitters are reclaimed here.
+    fn layout_particles(&mut self, device: &wgpu::Device) {
+        let Some(pipelines) = self.pipelines.as_ref() else {
+            return;
+        };
+        let total = self
+            .emitters
+            .iter()
+            .map(|(_, emitter)| emitter.settings.capacity)
+            .sum();
+        self.particles = Self::create_particle_buffer(device, total);
+
+        let mut first = 0;
+        for (_, emitter) in self.emitters.iter_mut() {
+            let capacity = emitter.settings.capacity;
+            emitter.buffers = Some(Self::create_buffers(
+                device,
+                pipelines,
+                &self.particles,
+                first,
+                capacity,
+            ));
+            emitter.spawner = Spawner::default();
+            first += capacity;
+        }
+    }
+
+    /// Records the simulation of every emitter
+    pub fn simulate(&self, encoder: &mut wgpu::CommandEncoder) {
+        let Some(pipelines) = self.pipelines.as_ref() else {
+            return;
+        };
+        for buffers in self.emitters.iter().filter_map(|(_, e)| e.buffers.as_ref()) {
+            if buffers.capacity == 0 {
+                continue;
+            }
+            pipelines.simulation.dispatch(
+                encoder,
+                &[&buffers.simulation_bind_group],
+                [workgroup_count(buffers.capacity, WORKGROUP_SIZE), 1, 1],
+            );
+        }
+    }
+
+    /// Draws every emitter into a pass whose depth already holds the scene
+    pub fn render<'rpass>(
+        &'rpass self,
+        render_pass: &mut wgpu::RenderPass<'rpass>,
+        scene_bind_group: &'rpass wgpu::BindGroup,
+    ) {
+        let Some(pipelines) = self.pipelines.as_ref() else {
+            return;
+        };
+        render_pass.set_bind_group(0, scene_bind_group, &[]);
+        let stride = std::mem::size_of::<Particle>() as wgpu::BufferAddress;
+        for (_, emitter) in self.emitters.iter() {
+            let Some(buffers) = emitter.buffers.as_ref() else {
+                continue;
+            };
+            if buffers.capacity == 0 {
+                continue;
+            }
+            render_pass.set_pipeline(&pipelines.render[emitter.settings.blend as usize]);
+            render_pass.set_bind_group(1, &buffers.render_bind_group, &[]);
+            let start = buffers.first as wgpu::BufferAddress * stride;
+            let end = start + buffers.capacity as wgpu::BufferAddress * stride;
+            render_pass.set_vertex_buffer(0, self.particles.buffer().slice(start..end));
+            render_pass.draw(0..6, 0..buffers.capacity);
+        }
+    }
+
+    fn shader_defines() -> ShaderDefines {
+        ShaderDefines::new().with_value("CURVE_SAMPLES", format!("{CURVE_SAMPLES}u"))
+    }
+
+    fn create_simulation_pipeline(
+        device: &wgpu::Device,
+        sources: Sources,
+    ) -> Result<ComputePipeline, PipelineError> {
+        let source = sources.preprocess("particle_simulation.wgsl", &Self::shader_defines())?;
+        ComputePipeline::new(device, "Particle Simulation", &source, "simulate")
+    }
+
+    fn create_render_pipelines(
+        device: &wgpu::Device,
+        surface_format: wgpu::TextureFormat,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        sources: Sources,
+        pipelines: &PipelineCache,
+    ) -> Result<[Arc<wgpu::RenderPipeline>; 2], PipelineError> {
+        let source = sources.preprocess("particles.wgsl", &Self::shader_defines())?;
+        let attributes = Particle::vertex_attributes();
+        let buffers = [Particle::description(&attributes)];
+        ShaderReflection::new(&source)?.validate_vertex_buffers("vertex_main", &buffers)?;
+
+        let create = |blend: ParticleBlend| {
+            PipelineBuilder::new("Particles", &source, surface_format)
+                .bind_group_layouts(bind_group_layouts)
+                .vertex_buffers(&buffers)
+                .blend(blend.blend())
+                // Particles are tested against the scene but never hide each other
+                .depth_test(wgpu::CompareFunction::Less, false)
+                .build(device, pipelines)
+        };
+        Ok([
+            create(ParticleBlend::Additive)?,
+            create(ParticleBlend::Alpha)?,
+        ])
+    }
+
+    /// Holds at least one particle, as empty buffers cannot be bound
+    fn create_particle_buffer(device: &wgpu::Device, capacity: u32) -> StorageBuffer<Particle> {
+        StorageBuffer::zeroed(
+            device,
+            "Particle Buffer",
+            (capacity as usize).max(1),
+            wgpu::BufferUsages::VERTEX,
+        )
+    }
+
+    fn create_buffers(
+        device: &wgpu::Device,
+        pipelines: &ParticlePipelines,
+        particles: &StorageBuffer<Particle>,
+        first: u32,
+        capacity: u32,
+    ) -> EmitterBuffers {
+        let uniform = UniformBuffer::new(
+            device,
+            "Particle Emitter Buffer",
+            &EmitterUniform::default(),
+        );
+        let render_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pipelines.emitter_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform.binding(),
+            }],
+            label: Some("particle_emitter_bind_group"),
+        });
+        let simulation_bind_group =
+            Self::create_simulation_bind_group(device, &pipelines.simulation, &uniform, particles);
+        EmitterBuffers {
+            first,
+            capacity,
+            uniform,
+            simulation_bind_group,
+            render_bind_group,
+        }
+    }
+
+    fn create_simulation_bind_group(
+        device: &wgpu::Device,
+        simulation: &ComputePipeline,
+        uniform: &UniformBuffer<EmitterUniform>,
+        particles: &StorageBuffer<Particle>,
+    ) -> wgpu::BindGroup {
+        simulation.bind_group(device, 0, &[uniform.binding(), particles.binding()])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curves_interpolate_between_keys() {
+        let curve = Curve {
+            keys: vec![(0.25, 1.0), (0.75, 3.0)],
+        };
+        assert_eq!(curve.sample(0.0), 1.0);
+        assert_eq!(curve.sample(0.5), 2.0);
+        assert_eq!(curve.sample(1.0), 3.0);
+        assert_eq!(Curve::constant(4.0).samples(), [4.0; CURVE_SAMPLES]);
+
+        let samples = Curve::linear(0.0, 1.0).samples();
+        assert_eq!(samples[0], 0.0);
+        assert_eq!(samples[CURVE_SAMPLES - 1], 1.0);
+    }
+
+    #[test]
+    fn curves_without_keys_sample_the_default() {
+        let curve = Curve::<f32> { keys: Vec::new() };
+        assert_eq!(curve.sample(0.5), 0.0);
+    }
+
+    #[test]
+    fn spawner_carries_fractions_and_wraps() {
+        let mut spawner = Spawner::default();
+        let spawns = (0..4)
+            .map(|_| spawner.advance(2.5, 8, 1.0))
+            .map(|spawn| (spawn.first, spawn.count))
+            .collect::<Vec<_>>();
+        assert_eq!(spawns, [(0, 2), (2, 3), (5, 2), (7, 3)]);
+
+        // Never spawns more than fit at once
+        assert_eq!(spawner.advance(1000.0, 8, 1.0).count, 8);
+    }
+}
@@ -0,0 +1,676 @@
+use std::{ops::Range, sync::Arc};
+
+use futures::channel::oneshot;
+use nalgebra_glm::{Mat4, Vec2, Vec3};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    buffer::UniformBuffer,
+    compute::{PendingReadback, Readback},
+    ecs::{Entity, WorldExtract},
+    indirect::MeshArena,
+    mesh::{Instance, Vertex},
+    pipeline::{PipelineBuilder, PipelineCache},
+    render_graph::{FrameGraph, PassResources, ResourceName, TextureSize, TransientTexture},
+    renderer::{Gpu, InstanceBuffer, SceneMesh, Slots},
+    scene_graph::{GraphExtract, NodeId},
+    shader::{PipelineError, ShaderDefines, ShaderLibrary, ShaderReflection, Sources},
+    shader_type,
+};
+
+/// What was drawn under a picked pixel
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PickTarget {
+    Entity(Entity),
+    Node(NodeId),
+    /// An instance added through `instances_mut`, by its position among the mesh's instances
+    Instance {
+        mesh: usize,
+        index: usize,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Pick {
+    pub target: PickTarget,
+    /// The picked point on the surface in world space, recovered from the depth buffer
+    pub position: Vec3,
+}
+
+/// A pick on its way back from the gpu. It is rendered with the next frame
+/// and arrives a frame or two later, without stalling the renderer.
+pub struct PickRequest {
+    state: PickState,
+}
+
+enum PickState {
+    /// Waiting for the frame that renders it
+    Queued(oneshot::Receiver<PickReadback>),
+    Reading(PickReadback),
+}
+
+impl PickRequest {
+    /// Returns `None` while the pick is on its way. Once it arrived, returns the pick,
+    /// or `Some(None)` when nothing was drawn under the pixel or it was outside the window.
+    pub fn try_read(&mut self) -> Option<Option<Pick>> {
+        if let PickState::Queued(receiver) = &mut self.state {
+            match receiver.try_recv() {
+                Ok(Some(readback)) => self.state = PickState::Reading(readback),
+                Ok(None) => return None,
+                Err(oneshot::Canceled) => return Some(None),
+            }
+        }
+        let PickState::Reading(readback) = &mut self.state else {
+            return None;
+        };
+        match readback.readback.try_read()? {
+            Ok(texels) => Some(readback.resolve(&texels)),
+            Err(_) => Some(None),
+        }
+    }
+
+    /// Waits for the pick. On native the device has to be polled meanwhile,
+    /// which the renderer does every frame.
+    pub async fn read(self) -> Option<Pick> {
+        let readback = match self.state {
+            PickState::Queued(receiver) => receiver.await.ok()?,
+            PickState::Reading(readback) => readback,
+        };
+        let PickReadback {
+            readback,
+            targets,
+            inverse_view_projection,
+            ndc,
+        } = readback;
+        let texels = readback.read().await.ok()?;
+        resolve(&texels, &targets, &inverse_view_projection, &ndc)
+    }
+}
+
+/// The texels under a pick, along with what is needed to make sense of them
+struct PickReadback {
+    readback: Readback<u32>,
+    targets: Arc<[PickTarget]>,
+    inverse_view_projection: Mat4,
+    ndc: Vec2,
+}
+
+impl PickReadback {
+    fn resolve(&self, texels: &[u32]) -> Option<Pick> {
+        resolve(
+            texels,
+            &self.targets,
+            &self.inverse_view_projection,
+            &self.ndc,
+        )
+    }
+}
+
+/// Turns the id and depth texels under a pick into what was drawn there.
+/// Ids start at one, zero being where nothing was drawn.
+fn resolve(
+    texels: &[u32],
+    targets: &[PickTarget],
+    inverse_view_projection: &Mat4,
+    ndc: &Vec2,
+) -> Option<Pick> {
+    let [id, depth] = *texels else {
+        return None;
+    };
+    let target = *targets.get(id.checked_sub(1)? as usize)?;
+    let position = unproject(
+        inverse_view_projection,
+        &Vec3::new(ndc.x, ndc.y, f32::from_bits(depth)),
+    );
+    Some(Pick { target, position })
+}
+
+/// The normalized device coordinates at the center of a pixel,
+/// with y pointing up while pixel rows go down
+pub fn pixel_to_ndc(x: u32, y: u32, width: u32, height: u32) -> Vec2 {
+    Vec2::new(
+        (x as f32 + 0.5) / width as f32 * 2.0 - 1.0,
+        1.0 - (y as f32 + 0.5) / height as f32 * 2.0,
+    )
+}
+
+/// The world space position of a point in normalized device coordinates,
+/// with depth in the zero to one range
+pub fn unproject(inverse_view_projection: &Mat4, ndc: &Vec3) -> Vec3 {
+    let position = inverse_view_projection * ndc.push(1.0);
+    position.xyz() / position.w
+}
+
+struct QueuedPick {
+    x: u32,
+    y: u32,
+    sender: oneshot::Sender<PickReadback>,
+}
+
+/// Draws a contiguous run of one mesh's instances
+struct PickDraw {
+    indices: Range<u32>,
+    base_vertex: i32,
+    instances: Range<u32>,
+}
+
+/// Renders the id of every instance into an `R32Uint` target for the frames that have
+/// picks queued, and reads back the texels under them. Nothing is drawn otherwise.
+pub(crate) struct Picking {
+    pub pipeline: Arc<wgpu::RenderPipeline>,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    uniform: UniformBuffer<PickingUniform>,
+    queued: Vec<QueuedPick>,
+    /// The picks rendered this frame, read back once the frame is recorded
+    rendering: Vec<QueuedPick>,
+    /// The surface size the picks of this frame are rendered at
+    size: (u32, u32),
+    instances: InstanceBuffer,
+    /// Holds one through the instance count, so the id of an instance is its index plus one
+    ids: wgpu::Buffer,
+    id_count: usize,
+    draws: Vec<PickDraw>,
+    targets: Arc<[PickTarget]>,
+    inverse_view_projection: Mat4,
+}
+
+impl Picking {
+    pub const ID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+    /// The id of the instance drawn at each texel
+    pub const ID_TEXTURE: ResourceName = "picking_ids";
+    /// The depth of each texel as the bits of a float, read back along with the id
+    pub const DEPTH_BITS_TEXTURE: ResourceName = "picking_depth_bits";
+    pub const DEPTH_TEXTURE: ResourceName = "picking_depth";
+    /// The picking shader along with every file it includes
+    pub const SHADERS: [&str; 2] = ["picking.wgsl", "instancing.wgsl"];
+
+    pub fn new(
+        device: &wgpu::Device,
+        shaders: &mut ShaderLibrary,
+        pipelines: &PipelineCache,
+    ) -> Self {
+        let uniform = UniformBuffer::new(device, "Picking Buffer", &PickingUniform::default());
+        let layout_entries = shaders.build(device, "Picking Reflection", |sources| {
+            let source = sources.preprocess("picking.wgsl", &ShaderDefines::new())?;
+            Ok(ShaderReflection::new(&source)?.bind_group_layout_entries(0)?)
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &layout_entries,
+            label: Some("picking_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform.binding(),
+            }],
+            label: Some("picking_bind_group"),
+        });
+        let pipeline = shaders.build(device, "Picking", |sources| {
+            Self::create_pipeline(device, &bind_group_layout, sources, pipelines)
+        });
+        Self {
+            pipeline,
+            bind_group_layout,
+            bind_group,
+            uniform,
+            queued: Vec::new(),
+            rendering: Vec::new(),
+            size: (1, 1),
+            instances: InstanceBuffer::new(device, 1, wgpu::BufferUsages::empty()),
+            ids: Self::create_ids(device, 1),
+            id_count: 1,
+            draws: Vec::new(),
+            targets: Arc::from([]),
+            inverse_view_projection: Mat4::identity(),
+        }
+    }
+
+    /// Queues a pick of the pixel at `x`, `y` for the next frame
+    pub fn pick(&mut self, x: u32, y: u32) -> PickRequest {
+        let (sender, receiver) = oneshot::channel();
+        self.queued.push(QueuedPick { x, y, sender });
+        PickRequest {
+            state: PickState::Queued(receiver),
+        }
+    }
+
+    /// Whether the picking pass runs this frame
+    pub fn is_active(&self) -> bool {
+        !self.rendering.is_empty()
+    }
+
+    /// Gathers every instance of the scene along with what it was drawn for, when picks are queued.
+    /// The most detailed level of each mesh is drawn, so picks do not depend on the camera distance.
+    pub fn prepare(
+        &mut self,
+        gpu: &Gpu,
+        view_projection: &Mat4,
+        meshes: &Slots<SceneMesh>,
+        world: &WorldExtract,
+        graph: &GraphExtract,
+    ) {
+        let size = (gpu.surface_config.width, gpu.surface_config.height);
+        if self.start(size) {
+            let instances = self.gather(meshes, world, graph);
+            self.upload(&gpu.device, &gpu.queue, size, view_projection, &instances);
+        }
+    }
+
+    /// Moves the queued picks that fall on the surface to this frame, returning whether there are any.
+    /// Picks outside of it are dropped, which resolves them to nothing.
+    fn start(&mut self, (width, height): (u32, u32)) -> bool {
+        self.rendering.clear();
+        self.rendering.extend(
+            self.queued
+                .drain(..)
+                .filter(|pick| pick.x < width && pick.y < height),
+        );
+        self.is_active()
+    }
+
+    /// Collects the instances to draw, recording what each one was drawn for
+    fn gather(
+        &mut self,
+        meshes: &Slots<SceneMesh>,
+        world: &WorldExtract,
+        graph: &GraphExtract,
+    ) -> Vec<Instance> {
+        let mut instances = Vec::new();
+        let mut targets = Vec::new();
+        self.draws.clear();
+        for (mesh_index, mesh) in meshes.iter() {
+            let Some(level) = mesh.levels.first() else {
+                continue;
+            };
+            let first = instances.len() as u32;
+            for (index, instance) in mesh.instances.iter().enumerate() {
+                instances.push(*instance);
+                targets.push(PickTarget::Instance {
+                    mesh: mesh_index,
+                    index,
+                });
+            }
+            for (instance, entity) in world
+                .instances(mesh_index)
+                .iter()
+                .zip(world.entities(mesh_index))
+            {
+                instances.push(*instance);
+                targets.push(PickTarget::Entity(*entity));
+            }
+            instances.extend_from_slice(graph.instances(mesh_index));
+            targets.extend(
+                graph
+                    .nodes(mesh_index)
+                    .iter()
+                    .copied()
+                    .map(PickTarget::Node),
+            );
+            let end = instances.len() as u32;
+            if end > first {
+                self.draws.push(PickDraw {
+                    indices: level.range.indices.clone(),
+                    base_vertex: level.range.base_vertex(),
+                    instances: first..end,
+                });
+            }
+        }
+        self.targets = targets.into();
+        instances
+    }
+
+    fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        size: (u32, u32),
+        view_projection: &Mat4,
+        instances: &[Instance],
+    ) {
+        self.instances.upload(device, queue, instances);
+        if instances.len() > self.id_count {
+            self.id_count = instances.len().next_power_of_two();
+            self.ids = Self::create_ids(device, self.id_count);
+        }
+        self.uniform.write(
+            queue,
+            &PickingUniform {
+                view_projection: *view_projection,
+            },
+        );
+        self.inverse_view_projection = view_projection.try_inverse().unwrap_or_else(Mat4::identity);
+        self.size = size;
+    }
+
+    /// Declares the targets the picking pass writes. They are only allocated on frames with picks.
+    pub fn create_textures(frame: &mut FrameGraph) {
+        let readable = |format| TransientTexture {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            ..TransientTexture::new(format, TextureSize::Surface)
+        };
+        frame.create_texture(Self::ID_TEXTURE, readable(Self::ID_FORMAT));
+        frame.create_texture(Self::DEPTH_BITS_TEXTURE, readable(Self::ID_FORMAT));
+        frame.create_texture(
+            Self::DEPTH_TEXTURE,
+            TransientTexture::new(
+                crate::renderer::Renderer::DEPTH_FORMAT,
+                TextureSize::Surface,
+            ),
+        );
+    }
+
+    /// Draws the ids and depth of every gathered instance into the textures of `create_textures`
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        resources: &PassResources,
+        arena: &MeshArena,
+    ) {
+        if !self.is_active() {
+            return;
+        }
+        let clear = wgpu::Operations {
+            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+            store: wgpu::StoreOp::Store,
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Picking Pass"),
+            color_attachments: &[
+                Some(wgpu::RenderPassColorAttachment {
+                    view: resources.texture(Self::ID_TEXTURE),
+                    resolve_target: None,
+                    ops: clear,
+                }),
+                Some(wgpu::RenderPassColorAttachment {
+                    view: resources.texture(Self::DEPTH_BITS_TEXTURE),
+                    resolve_target: None,
+                    ops: clear,
+                }),
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: resources.texture(Self::DEPTH_TEXTURE),
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Discard,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, arena.vertex_buffer().slice(..));
+        render_pass.set_vertex_buffer(1, self.instances.buffer.slice(..));
+        render_pass.set_vertex_buffer(2, self.ids.slice(..));
+        render_pass.set_index_buffer(arena.index_buffer().slice(..), wgpu::IndexFormat::Uint32);
+        for draw in &self.draws {
+            render_pass.draw_indexed(
+                draw.indices.clone(),
+                draw.base_vertex,
+                draw.instances.clone(),
+            );
+        }
+    }
+
+    /// Records the copies of the texels under this frame's picks and hands them to their
+    /// requests. The returned readbacks are mapped once the frame was submitted.
+    pub fn read(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        resources: &PassResources,
+    ) -> Vec<PendingReadback> {
+        let (width, height) = self.size;
+        self.rendering
+            .drain(..)
+            .map(|pick| {
+                let origin = wgpu::Origin3d {
+                    x: pick.x,
+                    y: pick.y,
+                    z: 0,
+                };
+                let texel = |texture| wgpu::ImageCopyTexture {
+                    texture,
+                    mip_level: 0,
+                    origin,
+                    aspect: wgpu::TextureAspect::All,
+                };
+                let (readback, pending) = Readback::texels(
+                    device,
+                    encoder,
+                    &[
+                        texel(resources.transient_texture(Self::ID_TEXTURE)),
+                        texel(resources.transient_texture(Self::DEPTH_BITS_TEXTURE)),
+                    ],
+                );
+                // The request may have been dropped already
+                let _ = pick.sender.send(PickReadback {
+                    readback,
+                    targets: self.targets.clone(),
+                    inverse_view_projection: self.inverse_view_projection,
+                    ndc: pixel_to_ndc(pick.x, pick.y, width, height),
+                });
+                pending
+            })
+            .collect()
+    }
+
+    fn create_ids(device: &wgpu::Device, count: usize) -> wgpu::Buffer {
+        let ids = (1..=count as u32).collect::<Vec<_>>();
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Picking Id Buffer"),
+            contents: bytemuck::cast_slice(&ids),
+            usage: wgpu::BufferUsages::VERTEX,
+        })
+    }
+
+    pub fn create_pipeline(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        sources: Sources,
+        pipelines: &PipelineCache,
+    ) -> Result<Arc<wgpu::RenderPipeline>, PipelineError> {
+        let shader_source = sources.preprocess("picking.wgsl", &ShaderDefines::new())?;
+        let vertex_attributes = Vertex::vertex_attributes();
+        let instance_attributes = Instance::vertex_attributes();
+        let id_attributes = wgpu::vertex_attr_array![10 => Uint32];
+        let buffers = [
+            Vertex::description(&vertex_attributes),
+            Instance::description(&instance_attributes),
+            wgpu::VertexBufferLayout {
+                array_stride: std::mem::size_of::<u32>() as wgpu::BufferAddress,
+                step_mode: wgpu::VertexStepMode::Instance,
+                attributes: &id_attributes,
+            },
+        ];
+        ShaderReflection::new(&shader_source)?.validate_vertex_buffers("vertex_main", &buffers)?;
+
+        let target = Some(wgpu::ColorTargetState {
+            format: Self::ID_FORMAT,
+            blend: None,
+            write_mask: wgpu::ColorWrites::ALL,
+        });
+        PipelineBuilder::new("Picking", &shader_source, Self::ID_FORMAT)
+            .bind_group_layouts(&[bind_group_layout])
+            .vertex_buffers(&buffers)
+            .color_targets(&[target.clone(), target])
+            .build(device, pipelines)
+    }
+}
+
+shader_type! {
+    #[repr(C)]
+    #[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+    struct PickingUniform {
+        view_projection: nalgebra_glm::Mat4,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nalgebra_glm::{vec2, vec3};
+
+    use crate::{
+        assets::Assets,
+        compute::HeadlessGpu,
+        ecs::{MeshRenderer, World},
+        mesh::Mesh,
+        render_graph::RenderGraph,
+        renderer::SceneLevel,
+        scene_graph::{Node, SceneGraph, Transform},
+    };
+
+    #[test]
+    fn pixel_centers_map_to_device_coordinates() {
+        assert_eq!(pixel_to_ndc(0, 0, 2, 2), vec2(-0.5, 0.5));
+        assert_eq!(pixel_to_ndc(1, 1, 2, 2), vec2(0.5, -0.5));
+        assert_eq!(pixel_to_ndc(50, 25, 101, 51), vec2(0.0, 0.0));
+    }
+
+    #[test]
+    fn depth_unprojects_to_the_world_position() {
+        let projection = nalgebra_glm::perspective_lh_zo(1.5, 60f32.to_radians(), 0.1, 100.0);
+        let view =
+            nalgebra_glm::look_at_lh(&vec3(1.0, 2.0, -5.0), &vec3(0.0, 0.0, 0.0), &Vec3::y());
+        let view_projection = projection * view;
+        let inverse = view_projection.try_inverse().unwrap();
+        let point = vec3(0.5, -0.25, 1.5);
+        let clip = view_projection * point.push(1.0);
+        let ndc = clip.xyz() / clip.w;
+        let position = unproject(&inverse, &ndc);
+        assert!(
+            nalgebra_glm::distance(&position, &point) < 1e-4,
+            "{position}"
+        );
+    }
+
+    #[test]
+    fn ids_resolve_to_their_targets() {
+        let targets = [
+            PickTarget::Instance { mesh: 0, index: 0 },
+            PickTarget::Instance { mesh: 2, index: 3 },
+        ];
+        let inverse = Mat4::identity();
+        let ndc = vec2(0.25, -0.5);
+        let pick = resolve(&[2, 0.75f32.to_bits()], &targets, &inverse, &ndc);
+        assert_eq!(
+            pick,
+            Some(Pick {
+                target: targets[1],
+                position: vec3(0.25, -0.5, 0.75),
+            })
+        );
+        // Zero is the cleared background, and ids past the targets were not drawn this frame
+        assert_eq!(resolve(&[0, 0], &targets, &inverse, &ndc), None);
+        assert_eq!(resolve(&[3, 0], &targets, &inverse, &ndc), None);
+    }
+
+    /// A square covering the screen with an identity view projection
+    fn quad() -> Mesh {
+        Mesh {
+            vertices: [[-1.0, -1.0], [1.0, -1.0], [-1.0, 1.0], [1.0, 1.0]]
+                .into_iter()
+                .map(|[x, y]| Vertex {
+                    position: [x, y, 0.0, 1.0],
+                    ..Default::default()
+                })
+                .collect(),
+            indices: vec![0, 2, 1, 1, 2, 3],
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a fallback adapter, run with `just test-gpu`"]
+    fn picks_on_fallback_adapter_read_back_the_nearest_target() {
+        let gpu = pollster::block_on(HeadlessGpu::new(true)).expect("There is no fallback adapter");
+        let (device, queue) = (&gpu.device, &gpu.queue);
+        let pipelines = PipelineCache::new(device, &gpu.adapter_info);
+        let mut shaders = ShaderLibrary::new();
+        let mut picking = Picking::new(device, &mut shaders, &pipelines);
+        assert!(shaders.errors().is_empty(), "{:?}", shaders.errors());
+
+        let mut arena = MeshArena::new(device);
+        let quad = quad();
+        let mut mesh = SceneMesh::new(
+            vec![SceneLevel::new(arena.insert(device, queue, &quad), 0.0)],
+            quad.bounds(),
+            0,
+        );
+        // The whole screen is covered behind the entity on the left and the node on the right
+        mesh.instances = vec![Instance::new(nalgebra_glm::translation(&vec3(
+            0.0, 0.0, 0.5,
+        )))];
+        let meshes = Slots::from(vec![mesh]);
+        let half = |translation| Transform {
+            translation,
+            scale: vec3(0.5, 0.5, 1.0),
+            ..Default::default()
+        };
+        let mut world = World::new();
+        let entity = world.spawn((half(vec3(-0.5, 0.0, 0.25)), MeshRenderer::new(0)));
+        let mut extract = WorldExtract::default();
+        extract.extract(&world, &Assets::default());
+        let mut graph = SceneGraph::new();
+        let node = graph.add(
+            Node::new("Node")
+                .with_mesh(0)
+                .with_transform(half(vec3(0.5, 0.0, 0.1))),
+        );
+        graph.update();
+        let mut graph_extract = GraphExtract::default();
+        graph_extract.extract(&graph);
+
+        let requests = [(1, 1), (2, 1), (0, 3)].map(|(x, y)| picking.pick(x, y));
+        let mut outside = picking.pick(4, 0);
+        assert!(picking.start((4, 4)));
+        let instances = picking.gather(&meshes, &extract, &graph_extract);
+        assert_eq!(instances.len(), 3);
+        picking.upload(device, queue, (4, 4), &Mat4::identity(), &instances);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Picking Test Encoder"),
+        });
+        let mut readbacks = Vec::new();
+        let mut graph = RenderGraph::new(4, 4);
+        let mut frame = graph.frame(device);
+        Picking::create_textures(&mut frame);
+        frame
+            .add_pass("Picking")
+            .write(Picking::ID_TEXTURE)
+            .write(Picking::DEPTH_BITS_TEXTURE)
+            .write(Picking::DEPTH_TEXTURE)
+            .execute(|encoder, resources| {
+                picking.render(encoder, resources, &arena);
+                readbacks = picking.read(device, encoder, resources);
+            });
+        frame.execute(&mut encoder).unwrap();
+        queue.submit(std::iter::once(encoder.finish()));
+        readbacks.into_iter().for_each(PendingReadback::map);
+        device.poll(wgpu::Maintain::Wait);
+
+        assert_eq!(outside.try_read(), Some(None));
+        let picks = requests.map(|request| pollster::block_on(request.read()));
+        let expected = [
+            (PickTarget::Entity(entity), vec3(-0.25, 0.25, 0.25)),
+            (PickTarget::Node(node), vec3(0.25, 0.25, 0.1)),
+            (
+                PickTarget::Instance { mesh: 0, index: 0 },
+                vec3(-0.75, -0.75, 0.5),
+            ),
+        ];
+        for (pick, (target, position)) in picks.iter().zip(expected) {
+            let pick = pick.expect("Every pick hits a quad");
+            assert_eq!(pick.target, target);
+            assert!(
+                nalgebra_glm::distance(&pick.position, &position) < 1e-5,
+                "{}",
+                pick.position
+            );
+        }
+    }
+}
@@ -0,0 +1,391 @@
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex},
+};
+
+use crate::{renderer::Renderer, shader::PipelineError};
+
+/// How a pipeline blends its output into the color target
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Blend {
+    /// Replaces the target
+    #[default]
+    Opaque,
+    /// Blends by the output alpha
+    Alpha,
+    /// Blends colors already multiplied by their alpha
+    Premultiplied,
+    /// Adds the output weighted by its alpha, for glows and particles
+    Additive,
+}
+
+impl Blend {
+    pub fn state(self) -> Option<wgpu::BlendState> {
+        match self {
+            Self::Opaque => None,
+            Self::Alpha => Some(wgpu::BlendState::ALPHA_BLENDING),
+            Self::Premultiplied => Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+            Self::Additive => Some(wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+            }),
+        }
+    }
+}
+
+/// Which triangles a pipeline discards
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Cull {
+    /// Draws both sides
+    #[default]
+    None,
+    Back,
+    Front,
+}
+
+impl Cull {
+    pub fn face(self) -> Option<wgpu::Face> {
+        match self {
+            Self::None => None,
+            Self::Back => Some(wgpu::Face::Back),
+            Self::Front => Some(wgpu::Face::Front),
+        }
+    }
+}
+
+/// Describes a render pipeline starting from the defaults of the engine: a triangle list
+/// with clockwise front faces, `vertex_main` and `fragment_main` entry points, one opaque
+/// color target, depth testing against `Renderer::DEPTH_FORMAT` and no multisampling.
+/// Bind group layouts are derived from the shader unless they are given.
+pub struct PipelineBuilder<'a> {
+    label: &'a str,
+    source: &'a str,
+    vertex_entry: &'a str,
+    fragment_entry: Option<&'a str>,
+    bind_group_layouts: Option<&'a [&'a wgpu::BindGroupLayout]>,
+    vertex_buffers: &'a [wgpu::VertexBufferLayout<'a>],
+    primitive: wgpu::PrimitiveState,
+    depth_stencil: Option<wgpu::DepthStencilState>,
+    sample_count: u32,
+    targets: Vec<Option<wgpu::ColorTargetState>>,
+}
+
+impl<'a> PipelineBuilder<'a> {
+    /// `source` is the preprocessed WGSL of the pipeline
+    pub fn new(label: &'a str, source: &'a str, color_format: wgpu::TextureFormat) -> Self {
+        Self {
+            label,
+            source,
+            vertex_entry: "vertex_main",
+            fragment_entry: Some("fragment_main"),
+            bind_group_layouts: None,
+            vertex_buffers: &[],
+            primitive: wgpu::PrimitiveState {
+                front_face: wgpu::FrontFace::Cw,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Renderer::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            sample_count: 1,
+            targets: vec![Some(wgpu::ColorTargetState {
+                format: color_format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }
+    }
+
+    /// A pipeline that only writes depth, like a shadow or depth prepass
+    pub fn depth_only(label: &'a str, source: &'a str) -> Self {
+        Self {
+            fragment_entry: None,
+            targets: Vec::new(),
+            ..Self::new(label, source, wgpu::TextureFormat::Rgba8Unorm)
+        }
+    }
+
+    pub fn vertex_entry(mut self, entry_point: &'a str) -> Self {
+        self.vertex_entry = entry_point;
+        self
+    }
+
+    pub fn fragment_entry(mut self, entry_point: &'a str) -> Self {
+        self.fragment_entry = Some(entry_point);
+        self
+    }
+
+    pub fn bind_group_layouts(mut self, layouts: &'a [&'a wgpu::BindGroupLayout]) -> Self {
+        self.bind_group_layouts = Some(layouts);
+        self
+    }
+
+    pub fn vertex_buffers(mut self, buffers: &'a [wgpu::VertexBufferLayout<'a>]) -> Self {
+        self.vertex_buffers = buffers;
+        self
+    }
+
+    pub fn topology(mut self, topology: wgpu::PrimitiveTopology) -> Self {
+        self.primitive.topology = topology;
+        self
+    }
+
+    pub fn front_face(mut self, front_face: wgpu::FrontFace) -> Self {
+        self.primitive.front_face = front_face;
+        self
+    }
+
+    pub fn cull(mut self, cull: Cull) -> Self {
+        self.primitive.cull_mode = cull.face();
+        self
+    }
+
+    /// Sets the blending of every color target
+    pub fn blend(mut self, blend: Blend) -> Self {
+        for target in self.targets.iter_mut().flatten() {
+            target.blend = blend.state();
+        }
+        self
+    }
+
+    pub fn color_targets(mut self, targets: &[Option<wgpu::ColorTargetState>]) -> Self {
+        self.targets = targets.to_vec();
+        self
+    }
+
+    pub fn depth_format(mut self, format: wgpu::TextureFormat) -> Self {
+        if let Some(depth_stencil) = self.depth_stencil.as_mut() {
+            depth_stencil.format = format;
+        }
+        self
+    }
+
+    pub fn depth_test(mut self, compare: wgpu::CompareFunction, write: bool) -> Self {
+        if let Some(depth_stencil) = self.depth_stencil.as_mut() {
+            depth_stencil.depth_compare = compare;
+            depth_stencil.depth_write_enabled = write;
+        }
+        self
+    }
+
+    pub fn depth_bias(mut self, bias: wgpu::DepthBiasState) -> Self {
+        if let Some(depth_stencil) = self.depth_stencil.as_mut() {
+            depth_stencil.bias = bias;
+        }
+        self
+    }
+
+    /// Renders without a depth attachment
+    pub fn no_depth(mut self) -> Self {
+        self.depth_stencil = None;
+        self
+    }
+
+    pub fn samples(mut self, sample_count: u32) -> Self {
+        self.sample_count = sample_count;
+        self
+    }
+
+    /// Returns the pipeline from the cache, or compiles it when no identical
+    /// pipeline was built before. Pipelines that fail validation are not cached.
+    pub fn build(
+        &self,
+        device: &wgpu::Device,
+        cache: &PipelineCache,
+    ) -> Result<Arc<wgpu::RenderPipeline>, PipelineError> {
+        let key = self.key();
+        if let Some(pipeline) = cache.get(key) {
+            return Ok(pipeline);
+        }
+        let pipeline = crate::shader::validated(device, || self.create(device, cache))
+            .map_err(PipelineError::Validation)?;
+        Ok(cache.insert(key, pipeline))
+    }
+
+    fn create(&self, device: &wgpu::Device, cache: &PipelineCache) -> wgpu::RenderPipeline {
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(self.label),
+            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(self.source)),
+        });
+        let layout = self.bind_group_layouts.map(|bind_group_layouts| {
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(self.label),
+                bind_group_layouts,
+                push_constant_ranges: &[],
+            })
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(self.label),
+            layout: layout.as_ref(),
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: Some(self.vertex_entry),
+                buffers: self.vertex_buffers,
+                compilation_options: Default::default(),
+            },
+            primitive: self.primitive,
+            depth_stencil: self.depth_stencil.clone(),
+            multisample: wgpu::MultisampleState {
+                count: self.sample_count,
+                ..Default::default()
+            },
+            fragment: self.fragment_entry.map(|entry_point| wgpu::FragmentState {
+                module: &module,
+                entry_point: Some(entry_point),
+                targets: &self.targets,
+                compilation_options: Default::default(),
+            }),
+            multiview: None,
+            cache: cache.persistent(),
+        })
+    }
+
+    /// Hashes everything that ends up in the pipeline descriptor except the label
+    fn key(&self) -> u64 {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        self.source.hash(&mut hasher);
+        self.vertex_entry.hash(&mut hasher);
+        self.fragment_entry.hash(&mut hasher);
+        self.bind_group_layouts.hash(&mut hasher);
+        for buffer in self.vertex_buffers.iter() {
+            buffer.array_stride.hash(&mut hasher);
+            buffer.step_mode.hash(&mut hasher);
+            buffer.attributes.hash(&mut hasher);
+        }
+        self.primitive.hash(&mut hasher);
+        self.depth_stencil.hash(&mut hasher);
+        self.sample_count.hash(&mut hasher);
+        self.targets.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+/// Render pipelines keyed on the hash of their descriptor, so identical pipelines
+/// are only compiled once. On native backends that support it, the driver's compiled
+/// pipelines are also persisted to disk and reused by the next run.
+pub struct PipelineCache {
+    pipelines: Mutex<HashMap<u64, Arc<wgpu::RenderPipeline>>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    persistent: Option<PersistentCache>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+struct PersistentCache {
+    cache: wgpu::PipelineCache,
+    path: std::path::PathBuf,
+    /// Whether pipelines were compiled since the data was last written
+    dirty: std::sync::atomic::AtomicBool,
+}
+
+impl PipelineCache {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn new(device: &wgpu::Device, adapter_info: &wgpu::AdapterInfo) -> Self {
+        let persistent = wgpu::util::pipeline_cache_key(adapter_info)
+            .filter(|_| device.features().contains(wgpu::Features::PIPELINE_CACHE))
+            .map(|key| {
+                let path = std::env::temp_dir().join(key);
+                let data = std::fs::read(&path).ok();
+                // SAFETY: the data was written by `get_data` for an adapter with the same
+                // cache key, and the fallback discards it if the driver rejects it anyway
+                let cache = unsafe {
+                    device.create_pipeline_cache(&wgpu::PipelineCacheDescriptor {
+                        label: Some("Pipeline Cache"),
+                        data: data.as_deref(),
+                        fallback: true,
+                    })
+                };
+                log::info!("Using the pipeline cache at {}", path.display());
+                PersistentCache {
+                    cache,
+                    path,
+                    dirty: std::sync::atomic::AtomicBool::new(false),
+                }
+            });
+        Self {
+            pipelines: Mutex::default(),
+            persistent,
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn new(_device: &wgpu::Device, _adapter_info: &wgpu::AdapterInfo) -> Self {
+        Self {
+            pipelines: Mutex::default(),
+        }
+    }
+
+    fn get(&self, key: u64) -> Option<Arc<wgpu::RenderPipeline>> {
+        self.pipelines.lock().unwrap().get(&key).cloned()
+    }
+
+    fn insert(&self, key: u64, pipeline: wgpu::RenderPipeline) -> Arc<wgpu::RenderPipeline> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(persistent) = self.persistent.as_ref() {
+            persistent
+                .dirty
+                .store(true, std::sync::atomic::Ordering::Relaxed);
+        }
+        let pipeline = Arc::new(pipeline);
+        self.pipelines.lock().unwrap().insert(key, pipeline.clone());
+        pipeline
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn persistent(&self) -> Option<&wgpu::PipelineCache> {
+        self.persistent.as_ref().map(|persistent| &persistent.cache)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn persistent(&self) -> Option<&wgpu::PipelineCache> {
+        None
+    }
+
+    /// Drops the pipelines only the cache still holds, like those replaced by a shader reload
+    pub fn prune(&self) {
+        self.pipelines
+            .lock()
+            .unwrap()
+            .retain(|_, pipeline| Arc::strong_count(pipeline) > 1);
+    }
+
+    /// Writes the driver's pipeline cache to disk if pipelines were compiled since the last write
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn persist(&self) {
+        let Some(persistent) = self.persistent.as_ref() else {
+            return;
+        };
+        if !persistent
+            .dirty
+            .swap(false, std::sync::atomic::Ordering::Relaxed)
+        {
+            return;
+        }
+        let Some(data) = persistent.cache.get_data() else {
+            return;
+        };
+        // Written next to the cache and renamed, so a crash never leaves a partial file behind
+        let temporary = persistent.path.with_extension("tmp");
+        let result = std::fs::write(&temporary, data)
+            .and_then(|_| std::fs::rename(&temporary, &persistent.path));
+        if let Err(error) = result {
+            log::warn!("Failed to write the pipeline cache: {error}");
+        }
+    }
+
+    /// There is no pipeline cache on the web
+    #[cfg(target_arch = "wasm32")]
+    pub fn persist(&self) {}
+}
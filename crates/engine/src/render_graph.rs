@@ -0,0 +1,527 @@
+use std::collections::HashMap;
+
+/// Resources are referred to by name, shared between textures and buffers
+pub type ResourceName = &'static str;
+
+/// How large a transient texture is
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TextureSize {
+    /// Follows the surface, so the texture is recreated on resize
+    Surface,
+    /// The surface size multiplied by a factor, rounded down and at least one texel
+    SurfaceScaled(f32),
+    Fixed {
+        width: u32,
+        height: u32,
+    },
+}
+
+impl TextureSize {
+    fn resolve(&self, surface_width: u32, surface_height: u32) -> (u32, u32) {
+        match *self {
+            Self::Surface => (surface_width, surface_height),
+            Self::SurfaceScaled(scale) => (
+                ((surface_width as f32 * scale) as u32).max(1),
+                ((surface_height as f32 * scale) as u32).max(1),
+            ),
+            Self::Fixed { width, height } => (width, height),
+        }
+    }
+}
+
+/// Describes a texture that only lives for the duration of a frame.
+/// The graph keeps the underlying gpu texture around and reuses it
+/// as long as the description and the resolved size stay the same.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TransientTexture {
+    pub format: wgpu::TextureFormat,
+    pub size: TextureSize,
+    pub usage: wgpu::TextureUsages,
+    pub sample_count: u32,
+}
+
+impl TransientTexture {
+    pub fn new(format: wgpu::TextureFormat, size: TextureSize) -> Self {
+        Self {
+            format,
+            size,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            sample_count: 1,
+        }
+    }
+}
+
+/// How many frames a transient texture is kept after the last frame that declared it,
+/// so passes that only run now and then, such as picking, do not reallocate every time
+const UNUSED_TEXTURE_FRAMES: u32 = 120;
+
+struct PooledTexture {
+    descriptor: TransientTexture,
+    extent: (u32, u32),
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    /// Frames since a frame last declared the texture
+    unused_frames: u32,
+}
+
+/// Owns the transient textures that persist between frames.
+/// A fresh `FrameGraph` is built from it every frame.
+pub struct RenderGraph {
+    pool: HashMap<ResourceName, PooledTexture>,
+    width: u32,
+    height: u32,
+}
+
+impl RenderGraph {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            pool: HashMap::new(),
+            width,
+            height,
+        }
+    }
+
+    /// Surface sized textures are recreated lazily the next time they are used
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+    }
+
+    pub fn frame<'a>(&'a mut self, device: &'a wgpu::Device) -> FrameGraph<'a> {
+        FrameGraph {
+            graph: self,
+            device,
+            transient_textures: Vec::new(),
+            imported_textures: HashMap::new(),
+            imported_buffers: HashMap::new(),
+            passes: Vec::new(),
+        }
+    }
+
+    fn allocate(&mut self, device: &wgpu::Device, name: ResourceName, texture: TransientTexture) {
+        let extent = texture.size.resolve(self.width, self.height);
+        if let Some(pooled) = self.pool.get_mut(name) {
+            if pooled.descriptor == texture && pooled.extent == extent {
+                pooled.unused_frames = 0;
+                return;
+            }
+        }
+        let gpu_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(name),
+            size: wgpu::Extent3d {
+                width: extent.0,
+                height: extent.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: texture.sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: texture.format,
+            usage: texture.usage,
+            view_formats: &[],
+        });
+        let view = gpu_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        self.pool.insert(
+            name,
+            PooledTexture {
+                descriptor: texture,
+                extent,
+                texture: gpu_texture,
+                view,
+                unused_frames: 0,
+            },
+        );
+    }
+}
+
+type PassCallback<'a> = Box<dyn FnOnce(&mut wgpu::CommandEncoder, &PassResources) + 'a>;
+
+struct PassNode<'a> {
+    name: &'static str,
+    reads: Vec<ResourceName>,
+    writes: Vec<ResourceName>,
+    execute: PassCallback<'a>,
+}
+
+/// The passes and resources of a single frame
+pub struct FrameGraph<'a> {
+    graph: &'a mut RenderGraph,
+    device: &'a wgpu::Device,
+    transient_textures: Vec<(ResourceName, TransientTexture)>,
+    imported_textures: HashMap<ResourceName, &'a wgpu::TextureView>,
+    imported_buffers: HashMap<ResourceName, &'a wgpu::Buffer>,
+    passes: Vec<PassNode<'a>>,
+}
+
+impl<'a> FrameGraph<'a> {
+    /// Declares a texture owned by the graph
+    pub fn create_texture(&mut self, name: ResourceName, texture: TransientTexture) {
+        self.transient_textures.push((name, texture));
+    }
+
+    /// Makes a texture owned elsewhere, such as the surface, visible to passes
+    pub fn import_texture(&mut self, name: ResourceName, view: &'a wgpu::TextureView) {
+        self.imported_textures.insert(name, view);
+    }
+
+    /// Makes a buffer owned elsewhere visible to passes, so they can declare reads and writes of it
+    pub fn import_buffer(&mut self, name: ResourceName, buffer: &'a wgpu::Buffer) {
+        self.imported_buffers.insert(name, buffer);
+    }
+
+    pub fn add_pass(&mut self, name: &'static str) -> PassBuilder<'_, 'a> {
+        PassBuilder {
+            frame: self,
+            name,
+            reads: Vec::new(),
+            writes: Vec::new(),
+        }
+    }
+
+    /// Allocates transient textures, orders the passes by their dependencies,
+    /// and records every pass into the encoder. Nothing is recorded if a pass
+    /// uses an undeclared resource or the passes depend on each other in a cycle.
+    pub fn execute(self, encoder: &mut wgpu::CommandEncoder) -> Result<(), RenderGraphError> {
+        let Self {
+            graph,
+            device,
+            transient_textures,
+            imported_textures,
+            imported_buffers,
+            passes,
+        } = self;
+
+        let accesses = passes
+            .iter()
+            .map(|pass| PassAccess {
+                name: pass.name,
+                reads: &pass.reads,
+                writes: &pass.writes,
+            })
+            .collect::<Vec<_>>();
+        let declared = transient_textures
+            .iter()
+            .map(|(name, _)| *name)
+            .chain(imported_textures.keys().copied())
+            .chain(imported_buffers.keys().copied())
+            .collect::<Vec<_>>();
+        check_declared(&accesses, &declared)?;
+        let order = order_passes(&accesses)?;
+
+        for pooled in graph.pool.values_mut() {
+            pooled.unused_frames += 1;
+        }
+        for (name, texture) in transient_textures.iter() {
+            graph.allocate(device, name, *texture);
+        }
+        graph
+            .pool
+            .retain(|_, pooled| pooled.unused_frames <= UNUSED_TEXTURE_FRAMES);
+
+        // Textures kept for later frames stay out of sight of this frame's passes
+        let mut textures = imported_textures;
+        let mut frame_textures = HashMap::new();
+        for (name, _) in transient_textures.iter() {
+            let pooled = &graph.pool[name];
+            textures.insert(*name, &pooled.view);
+            frame_textures.insert(*name, &pooled.texture);
+        }
+        let resources = PassResources {
+            textures,
+            transient_textures: frame_textures,
+            buffers: imported_buffers,
+        };
+
+        let mut passes = passes.into_iter().map(Some).collect::<Vec<_>>();
+        for index in order {
+            let pass = passes[index]
+                .take()
+                .expect("Render pass was scheduled twice!");
+            encoder.push_debug_group(pass.name);
+            (pass.execute)(encoder, &resources);
+            encoder.pop_debug_group();
+        }
+        Ok(())
+    }
+}
+
+pub struct PassBuilder<'f, 'a> {
+    frame: &'f mut FrameGraph<'a>,
+    name: &'static str,
+    reads: Vec<ResourceName>,
+    writes: Vec<ResourceName>,
+}
+
+impl<'a> PassBuilder<'_, 'a> {
+    pub fn read(mut self, name: ResourceName) -> Self {
+        self.reads.push(name);
+        self
+    }
+
+    pub fn write(mut self, name: ResourceName) -> Self {
+        self.writes.push(name);
+        self
+    }
+
+    pub fn execute(self, callback: impl FnOnce(&mut wgpu::CommandEncoder, &PassResources) + 'a) {
+        self.frame.passes.push(PassNode {
+            name: self.name,
+            reads: self.reads,
+            writes: self.writes,
+            execute: Box::new(callback),
+        });
+    }
+}
+
+/// The resources of a frame, as seen by the passes that record into it
+pub struct PassResources<'a> {
+    textures: HashMap<ResourceName, &'a wgpu::TextureView>,
+    transient_textures: HashMap<ResourceName, &'a wgpu::Texture>,
+    buffers: HashMap<ResourceName, &'a wgpu::Buffer>,
+}
+
+impl PassResources<'_> {
+    pub fn texture(&self, name: ResourceName) -> &wgpu::TextureView {
+        self.textures
+            .get(name)
+            .unwrap_or_else(|| panic!("Render graph texture '{name}' was never declared!"))
+    }
+
+    /// The texture behind a view the graph owns, for copying out of it
+    pub fn transient_texture(&self, name: ResourceName) -> &wgpu::Texture {
+        self.transient_textures.get(name).unwrap_or_else(|| {
+            panic!("Render graph texture '{name}' was never created by the graph!")
+        })
+    }
+
+    pub fn buffer(&self, name: ResourceName) -> &wgpu::Buffer {
+        self.buffers
+            .get(name)
+            .unwrap_or_else(|| panic!("Render graph buffer '{name}' was never declared!"))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RenderGraphError {
+    /// The passes depend on each other through their reads and writes
+    Cycle { passes: Vec<&'static str> },
+    /// A pass reads or writes a resource that was neither created nor imported this frame
+    UndeclaredResource {
+        pass: &'static str,
+        resource: ResourceName,
+    },
+}
+
+impl std::fmt::Display for RenderGraphError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Cycle { passes } => write!(
+                f,
+                "Render graph passes {} depend on each other in a cycle",
+                passes.join(", ")
+            ),
+            Self::UndeclaredResource { pass, resource } => write!(
+                f,
+                "Render graph pass {pass} uses the undeclared resource '{resource}'"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RenderGraphError {}
+
+/// How a pass touches a resource. Passes that only write a resource produce it,
+/// passes that read and write it modify it in declaration order,
+/// and passes that only read it run after all of them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Access {
+    Write,
+    ReadWrite,
+    Read,
+}
+
+fn access(reads: &[ResourceName], writes: &[ResourceName], name: ResourceName) -> Option<Access> {
+    match (reads.contains(&name), writes.contains(&name)) {
+        (false, true) => Some(Access::Write),
+        (true, true) => Some(Access::ReadWrite),
+        (true, false) => Some(Access::Read),
+        (false, false) => None,
+    }
+}
+
+struct PassAccess<'a> {
+    name: &'static str,
+    reads: &'a [ResourceName],
+    writes: &'a [ResourceName],
+}
+
+/// Checks every resource the passes touch was created or imported
+fn check_declared(
+    passes: &[PassAccess],
+    declared: &[ResourceName],
+) -> Result<(), RenderGraphError> {
+    for pass in passes {
+        let undeclared = pass
+            .reads
+            .iter()
+            .chain(pass.writes.iter())
+            .find(|name| !declared.contains(name));
+        if let Some(resource) = undeclared {
+            return Err(RenderGraphError::UndeclaredResource {
+                pass: pass.name,
+                resource,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Topologically sorts passes given their reads and writes,
+/// keeping declaration order between passes that do not depend on each other.
+/// Every resource two passes share orders them, so conflicting orders form a cycle.
+fn order_passes(passes: &[PassAccess]) -> Result<Vec<usize>, RenderGraphError> {
+    let mut dependents = vec![Vec::new(); passes.len()];
+    let mut dependency_counts = vec![0usize; passes.len()];
+    for (first_index, first) in passes.iter().enumerate() {
+        for (second_index, second) in passes.iter().enumerate().skip(first_index + 1) {
+            for name in first.reads.iter().chain(first.writes.iter()) {
+                let Some(first_access) = access(first.reads, first.writes, name) else {
+                    continue;
+                };
+                let Some(second_access) = access(second.reads, second.writes, name) else {
+                    continue;
+                };
+                let (before, after) = match (first_access, second_access) {
+                    (Access::Read, Access::Read) => continue,
+                    (first_access, second_access) if first_access <= second_access => {
+                        (first_index, second_index)
+                    }
+                    _ => (second_index, first_index),
+                };
+                if !dependents[before].contains(&after) {
+                    dependents[before].push(after);
+                    dependency_counts[after] += 1;
+                }
+            }
+        }
+    }
+
+    let mut order = Vec::with_capacity(passes.len());
+    let mut scheduled = vec![false; passes.len()];
+    while order.len() < passes.len() {
+        let Some(next) =
+            (0..passes.len()).find(|index| !scheduled[*index] && dependency_counts[*index] == 0)
+        else {
+            let passes = passes
+                .iter()
+                .zip(scheduled)
+                .filter(|(_, scheduled)| !scheduled)
+                .map(|(pass, _)| pass.name)
+                .collect();
+            return Err(RenderGraphError::Cycle { passes });
+        };
+        scheduled[next] = true;
+        order.push(next);
+        for dependent in dependents[next].iter() {
+            dependency_counts[*dependent] -= 1;
+        }
+    }
+    Ok(order)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pass<'a>(
+        name: &'static str,
+        reads: &'a [ResourceName],
+        writes: &'a [ResourceName],
+    ) -> PassAccess<'a> {
+        PassAccess {
+            name,
+            reads,
+            writes,
+        }
+    }
+
+    #[test]
+    fn writers_run_before_readers() {
+        let passes = [
+            pass("Scene", &["shadow_map"], &["surface"]),
+            pass("Shadows", &[], &["shadow_map"]),
+        ];
+        assert_eq!(order_passes(&passes), Ok(vec![1, 0]));
+    }
+
+    #[test]
+    fn read_write_passes_chain_in_declaration_order() {
+        let passes = [
+            pass("Present", &["surface"], &[]),
+            pass("Ui", &["surface"], &["surface"]),
+            pass("Debug Lines", &["surface"], &["surface"]),
+            pass("Scene", &[], &["surface"]),
+        ];
+        assert_eq!(order_passes(&passes), Ok(vec![3, 1, 2, 0]));
+    }
+
+    #[test]
+    fn independent_passes_keep_declaration_order() {
+        let passes = [
+            pass("First", &["a"], &["b"]),
+            pass("Second", &["a"], &["c"]),
+            pass("Third", &[], &["d"]),
+        ];
+        assert_eq!(order_passes(&passes), Ok(vec![0, 1, 2]));
+    }
+
+    #[test]
+    fn every_shared_resource_orders_passes() {
+        // The first shared resource puts Blur first, the second puts Bloom first
+        let passes = [
+            pass("Blur", &["depth"], &["bloom"]),
+            pass("Bloom", &["bloom", "depth"], &["depth"]),
+        ];
+        assert_eq!(
+            order_passes(&passes),
+            Err(RenderGraphError::Cycle {
+                passes: vec!["Blur", "Bloom"],
+            })
+        );
+    }
+
+    #[test]
+    fn undeclared_resources_are_reported() {
+        let passes = [
+            pass("Shadows", &[], &["shadow_map"]),
+            pass("Scene", &["shadow_map", "bloom"], &["surface"]),
+        ];
+        assert_eq!(
+            check_declared(&passes, &["shadow_map", "surface", "bloom"]),
+            Ok(())
+        );
+        assert_eq!(
+            check_declared(&passes, &["shadow_map", "surface"]),
+            Err(RenderGraphError::UndeclaredResource {
+                pass: "Scene",
+                resource: "bloom",
+            })
+        );
+    }
+
+    #[test]
+    fn cycles_name_their_passes() {
+        let passes = [
+            pass("Independent", &[], &["surface"]),
+            pass("First", &["b"], &["a"]),
+            pass("Second", &["c"], &["b"]),
+            pass("Third", &["a"], &["c"]),
+        ];
+        assert_eq!(
+            order_passes(&passes),
+            Err(RenderGraphError::Cycle {
+                passes: vec!["First", "Second", "Third"],
+            })
+        );
+    }
+}
@@ -1,12 +1,61 @@
+use std::sync::Arc;
+
+use crate::{
+    assets::Assets,
+    buffer::UniformBuffer,
+    camera::OrbitCamera,
+    compute::{PendingReadback, Readback},
+    culling::{partition_visible, Bounds, CullStats, Frustum},
+    debug_draw::{DebugDraw, DebugLines},
+    ecs::WorldExtract,
+    environment::Environment,
+    indirect::{IndirectDraws, IndirectMesh, MeshArena, MeshRange},
+    light::Light,
+    lod::{screen_size, select_level, LodMesh, LOD_HYSTERESIS},
+    material::{Image, Material},
+    mesh::{Instance, Mesh, Vertex},
+    overlay::{Grid, OverlaySettings},
+    particles::{ParticleEmitter, ParticleSystem},
+    picking::{PickRequest, Picking},
+    pipeline::{Blend, PipelineBuilder, PipelineCache},
+    render_graph::{RenderGraph, TextureSize, TransientTexture},
+    scene_graph::{GraphExtract, SceneGraph},
+    shader::{
+        PermutationCache, PipelineError, ShaderDefines, ShaderError, ShaderLibrary,
+        ShaderReflection, Sources,
+    },
+    shader_type,
+    shadow::{ShadowCamera, ShadowMaps, ShadowSettings, SHADOW_LAYER_COUNT},
+};
+
 pub struct Renderer {
     gpu: Gpu,
-    depth_texture_view: wgpu::TextureView,
+    graph: RenderGraph,
     egui_renderer: egui_wgpu::Renderer,
     scene: Scene,
+    shadows: ShadowMaps,
+    environment: Environment,
+    particles: ParticleSystem,
+    picking: Picking,
+    debug_lines: DebugLines,
+    grid: Grid,
+    overlays: OverlaySettings,
+    shaders: ShaderLibrary,
+    pipelines: PipelineCache,
+    /// Compute work recorded by the application, submitted ahead of the frame
+    compute_encoder: Option<wgpu::CommandEncoder>,
+    readbacks: Vec<PendingReadback>,
 }
 
 impl Renderer {
-    const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    /// The background of scenes without an environment loaded
+    pub const CLEAR_COLOR: wgpu::Color = wgpu::Color {
+        r: 0.19,
+        g: 0.24,
+        b: 0.42,
+        a: 1.0,
+    };
 
     pub async fn new(
         window: impl Into<wgpu::SurfaceTarget<'static>>,
@@ -14,29 +63,414 @@ impl Renderer {
         height: u32,
     ) -> Self {
         let gpu = Gpu::new_async(window, width, height).await;
-        let depth_texture_view = gpu.create_depth_texture(width, height);
+        let graph = RenderGraph::new(width, height);
 
-        let egui_renderer = egui_wgpu::Renderer::new(
+        let egui_renderer =
+            egui_wgpu::Renderer::new(&gpu.device, gpu.surface_config.format, None, 1, false);
+
+        let mut shaders = ShaderLibrary::new();
+        let pipelines = PipelineCache::new(&gpu.device, &gpu.adapter_info);
+        let [uniform_entries, material_entries, shadow_entries, environment_entries] = shaders
+            .build(&gpu.device, "Scene Reflection", |sources| {
+                Scene::bind_group_layout_entries(sources)
+            });
+        let shadows = ShadowMaps::new(&gpu, &shadow_entries, &mut shaders, &pipelines);
+        let environment = Environment::new(
+            &gpu,
+            gpu.surface_format,
+            Self::DEPTH_FORMAT,
+            &environment_entries,
+            &mut shaders,
+            &pipelines,
+        );
+        let indirect = IndirectDraws::new(&gpu, &mut shaders);
+        let scene = Scene::new(
             &gpu.device,
-            gpu.surface_config.format,
-            Some(Self::DEPTH_FORMAT),
-            1,
-            false,
+            &gpu.queue,
+            &[uniform_entries, material_entries],
+            indirect,
+        );
+        let particles = ParticleSystem::new(
+            &gpu,
+            &mut shaders,
+            &pipelines,
+            &scene.uniform.bind_group_layout,
+        );
+        let picking = Picking::new(&gpu.device, &mut shaders, &pipelines);
+        let grid = Grid::new(&gpu, &mut shaders, &pipelines);
+        let debug_lines = DebugLines::new(
+            &gpu,
+            &mut shaders,
+            &pipelines,
+            &scene.uniform.bind_group_layout,
         );
 
-        let scene = Scene::new(&gpu.device, gpu.surface_format);
-
-        Self {
+        let mut renderer = Self {
             gpu,
-            depth_texture_view,
+            graph,
             egui_renderer,
             scene,
+            shadows,
+            environment,
+            particles,
+            picking,
+            debug_lines,
+            grid,
+            overlays: OverlaySettings::default(),
+            shaders,
+            pipelines,
+            compute_encoder: None,
+            readbacks: Vec::new(),
+        };
+        renderer.prepare_scene_pipeline();
+        renderer
+    }
+
+    /// Uploads a mesh to the gpu and adds it to the scene with a single identity instance,
+    /// drawn with the given material. Returns the index used to refer to the mesh.
+    pub fn add_mesh(&mut self, mesh: &Mesh, material: usize) -> usize {
+        self.scene
+            .add_mesh(&self.gpu.device, &self.gpu.queue, [(mesh, 0.0)], material)
+    }
+
+    /// Adds a mesh whose instances are each drawn with the level of detail matching their size
+    /// on screen. Its index refers to all levels, like the index of a mesh with a single level.
+    /// A mesh without levels is added as an empty mesh.
+    pub fn add_lod_mesh(&mut self, mesh: &LodMesh, material: usize) -> usize {
+        let levels = mesh
+            .levels
+            .iter()
+            .map(|level| (&level.mesh, level.min_screen_size));
+        self.scene
+            .add_mesh(&self.gpu.device, &self.gpu.queue, levels, material)
+    }
+
+    /// Frees the space of a mesh in the mesh arena. Its index may be reused by a mesh added later.
+    pub fn remove_mesh(&mut self, mesh: usize) {
+        self.scene.remove_mesh(mesh);
+    }
+
+    /// Swaps the vertices and indices of a mesh for those of `mesh`,
+    /// keeping its index, material and instances
+    pub fn replace_mesh(&mut self, index: usize, mesh: &Mesh) {
+        self.scene
+            .replace_mesh(&self.gpu.device, &self.gpu.queue, index, mesh);
+    }
+
+    /// The instances a mesh is drawn with, uploaded every frame.
+    /// Returns `None` if the mesh was removed.
+    pub fn instances_mut(&mut self, mesh: usize) -> Option<&mut Vec<Instance>> {
+        self.scene
+            .meshes
+            .get_mut(mesh)
+            .map(|mesh| &mut mesh.instances)
+    }
+
+    /// Uploads a material's textures and factors, returning its index.
+    /// Index zero is reserved for the default material.
+    pub fn add_material(&mut self, material: &Material) -> usize {
+        self.scene.materials.insert(GpuMaterial::new(
+            &self.gpu.device,
+            &self.gpu.queue,
+            &self.scene.material_bind_group_layout,
+            &self.scene.sampler,
+            material,
+        ))
+    }
+
+    /// Uploads the textures and factors of `material` in place of those at `index`,
+    /// so the meshes using it pick up the change
+    pub fn replace_material(&mut self, index: usize, material: &Material) {
+        if let Some(gpu_material) = self.scene.materials.get_mut(index) {
+            *gpu_material = GpuMaterial::new(
+                &self.gpu.device,
+                &self.gpu.queue,
+                &self.scene.material_bind_group_layout,
+                &self.scene.sampler,
+                material,
+            );
+        }
+    }
+
+    /// Frees the textures of a material. Meshes still using it fall back to the default material.
+    pub fn remove_material(&mut self, material: usize) {
+        if material != 0 {
+            self.scene.materials.remove(material);
+        }
+    }
+
+    /// Adds a particle emitter, returning the index used to refer to it
+    pub fn add_emitter(&mut self, emitter: ParticleEmitter) -> usize {
+        self.particles.add_emitter(emitter)
+    }
+
+    pub fn emitter_mut(&mut self, index: usize) -> Option<&mut ParticleEmitter> {
+        self.particles.emitter_mut(index)
+    }
+
+    pub fn remove_emitter(&mut self, index: usize) -> Option<ParticleEmitter> {
+        self.particles.remove_emitter(index)
+    }
+
+    /// The camera the scene is rendered from
+    pub fn camera_mut(&mut self) -> &mut OrbitCamera {
+        &mut self.scene.camera
+    }
+
+    pub fn overlay_settings(&self) -> &OverlaySettings {
+        &self.overlays
+    }
+
+    pub fn overlay_settings_mut(&mut self) -> &mut OverlaySettings {
+        &mut self.overlays
+    }
+
+    pub fn debug_draw(&mut self) -> &mut DebugDraw {
+        &mut self.debug_lines.draw
+    }
+
+    pub fn device(&self) -> &wgpu::Device {
+        &self.gpu.device
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.gpu.queue
+    }
+
+    /// An encoder for compute work that runs this frame, before the scene is rendered
+    pub fn compute_encoder(&mut self) -> &mut wgpu::CommandEncoder {
+        let device = &self.gpu.device;
+        self.compute_encoder
+            .get_or_insert_with(|| Self::create_compute_encoder(device))
+    }
+
+    fn create_compute_encoder(device: &wgpu::Device) -> wgpu::CommandEncoder {
+        device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Compute Encoder"),
+        })
+    }
+
+    /// Copies a buffer back to the cpu after this frame's compute work ran.
+    /// The buffer needs `COPY_SRC` usage.
+    pub fn read_buffer<T: bytemuck::Pod>(&mut self, buffer: &wgpu::Buffer) -> Readback<T> {
+        let device = &self.gpu.device;
+        let encoder = self
+            .compute_encoder
+            .get_or_insert_with(|| Self::create_compute_encoder(device));
+        let (readback, pending) = Readback::new(device, encoder, buffer);
+        self.readbacks.push(pending);
+        readback
+    }
+
+    /// Updates the factors of an uploaded material. Textures are kept as uploaded.
+    pub fn update_material(&mut self, index: usize, material: &Material) {
+        if let Some(gpu_material) = self.scene.materials.get(index) {
+            gpu_material.update_factors(&self.gpu.queue, material);
         }
     }
 
+    /// How many instances the last frame drew and culled against the view frustum.
+    /// When culling on the gpu, the counts are read back and lag a few frames behind.
+    pub fn cull_stats(&self) -> CullStats {
+        self.scene.cull_stats
+    }
+
+    /// Culls instances in a compute pass and draws them with indirect draws, where the
+    /// adapter supports it, instead of culling them on the cpu. Meant for large scenes.
+    pub fn set_gpu_driven(&mut self, enabled: bool) {
+        self.scene.gpu_driven = enabled;
+    }
+
+    /// Whether instances are culled and drawn by the gpu, which needs adapter support
+    pub fn gpu_driven(&self) -> bool {
+        self.scene.indirect().is_some()
+    }
+
+    /// Reads back what is drawn at the pixel `x`, `y` of the next frame, counted from the top left
+    pub fn pick(&mut self, x: u32, y: u32) -> PickRequest {
+        self.picking.pick(x, y)
+    }
+
+    pub fn lights_mut(&mut self) -> &mut Vec<Light> {
+        &mut self.scene.lights
+    }
+
+    /// Gathers the meshes, lights and camera of the entity world for the next frame
+    pub fn extract_world(&mut self, world: &hecs::World, assets: &Assets) {
+        self.scene.world.extract(world, assets);
+    }
+
+    /// The node hierarchy that meshes, lights and the camera attach to
+    pub fn scene_graph(&self) -> &SceneGraph {
+        &self.scene.graph
+    }
+
+    pub fn scene_graph_mut(&mut self) -> &mut SceneGraph {
+        &mut self.scene.graph
+    }
+
+    pub fn shadow_settings_mut(&mut self) -> &mut ShadowSettings {
+        &mut self.shadows.settings
+    }
+
+    /// Bakes image based lighting from a radiance `.hdr` file and shows it as the skybox
+    pub fn load_environment(&mut self, hdr_bytes: &[u8]) -> Result<(), image::ImageError> {
+        self.environment
+            .load(&self.gpu, hdr_bytes, &mut self.shaders, &self.pipelines)
+    }
+
+    pub fn clear_environment(&mut self) {
+        self.environment.clear(&self.gpu);
+    }
+
+    pub fn set_environment_intensity(&mut self, intensity: f32) {
+        self.environment.intensity = intensity;
+    }
+
+    /// Pipelines whose shaders failed to compile, keeping their previous version
+    pub fn shader_errors(&self) -> &[ShaderError] {
+        self.shaders.errors()
+    }
+
+    /// Rebuilds the pipelines of every shader file that changed on disk
+    fn reload_shaders(&mut self) {
+        let changed = self.shaders.poll_changes();
+        if changed.is_empty() {
+            return;
+        }
+        let device = &self.gpu.device;
+
+        // Only the permutation in use is rebuilt right away, the others are rebuilt when next used
+        if changed.iter().any(|name| Scene::SHADERS.contains(name)) {
+            let layouts = [
+                &self.scene.uniform.bind_group_layout,
+                &self.scene.material_bind_group_layout,
+                &self.shadows.sampling_bind_group_layout,
+                &self.environment.bind_group_layout,
+            ];
+            let defines = &self.scene.permutation;
+            if let Some(pipeline) = self.shaders.rebuild(device, "Scene", |sources| {
+                Scene::create_pipeline(
+                    device,
+                    self.gpu.surface_format,
+                    &layouts,
+                    sources,
+                    defines,
+                    &self.pipelines,
+                )
+            }) {
+                self.scene.pipelines.clear();
+                self.scene.pipelines.insert(defines.clone(), pipeline);
+            }
+        }
+
+        if changed
+            .iter()
+            .any(|name| ShadowMaps::SHADERS.contains(name))
+        {
+            if let Some(pipeline) = self.shaders.rebuild(device, "Shadow", |sources| {
+                ShadowMaps::create_pipeline(
+                    device,
+                    &self.shadows.pass_bind_group_layout,
+                    sources,
+                    &self.pipelines,
+                )
+            }) {
+                self.shadows.pipeline = pipeline;
+            }
+        }
+
+        if changed.iter().any(|name| Picking::SHADERS.contains(name)) {
+            if let Some(pipeline) = self.shaders.rebuild(device, "Picking", |sources| {
+                Picking::create_pipeline(
+                    device,
+                    &self.picking.bind_group_layout,
+                    sources,
+                    &self.pipelines,
+                )
+            }) {
+                self.picking.pipeline = pipeline;
+            }
+        }
+
+        if changed.contains(&Environment::SKYBOX_SHADER) {
+            if let Some(pipeline) = self.shaders.rebuild(device, "Skybox", |sources| {
+                self.environment
+                    .create_skybox_pipeline(device, sources, &self.pipelines)
+            }) {
+                self.environment.set_skybox_pipeline(device, pipeline);
+            }
+        }
+
+        if changed.iter().any(|name| Grid::SHADERS.contains(name)) {
+            if let Some(pipeline) = self.shaders.rebuild(device, "Grid", |sources| {
+                Grid::create_pipeline(
+                    device,
+                    self.gpu.surface_format,
+                    &self.grid.bind_group_layout,
+                    sources,
+                    &self.pipelines,
+                )
+            }) {
+                self.grid.pipeline = pipeline;
+            }
+        }
+
+        if let Some(indirect) = self.scene.indirect.as_mut() {
+            indirect.reload_shaders(&changed, device, &mut self.shaders);
+        }
+
+        self.particles.reload_shaders(
+            &changed,
+            device,
+            self.gpu.surface_format,
+            &mut self.shaders,
+            &self.pipelines,
+            &self.scene.uniform.bind_group_layout,
+        );
+        self.debug_lines.reload_shaders(
+            &changed,
+            device,
+            self.gpu.surface_format,
+            &mut self.shaders,
+            &self.pipelines,
+            &self.scene.uniform.bind_group_layout,
+        );
+
+        self.pipelines.prune();
+    }
+
+    /// Selects the scene shader permutation matching the enabled features,
+    /// compiling it the first time it is used
+    fn prepare_scene_pipeline(&mut self) {
+        let defines =
+            Scene::shader_defines(self.shadows.settings.enabled, self.environment.is_loaded());
+        if !self.scene.pipelines.contains(&defines) {
+            let device = &self.gpu.device;
+            let layouts = [
+                &self.scene.uniform.bind_group_layout,
+                &self.scene.material_bind_group_layout,
+                &self.shadows.sampling_bind_group_layout,
+                &self.environment.bind_group_layout,
+            ];
+            let pipeline = self.shaders.build(device, "Scene", |sources| {
+                Scene::create_pipeline(
+                    device,
+                    self.gpu.surface_format,
+                    &layouts,
+                    sources,
+                    &defines,
+                    &self.pipelines,
+                )
+            });
+            self.scene.pipelines.insert(defines.clone(), pipeline);
+        }
+        self.scene.permutation = defines;
+    }
+
     pub fn resize(&mut self, width: u32, height: u32) {
         self.gpu.resize(width, height);
-        self.depth_texture_view = self.gpu.create_depth_texture(width, height);
+        self.graph.resize(width, height);
     }
 
     pub fn render_frame(
@@ -48,8 +482,45 @@ impl Renderer {
     ) {
         let delta_time = delta_time.as_secs_f32();
 
+        // Completes the readbacks of earlier frames
+        #[cfg(not(target_arch = "wasm32"))]
+        self.gpu.device.poll(wgpu::Maintain::Poll);
+
+        self.reload_shaders();
+        self.prepare_scene_pipeline();
+
         self.scene
-            .update(&self.gpu.queue, self.gpu.aspect_ratio(), delta_time);
+            .update(&self.gpu.device, &self.gpu.queue, self.gpu.aspect_ratio());
+        self.picking.prepare(
+            &self.gpu,
+            &(self.scene.projection * self.scene.view),
+            &self.scene.meshes,
+            &self.scene.world,
+            &self.scene.graph_extract,
+        );
+        self.environment
+            .update(&self.gpu.queue, &self.scene.view, &self.scene.projection);
+        self.grid.update(
+            &self.gpu.queue,
+            &self.overlays.grid,
+            &self.scene.camera_position,
+            &(self.scene.projection * self.scene.view),
+        );
+        self.particles
+            .update(&self.gpu.device, &self.gpu.queue, delta_time);
+        self.debug_lines
+            .update(&self.gpu.device, &self.gpu.queue, delta_time);
+        self.shadows.update(
+            &self.gpu,
+            &self.scene.frame_lights,
+            &self.scene.shadow_camera(),
+        );
+        self.scene.uniform.update_lights(
+            &self.gpu.queue,
+            &self.scene.frame_lights,
+            &self.scene.ambient_color,
+            &self.shadows.light_layers,
+        );
 
         for (id, image_delta) in &textures_delta.set {
             self.egui_renderer
@@ -95,30 +566,92 @@ impl Renderer {
                     array_layer_count: None,
                 });
 
-        encoder.insert_debug_marker("Render scene");
+        let compute_commands = self
+            .compute_encoder
+            .take()
+            .map(wgpu::CommandEncoder::finish);
 
-        // This scope around the crate::render_pass prevents the
-        // crate::render_pass from holding a borrow to the encoder,
-        // which would prevent calling `.finish()` in
-        // preparation for queue submission.
-        {
+        let Self {
+            gpu,
+            graph,
+            egui_renderer,
+            scene,
+            shadows,
+            environment,
+            particles,
+            picking,
+            debug_lines,
+            grid,
+            overlays,
+            readbacks,
+            ..
+        } = self;
+
+        let mut frame = graph.frame(&gpu.device);
+        frame.import_texture("surface", &surface_texture_view);
+        frame.import_texture("shadow_map", &shadows.view);
+        frame.create_texture(
+            "depth",
+            TransientTexture::new(Self::DEPTH_FORMAT, TextureSize::Surface),
+        );
+
+        frame.import_buffer("particles", particles.particle_buffer());
+
+        frame
+            .add_pass("Particle Simulation")
+            .write("particles")
+            .execute(|encoder, _| particles.simulate(encoder));
+
+        frame.import_buffer("instances", &scene.instance_buffer.buffer);
+        let indirect = scene.indirect();
+        if let Some(indirect) = indirect {
+            frame.import_buffer("indirect_draws", indirect.draw_buffer());
+            frame.import_buffer("visible_instances", indirect.visible_instance_buffer());
+            frame
+                .add_pass("Culling")
+                .read("instances")
+                .write("indirect_draws")
+                .write("visible_instances")
+                .execute(|encoder, _| indirect.cull(encoder));
+        }
+
+        // Shadows draw every instance, including those the culling pass skips
+        frame
+            .add_pass("Shadows")
+            .read("instances")
+            .write("shadow_map")
+            .execute(|encoder, _| {
+                shadows.render(encoder, &scene.meshes, &scene.arena, &scene.instance_buffer)
+            });
+
+        let mut scene_pass = frame
+            .add_pass("Scene")
+            .read("shadow_map")
+            .read("particles")
+            .write("surface")
+            .write("depth");
+        scene_pass = match indirect {
+            Some(_) => scene_pass.read("indirect_draws").read("visible_instances"),
+            None => scene_pass.read("instances"),
+        };
+        scene_pass.execute(|encoder, resources| {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
+                label: Some("Scene Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &surface_texture_view,
+                    view: resources.texture("surface"),
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.19,
-                            g: 0.24,
-                            b: 0.42,
-                            a: 1.0,
+                        // A loaded environment's skybox covers every pixel
+                        load: wgpu::LoadOp::Clear(if environment.is_loaded() {
+                            wgpu::Color::BLACK
+                        } else {
+                            Self::CLEAR_COLOR
                         }),
                         store: wgpu::StoreOp::Store,
                     },
                 })],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture_view,
+                    view: resources.texture("depth"),
                     depth_ops: Some(wgpu::Operations {
                         load: wgpu::LoadOp::Clear(1.0),
                         store: wgpu::StoreOp::Store,
@@ -128,16 +661,146 @@ impl Renderer {
                 timestamp_writes: None,
                 occlusion_query_set: None,
             });
-            self.scene.render(&mut render_pass);
-            self.egui_renderer.render(
-                &mut render_pass.forget_lifetime(),
-                &paint_jobs,
-                &screen_descriptor,
+            environment.render_skybox(&mut render_pass);
+            scene.render(
+                &mut render_pass,
+                &shadows.sampling_bind_group,
+                &environment.bind_group,
             );
+            if overlays.grid.enabled {
+                grid.render(&mut render_pass);
+            }
+            particles.render(&mut render_pass, &scene.uniform.bind_group);
+        });
+
+        // Only runs on frames with picks queued
+        if picking.is_active() {
+            Picking::create_textures(&mut frame);
+            frame
+                .add_pass("Picking")
+                .write(Picking::ID_TEXTURE)
+                .write(Picking::DEPTH_BITS_TEXTURE)
+                .write(Picking::DEPTH_TEXTURE)
+                .execute(|encoder, resources| {
+                    picking.render(encoder, resources, &scene.arena);
+                    readbacks.extend(picking.read(&gpu.device, encoder, resources));
+                });
         }
 
-        self.gpu.queue.submit(std::iter::once(encoder.finish()));
+        frame
+            .add_pass("Debug Lines")
+            .read("depth")
+            .read("surface")
+            .write("surface")
+            .execute(|encoder, resources| {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Debug Line Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: resources.texture("surface"),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: resources.texture("depth"),
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: wgpu::StoreOp::Store,
+                        }),
+                        stencil_ops: None,
+                    }),
+                    timestamp_writes: None,
+                    occlusion_query_set: None,
+                });
+                debug_lines.render(&mut render_pass, &scene.uniform.bind_group);
+            });
+
+        frame
+            .add_pass("Ui")
+            .read("surface")
+            .write("surface")
+            .execute(|encoder, resources| {
+                let render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Ui Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: resources.texture("surface"),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                    timestamp_writes: None,
+                    occlusion_query_set: None,
+                });
+                egui_renderer.render(
+                    &mut render_pass.forget_lifetime(),
+                    &paint_jobs,
+                    &screen_descriptor,
+                );
+            });
+
+        if let Err(error) = frame.execute(&mut encoder) {
+            log::error!("{error}");
+        }
+
+        if let Some(indirect) = scene.indirect_mut() {
+            readbacks.extend(indirect.read_stats(&gpu.device, &mut encoder));
+        }
+
+        gpu.queue.submit(
+            compute_commands
+                .into_iter()
+                .chain(std::iter::once(encoder.finish())),
+        );
         surface_texture.present();
+
+        for readback in self.readbacks.drain(..) {
+            readback.map();
+        }
+
+        self.pipelines.persist();
+    }
+}
+
+impl crate::assets::RenderResources for Renderer {
+    fn device(&self) -> &wgpu::Device {
+        &self.gpu.device
+    }
+
+    fn queue(&self) -> &wgpu::Queue {
+        &self.gpu.queue
+    }
+
+    fn add_mesh(&mut self, mesh: &Mesh, material: usize) -> usize {
+        Renderer::add_mesh(self, mesh, material)
+    }
+
+    fn replace_mesh(&mut self, index: usize, mesh: &Mesh) {
+        Renderer::replace_mesh(self, index, mesh);
+    }
+
+    fn remove_mesh(&mut self, mesh: usize) {
+        Renderer::remove_mesh(self, mesh);
+    }
+
+    fn instances_mut(&mut self, mesh: usize) -> Option<&mut Vec<Instance>> {
+        Renderer::instances_mut(self, mesh)
+    }
+
+    fn add_material(&mut self, material: &Material) -> usize {
+        Renderer::add_material(self, material)
+    }
+
+    fn replace_material(&mut self, index: usize, material: &Material) {
+        Renderer::replace_material(self, index, material);
+    }
+
+    fn remove_material(&mut self, material: usize) {
+        Renderer::remove_material(self, material);
     }
 }
 
@@ -147,6 +810,8 @@ pub struct Gpu {
     pub queue: wgpu::Queue,
     pub surface_config: wgpu::SurfaceConfiguration,
     pub surface_format: wgpu::TextureFormat,
+    pub adapter_info: wgpu::AdapterInfo,
+    pub downlevel_flags: wgpu::DownlevelFlags,
 }
 
 impl Gpu {
@@ -160,14 +825,20 @@ impl Gpu {
         self.surface.configure(&self.device, &self.surface_config);
     }
 
-    pub fn create_depth_texture(&self, width: u32, height: u32) -> wgpu::TextureView {
-        let texture = self.device.create_texture(
+    /// Creates a depth texture with `layers` array layers that can also be sampled
+    pub fn create_depth_texture_array(
+        &self,
+        width: u32,
+        height: u32,
+        layers: u32,
+    ) -> wgpu::Texture {
+        self.device.create_texture(
             &(wgpu::TextureDescriptor {
                 label: Some("Depth Texture"),
                 size: wgpu::Extent3d {
                     width,
                     height,
-                    depth_or_array_layers: 1,
+                    depth_or_array_layers: layers,
                 },
                 mip_level_count: 1,
                 sample_count: 1,
@@ -177,17 +848,7 @@ impl Gpu {
                     | wgpu::TextureUsages::TEXTURE_BINDING,
                 view_formats: &[],
             }),
-        );
-        texture.create_view(&wgpu::TextureViewDescriptor {
-            label: None,
-            format: Some(wgpu::TextureFormat::Depth32Float),
-            dimension: Some(wgpu::TextureViewDimension::D2),
-            aspect: wgpu::TextureAspect::All,
-            base_mip_level: 0,
-            base_array_layer: 0,
-            array_layer_count: None,
-            mip_level_count: None,
-        })
+        )
     }
 
     pub async fn new_async(
@@ -217,8 +878,13 @@ impl Gpu {
                     &wgpu::DeviceDescriptor {
                         label: Some("WGPU Device"),
 
+                        // Pipeline caches are persisted between runs where the backend supports it,
+                        // and gpu driven drawing batches draws where multi draws are supported
                         #[cfg(not(target_arch = "wasm32"))]
-                        required_features: wgpu::Features::default(),
+                        required_features: adapter.features()
+                            & (wgpu::Features::PIPELINE_CACHE
+                                | wgpu::Features::MULTI_DRAW_INDIRECT
+                                | wgpu::Features::INDIRECT_FIRST_INSTANCE),
 
                         #[cfg(all(target_arch = "wasm32", feature = "webgpu"))]
                         required_features: wgpu::Features::all_webgpu_mask(),
@@ -275,273 +941,960 @@ impl Gpu {
             queue,
             surface_config,
             surface_format,
+            adapter_info: adapter.get_info(),
+            downlevel_flags: adapter.get_downlevel_capabilities().flags,
+        }
+    }
+}
+
+/// Grows to fit the largest instance count it has been given
+pub struct InstanceBuffer {
+    pub buffer: wgpu::Buffer,
+    pub capacity: usize,
+    pub count: u32,
+    /// Added to `VERTEX | COPY_DST`, e.g. `STORAGE` for culling on the gpu
+    usage: wgpu::BufferUsages,
+}
+
+impl InstanceBuffer {
+    pub fn new(device: &wgpu::Device, capacity: usize, usage: wgpu::BufferUsages) -> Self {
+        Self {
+            buffer: Self::create_buffer(device, capacity, usage),
+            capacity,
+            count: 0,
+            usage,
+        }
+    }
+
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, instances: &[Instance]) {
+        if instances.len() > self.capacity {
+            self.capacity = instances.len().next_power_of_two();
+            self.buffer = Self::create_buffer(device, self.capacity, self.usage);
+        }
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(instances));
+        self.count = instances.len() as u32;
+    }
+
+    fn create_buffer(
+        device: &wgpu::Device,
+        capacity: usize,
+        usage: wgpu::BufferUsages,
+    ) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Buffer"),
+            size: (capacity.max(1) * std::mem::size_of::<Instance>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST | usage,
+            mapped_at_creation: false,
+        })
+    }
+}
+
+/// Storage whose indices stay valid when other items are removed.
+/// Indices of removed items are reused by the next insertions.
+pub struct Slots<T> {
+    items: Vec<Option<T>>,
+    free: Vec<usize>,
+}
+
+impl<T> Default for Slots<T> {
+    fn default() -> Self {
+        Self {
+            items: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+}
+
+impl<T> From<Vec<T>> for Slots<T> {
+    fn from(items: Vec<T>) -> Self {
+        Self {
+            items: items.into_iter().map(Some).collect(),
+            free: Vec::new(),
+        }
+    }
+}
+
+impl<T> Slots<T> {
+    pub fn insert(&mut self, item: T) -> usize {
+        match self.free.pop() {
+            Some(index) => {
+                self.items[index] = Some(item);
+                index
+            }
+            None => {
+                self.items.push(Some(item));
+                self.items.len() - 1
+            }
+        }
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<T> {
+        let item = self.items.get_mut(index)?.take()?;
+        self.free.push(index);
+        Some(item)
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.items.get(index)?.as_ref()
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        self.items.get_mut(index)?.as_mut()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.items
+            .iter()
+            .enumerate()
+            .filter_map(|(index, item)| Some((index, item.as_ref()?)))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (usize, &mut T)> {
+        self.items
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, item)| Some((index, item.as_mut()?)))
+    }
+}
+
+/// A level of detail of a scene mesh, along with the instances drawn with it this frame
+pub struct SceneLevel {
+    /// Where the vertices and indices are in the scene's mesh arena
+    pub range: MeshRange,
+    pub min_screen_size: f32,
+    /// Where this frame's instances are in the scene's instance buffer. When culling on the cpu,
+    /// those inside the view frustum come first, followed by the culled ones that may still cast shadows.
+    pub first_instance: u32,
+    pub instance_count: u32,
+    pub visible_count: u32,
+}
+
+impl SceneLevel {
+    pub fn new(range: MeshRange, min_screen_size: f32) -> Self {
+        Self {
+            range,
+            min_screen_size,
+            first_instance: 0,
+            instance_count: 0,
+            visible_count: 0,
+        }
+    }
+
+    /// The instances uploaded this frame
+    pub fn instance_range(&self) -> std::ops::Range<u32> {
+        self.first_instance..self.first_instance + self.instance_count
+    }
+}
+
+pub struct SceneMesh {
+    /// The levels of detail, the most detailed first. Most meshes only have one.
+    pub levels: Vec<SceneLevel>,
+    /// Computed from the most detailed level when the mesh is added,
+    /// to cull its instances and select their levels
+    pub bounds: Bounds,
+    pub material: usize,
+    pub instances: Vec<Instance>,
+    /// The level each instance was drawn with last frame, by its position among the mesh's instances
+    instance_levels: Vec<usize>,
+}
+
+impl SceneMesh {
+    pub fn new(levels: Vec<SceneLevel>, bounds: Bounds, material: usize) -> Self {
+        Self {
+            levels,
+            bounds,
+            material,
+            instances: vec![Instance::default()],
+            instance_levels: Vec::new(),
+        }
+    }
+
+    /// Sorts this frame's instances of the mesh by the level they are drawn with, selected by
+    /// their size on screen, and records where the instances of each level are
+    fn assign_levels(
+        &mut self,
+        instances: &mut [Instance],
+        first_instance: u32,
+        camera_position: &nalgebra_glm::Vec3,
+        fov_y: f32,
+        scratch: &mut Vec<Instance>,
+    ) {
+        if let [level] = self.levels.as_mut_slice() {
+            level.first_instance = first_instance;
+            level.instance_count = instances.len() as u32;
+            return;
+        }
+
+        let min_screen_sizes = self
+            .levels
+            .iter()
+            .map(|level| level.min_screen_size)
+            .collect::<Vec<_>>();
+        let previous = std::mem::take(&mut self.instance_levels);
+        self.instance_levels = instances
+            .iter()
+            .enumerate()
+            .map(|(index, instance)| {
+                let sphere = self.bounds.sphere.transformed(&instance.transform);
+                select_level(
+                    &min_screen_sizes,
+                    screen_size(&sphere, camera_position, fov_y),
+                    previous.get(index).copied(),
+                    LOD_HYSTERESIS,
+                )
+            })
+            .collect();
+
+        let mut next = Vec::with_capacity(self.levels.len());
+        let mut start = first_instance;
+        for (index, level) in self.levels.iter_mut().enumerate() {
+            let count = self.instance_levels.iter().filter(|l| **l == index).count();
+            level.first_instance = start;
+            level.instance_count = count as u32;
+            next.push((start - first_instance) as usize);
+            start += count as u32;
+        }
+        scratch.clear();
+        scratch.extend_from_slice(instances);
+        for (instance, level) in scratch.iter().zip(&self.instance_levels) {
+            instances[next[*level]] = *instance;
+            next[*level] += 1;
         }
     }
 }
 
 struct Scene {
-    pub model: nalgebra_glm::Mat4,
-    pub vertex_buffer: wgpu::Buffer,
-    pub index_buffer: wgpu::Buffer,
+    pub graph: SceneGraph,
+    /// The nodes drawing each mesh this frame
+    pub graph_extract: GraphExtract,
+    /// The entities rendered this frame
+    pub world: WorldExtract,
+    pub meshes: Slots<SceneMesh>,
+    pub materials: Slots<GpuMaterial>,
+    pub lights: Vec<Light>,
+    /// The lights added directly, followed by those of entities and nodes in world space
+    pub frame_lights: Vec<Light>,
+    /// Holds the vertices and indices of every mesh
+    pub arena: MeshArena,
+    /// Gathers the instances of every mesh before they are uploaded
+    frame_instances: Vec<Instance>,
+    /// Holds the instances of a mesh while they are sorted by level of detail
+    level_instances: Vec<Instance>,
+    /// The meshes sorted by material, which is the order their instances are uploaded in
+    frame_meshes: Vec<usize>,
+    pub instance_buffer: InstanceBuffer,
+    /// Culls and draws on the gpu, when the adapter supports it
+    pub indirect: Option<IndirectDraws>,
+    pub gpu_driven: bool,
+    pub cull_stats: CullStats,
+    pub ambient_color: nalgebra_glm::Vec3,
+    pub view: nalgebra_glm::Mat4,
+    pub projection: nalgebra_glm::Mat4,
+    pub aspect_ratio: f32,
+    pub camera: OrbitCamera,
+    /// Where the scene was last rendered from, which may be a node rather than the orbit camera
+    pub camera_position: nalgebra_glm::Vec3,
     pub uniform: UniformBinding,
-    pub pipeline: wgpu::RenderPipeline,
+    pub material_bind_group_layout: wgpu::BindGroupLayout,
+    pub sampler: wgpu::Sampler,
+    pub pipelines: PermutationCache<Arc<wgpu::RenderPipeline>>,
+    /// The define set of the pipeline the scene is drawn with
+    pub permutation: ShaderDefines,
 }
 
 impl Scene {
-    pub fn new(device: &wgpu::Device, surface_format: wgpu::TextureFormat) -> Self {
-        let vertex_buffer = wgpu::util::DeviceExt::create_buffer_init(
+    const FOV_Y: f32 = 80.0;
+    const Z_NEAR: f32 = 0.1;
+    const Z_FAR: f32 = 1000.0;
+    /// The scene shader along with every file it includes
+    const SHADERS: [&str; 5] = [
+        "scene.wgsl",
+        "scene_uniform.wgsl",
+        "instancing.wgsl",
+        "shadow_sampling.wgsl",
+        "environment_sampling.wgsl",
+    ];
+
+    /// Takes the uniform and material bind group layouts reflected from the scene shader
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout_entries: &[Vec<wgpu::BindGroupLayoutEntry>; 2],
+        indirect: Option<IndirectDraws>,
+    ) -> Self {
+        let triangle = Mesh {
+            vertices: VERTICES.to_vec(),
+            indices: INDICES.to_vec(),
+        };
+        let uniform = UniformBinding::new(device, &layout_entries[0]);
+        let material_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &layout_entries[1],
+                label: Some("material_bind_group_layout"),
+            });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Material Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let default_material = GpuMaterial::new(
             device,
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Vertex Buffer"),
-                contents: bytemuck::cast_slice(&VERTICES),
-                usage: wgpu::BufferUsages::VERTEX,
-            },
+            queue,
+            &material_bind_group_layout,
+            &sampler,
+            &Material::default(),
         );
-        let index_buffer = wgpu::util::DeviceExt::create_buffer_init(
-            device,
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("index Buffer"),
-                contents: bytemuck::cast_slice(&INDICES),
-                usage: wgpu::BufferUsages::INDEX,
-            },
+        let mut arena = MeshArena::new(device);
+        let triangle = SceneMesh::new(
+            vec![SceneLevel::new(arena.insert(device, queue, &triangle), 0.0)],
+            triangle.bounds(),
+            0,
         );
-        let uniform = UniformBinding::new(device);
-        let pipeline = Self::create_pipeline(device, surface_format, &uniform);
+        // The culling pass reads the instances as storage
+        let instance_usage = match indirect {
+            Some(_) => wgpu::BufferUsages::STORAGE,
+            None => wgpu::BufferUsages::empty(),
+        };
         Self {
-            model: nalgebra_glm::Mat4::identity(),
+            graph: SceneGraph::new(),
+            graph_extract: GraphExtract::default(),
+            world: WorldExtract::default(),
+            meshes: Slots::from(vec![triangle]),
+            materials: Slots::from(vec![default_material]),
+            lights: vec![Light::default()],
+            frame_lights: Vec::new(),
+            arena,
+            frame_instances: Vec::new(),
+            level_instances: Vec::new(),
+            frame_meshes: Vec::new(),
+            instance_buffer: InstanceBuffer::new(device, 1, instance_usage),
+            indirect,
+            gpu_driven: false,
+            cull_stats: CullStats::default(),
+            ambient_color: nalgebra_glm::vec3(0.03, 0.03, 0.03),
+            view: nalgebra_glm::Mat4::identity(),
+            projection: nalgebra_glm::Mat4::identity(),
+            aspect_ratio: 1.0,
+            camera: OrbitCamera::default(),
+            camera_position: nalgebra_glm::Vec3::zeros(),
             uniform,
-            pipeline,
-            vertex_buffer,
-            index_buffer,
+            material_bind_group_layout,
+            sampler,
+            pipelines: PermutationCache::default(),
+            permutation: ShaderDefines::new(),
         }
     }
 
-    pub fn render<'rpass>(&'rpass self, renderpass: &mut wgpu::RenderPass<'rpass>) {
-        renderpass.set_pipeline(&self.pipeline);
+    pub fn render<'rpass>(
+        &'rpass self,
+        renderpass: &mut wgpu::RenderPass<'rpass>,
+        shadow_bind_group: &'rpass wgpu::BindGroup,
+        environment_bind_group: &'rpass wgpu::BindGroup,
+    ) {
+        let Some(pipeline) = self.pipelines.get(&self.permutation) else {
+            return;
+        };
+        renderpass.set_pipeline(pipeline);
         renderpass.set_bind_group(0, &self.uniform.bind_group, &[]);
+        renderpass.set_bind_group(2, shadow_bind_group, &[]);
+        renderpass.set_bind_group(3, environment_bind_group, &[]);
+        renderpass.set_vertex_buffer(0, self.arena.vertex_buffer().slice(..));
+        renderpass.set_index_buffer(
+            self.arena.index_buffer().slice(..),
+            wgpu::IndexFormat::Uint32,
+        );
 
-        renderpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-        renderpass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        if let Some(indirect) = self.indirect() {
+            for batch in indirect.batches() {
+                renderpass.set_bind_group(1, &self.material(batch.material).bind_group, &[]);
+                indirect.draw(renderpass, batch);
+            }
+            return;
+        }
 
-        renderpass.draw_indexed(0..(INDICES.len() as _), 0, 0..1);
+        renderpass.set_vertex_buffer(1, self.instance_buffer.buffer.slice(..));
+        for (_, mesh) in self.meshes.iter() {
+            if mesh.levels.iter().all(|level| level.visible_count == 0) {
+                continue;
+            }
+            renderpass.set_bind_group(1, &self.material(mesh.material).bind_group, &[]);
+            for level in mesh.levels.iter().filter(|level| level.visible_count > 0) {
+                renderpass.draw_indexed(
+                    level.range.indices.clone(),
+                    level.range.base_vertex(),
+                    level.first_instance..level.first_instance + level.visible_count,
+                );
+            }
+        }
     }
 
-    pub fn update(&mut self, queue: &wgpu::Queue, aspect_ratio: f32, delta_time: f32) {
-        let projection =
-            nalgebra_glm::perspective_lh_zo(aspect_ratio, 80_f32.to_radians(), 0.1, 1000.0);
-        let view = nalgebra_glm::look_at_lh(
-            &nalgebra_glm::vec3(0.0, 0.0, 3.0),
-            &nalgebra_glm::vec3(0.0, 0.0, 0.0),
-            &nalgebra_glm::Vec3::y(),
-        );
-        self.model = nalgebra_glm::rotate(
-            &self.model,
-            30_f32.to_radians() * delta_time,
-            &nalgebra_glm::Vec3::y(),
+    /// The gpu culling in use, if enabled and supported
+    pub fn indirect(&self) -> Option<&IndirectDraws> {
+        self.indirect.as_ref().filter(|_| self.gpu_driven)
+    }
+
+    pub fn indirect_mut(&mut self) -> Option<&mut IndirectDraws> {
+        self.indirect.as_mut().filter(|_| self.gpu_driven)
+    }
+
+    fn material(&self, index: usize) -> &GpuMaterial {
+        self.materials
+            .get(index)
+            .or_else(|| self.materials.get(0))
+            .expect("The default material is never removed")
+    }
+
+    /// Takes each level of detail along with its minimum screen size, the most detailed first
+    pub fn add_mesh<'a>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        levels: impl IntoIterator<Item = (&'a Mesh, f32)>,
+        material: usize,
+    ) -> usize {
+        let mut bounds = None;
+        let mut levels = levels
+            .into_iter()
+            .map(|(mesh, min_screen_size)| {
+                bounds.get_or_insert_with(|| mesh.bounds());
+                SceneLevel::new(self.arena.insert(device, queue, mesh), min_screen_size)
+            })
+            .collect::<Vec<_>>();
+        // Without levels there would be none for its instances to be drawn with
+        if levels.is_empty() {
+            let empty = self.arena.insert(device, queue, &Mesh::default());
+            levels.push(SceneLevel::new(empty, 0.0));
+        }
+        let bounds = bounds.unwrap_or_else(|| Mesh::default().bounds());
+        self.meshes.insert(SceneMesh::new(levels, bounds, material))
+    }
+
+    pub fn remove_mesh(&mut self, index: usize) {
+        if let Some(mesh) = self.meshes.remove(index) {
+            for level in mesh.levels {
+                self.arena.remove(level.range);
+            }
+        }
+    }
+
+    pub fn replace_mesh(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        index: usize,
+        mesh: &Mesh,
+    ) {
+        let Some(scene_mesh) = self.meshes.get_mut(index) else {
+            return;
+        };
+        for level in scene_mesh.levels.drain(..) {
+            self.arena.remove(level.range);
+        }
+        let range = self.arena.insert(device, queue, mesh);
+        scene_mesh.levels.push(SceneLevel::new(range, 0.0));
+        scene_mesh.bounds = mesh.bounds();
+        scene_mesh.instance_levels.clear();
+    }
+
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, aspect_ratio: f32) {
+        self.graph.update();
+        self.graph_extract.extract(&self.graph);
+
+        self.frame_lights.clear();
+        self.frame_lights.extend_from_slice(&self.lights);
+        self.frame_lights.extend_from_slice(&self.world.lights);
+        self.frame_lights
+            .extend(self.graph.iter().filter_map(|(_, node)| node.world_light()));
+
+        let projection = nalgebra_glm::perspective_lh_zo(
+            aspect_ratio,
+            Self::FOV_Y.to_radians(),
+            Self::Z_NEAR,
+            Self::Z_FAR,
         );
-        self.uniform.update_buffer(
+        // Camera entities and nodes look along their +Z axis, like the view space they define
+        let camera = self.world.camera.or_else(|| {
+            self.graph
+                .active_camera()
+                .and_then(|camera| self.graph.world_matrix(camera).copied())
+        });
+        let (view, camera_position) = match camera {
+            Some(world) => (
+                world
+                    .try_inverse()
+                    .unwrap_or_else(nalgebra_glm::Mat4::identity),
+                world.column(3).xyz(),
+            ),
+            None => (self.camera.view(), self.camera.position()),
+        };
+        let view_projection = projection * view;
+        self.uniform.buffer.write(
             queue,
-            0,
-            UniformBuffer {
-                mvp: projection * view * self.model,
+            &SceneUniform {
+                view_projection,
+                view,
+                camera_position: nalgebra_glm::vec3_to_vec4(&camera_position),
             },
         );
+        let frustum = Frustum::from_view_projection(&view_projection);
+        let gpu_driven = self.indirect().is_some();
+
+        // Meshes sharing a material are consecutive, so the gpu can draw them as one batch
+        self.frame_meshes.clear();
+        self.frame_meshes
+            .extend(self.meshes.iter().map(|(index, _)| index));
+        let meshes = &self.meshes;
+        self.frame_meshes
+            .sort_by_key(|index| meshes.get(*index).map(|mesh| mesh.material));
+
+        self.frame_instances.clear();
+        self.cull_stats = CullStats::default();
+        for &index in &self.frame_meshes {
+            let Some(mesh) = self.meshes.get_mut(index) else {
+                continue;
+            };
+            let first = self.frame_instances.len();
+            self.frame_instances.extend_from_slice(&mesh.instances);
+            self.frame_instances
+                .extend_from_slice(self.world.instances(index));
+            self.frame_instances
+                .extend_from_slice(self.graph_extract.instances(index));
+            mesh.assign_levels(
+                &mut self.frame_instances[first..],
+                first as u32,
+                &camera_position,
+                Self::FOV_Y.to_radians(),
+                &mut self.level_instances,
+            );
+            if gpu_driven {
+                continue;
+            }
+            for level in mesh.levels.iter_mut() {
+                let range = level.instance_range();
+                let instances = &mut self.frame_instances[range.start as usize..range.end as usize];
+                let visible = partition_visible(instances, &mesh.bounds, &frustum);
+                level.visible_count = visible as u32;
+                self.cull_stats.drawn += visible as u32;
+                self.cull_stats.culled += (instances.len() - visible) as u32;
+            }
+        }
+        self.instance_buffer
+            .upload(device, queue, &self.frame_instances);
+
+        if let Some(indirect) = self.indirect.as_mut().filter(|_| gpu_driven) {
+            let meshes = self
+                .frame_meshes
+                .iter()
+                .filter_map(|index| self.meshes.get(*index))
+                .flat_map(|mesh| {
+                    mesh.levels.iter().map(|level| IndirectMesh {
+                        range: &level.range,
+                        bounds: &mesh.bounds,
+                        material: mesh.material,
+                        instances: level.instance_range(),
+                    })
+                });
+            indirect.prepare(device, queue, meshes, &self.instance_buffer, &frustum);
+            self.cull_stats = indirect.stats();
+        }
+        self.view = view;
+        self.camera_position = camera_position;
+        self.projection = projection;
+        self.aspect_ratio = aspect_ratio;
+    }
+
+    pub fn shadow_camera(&self) -> ShadowCamera {
+        ShadowCamera {
+            view: self.view,
+            aspect_ratio: self.aspect_ratio,
+            fov_y: Self::FOV_Y.to_radians(),
+            z_near: Self::Z_NEAR,
+        }
+    }
+
+    /// Sizes are always defined. `SHADOWS` and `ENVIRONMENT` toggle
+    /// shadow sampling and image based lighting.
+    fn shader_defines(shadows: bool, environment: bool) -> ShaderDefines {
+        let mut defines = ShaderDefines::new()
+            .with_value("MAX_LIGHTS", format!("{MAX_LIGHTS}u"))
+            .with_value("SHADOW_LAYER_COUNT", format!("{SHADOW_LAYER_COUNT}u"));
+        if shadows {
+            defines = defines.with("SHADOWS");
+        }
+        if environment {
+            defines = defines.with("ENVIRONMENT");
+        }
+        defines
+    }
+
+    /// The entries of the uniform, material, shadow sampling and environment bind groups,
+    /// reflected from the scene shader with every feature enabled so each binding
+    /// has its full visibility
+    fn bind_group_layout_entries(
+        sources: Sources,
+    ) -> Result<[Vec<wgpu::BindGroupLayoutEntry>; 4], PipelineError> {
+        let shader_source = sources.preprocess("scene.wgsl", &Self::shader_defines(true, true))?;
+        let reflection = ShaderReflection::new(&shader_source)?;
+        Ok([
+            reflection.bind_group_layout_entries(0)?,
+            reflection.bind_group_layout_entries(1)?,
+            reflection.bind_group_layout_entries(2)?,
+            reflection.bind_group_layout_entries(3)?,
+        ])
     }
 
     fn create_pipeline(
         device: &wgpu::Device,
         surface_format: wgpu::TextureFormat,
-        uniform: &UniformBinding,
-    ) -> wgpu::RenderPipeline {
-        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: None,
-            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(SHADER_SOURCE)),
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        sources: Sources,
+        defines: &ShaderDefines,
+        pipelines: &PipelineCache,
+    ) -> Result<Arc<wgpu::RenderPipeline>, PipelineError> {
+        let shader_source = sources.preprocess("scene.wgsl", defines)?;
+        let vertex_attributes = Vertex::vertex_attributes();
+        let instance_attributes = Instance::vertex_attributes();
+        let buffers = [
+            Vertex::description(&vertex_attributes),
+            Instance::description(&instance_attributes),
+        ];
+        ShaderReflection::new(&shader_source)?.validate_vertex_buffers("vertex_main", &buffers)?;
+
+        PipelineBuilder::new("Scene", &shader_source, surface_format)
+            .bind_group_layouts(bind_group_layouts)
+            .vertex_buffers(&buffers)
+            .blend(Blend::Alpha)
+            .build(device, pipelines)
+    }
+}
+
+pub struct GpuMaterial {
+    uniform_buffer: UniformBuffer<MaterialUniform>,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl GpuMaterial {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        material: &Material,
+    ) -> Self {
+        let uniform_buffer = UniformBuffer::new(
+            device,
+            "Material Uniform Buffer",
+            &MaterialUniform::from(material),
+        );
+
+        // Missing textures are replaced by a single texel that leaves the factor unchanged
+        let white = Image::from_rgba8(1, 1, vec![255, 255, 255, 255]);
+        let flat_normal = Image::from_rgba8(1, 1, vec![128, 128, 255, 255]);
+        let textures = [
+            (
+                material.base_color_texture.as_ref().unwrap_or(&white),
+                wgpu::TextureFormat::Rgba8UnormSrgb,
+            ),
+            (
+                material
+                    .metallic_roughness_texture
+                    .as_ref()
+                    .unwrap_or(&white),
+                wgpu::TextureFormat::Rgba8Unorm,
+            ),
+            (
+                material.normal_texture.as_ref().unwrap_or(&flat_normal),
+                wgpu::TextureFormat::Rgba8Unorm,
+            ),
+            (
+                material.occlusion_texture.as_ref().unwrap_or(&white),
+                wgpu::TextureFormat::Rgba8Unorm,
+            ),
+            (
+                material.emissive_texture.as_ref().unwrap_or(&white),
+                wgpu::TextureFormat::Rgba8UnormSrgb,
+            ),
+        ]
+        .map(|(image, format)| {
+            create_texture(device, queue, image, format)
+                .create_view(&wgpu::TextureViewDescriptor::default())
         });
 
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: None,
-            bind_group_layouts: &[&uniform.bind_group_layout],
-            push_constant_ranges: &[],
+        let mut entries = vec![wgpu::BindGroupEntry {
+            binding: 0,
+            resource: uniform_buffer.binding(),
+        }];
+        entries.extend(
+            textures
+                .iter()
+                .enumerate()
+                .map(|(index, view)| wgpu::BindGroupEntry {
+                    binding: index as u32 + 1,
+                    resource: wgpu::BindingResource::TextureView(view),
+                }),
+        );
+        entries.push(wgpu::BindGroupEntry {
+            binding: MATERIAL_TEXTURE_COUNT + 1,
+            resource: wgpu::BindingResource::Sampler(sampler),
         });
 
-        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: None,
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader_module,
-                entry_point: Some("vertex_main"),
-                buffers: &[Vertex::description(&Vertex::vertex_attributes())],
-                compilation_options: Default::default(),
-            },
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleStrip,
-                strip_index_format: Some(wgpu::IndexFormat::Uint32),
-                front_face: wgpu::FrontFace::Cw,
-                cull_mode: None,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                conservative: false,
-                unclipped_depth: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: Renderer::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bind_group_layout,
+            entries: &entries,
+            label: Some("material_bind_group"),
+        });
+
+        Self {
+            uniform_buffer,
+            bind_group,
+        }
+    }
+
+    /// Rewrites the material factors, leaving its textures untouched
+    pub fn update_factors(&self, queue: &wgpu::Queue, material: &Material) {
+        self.uniform_buffer
+            .write(queue, &MaterialUniform::from(material));
+    }
+}
+
+pub(crate) fn create_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    image: &Image,
+    format: wgpu::TextureFormat,
+) -> wgpu::Texture {
+    wgpu::util::DeviceExt::create_texture_with_data(
+        device,
+        queue,
+        &wgpu::TextureDescriptor {
+            label: Some("Material Texture"),
+            size: wgpu::Extent3d {
+                width: image.width,
+                height: image.height,
+                depth_or_array_layers: 1,
             },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader_module,
-                entry_point: Some("fragment_main"),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: surface_format,
-                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: Default::default(),
-            }),
-            multiview: None,
-            cache: None,
-        })
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        },
+        wgpu::util::TextureDataOrder::LayerMajor,
+        &image.pixels,
+    )
+}
+
+const MATERIAL_TEXTURE_COUNT: u32 = 5;
+
+shader_type! {
+    #[repr(C)]
+    #[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+    struct MaterialUniform {
+        base_color_factor: [f32; 4],
+        emissive_factor: [f32; 4],
+        metallic_factor: f32,
+        roughness_factor: f32,
+        normal_scale: f32,
+        occlusion_strength: f32,
     }
 }
 
-#[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-struct Vertex {
-    position: [f32; 4],
-    color: [f32; 4],
+impl From<&Material> for MaterialUniform {
+    fn from(material: &Material) -> Self {
+        let [emissive_r, emissive_g, emissive_b] = material.emissive_factor;
+        Self {
+            base_color_factor: material.base_color_factor,
+            emissive_factor: [emissive_r, emissive_g, emissive_b, 0.0],
+            metallic_factor: material.metallic_factor,
+            roughness_factor: material.roughness_factor,
+            normal_scale: material.normal_scale,
+            occlusion_strength: material.occlusion_strength,
+        }
+    }
+}
+
+shader_type! {
+    #[repr(C)]
+    #[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+    struct SceneUniform {
+        view_projection: nalgebra_glm::Mat4,
+        view: nalgebra_glm::Mat4,
+        camera_position: nalgebra_glm::Vec4,
+    }
 }
 
-impl Vertex {
-    pub fn vertex_attributes() -> Vec<wgpu::VertexAttribute> {
-        wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4].to_vec()
+/// The maximum number of lights uploaded each frame, shared with the shader
+pub const MAX_LIGHTS: usize = 16;
+
+const LIGHT_KIND_DIRECTIONAL: f32 = 0.0;
+const LIGHT_KIND_POINT: f32 = 1.0;
+const LIGHT_KIND_SPOT: f32 = 2.0;
+
+shader_type! {
+    #[repr(C)]
+    #[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+    struct GpuLight {
+        /// xyz: position, w: kind
+        position: [f32; 4],
+        /// xyz: direction, w: range
+        direction: [f32; 4],
+        /// xyz: color, w: intensity
+        color: [f32; 4],
+        /// x: cosine of the inner cone angle, y: cosine of the outer cone angle,
+        /// z: first shadow map layer or -1.0 when the light casts no shadows
+        cone: [f32; 4],
     }
+}
 
-    pub fn description(attributes: &[wgpu::VertexAttribute]) -> wgpu::VertexBufferLayout {
-        wgpu::VertexBufferLayout {
-            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes,
+impl From<&Light> for GpuLight {
+    fn from(light: &Light) -> Self {
+        match *light {
+            Light::Directional {
+                direction,
+                color,
+                intensity,
+            } => Self {
+                position: [0.0, 0.0, 0.0, LIGHT_KIND_DIRECTIONAL],
+                direction: [direction.x, direction.y, direction.z, 0.0],
+                color: [color.x, color.y, color.z, intensity],
+                cone: [0.0; 4],
+            },
+            Light::Point {
+                position,
+                color,
+                intensity,
+                range,
+            } => Self {
+                position: [position.x, position.y, position.z, LIGHT_KIND_POINT],
+                direction: [0.0, 0.0, 0.0, range],
+                color: [color.x, color.y, color.z, intensity],
+                cone: [0.0; 4],
+            },
+            Light::Spot {
+                position,
+                direction,
+                color,
+                intensity,
+                range,
+                inner_cone_angle,
+                outer_cone_angle,
+            } => Self {
+                position: [position.x, position.y, position.z, LIGHT_KIND_SPOT],
+                direction: [direction.x, direction.y, direction.z, range],
+                color: [color.x, color.y, color.z, intensity],
+                cone: [inner_cone_angle.cos(), outer_cone_angle.cos(), 0.0, 0.0],
+            },
         }
     }
 }
 
-#[repr(C)]
-#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct UniformBuffer {
-    mvp: nalgebra_glm::Mat4,
+shader_type! {
+    #[repr(C)]
+    #[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+    struct LightsUniform {
+        /// rgb: ambient color
+        ambient: [f32; 4],
+        /// x: number of active lights
+        count: [u32; 4],
+        lights: [GpuLight; MAX_LIGHTS],
+    }
 }
 
 struct UniformBinding {
-    pub buffer: wgpu::Buffer,
+    pub buffer: UniformBuffer<SceneUniform>,
+    pub light_buffer: UniformBuffer<LightsUniform>,
     pub bind_group: wgpu::BindGroup,
     pub bind_group_layout: wgpu::BindGroupLayout,
 }
 
 impl UniformBinding {
-    pub fn new(device: &wgpu::Device) -> Self {
-        let buffer = wgpu::util::DeviceExt::create_buffer_init(
-            device,
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Uniform Buffer"),
-                contents: bytemuck::cast_slice(&[UniformBuffer::default()]),
-                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            },
-        );
+    pub fn new(device: &wgpu::Device, layout_entries: &[wgpu::BindGroupLayoutEntry]) -> Self {
+        let buffer = UniformBuffer::new(device, "Uniform Buffer", &SceneUniform::default());
+        let light_buffer = UniformBuffer::new(device, "Light Buffer", &LightsUniform::default());
 
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            }],
+            entries: layout_entries,
             label: Some("uniform_bind_group_layout"),
         });
 
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: buffer.as_entire_binding(),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: light_buffer.binding(),
+                },
+            ],
             label: Some("uniform_bind_group"),
         });
 
         Self {
             buffer,
+            light_buffer,
             bind_group,
             bind_group_layout,
         }
     }
 
-    pub fn update_buffer(
+    pub fn update_lights(
         &mut self,
         queue: &wgpu::Queue,
-        offset: wgpu::BufferAddress,
-        uniform_buffer: UniformBuffer,
+        lights: &[Light],
+        ambient_color: &nalgebra_glm::Vec3,
+        shadow_layers: &[Option<u32>],
     ) {
-        queue.write_buffer(
-            &self.buffer,
-            offset,
-            bytemuck::cast_slice(&[uniform_buffer]),
-        )
+        if lights.len() > MAX_LIGHTS {
+            log::warn!(
+                "Only the first {MAX_LIGHTS} of {} lights will be rendered",
+                lights.len()
+            );
+        }
+        let mut uniform = LightsUniform {
+            ambient: [ambient_color.x, ambient_color.y, ambient_color.z, 1.0],
+            count: [lights.len().min(MAX_LIGHTS) as u32, 0, 0, 0],
+            ..Default::default()
+        };
+        for (index, (gpu_light, light)) in uniform.lights.iter_mut().zip(lights).enumerate() {
+            *gpu_light = GpuLight::from(light);
+            gpu_light.cone[2] = shadow_layers
+                .get(index)
+                .copied()
+                .flatten()
+                .map_or(-1.0, |layer| layer as f32);
+        }
+        self.light_buffer.write(queue, &uniform);
     }
 }
 
 const VERTICES: [Vertex; 3] = [
     Vertex {
         position: [1.0, -1.0, 0.0, 1.0],
+        normal: [0.0, 0.0, 1.0, 0.0],
         color: [1.0, 0.0, 0.0, 1.0],
+        tex_coords: [1.0, 1.0],
     },
     Vertex {
         position: [-1.0, -1.0, 0.0, 1.0],
+        normal: [0.0, 0.0, 1.0, 0.0],
         color: [0.0, 1.0, 0.0, 1.0],
+        tex_coords: [0.0, 1.0],
     },
     Vertex {
         position: [0.0, 1.0, 0.0, 1.0],
+        normal: [0.0, 0.0, 1.0, 0.0],
         color: [0.0, 0.0, 1.0, 1.0],
+        tex_coords: [0.5, 0.0],
     },
 ];
 
 const INDICES: [u32; 3] = [0, 1, 2]; // Clockwise winding order
-
-const SHADER_SOURCE: &str = "
-struct Uniform {
-    mvp: mat4x4<f32>,
-};
-
-@group(0) @binding(0)
-var<uniform> ubo: Uniform;
-
-struct VertexInput {
-    @location(0) position: vec4<f32>,
-    @location(1) color: vec4<f32>,
-};
-struct VertexOutput {
-    @builtin(position) position: vec4<f32>,
-    @location(0) color: vec4<f32>,
-};
-
-@vertex
-fn vertex_main(vert: VertexInput) -> VertexOutput {
-    var out: VertexOutput;
-    out.color = vert.color;
-    out.position = ubo.mvp * vert.position;
-    return out;
-};
-
-@fragment
-fn fragment_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4<f32>(in.color);
-}
-";
@@ -0,0 +1,542 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    ecs::{Camera, Entity, MeshRenderer, World},
+    light::Light,
+    scene_graph::{Node, NodeId, SceneGraph, Transform},
+};
+
+/// A mesh asset drawn with a material, both referred to by path
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MeshReference {
+    pub mesh: String,
+    /// Drawn with the default material when missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub material: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeDocument {
+    pub name: String,
+    pub transform: Transform,
+    /// Refers to an entry of `SceneDocument::meshes`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mesh: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub light: Option<Light>,
+    /// Refers to an earlier entry of `SceneDocument::nodes`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<usize>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EntityDocument {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transform: Option<Transform>,
+    /// The mesh refers to an entry of `SceneDocument::meshes`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mesh_renderer: Option<MeshRenderer>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub camera: Option<Camera>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub light: Option<Light>,
+    /// Components registered with a `ComponentRegistry`, by name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub components: BTreeMap<String, serde_json::Value>,
+}
+
+impl EntityDocument {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// A scene graph and entity world in a form that is saved as RON or JSON.
+/// Meshes are referred to by asset path, so a document loads into a renderer
+/// whose meshes were added in any order.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SceneDocument {
+    pub meshes: Vec<MeshReference>,
+    /// Every node after its parent, in draw order
+    pub nodes: Vec<NodeDocument>,
+    /// Refers to an entry of `nodes`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_camera: Option<usize>,
+    pub entities: Vec<EntityDocument>,
+}
+
+/// The nodes and entities a document added
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SceneInstance {
+    /// In the order of `SceneDocument::nodes`
+    pub nodes: Vec<NodeId>,
+    /// In the order of `SceneDocument::entities`
+    pub entities: Vec<Entity>,
+}
+
+#[derive(Debug)]
+pub enum SceneFileError {
+    Ron(ron::Error),
+    RonSyntax(ron::error::SpannedError),
+    Json(serde_json::Error),
+    /// A node or entity refers to a mesh index the document does not have
+    InvalidMesh(usize),
+    /// A node refers to a parent that is not listed before it
+    InvalidParent {
+        node: usize,
+        parent: usize,
+    },
+    /// The mesh could not be provided when loading the document
+    MissingMesh(MeshReference),
+    Component {
+        name: String,
+        error: serde_json::Error,
+    },
+}
+
+impl std::fmt::Display for SceneFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ron(error) => write!(f, "Failed to write scene: {error}"),
+            Self::RonSyntax(error) => write!(f, "Failed to read scene: {error}"),
+            Self::Json(error) => write!(f, "Failed to read or write scene: {error}"),
+            Self::InvalidMesh(mesh) => {
+                write!(f, "Scene refers to mesh {mesh}, which it does not list")
+            }
+            Self::InvalidParent { node, parent } => write!(
+                f,
+                "Scene node {node} refers to parent {parent}, which is not listed before it"
+            ),
+            Self::MissingMesh(reference) => {
+                write!(f, "Scene mesh {:?} is not loaded", reference.mesh)
+            }
+            Self::Component { name, error } => {
+                write!(f, "Failed to convert scene component {name:?}: {error}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SceneFileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Ron(error) => Some(error),
+            Self::RonSyntax(error) => Some(error),
+            Self::Json(error) | Self::Component { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<ron::Error> for SceneFileError {
+    fn from(error: ron::Error) -> Self {
+        Self::Ron(error)
+    }
+}
+
+impl From<ron::error::SpannedError> for SceneFileError {
+    fn from(error: ron::error::SpannedError) -> Self {
+        Self::RonSyntax(error)
+    }
+}
+
+impl From<serde_json::Error> for SceneFileError {
+    fn from(error: serde_json::Error) -> Self {
+        Self::Json(error)
+    }
+}
+
+type SaveComponent = fn(&hecs::EntityRef) -> Option<Result<serde_json::Value, serde_json::Error>>;
+type LoadComponent =
+    fn(&mut hecs::EntityBuilder, serde_json::Value) -> Result<(), serde_json::Error>;
+
+struct RegisteredComponent {
+    name: String,
+    save: SaveComponent,
+    load: LoadComponent,
+}
+
+/// The application components saved along with the built in ones, each under a unique name
+#[derive(Default)]
+pub struct ComponentRegistry {
+    components: Vec<RegisteredComponent>,
+}
+
+impl ComponentRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<T: hecs::Component + Serialize + DeserializeOwned>(
+        &mut self,
+        name: impl Into<String>,
+    ) -> &mut Self {
+        self.components.push(RegisteredComponent {
+            name: name.into(),
+            save: |entity| {
+                entity
+                    .get::<&T>()
+                    .map(|component| serde_json::to_value(&*component))
+            },
+            load: |builder, value| {
+                builder.add(serde_json::from_value::<T>(value)?);
+                Ok(())
+            },
+        });
+        self
+    }
+
+    fn find(&self, name: &str) -> Option<&RegisteredComponent> {
+        self.components
+            .iter()
+            .find(|component| component.name == name)
+    }
+}
+
+impl SceneDocument {
+    /// Saves every node of the graph and every entity with a built in or registered component.
+    /// `meshes` describes the renderer's meshes, indexed like `Context::add_mesh` returned them.
+    pub fn capture(
+        graph: &SceneGraph,
+        world: &World,
+        meshes: &[MeshReference],
+        registry: &ComponentRegistry,
+    ) -> Result<Self, SceneFileError> {
+        let check_mesh = |mesh: usize| {
+            if mesh < meshes.len() {
+                Ok(mesh)
+            } else {
+                Err(SceneFileError::InvalidMesh(mesh))
+            }
+        };
+
+        let mut indices = HashMap::new();
+        let mut nodes = Vec::new();
+        for (id, node) in graph.iter() {
+            indices.insert(id, nodes.len());
+            nodes.push(NodeDocument {
+                name: node.name.clone(),
+                transform: *node.transform(),
+                mesh: node.mesh.map(check_mesh).transpose()?,
+                light: node.light,
+                parent: node.parent().map(|parent| indices[&parent]),
+            });
+        }
+
+        let mut entities = Vec::new();
+        for entity in world.iter() {
+            let mut document = EntityDocument {
+                transform: entity.get::<&Transform>().map(|transform| *transform),
+                mesh_renderer: entity.get::<&MeshRenderer>().map(|renderer| *renderer),
+                camera: entity.get::<&Camera>().map(|camera| *camera),
+                light: entity.get::<&Light>().map(|light| *light),
+                components: BTreeMap::new(),
+            };
+            if let Some(renderer) = document.mesh_renderer {
+                check_mesh(renderer.mesh)?;
+            }
+            for component in registry.components.iter() {
+                if let Some(value) = (component.save)(&entity) {
+                    let value = value.map_err(|error| SceneFileError::Component {
+                        name: component.name.clone(),
+                        error,
+                    })?;
+                    document.components.insert(component.name.clone(), value);
+                }
+            }
+            // Entities the document cannot describe are left out rather than saved empty
+            if !document.is_empty() {
+                entities.push(document);
+            }
+        }
+
+        Ok(Self {
+            meshes: meshes.to_vec(),
+            nodes,
+            active_camera: graph.active_camera().map(|camera| indices[&camera]),
+            entities,
+        })
+    }
+
+    /// Adds the nodes and entities of the document to a graph and world.
+    /// `resolve_mesh` returns the renderer index of each referenced mesh, loading it if needed.
+    /// Components that are not registered are skipped with a warning.
+    pub fn instantiate(
+        &self,
+        graph: &mut SceneGraph,
+        world: &mut World,
+        registry: &ComponentRegistry,
+        mut resolve_mesh: impl FnMut(&MeshReference) -> Option<usize>,
+    ) -> Result<SceneInstance, SceneFileError> {
+        let mut resolved = vec![None; self.meshes.len()];
+        let mut mesh = |index: usize| -> Result<usize, SceneFileError> {
+            let reference = self
+                .meshes
+                .get(index)
+                .ok_or(SceneFileError::InvalidMesh(index))?;
+            if resolved[index].is_none() {
+                resolved[index] = Some(
+                    resolve_mesh(reference)
+                        .ok_or_else(|| SceneFileError::MissingMesh(reference.clone()))?,
+                );
+            }
+            Ok(resolved[index].unwrap_or_default())
+        };
+
+        // Everything is validated before the graph or world changes
+        let mut builders = Vec::with_capacity(self.entities.len());
+        for document in self.entities.iter() {
+            let mut builder = hecs::EntityBuilder::new();
+            if let Some(transform) = document.transform {
+                builder.add(transform);
+            }
+            if let Some(renderer) = document.mesh_renderer {
+                builder.add(MeshRenderer {
+                    mesh: mesh(renderer.mesh)?,
+                    ..renderer
+                });
+            }
+            if let Some(camera) = document.camera {
+                builder.add(camera);
+            }
+            if let Some(light) = document.light {
+                builder.add(light);
+            }
+            for (name, value) in document.components.iter() {
+                let Some(component) = registry.find(name) else {
+                    log::warn!("Skipping scene component {name:?}, which is not registered");
+                    continue;
+                };
+                (component.load)(&mut builder, value.clone()).map_err(|error| {
+                    SceneFileError::Component {
+                        name: name.clone(),
+                        error,
+                    }
+                })?;
+            }
+            builders.push(builder);
+        }
+        let mut nodes = Vec::with_capacity(self.nodes.len());
+        for (index, document) in self.nodes.iter().enumerate() {
+            let mut node = Node::new(document.name.clone()).with_transform(document.transform);
+            node.mesh = document.mesh.map(&mut mesh).transpose()?;
+            node.light = document.light;
+            if let Some(parent) = document.parent.filter(|parent| *parent >= index) {
+                return Err(SceneFileError::InvalidParent {
+                    node: index,
+                    parent,
+                });
+            }
+            nodes.push((node, document.parent));
+        }
+
+        let mut instance = SceneInstance::default();
+        for (node, parent) in nodes {
+            let id = match parent {
+                Some(parent) => graph
+                    .add_child(instance.nodes[parent], node)
+                    .expect("parents are added before their children"),
+                None => graph.add(node),
+            };
+            instance.nodes.push(id);
+        }
+        if let Some(camera) = self
+            .active_camera
+            .and_then(|camera| instance.nodes.get(camera))
+        {
+            graph.set_active_camera(Some(*camera));
+        }
+        instance.entities = builders
+            .iter_mut()
+            .map(|builder| world.spawn(builder.build()))
+            .collect();
+        Ok(instance)
+    }
+
+    pub fn to_ron(&self) -> Result<String, SceneFileError> {
+        Ok(ron::ser::to_string_pretty(
+            self,
+            ron::ser::PrettyConfig::default(),
+        )?)
+    }
+
+    pub fn from_ron(source: &str) -> Result<Self, SceneFileError> {
+        Ok(ron::from_str(source)?)
+    }
+
+    pub fn to_json(&self) -> Result<String, SceneFileError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(source: &str) -> Result<Self, SceneFileError> {
+        Ok(serde_json::from_str(source)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Health {
+        current: f32,
+        maximum: f32,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    enum Team {
+        Red,
+        Blue { squad: u32 },
+    }
+
+    fn registry() -> ComponentRegistry {
+        let mut registry = ComponentRegistry::new();
+        registry
+            .register::<Health>("health")
+            .register::<Team>("team");
+        registry
+    }
+
+    fn meshes() -> Vec<MeshReference> {
+        vec![
+            MeshReference {
+                mesh: "builtin://triangle".into(),
+                material: None,
+            },
+            MeshReference {
+                mesh: "models/crate.glb".into(),
+                material: Some("materials/wood.ron".into()),
+            },
+        ]
+    }
+
+    fn scene() -> (SceneGraph, World) {
+        let mut graph = SceneGraph::new();
+        let root = graph.add(Node::new("Root").with_mesh(1).with_transform(Transform {
+            translation: nalgebra_glm::vec3(1.5, -2.0, 0.25),
+            rotation: nalgebra_glm::quat_angle_axis(
+                0.7,
+                &nalgebra_glm::vec3(0.3, 1.0, 0.1).normalize(),
+            ),
+            scale: nalgebra_glm::vec3(2.0, 0.5, 1.0),
+        }));
+        graph
+            .add_child(root, Node::new("Lamp").with_light(Light::default()))
+            .unwrap();
+        let camera = graph
+            .add_child(
+                root,
+                Node::new("Camera").with_transform(Transform::from_translation(
+                    nalgebra_glm::vec3(0.0, 1.0, -5.0),
+                )),
+            )
+            .unwrap();
+        graph.add(Node::new("Second root").with_mesh(0));
+        graph.set_active_camera(Some(camera));
+
+        let mut world = World::new();
+        world.spawn((
+            Transform::from_translation(nalgebra_glm::vec3(0.1, 0.2, 0.3)),
+            MeshRenderer {
+                mesh: 1,
+                color: [0.2, 0.4, 0.6, 1.0],
+            },
+            Health {
+                current: 7.5,
+                maximum: 10.0,
+            },
+            Team::Blue { squad: 3 },
+        ));
+        world.spawn((
+            Transform::default(),
+            Light::Spot {
+                position: nalgebra_glm::Vec3::zeros(),
+                direction: -nalgebra_glm::Vec3::y(),
+                color: nalgebra_glm::vec3(1.0, 0.9, 0.8),
+                intensity: 4.0,
+                range: 12.0,
+                inner_cone_angle: 0.3,
+                outer_cone_angle: 0.5,
+            },
+        ));
+        world.spawn((Transform::default(), Camera { priority: 2 }, Team::Red));
+        // Nothing on this entity is saved
+        world.spawn((42_u32,));
+        (graph, world)
+    }
+
+    fn load(document: &SceneDocument) -> (SceneGraph, World) {
+        let mut graph = SceneGraph::new();
+        let mut world = World::new();
+        let meshes = meshes();
+        document
+            .instantiate(&mut graph, &mut world, &registry(), |reference| {
+                meshes.iter().position(|mesh| mesh == reference)
+            })
+            .unwrap();
+        (graph, world)
+    }
+
+    #[test]
+    fn ron_round_trips() {
+        let (graph, world) = scene();
+        let document = SceneDocument::capture(&graph, &world, &meshes(), &registry()).unwrap();
+        assert_eq!(document.nodes.len(), 4);
+        assert_eq!(document.entities.len(), 3);
+
+        let loaded = SceneDocument::from_ron(&document.to_ron().unwrap()).unwrap();
+        assert_eq!(loaded, document);
+    }
+
+    #[test]
+    fn json_round_trips() {
+        let (graph, world) = scene();
+        let document = SceneDocument::capture(&graph, &world, &meshes(), &registry()).unwrap();
+        let loaded = SceneDocument::from_json(&document.to_json().unwrap()).unwrap();
+        assert_eq!(loaded, document);
+    }
+
+    #[test]
+    fn instantiating_restores_the_captured_scene() {
+        let (graph, world) = scene();
+        let document = SceneDocument::capture(&graph, &world, &meshes(), &registry()).unwrap();
+        let (loaded_graph, loaded_world) =
+            load(&SceneDocument::from_ron(&document.to_ron().unwrap()).unwrap());
+        assert_eq!(
+            SceneDocument::capture(&loaded_graph, &loaded_world, &meshes(), &registry()).unwrap(),
+            document
+        );
+
+        let camera = loaded_graph.active_camera().unwrap();
+        assert_eq!(loaded_graph.get(camera).unwrap().name, "Camera");
+        let mut health = loaded_world.query::<&Health>();
+        assert_eq!(
+            health.iter().next(),
+            Some(&Health {
+                current: 7.5,
+                maximum: 10.0
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_documents_are_rejected_before_anything_is_added() {
+        let (graph, world) = scene();
+        let mut document = SceneDocument::capture(&graph, &world, &meshes(), &registry()).unwrap();
+        document.nodes[1].parent = Some(3);
+
+        let mut graph = SceneGraph::new();
+        let mut world = World::new();
+        let result = document.instantiate(&mut graph, &mut world, &registry(), |_| Some(0));
+        assert!(matches!(
+            result,
+            Err(SceneFileError::InvalidParent { node: 1, parent: 3 })
+        ));
+        assert!(graph.is_empty() && world.is_empty());
+
+        let result = document.instantiate(&mut graph, &mut world, &registry(), |_| None);
+        assert!(matches!(result, Err(SceneFileError::MissingMesh(_))));
+    }
+}
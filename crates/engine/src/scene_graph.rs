@@ -0,0 +1,609 @@
+use nalgebra_glm::{Mat4, Quat, Vec3};
+
+use crate::{light::Light, mesh::Instance};
+
+/// A position, rotation and scale, applied in scale, rotation, translation order
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: Vec3::zeros(),
+            rotation: Quat::identity(),
+            scale: nalgebra_glm::vec3(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Transform {
+    pub fn from_translation(translation: Vec3) -> Self {
+        Self {
+            translation,
+            ..Default::default()
+        }
+    }
+
+    pub fn matrix(&self) -> Mat4 {
+        nalgebra_glm::translation(&self.translation)
+            * nalgebra_glm::quat_to_mat4(&self.rotation)
+            * nalgebra_glm::scaling(&self.scale)
+    }
+}
+
+/// Refers to a node for as long as it exists. Ids of removed nodes are never reused.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct NodeId {
+    index: u32,
+    generation: u32,
+}
+
+/// A node of the scene graph, along with what is attached to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub name: String,
+    /// A mesh drawn with the world transform of the node
+    pub mesh: Option<usize>,
+    /// A light placed and pointed by the world transform of the node
+    pub light: Option<Light>,
+    transform: Transform,
+    world: Mat4,
+    dirty: bool,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+}
+
+impl Node {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            mesh: None,
+            light: None,
+            transform: Transform::default(),
+            world: Mat4::identity(),
+            dirty: true,
+            parent: None,
+            children: Vec::new(),
+        }
+    }
+
+    pub fn with_transform(mut self, transform: Transform) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    pub fn with_mesh(mut self, mesh: usize) -> Self {
+        self.mesh = Some(mesh);
+        self
+    }
+
+    pub fn with_light(mut self, light: Light) -> Self {
+        self.light = Some(light);
+        self
+    }
+
+    /// The transform relative to the parent
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+
+    /// The transform relative to the world, as of the last `SceneGraph::update`
+    pub fn world_matrix(&self) -> &Mat4 {
+        &self.world
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+
+    /// The light moved into world space
+    pub fn world_light(&self) -> Option<Light> {
+        self.light.map(|light| light.transformed(&self.world))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SceneGraphError {
+    /// The node was removed, or belongs to another graph
+    MissingNode(NodeId),
+    /// The new parent is the node itself or one of its descendants
+    Cycle { node: NodeId, parent: NodeId },
+}
+
+impl std::fmt::Display for SceneGraphError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingNode(node) => write!(f, "Scene graph node {node:?} does not exist"),
+            Self::Cycle { node, parent } => write!(
+                f,
+                "Scene graph node {parent:?} cannot become the parent of {node:?}, which contains it"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SceneGraphError {}
+
+#[derive(Debug, Clone)]
+struct Slot {
+    generation: u32,
+    node: Option<Node>,
+}
+
+/// A hierarchy of nodes with local transforms. World matrices are cached
+/// and only recomputed for nodes whose transform, or whose ancestors' transform, changed.
+#[derive(Debug, Clone, Default)]
+pub struct SceneGraph {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+    /// Top level nodes in insertion order
+    roots: Vec<NodeId>,
+    active_camera: Option<NodeId>,
+}
+
+impl SceneGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a node at the top of the hierarchy
+    pub fn add(&mut self, node: Node) -> NodeId {
+        let id = self.insert(node);
+        self.roots.push(id);
+        id
+    }
+
+    pub fn add_child(&mut self, parent: NodeId, node: Node) -> Result<NodeId, SceneGraphError> {
+        if !self.contains(parent) {
+            return Err(SceneGraphError::MissingNode(parent));
+        }
+        let id = self.insert(node);
+        self.attach(id, Some(parent));
+        Ok(id)
+    }
+
+    /// Removes a node along with all of its descendants
+    pub fn remove(&mut self, id: NodeId) -> Option<Node> {
+        if !self.contains(id) {
+            return None;
+        }
+        self.detach(id);
+        let mut node = self.take(id)?;
+        let mut pending = std::mem::take(&mut node.children);
+        while let Some(child) = pending.pop() {
+            if let Some(mut removed) = self.take(child) {
+                pending.append(&mut removed.children);
+            }
+        }
+        node.parent = None;
+        Some(node)
+    }
+
+    /// Moves a node and its descendants under a new parent, or to the top level.
+    /// The local transform is kept, so the node moves along with its new parent.
+    pub fn set_parent(
+        &mut self,
+        id: NodeId,
+        parent: Option<NodeId>,
+    ) -> Result<(), SceneGraphError> {
+        if !self.contains(id) {
+            return Err(SceneGraphError::MissingNode(id));
+        }
+        if let Some(parent) = parent {
+            if !self.contains(parent) {
+                return Err(SceneGraphError::MissingNode(parent));
+            }
+            if parent == id || self.ancestors(parent).any(|ancestor| ancestor == id) {
+                return Err(SceneGraphError::Cycle { node: id, parent });
+            }
+        }
+        self.detach(id);
+        self.attach(id, parent);
+        Ok(())
+    }
+
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.get(id).is_some()
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&Node> {
+        self.slots
+            .get(id.index as usize)
+            .filter(|slot| slot.generation == id.generation)?
+            .node
+            .as_ref()
+    }
+
+    /// Gives access to the name and attachments of a node.
+    /// Transforms are changed through `transform_mut`, which keeps world matrices in sync.
+    pub fn get_mut(&mut self, id: NodeId) -> Option<&mut Node> {
+        self.slots
+            .get_mut(id.index as usize)
+            .filter(|slot| slot.generation == id.generation)?
+            .node
+            .as_mut()
+    }
+
+    /// The local transform of a node, marking its world matrix for recomputation
+    pub fn transform_mut(&mut self, id: NodeId) -> Option<&mut Transform> {
+        let node = self.get_mut(id)?;
+        node.dirty = true;
+        Some(&mut node.transform)
+    }
+
+    /// The world matrix of a node as of the last `update`
+    pub fn world_matrix(&self, id: NodeId) -> Option<&Mat4> {
+        self.get(id).map(Node::world_matrix)
+    }
+
+    /// The node the scene is viewed from, looking along its +Z axis
+    pub fn active_camera(&self) -> Option<NodeId> {
+        self.active_camera.filter(|camera| self.contains(*camera))
+    }
+
+    /// Views the scene from a node instead of the orbit camera, or goes back to it with `None`
+    pub fn set_active_camera(&mut self, camera: Option<NodeId>) {
+        self.active_camera = camera;
+    }
+
+    pub fn roots(&self) -> &[NodeId] {
+        &self.roots
+    }
+
+    /// The parent of a node, followed by its parent, up to the top level
+    pub fn ancestors(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        std::iter::successors(Some(id), |id| self.get(*id)?.parent).skip(1)
+    }
+
+    /// Every node, each followed by its descendants, in the order they are drawn
+    pub fn iter(&self) -> impl Iterator<Item = (NodeId, &Node)> {
+        let mut pending = self.roots.iter().rev().copied().collect::<Vec<_>>();
+        std::iter::from_fn(move || {
+            let id = pending.pop()?;
+            let node = self.get(id)?;
+            pending.extend(node.children.iter().rev());
+            Some((id, node))
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.iter().filter(|slot| slot.node.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roots.is_empty()
+    }
+
+    /// Recomputes the world matrices of nodes that moved, and of everything below them
+    pub fn update(&mut self) {
+        let mut pending = self
+            .roots
+            .iter()
+            .rev()
+            .map(|id| (*id, Mat4::identity(), false))
+            .collect::<Vec<_>>();
+        while let Some((id, parent_world, parent_changed)) = pending.pop() {
+            let Some(node) = self.get_mut(id) else {
+                continue;
+            };
+            let changed = node.dirty || parent_changed;
+            if changed {
+                node.world = parent_world * node.transform.matrix();
+                node.dirty = false;
+            }
+            let world = node.world;
+            pending.extend(
+                node.children
+                    .iter()
+                    .rev()
+                    .map(|child| (*child, world, changed)),
+            );
+        }
+    }
+
+    fn insert(&mut self, node: Node) -> NodeId {
+        let node = Node {
+            parent: None,
+            children: Vec::new(),
+            dirty: true,
+            ..node
+        };
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.node = Some(node);
+                NodeId {
+                    index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    node: Some(node),
+                });
+                NodeId {
+                    index: self.slots.len() as u32 - 1,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    /// Empties the slot of a node, so its id no longer matches
+    fn take(&mut self, id: NodeId) -> Option<Node> {
+        let slot = self
+            .slots
+            .get_mut(id.index as usize)
+            .filter(|slot| slot.generation == id.generation)?;
+        let node = slot.node.take()?;
+        slot.generation += 1;
+        self.free.push(id.index);
+        Some(node)
+    }
+
+    fn detach(&mut self, id: NodeId) {
+        match self.get(id).and_then(|node| node.parent) {
+            Some(parent) => {
+                if let Some(parent) = self.get_mut(parent) {
+                    parent.children.retain(|child| *child != id);
+                }
+            }
+            None => self.roots.retain(|root| *root != id),
+        }
+    }
+
+    fn attach(&mut self, id: NodeId, parent: Option<NodeId>) {
+        match parent.and_then(|parent| self.get_mut(parent)) {
+            Some(parent_node) => parent_node.children.push(id),
+            None => self.roots.push(id),
+        }
+        if let Some(node) = self.get_mut(id) {
+            node.parent = parent;
+            node.dirty = true;
+        }
+    }
+}
+
+/// The mesh instances of the graph this frame, gathered in one pass over its nodes
+#[derive(Debug, Default)]
+pub(crate) struct GraphExtract {
+    /// Instances indexed by mesh
+    instances: Vec<Vec<Instance>>,
+    /// The node each instance was extracted from, indexed like `instances`
+    nodes: Vec<Vec<NodeId>>,
+}
+
+impl GraphExtract {
+    /// Gathers the nodes drawing a mesh, reusing the allocations of the previous frame
+    pub fn extract(&mut self, graph: &SceneGraph) {
+        self.instances.iter_mut().for_each(Vec::clear);
+        self.nodes.iter_mut().for_each(Vec::clear);
+        for (id, node) in graph.iter() {
+            let Some(mesh) = node.mesh else {
+                continue;
+            };
+            if self.instances.len() <= mesh {
+                self.instances.resize_with(mesh + 1, Vec::new);
+                self.nodes.resize_with(mesh + 1, Vec::new);
+            }
+            self.instances[mesh].push(Instance::new(*node.world_matrix()));
+            self.nodes[mesh].push(id);
+        }
+    }
+
+    pub fn instances(&self, mesh: usize) -> &[Instance] {
+        self.instances.get(mesh).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn nodes(&self, mesh: usize) -> &[NodeId] {
+        self.nodes.get(mesh).map_or(&[], Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(first: &Mat4, second: &Mat4) {
+        assert!((first - second).abs().max() < 1e-5, "{first} != {second}");
+    }
+
+    fn world_position(graph: &SceneGraph, id: NodeId) -> Vec3 {
+        (graph.world_matrix(id).unwrap() * nalgebra_glm::vec4(0.0, 0.0, 0.0, 1.0)).xyz()
+    }
+
+    #[test]
+    fn transforms_apply_scale_then_rotation_then_translation() {
+        let transform = Transform {
+            translation: nalgebra_glm::vec3(1.0, 0.0, 0.0),
+            rotation: nalgebra_glm::quat_angle_axis(90_f32.to_radians(), &Vec3::y()),
+            scale: nalgebra_glm::vec3(2.0, 2.0, 2.0),
+        };
+        let point = (transform.matrix() * nalgebra_glm::vec4(1.0, 0.0, 0.0, 1.0)).xyz();
+        assert!((point - nalgebra_glm::vec3(1.0, 0.0, -2.0)).norm() < 1e-5);
+    }
+
+    #[test]
+    fn children_inherit_their_parents_transform() {
+        let mut graph = SceneGraph::new();
+        let parent_transform = Transform {
+            translation: nalgebra_glm::vec3(0.0, 1.0, 0.0),
+            rotation: nalgebra_glm::quat_angle_axis(90_f32.to_radians(), &Vec3::z()),
+            scale: nalgebra_glm::vec3(1.0, 1.0, 1.0),
+        };
+        let child_transform = Transform::from_translation(nalgebra_glm::vec3(2.0, 0.0, 0.0));
+        let parent = graph.add(Node::new("parent").with_transform(parent_transform));
+        let child = graph
+            .add_child(parent, Node::new("child").with_transform(child_transform))
+            .unwrap();
+        graph.update();
+
+        assert_near(
+            graph.world_matrix(child).unwrap(),
+            &(parent_transform.matrix() * child_transform.matrix()),
+        );
+        assert!((world_position(&graph, child) - nalgebra_glm::vec3(0.0, 3.0, 0.0)).norm() < 1e-5);
+    }
+
+    #[test]
+    fn moving_a_parent_updates_its_descendants() {
+        let mut graph = SceneGraph::new();
+        let root = graph.add(Node::new("root"));
+        let child = graph
+            .add_child(
+                root,
+                Node::new("child").with_transform(Transform::from_translation(Vec3::x())),
+            )
+            .unwrap();
+        let grandchild = graph
+            .add_child(
+                child,
+                Node::new("grandchild").with_transform(Transform::from_translation(Vec3::y())),
+            )
+            .unwrap();
+        graph.update();
+        assert_eq!(
+            world_position(&graph, grandchild),
+            nalgebra_glm::vec3(1.0, 1.0, 0.0)
+        );
+
+        graph.transform_mut(root).unwrap().translation = Vec3::z();
+        // World matrices are cached until the next update
+        assert_eq!(
+            world_position(&graph, grandchild),
+            nalgebra_glm::vec3(1.0, 1.0, 0.0)
+        );
+        graph.update();
+        assert_eq!(
+            world_position(&graph, grandchild),
+            nalgebra_glm::vec3(1.0, 1.0, 1.0)
+        );
+        assert!(graph.iter().all(|(_, node)| !node.dirty));
+    }
+
+    #[test]
+    fn reparenting_keeps_the_local_transform_and_rejects_cycles() {
+        let mut graph = SceneGraph::new();
+        let first =
+            graph.add(Node::new("first").with_transform(Transform::from_translation(Vec3::x())));
+        let second =
+            graph.add(Node::new("second").with_transform(Transform::from_translation(Vec3::y())));
+        let child = graph
+            .add_child(
+                first,
+                Node::new("child").with_transform(Transform::from_translation(Vec3::z())),
+            )
+            .unwrap();
+
+        graph.set_parent(child, Some(second)).unwrap();
+        graph.update();
+        assert_eq!(
+            world_position(&graph, child),
+            nalgebra_glm::vec3(0.0, 1.0, 1.0)
+        );
+        assert!(graph.get(first).unwrap().children().is_empty());
+        assert_eq!(graph.get(child).unwrap().parent(), Some(second));
+
+        assert_eq!(
+            graph.set_parent(second, Some(child)),
+            Err(SceneGraphError::Cycle {
+                node: second,
+                parent: child
+            })
+        );
+        assert_eq!(
+            graph.set_parent(second, Some(second)),
+            Err(SceneGraphError::Cycle {
+                node: second,
+                parent: second
+            })
+        );
+
+        graph.set_parent(child, None).unwrap();
+        assert_eq!(graph.roots(), [first, second, child]);
+        graph.update();
+        assert_eq!(world_position(&graph, child), Vec3::z());
+    }
+
+    #[test]
+    fn removing_a_node_removes_its_descendants() {
+        let mut graph = SceneGraph::new();
+        let root = graph.add(Node::new("root"));
+        let child = graph.add_child(root, Node::new("child")).unwrap();
+        let grandchild = graph.add_child(child, Node::new("grandchild")).unwrap();
+        let other = graph.add(Node::new("other"));
+
+        assert_eq!(
+            graph.remove(child).map(|node| node.name),
+            Some("child".into())
+        );
+        assert!(!graph.contains(grandchild));
+        assert!(graph.get(root).unwrap().children().is_empty());
+        assert_eq!(graph.len(), 2);
+
+        // A reused slot does not revive stale ids
+        let reused = graph.add(Node::new("reused"));
+        assert!(!graph.contains(child) && !graph.contains(grandchild));
+        assert!(graph.contains(reused) && graph.contains(other));
+        assert_eq!(
+            graph.add_child(child, Node::new("orphan")),
+            Err(SceneGraphError::MissingNode(child))
+        );
+    }
+
+    #[test]
+    fn iteration_visits_parents_before_their_children() {
+        let mut graph = SceneGraph::new();
+        let a = graph.add(Node::new("a"));
+        let b = graph.add(Node::new("b"));
+        graph.add_child(a, Node::new("a1")).unwrap();
+        let a2 = graph.add_child(a, Node::new("a2")).unwrap();
+        graph.add_child(a2, Node::new("a2x")).unwrap();
+        graph.add_child(b, Node::new("b1")).unwrap();
+
+        let names = graph
+            .iter()
+            .map(|(_, node)| node.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a", "a1", "a2", "a2x", "b", "b1"]);
+    }
+
+    #[test]
+    fn extract_buckets_node_instances_by_mesh() {
+        let mut graph = SceneGraph::new();
+        let first = graph.add(Node::new("first").with_mesh(1));
+        let camera = graph.add(Node::new("camera"));
+        let second = graph
+            .add_child(
+                camera,
+                Node::new("second")
+                    .with_mesh(1)
+                    .with_transform(Transform::from_translation(Vec3::x())),
+            )
+            .unwrap();
+        graph.update();
+
+        let mut extract = GraphExtract::default();
+        extract.extract(&graph);
+        assert!(extract.nodes(0).is_empty());
+        assert_eq!(extract.nodes(1), [first, second]);
+        assert_eq!(
+            extract.instances(1)[1].transform,
+            *graph.world_matrix(second).unwrap()
+        );
+        assert!(extract.instances(2).is_empty());
+
+        graph.remove(first);
+        extract.extract(&graph);
+        assert_eq!(extract.nodes(1), [second]);
+    }
+}
@@ -0,0 +1,377 @@
+use std::collections::HashMap;
+
+mod preprocessor;
+mod reflection;
+
+pub use preprocessor::*;
+pub use reflection::*;
+
+/// Every shader file the engine uses. They are embedded into the binary so wasm builds,
+/// and native builds without the shader directory, always have a source to fall back on.
+const EMBEDDED_SHADERS: &[(&str, &str)] = &[
+    ("scene.wgsl", include_str!("../shaders/scene.wgsl")),
+    (
+        "scene_uniform.wgsl",
+        include_str!("../shaders/scene_uniform.wgsl"),
+    ),
+    (
+        "shadow_sampling.wgsl",
+        include_str!("../shaders/shadow_sampling.wgsl"),
+    ),
+    (
+        "environment_sampling.wgsl",
+        include_str!("../shaders/environment_sampling.wgsl"),
+    ),
+    (
+        "instancing.wgsl",
+        include_str!("../shaders/instancing.wgsl"),
+    ),
+    ("shadow.wgsl", include_str!("../shaders/shadow.wgsl")),
+    ("skybox.wgsl", include_str!("../shaders/skybox.wgsl")),
+    (
+        "environment_bake.wgsl",
+        include_str!("../shaders/environment_bake.wgsl"),
+    ),
+    ("particle.wgsl", include_str!("../shaders/particle.wgsl")),
+    (
+        "particle_simulation.wgsl",
+        include_str!("../shaders/particle_simulation.wgsl"),
+    ),
+    ("particles.wgsl", include_str!("../shaders/particles.wgsl")),
+    (
+        "debug_lines.wgsl",
+        include_str!("../shaders/debug_lines.wgsl"),
+    ),
+    ("grid.wgsl", include_str!("../shaders/grid.wgsl")),
+    ("culling.wgsl", include_str!("../shaders/culling.wgsl")),
+    ("picking.wgsl", include_str!("../shaders/picking.wgsl")),
+];
+
+/// Where native builds read shaders from and watch them for changes,
+/// relative to the working directory unless `SHADER_DIRECTORY_VARIABLE` is set
+#[cfg(not(target_arch = "wasm32"))]
+const SHADER_DIRECTORY: &str = "shaders";
+
+/// The environment variable that points native builds at another shader directory,
+/// such as the engine's own while developing it
+#[cfg(not(target_arch = "wasm32"))]
+const SHADER_DIRECTORY_VARIABLE: &str = "SHADER_DIRECTORY";
+
+/// A pipeline that failed to build from the current shader sources
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShaderError {
+    pub pipeline: &'static str,
+    pub message: String,
+}
+
+/// The WGSL sources of the engine, keyed by file name.
+/// On native the sources are loaded from disk and reloaded when the files change.
+pub struct ShaderLibrary {
+    sources: HashMap<&'static str, String>,
+    errors: Vec<ShaderError>,
+    #[cfg(not(target_arch = "wasm32"))]
+    watcher: Option<ShaderWatcher>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+struct ShaderWatcher {
+    // Stops watching when dropped
+    _watcher: notify::RecommendedWatcher,
+    events: std::sync::mpsc::Receiver<notify::Result<notify::Event>>,
+}
+
+impl ShaderLibrary {
+    pub fn new() -> Self {
+        let mut sources = EMBEDDED_SHADERS
+            .iter()
+            .map(|(name, source)| (*name, source.to_string()))
+            .collect::<HashMap<_, _>>();
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let directory = std::env::var_os(SHADER_DIRECTORY_VARIABLE)
+                .map(std::path::PathBuf::from)
+                .unwrap_or_else(|| SHADER_DIRECTORY.into());
+            if !directory.is_dir() {
+                log::info!(
+                    "Using the embedded shaders, there is no shader directory at {}",
+                    directory.display()
+                );
+                return Self {
+                    sources,
+                    errors: Vec::new(),
+                    watcher: None,
+                };
+            }
+
+            let mut missing = Vec::new();
+            for (name, source) in sources.iter_mut() {
+                match std::fs::read_to_string(directory.join(name)) {
+                    Ok(contents) => *source = contents,
+                    Err(_) => missing.push(*name),
+                }
+            }
+            if !missing.is_empty() {
+                missing.sort_unstable();
+                log::warn!(
+                    "Using the embedded shaders missing from {}: {}",
+                    directory.display(),
+                    missing.join(", ")
+                );
+            }
+            Self {
+                sources,
+                errors: Vec::new(),
+                watcher: ShaderWatcher::new(&directory),
+            }
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        Self {
+            sources,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Builds a pipeline from the current sources. When they fail to preprocess, reflect or compile
+    /// the error is recorded and the pipeline is built from the embedded sources instead.
+    pub fn build<T>(
+        &mut self,
+        device: &wgpu::Device,
+        pipeline: &'static str,
+        create: impl Fn(Sources) -> Result<T, PipelineError>,
+    ) -> T {
+        let result = self.validated_create(device, &create);
+        self.report(pipeline, &result);
+        result.unwrap_or_else(|_| {
+            create(Sources::Embedded)
+                .unwrap_or_else(|error| panic!("Embedded shaders failed to build: {error}"))
+        })
+    }
+
+    /// Rebuilds a pipeline after its sources changed, returning `None`
+    /// so the previous pipeline is kept when they fail to preprocess, reflect or compile
+    pub fn rebuild<T>(
+        &mut self,
+        device: &wgpu::Device,
+        pipeline: &'static str,
+        create: impl Fn(Sources) -> Result<T, PipelineError>,
+    ) -> Option<T> {
+        let result = self.validated_create(device, &create);
+        self.report(pipeline, &result);
+        result.ok()
+    }
+
+    fn validated_create<T>(
+        &self,
+        device: &wgpu::Device,
+        create: &impl Fn(Sources) -> Result<T, PipelineError>,
+    ) -> Result<T, String> {
+        validated(device, || create(Sources::Current(self)))
+            .and_then(|result| result.map_err(|error| error.to_string()))
+    }
+
+    /// Rereads every shader file that changed on disk since the last call,
+    /// returning the names of the shaders whose source changed
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn poll_changes(&mut self) -> Vec<&'static str> {
+        let Some(watcher) = self.watcher.as_ref() else {
+            return Vec::new();
+        };
+        let mut changed = Vec::new();
+        for event in watcher.events.try_iter() {
+            let event = match event {
+                Ok(event) => event,
+                Err(error) => {
+                    log::warn!("Shader watcher error: {error}");
+                    continue;
+                }
+            };
+            if !(event.kind.is_modify() || event.kind.is_create()) {
+                continue;
+            }
+            for path in event.paths.iter() {
+                let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+                    continue;
+                };
+                let Some((name, _)) = EMBEDDED_SHADERS.iter().find(|(name, _)| *name == file_name)
+                else {
+                    continue;
+                };
+                // Editors often truncate before writing, so empty reads are skipped
+                let Ok(contents) = std::fs::read_to_string(path) else {
+                    continue;
+                };
+                if contents.is_empty() || self.sources.get(name) == Some(&contents) {
+                    continue;
+                }
+                log::info!("Reloading shader {name}");
+                self.sources.insert(name, contents);
+                if !changed.contains(name) {
+                    changed.push(*name);
+                }
+            }
+        }
+        changed
+    }
+
+    /// Shaders are embedded on wasm, so they never change
+    #[cfg(target_arch = "wasm32")]
+    pub fn poll_changes(&mut self) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    pub fn errors(&self) -> &[ShaderError] {
+        &self.errors
+    }
+
+    /// Records the outcome of building a pipeline, replacing its previous error
+    fn report<T>(&mut self, pipeline: &'static str, result: &Result<T, String>) {
+        self.errors.retain(|error| error.pipeline != pipeline);
+        if let Err(message) = result {
+            log::error!("Failed to build the {pipeline} pipeline:\n{message}");
+            self.errors.push(ShaderError {
+                pipeline,
+                message: message.clone(),
+            });
+        }
+    }
+}
+
+/// Why a pipeline could not be created from its shader sources
+#[derive(Debug, Clone, PartialEq)]
+pub enum PipelineError {
+    Preprocess(PreprocessError),
+    Reflection(ReflectionError),
+    /// The shader failed to compile or the pipeline did not match it
+    Validation(String),
+}
+
+impl std::fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Preprocess(error) => write!(f, "{error}"),
+            Self::Reflection(error) => write!(f, "{error}"),
+            Self::Validation(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for PipelineError {}
+
+impl From<PreprocessError> for PipelineError {
+    fn from(error: PreprocessError) -> Self {
+        Self::Preprocess(error)
+    }
+}
+
+impl From<ReflectionError> for PipelineError {
+    fn from(error: ReflectionError) -> Self {
+        Self::Reflection(error)
+    }
+}
+
+/// Where a pipeline reads its shader sources from
+#[derive(Copy, Clone)]
+pub enum Sources<'a> {
+    Current(&'a ShaderLibrary),
+    Embedded,
+}
+
+impl<'a> Sources<'a> {
+    /// Expands the includes and conditionals of a shader for one permutation
+    pub fn preprocess(
+        &self,
+        name: &str,
+        defines: &ShaderDefines,
+    ) -> Result<String, PreprocessError> {
+        preprocess(name, defines, |include| self.find(include))
+    }
+
+    fn find(&self, name: &str) -> Option<&'a str> {
+        match self {
+            Self::Current(library) => library.sources.get(name).map(String::as_str),
+            Self::Embedded => EMBEDDED_SHADERS
+                .iter()
+                .find(|(embedded, _)| *embedded == name)
+                .map(|(_, source)| *source),
+        }
+    }
+}
+
+/// Pipelines compiled from the same shaders, one per define set
+pub struct PermutationCache<T> {
+    permutations: HashMap<ShaderDefines, T>,
+}
+
+impl<T> Default for PermutationCache<T> {
+    fn default() -> Self {
+        Self {
+            permutations: HashMap::new(),
+        }
+    }
+}
+
+impl<T> PermutationCache<T> {
+    pub fn get(&self, defines: &ShaderDefines) -> Option<&T> {
+        self.permutations.get(defines)
+    }
+
+    pub fn contains(&self, defines: &ShaderDefines) -> bool {
+        self.permutations.contains_key(defines)
+    }
+
+    pub fn insert(&mut self, defines: ShaderDefines, value: T) {
+        self.permutations.insert(defines, value);
+    }
+
+    /// Drops every permutation, so they are rebuilt from fresh sources when next used
+    pub fn clear(&mut self) {
+        self.permutations.clear();
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ShaderWatcher {
+    fn new(directory: &std::path::Path) -> Option<Self> {
+        use notify::Watcher;
+
+        let (sender, events) = std::sync::mpsc::channel();
+        let watcher = notify::recommended_watcher(move |event| {
+            let _ = sender.send(event);
+        })
+        .and_then(|mut watcher| {
+            watcher.watch(directory, notify::RecursiveMode::NonRecursive)?;
+            Ok(watcher)
+        });
+        match watcher {
+            Ok(watcher) => Some(Self {
+                _watcher: watcher,
+                events,
+            }),
+            Err(error) => {
+                log::warn!("Shader hot reloading is disabled: {error}");
+                None
+            }
+        }
+    }
+}
+
+/// Runs `create` inside a validation error scope, so shader compilation
+/// and pipeline errors are returned instead of reaching the uncaptured error handler
+#[cfg(not(target_arch = "wasm32"))]
+pub fn validated<T>(device: &wgpu::Device, create: impl FnOnce() -> T) -> Result<T, String> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let value = create();
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(error) => Err(error.to_string()),
+        None => Ok(value),
+    }
+}
+
+/// Error scopes resolve asynchronously on the web, and the embedded shaders
+/// are known to compile, so they are created directly
+#[cfg(target_arch = "wasm32")]
+pub fn validated<T>(_device: &wgpu::Device, create: impl FnOnce() -> T) -> Result<T, String> {
+    Ok(create())
+}
@@ -0,0 +1,431 @@
+use std::collections::{BTreeMap, HashSet};
+
+/// A set of preprocessor defines. Defines are kept sorted,
+/// so equal sets compare and hash equally and can key a cache of pipelines.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ShaderDefines {
+    defines: BTreeMap<String, String>,
+}
+
+impl ShaderDefines {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a feature toggle, checked with `#ifdef`
+    pub fn with(mut self, name: impl Into<String>) -> Self {
+        self.define(name, "");
+        self
+    }
+
+    /// Adds a define whose value replaces every occurrence of its name in the source
+    pub fn with_value(mut self, name: impl Into<String>, value: impl ToString) -> Self {
+        self.define(name, value);
+        self
+    }
+
+    pub fn define(&mut self, name: impl Into<String>, value: impl ToString) {
+        self.defines.insert(name.into(), value.to_string());
+    }
+
+    pub fn undefine(&mut self, name: &str) {
+        self.defines.remove(name);
+    }
+
+    pub fn is_defined(&self, name: &str) -> bool {
+        self.defines.contains_key(name)
+    }
+
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.defines.get(name).map(String::as_str)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PreprocessError {
+    MissingInclude {
+        file: String,
+        line: usize,
+        include: String,
+    },
+    IncludeCycle {
+        file: String,
+        line: usize,
+        include: String,
+    },
+    InvalidDirective {
+        file: String,
+        line: usize,
+        message: String,
+    },
+    UnterminatedConditional {
+        file: String,
+    },
+}
+
+impl std::fmt::Display for PreprocessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingInclude {
+                file,
+                line,
+                include,
+            } => write!(f, "{file}:{line}: included file {include} was not found"),
+            Self::IncludeCycle {
+                file,
+                line,
+                include,
+            } => write!(f, "{file}:{line}: including {include} forms a cycle"),
+            Self::InvalidDirective {
+                file,
+                line,
+                message,
+            } => write!(f, "{file}:{line}: {message}"),
+            Self::UnterminatedConditional { file } => {
+                write!(f, "{file}: #ifdef or #ifndef is missing its #endif")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PreprocessError {}
+
+/// Expands `#include "file.wgsl"`, `#define NAME [value]`, `#undef NAME`,
+/// `#ifdef NAME`, `#ifndef NAME`, `#else`, and `#endif` directives.
+/// Each file is included at most once, so shared declarations are never duplicated.
+/// Defines with a value replace whole identifiers matching their name.
+pub fn preprocess<'a>(
+    name: &str,
+    defines: &ShaderDefines,
+    mut resolve: impl FnMut(&str) -> Option<&'a str>,
+) -> Result<String, PreprocessError> {
+    let source = resolve(name).ok_or_else(|| PreprocessError::MissingInclude {
+        file: name.to_string(),
+        line: 0,
+        include: name.to_string(),
+    })?;
+    let mut preprocessor = Preprocessor {
+        defines: defines.clone(),
+        included: HashSet::from([name.to_string()]),
+        stack: vec![name.to_string()],
+        output: String::with_capacity(source.len()),
+        resolve: &mut resolve,
+    };
+    preprocessor.process(name, source)?;
+    Ok(preprocessor.output)
+}
+
+struct Preprocessor<'r, 'a> {
+    defines: ShaderDefines,
+    included: HashSet<String>,
+    /// The files currently being expanded, used to detect include cycles
+    stack: Vec<String>,
+    output: String,
+    resolve: &'r mut dyn FnMut(&str) -> Option<&'a str>,
+}
+
+struct Conditional {
+    /// Whether the enclosing block is emitted
+    parent_active: bool,
+    condition: bool,
+    in_else: bool,
+}
+
+impl Conditional {
+    fn active(&self) -> bool {
+        self.parent_active && (self.condition != self.in_else)
+    }
+}
+
+impl Preprocessor<'_, '_> {
+    fn process(&mut self, file: &str, source: &str) -> Result<(), PreprocessError> {
+        let mut conditionals: Vec<Conditional> = Vec::new();
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+            let active = conditionals.last().is_none_or(Conditional::active);
+            let invalid = |message: String| PreprocessError::InvalidDirective {
+                file: file.to_string(),
+                line: line_number,
+                message,
+            };
+
+            let Some(directive) = line.trim_start().strip_prefix('#') else {
+                if active {
+                    self.emit(line);
+                }
+                continue;
+            };
+            let mut words = directive.split_whitespace();
+            let keyword = words.next().unwrap_or_default();
+            let argument = words.next();
+            let rest = words.collect::<Vec<_>>().join(" ");
+
+            match keyword {
+                "ifdef" | "ifndef" => {
+                    let name = argument
+                        .ok_or_else(|| invalid(format!("#{keyword} needs a define name")))?;
+                    conditionals.push(Conditional {
+                        parent_active: active,
+                        condition: self.defines.is_defined(name) == (keyword == "ifdef"),
+                        in_else: false,
+                    });
+                }
+                "else" => {
+                    let conditional = conditionals
+                        .last_mut()
+                        .filter(|conditional| !conditional.in_else)
+                        .ok_or_else(|| invalid("#else without a matching #ifdef".to_string()))?;
+                    conditional.in_else = true;
+                }
+                "endif" => {
+                    conditionals
+                        .pop()
+                        .ok_or_else(|| invalid("#endif without a matching #ifdef".to_string()))?;
+                }
+                _ if !active => {}
+                "define" => {
+                    let name = argument
+                        .ok_or_else(|| invalid("#define needs a define name".to_string()))?;
+                    self.defines.define(name, rest);
+                }
+                "undef" => {
+                    let name = argument
+                        .ok_or_else(|| invalid("#undef needs a define name".to_string()))?;
+                    self.defines.undefine(name);
+                }
+                "include" => {
+                    let include = argument
+                        .and_then(|argument| argument.strip_prefix('"'))
+                        .and_then(|argument| argument.strip_suffix('"'))
+                        .ok_or_else(|| invalid("#include needs a quoted file name".to_string()))?;
+                    self.include(file, line_number, include)?;
+                }
+                _ => return Err(invalid(format!("unknown directive #{keyword}"))),
+            }
+        }
+
+        if conditionals.is_empty() {
+            Ok(())
+        } else {
+            Err(PreprocessError::UnterminatedConditional {
+                file: file.to_string(),
+            })
+        }
+    }
+
+    fn include(&mut self, file: &str, line: usize, include: &str) -> Result<(), PreprocessError> {
+        if self.stack.iter().any(|open| open == include) {
+            return Err(PreprocessError::IncludeCycle {
+                file: file.to_string(),
+                line,
+                include: include.to_string(),
+            });
+        }
+        if !self.included.insert(include.to_string()) {
+            return Ok(());
+        }
+        let source = (self.resolve)(include).ok_or_else(|| PreprocessError::MissingInclude {
+            file: file.to_string(),
+            line,
+            include: include.to_string(),
+        })?;
+        self.stack.push(include.to_string());
+        self.process(include, source)?;
+        self.stack.pop();
+        Ok(())
+    }
+
+    fn emit(&mut self, line: &str) {
+        let mut rest = line;
+        while let Some(start) = rest.find(is_identifier_start) {
+            let (before, from_start) = rest.split_at(start);
+            let end = from_start
+                .find(|character: char| !is_identifier_character(character))
+                .unwrap_or(from_start.len());
+            let (identifier, after) = from_start.split_at(end);
+            self.output.push_str(before);
+            match self.defines.value(identifier) {
+                Some(value) if !value.is_empty() => self.output.push_str(value),
+                _ => self.output.push_str(identifier),
+            }
+            rest = after;
+        }
+        self.output.push_str(rest);
+        self.output.push('\n');
+    }
+}
+
+fn is_identifier_start(character: char) -> bool {
+    character.is_ascii_alphabetic() || character == '_'
+}
+
+fn is_identifier_character(character: char) -> bool {
+    character.is_ascii_alphanumeric() || character == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files<'a>(files: &'a [(&str, &'a str)]) -> impl FnMut(&str) -> Option<&'a str> + 'a {
+        move |name| {
+            files
+                .iter()
+                .find(|(file, _)| *file == name)
+                .map(|(_, source)| *source)
+        }
+    }
+
+    fn run(source: &str, defines: &ShaderDefines) -> Result<String, PreprocessError> {
+        preprocess("main.wgsl", defines, files(&[("main.wgsl", source)]))
+    }
+
+    #[test]
+    fn passes_plain_source_through() {
+        let source = "fn main() {\n    return;\n}\n";
+        assert_eq!(run(source, &ShaderDefines::new()).unwrap(), source);
+    }
+
+    #[test]
+    fn ifdef_selects_branch() {
+        let source = "#ifdef SHADOWS\nshadowed\n#else\nlit\n#endif\n";
+        assert_eq!(run(source, &ShaderDefines::new()).unwrap(), "lit\n");
+        assert_eq!(
+            run(source, &ShaderDefines::new().with("SHADOWS")).unwrap(),
+            "shadowed\n"
+        );
+    }
+
+    #[test]
+    fn ifndef_and_nested_conditionals() {
+        let source =
+            "#ifndef A\nno_a\n#ifdef B\nno_a_b\n#endif\n#else\na\n#ifdef B\na_b\n#endif\n#endif\n";
+        assert_eq!(run(source, &ShaderDefines::new()).unwrap(), "no_a\n");
+        assert_eq!(
+            run(source, &ShaderDefines::new().with("B")).unwrap(),
+            "no_a\nno_a_b\n"
+        );
+        assert_eq!(
+            run(source, &ShaderDefines::new().with("A").with("B")).unwrap(),
+            "a\na_b\n"
+        );
+    }
+
+    #[test]
+    fn defines_in_source_apply_to_later_lines() {
+        let source = "#define FAST\n#ifdef FAST\nfast\n#endif\n#undef FAST\n#ifdef FAST\nstill_fast\n#endif\n";
+        assert_eq!(run(source, &ShaderDefines::new()).unwrap(), "fast\n");
+    }
+
+    #[test]
+    fn defines_inside_inactive_blocks_are_ignored() {
+        let source = "#ifdef MISSING\n#define FAST\n#endif\n#ifdef FAST\nfast\n#endif\n";
+        assert_eq!(run(source, &ShaderDefines::new()).unwrap(), "");
+    }
+
+    #[test]
+    fn values_replace_whole_identifiers() {
+        let source =
+            "var<uniform> lights: array<Light, MAX_LIGHTS>;\nlet MAX_LIGHTS_SQUARED = 0;\n";
+        assert_eq!(
+            run(
+                source,
+                &ShaderDefines::new().with_value("MAX_LIGHTS", "16u")
+            )
+            .unwrap(),
+            "var<uniform> lights: array<Light, 16u>;\nlet MAX_LIGHTS_SQUARED = 0;\n"
+        );
+    }
+
+    #[test]
+    fn includes_are_expanded_once() {
+        let sources = [
+            (
+                "main.wgsl",
+                "#include \"common.wgsl\"\n#include \"lighting.wgsl\"\nmain\n",
+            ),
+            ("lighting.wgsl", "#include \"common.wgsl\"\nlighting\n"),
+            ("common.wgsl", "common\n"),
+        ];
+        assert_eq!(
+            preprocess("main.wgsl", &ShaderDefines::new(), files(&sources)).unwrap(),
+            "common\nlighting\nmain\n"
+        );
+    }
+
+    #[test]
+    fn includes_inside_inactive_blocks_are_skipped() {
+        let sources = [(
+            "main.wgsl",
+            "#ifdef SHADOWS\n#include \"missing.wgsl\"\n#endif\nmain\n",
+        )];
+        assert_eq!(
+            preprocess("main.wgsl", &ShaderDefines::new(), files(&sources)).unwrap(),
+            "main\n"
+        );
+    }
+
+    #[test]
+    fn missing_include_reports_location() {
+        let sources = [("main.wgsl", "main\n#include \"missing.wgsl\"\n")];
+        assert_eq!(
+            preprocess("main.wgsl", &ShaderDefines::new(), files(&sources)),
+            Err(PreprocessError::MissingInclude {
+                file: "main.wgsl".to_string(),
+                line: 2,
+                include: "missing.wgsl".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn include_cycle_is_an_error() {
+        let sources = [
+            ("main.wgsl", "#include \"a.wgsl\"\n"),
+            ("a.wgsl", "#include \"b.wgsl\"\n"),
+            ("b.wgsl", "#include \"a.wgsl\"\n"),
+        ];
+        assert_eq!(
+            preprocess("main.wgsl", &ShaderDefines::new(), files(&sources)),
+            Err(PreprocessError::IncludeCycle {
+                file: "b.wgsl".to_string(),
+                line: 1,
+                include: "a.wgsl".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn unbalanced_conditionals_are_errors() {
+        assert_eq!(
+            run("#ifdef A\na\n", &ShaderDefines::new()),
+            Err(PreprocessError::UnterminatedConditional {
+                file: "main.wgsl".to_string()
+            })
+        );
+        assert!(matches!(
+            run("a\n#endif\n", &ShaderDefines::new()),
+            Err(PreprocessError::InvalidDirective { line: 2, .. })
+        ));
+        assert!(matches!(
+            run("#ifdef A\n#else\n#else\n#endif\n", &ShaderDefines::new()),
+            Err(PreprocessError::InvalidDirective { line: 3, .. })
+        ));
+        assert!(matches!(
+            run("#pragma once\n", &ShaderDefines::new()),
+            Err(PreprocessError::InvalidDirective { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn define_sets_are_order_independent() {
+        let first = ShaderDefines::new()
+            .with("SHADOWS")
+            .with_value("MAX_LIGHTS", 16);
+        let second = ShaderDefines::new()
+            .with_value("MAX_LIGHTS", 16)
+            .with("SHADOWS");
+        assert_eq!(first, second);
+        assert_ne!(first, second.with("ENVIRONMENT"));
+    }
+}
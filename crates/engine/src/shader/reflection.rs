@@ -0,0 +1,412 @@
+/// Bind group layouts and vertex inputs read from a WGSL module, used to build layouts
+/// that always match the shader and to check the Rust side vertex layouts against it
+pub struct ShaderReflection {
+    module: naga::Module,
+    info: naga::valid::ModuleInfo,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReflectionError {
+    Parse(String),
+    Validation(String),
+    MissingEntryPoint(String),
+    UnsupportedBinding {
+        group: u32,
+        binding: u32,
+    },
+    MissingVertexInput {
+        entry_point: String,
+        location: u32,
+    },
+    VertexInputMismatch {
+        entry_point: String,
+        location: u32,
+        shader: String,
+        rust: wgpu::VertexFormat,
+    },
+}
+
+impl std::fmt::Display for ReflectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Parse(message) | Self::Validation(message) => write!(f, "{message}"),
+            Self::MissingEntryPoint(name) => write!(f, "entry point {name} was not found"),
+            Self::UnsupportedBinding { group, binding } => write!(
+                f,
+                "@group({group}) @binding({binding}) has a type that cannot be reflected"
+            ),
+            Self::MissingVertexInput {
+                entry_point,
+                location,
+            } => write!(
+                f,
+                "{entry_point} reads @location({location}), but no vertex buffer provides it"
+            ),
+            Self::VertexInputMismatch {
+                entry_point,
+                location,
+                shader,
+                rust,
+            } => write!(
+                f,
+                "{entry_point} reads @location({location}) as {shader}, but the vertex buffer provides {rust:?}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReflectionError {}
+
+impl ShaderReflection {
+    pub fn new(source: &str) -> Result<Self, ReflectionError> {
+        let module = naga::front::wgsl::parse_str(source)
+            .map_err(|error| ReflectionError::Parse(error.emit_to_string(source)))?;
+        let info = naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::all(),
+        )
+        .validate(&module)
+        .map_err(|error| ReflectionError::Validation(error.emit_to_string(source)))?;
+        Ok(Self { module, info })
+    }
+
+    /// The entries of a bind group, visible to every stage that uses them.
+    /// Uniform buffers get their size as the minimum binding size. Dynamic offsets
+    /// cannot be expressed in WGSL, so they are left for the caller to enable.
+    pub fn bind_group_layout_entries(
+        &self,
+        group: u32,
+    ) -> Result<Vec<wgpu::BindGroupLayoutEntry>, ReflectionError> {
+        let mut entries = Vec::new();
+        for (handle, variable) in self.module.global_variables.iter() {
+            let Some(binding) = variable
+                .binding
+                .as_ref()
+                .filter(|binding| binding.group == group)
+            else {
+                continue;
+            };
+            let unsupported = || ReflectionError::UnsupportedBinding {
+                group,
+                binding: binding.binding,
+            };
+            let inner = &self.module.types[variable.ty].inner;
+            let ty = match (variable.space, inner) {
+                (naga::AddressSpace::Uniform, _) => wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: wgpu::BufferSize::new(inner.size(self.module.to_ctx()) as u64),
+                },
+                (naga::AddressSpace::Storage { access }, _) => wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage {
+                        read_only: !access.contains(naga::StorageAccess::STORE),
+                    },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                (naga::AddressSpace::Handle, naga::TypeInner::Sampler { comparison }) => {
+                    wgpu::BindingType::Sampler(if *comparison {
+                        wgpu::SamplerBindingType::Comparison
+                    } else {
+                        wgpu::SamplerBindingType::Filtering
+                    })
+                }
+                (
+                    naga::AddressSpace::Handle,
+                    naga::TypeInner::Image {
+                        dim,
+                        arrayed,
+                        class,
+                    },
+                ) => {
+                    let view_dimension = match (dim, arrayed) {
+                        (naga::ImageDimension::D1, false) => wgpu::TextureViewDimension::D1,
+                        (naga::ImageDimension::D2, false) => wgpu::TextureViewDimension::D2,
+                        (naga::ImageDimension::D2, true) => wgpu::TextureViewDimension::D2Array,
+                        (naga::ImageDimension::D3, false) => wgpu::TextureViewDimension::D3,
+                        (naga::ImageDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
+                        (naga::ImageDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
+                        _ => return Err(unsupported()),
+                    };
+                    match class {
+                        naga::ImageClass::Sampled { kind, multi } => wgpu::BindingType::Texture {
+                            sample_type: match kind {
+                                naga::ScalarKind::Float => {
+                                    wgpu::TextureSampleType::Float { filterable: true }
+                                }
+                                naga::ScalarKind::Sint => wgpu::TextureSampleType::Sint,
+                                naga::ScalarKind::Uint => wgpu::TextureSampleType::Uint,
+                                _ => return Err(unsupported()),
+                            },
+                            view_dimension,
+                            multisampled: *multi,
+                        },
+                        naga::ImageClass::Depth { multi } => wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Depth,
+                            view_dimension,
+                            multisampled: *multi,
+                        },
+                        naga::ImageClass::Storage { .. } => return Err(unsupported()),
+                    }
+                }
+                _ => return Err(unsupported()),
+            };
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: binding.binding,
+                visibility: self.visibility(handle),
+                ty,
+                count: None,
+            });
+        }
+        entries.sort_by_key(|entry| entry.binding);
+        Ok(entries)
+    }
+
+    /// Checks that every location the vertex entry point reads is provided by one
+    /// of the buffers, with the same scalar kind and component count
+    pub fn validate_vertex_buffers(
+        &self,
+        entry_point: &str,
+        buffers: &[wgpu::VertexBufferLayout],
+    ) -> Result<(), ReflectionError> {
+        let function = &self
+            .module
+            .entry_points
+            .iter()
+            .find(|candidate| {
+                candidate.name == entry_point && candidate.stage == naga::ShaderStage::Vertex
+            })
+            .ok_or_else(|| ReflectionError::MissingEntryPoint(entry_point.to_string()))?
+            .function;
+
+        for (location, ty) in self.vertex_inputs(function) {
+            let format = buffers
+                .iter()
+                .flat_map(|buffer| buffer.attributes.iter())
+                .find(|attribute| attribute.shader_location == location)
+                .map(|attribute| attribute.format)
+                .ok_or_else(|| ReflectionError::MissingVertexInput {
+                    entry_point: entry_point.to_string(),
+                    location,
+                })?;
+            let expected = match &self.module.types[ty].inner {
+                naga::TypeInner::Scalar(scalar) => Some((scalar.kind, 1)),
+                naga::TypeInner::Vector { size, scalar } => Some((scalar.kind, *size as u32)),
+                _ => None,
+            };
+            if expected.is_none() || expected != vertex_format_shape(format) {
+                return Err(ReflectionError::VertexInputMismatch {
+                    entry_point: entry_point.to_string(),
+                    location,
+                    shader: self.module.types[ty].inner.to_wgsl(&self.module.to_ctx()),
+                    rust: format,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// The locations read by a function, looking inside struct arguments
+    fn vertex_inputs(&self, function: &naga::Function) -> Vec<(u32, naga::Handle<naga::Type>)> {
+        let mut inputs = Vec::new();
+        for argument in function.arguments.iter() {
+            match (&argument.binding, &self.module.types[argument.ty].inner) {
+                (Some(naga::Binding::Location { location, .. }), _) => {
+                    inputs.push((*location, argument.ty));
+                }
+                (None, naga::TypeInner::Struct { members, .. }) => {
+                    inputs.extend(members.iter().filter_map(|member| match member.binding {
+                        Some(naga::Binding::Location { location, .. }) => {
+                            Some((location, member.ty))
+                        }
+                        _ => None,
+                    }));
+                }
+                _ => {}
+            }
+        }
+        inputs
+    }
+
+    fn visibility(&self, handle: naga::Handle<naga::GlobalVariable>) -> wgpu::ShaderStages {
+        self.module
+            .entry_points
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !self.info.get_entry_point(*index)[handle].is_empty())
+            .fold(wgpu::ShaderStages::NONE, |stages, (_, entry_point)| {
+                stages
+                    | match entry_point.stage {
+                        naga::ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+                        naga::ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+                        naga::ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+                    }
+            })
+    }
+}
+
+/// The scalar kind and component count a vertex format is read as in a shader
+fn vertex_format_shape(format: wgpu::VertexFormat) -> Option<(naga::ScalarKind, u32)> {
+    use wgpu::VertexFormat::*;
+    let kind = match format {
+        Uint8x2 | Uint8x4 | Uint16x2 | Uint16x4 | Uint32 | Uint32x2 | Uint32x3 | Uint32x4 => {
+            naga::ScalarKind::Uint
+        }
+        Sint8x2 | Sint8x4 | Sint16x2 | Sint16x4 | Sint32 | Sint32x2 | Sint32x3 | Sint32x4 => {
+            naga::ScalarKind::Sint
+        }
+        Float64 | Float64x2 | Float64x3 | Float64x4 => return None,
+        _ => naga::ScalarKind::Float,
+    };
+    let components = match format {
+        Uint32 | Sint32 | Float32 => 1,
+        Uint8x2 | Sint8x2 | Unorm8x2 | Snorm8x2 | Uint16x2 | Sint16x2 | Unorm16x2 | Snorm16x2
+        | Float16x2 | Float32x2 | Uint32x2 | Sint32x2 => 2,
+        Float32x3 | Uint32x3 | Sint32x3 => 3,
+        _ => 4,
+    };
+    Some((kind, components))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::{Instance, Vertex};
+
+    const SOURCE: &str = "
+struct Camera {
+    view_projection: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+@group(0) @binding(1)
+var color_texture: texture_2d<f32>;
+
+@group(0) @binding(2)
+var color_sampler: sampler;
+
+@group(1) @binding(0)
+var shadow_map: texture_depth_2d_array;
+
+struct VertexInput {
+    @location(0) position: vec4<f32>,
+    @location(3) tex_coords: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vertex_main(vert: VertexInput, @location(8) color: vec4<f32>) -> VertexOutput {
+    var out: VertexOutput;
+    out.position = camera.view_projection * vert.position * color.a;
+    out.tex_coords = vert.tex_coords;
+    return out;
+}
+
+@fragment
+fn fragment_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(color_texture, color_sampler, in.tex_coords);
+}
+";
+
+    #[test]
+    fn reflects_bind_group_entries() {
+        let reflection = ShaderReflection::new(SOURCE).unwrap();
+        let entries = reflection.bind_group_layout_entries(0).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].visibility, wgpu::ShaderStages::VERTEX);
+        assert_eq!(
+            entries[0].ty,
+            wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: wgpu::BufferSize::new(64),
+            }
+        );
+        assert_eq!(entries[1].visibility, wgpu::ShaderStages::FRAGMENT);
+        assert_eq!(
+            entries[1].ty,
+            wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            }
+        );
+        assert_eq!(
+            entries[2].ty,
+            wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering)
+        );
+
+        // Declared but unused bindings are not visible to any stage
+        let entries = reflection.bind_group_layout_entries(1).unwrap();
+        assert_eq!(entries[0].visibility, wgpu::ShaderStages::NONE);
+        assert_eq!(
+            entries[0].ty,
+            wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Depth,
+                view_dimension: wgpu::TextureViewDimension::D2Array,
+                multisampled: false,
+            }
+        );
+    }
+
+    #[test]
+    fn vertex_inputs_match_engine_layouts() {
+        let reflection = ShaderReflection::new(SOURCE).unwrap();
+        let vertex_attributes = Vertex::vertex_attributes();
+        let instance_attributes = Instance::vertex_attributes();
+        let buffers = [
+            Vertex::description(&vertex_attributes),
+            Instance::description(&instance_attributes),
+        ];
+        assert_eq!(
+            reflection.validate_vertex_buffers("vertex_main", &buffers),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn reports_vertex_input_mismatches() {
+        let reflection = ShaderReflection::new(SOURCE).unwrap();
+        let vertex_attributes = Vertex::vertex_attributes();
+        assert_eq!(
+            reflection
+                .validate_vertex_buffers("vertex_main", &[Vertex::description(&vertex_attributes)]),
+            Err(ReflectionError::MissingVertexInput {
+                entry_point: "vertex_main".to_string(),
+                location: 8,
+            })
+        );
+
+        let attributes = wgpu::vertex_attr_array![
+            0 => Float32x4,
+            3 => Float32x4,
+            8 => Float32x4
+        ];
+        assert!(matches!(
+            reflection.validate_vertex_buffers("vertex_main", &[Vertex::description(&attributes)]),
+            Err(ReflectionError::VertexInputMismatch {
+                location: 3,
+                rust: wgpu::VertexFormat::Float32x4,
+                ..
+            })
+        ));
+        assert_eq!(
+            reflection.validate_vertex_buffers("missing", &[]),
+            Err(ReflectionError::MissingEntryPoint("missing".to_string()))
+        );
+    }
+
+    #[test]
+    fn reports_parse_errors() {
+        assert!(matches!(
+            ShaderReflection::new("fn broken( {"),
+            Err(ReflectionError::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,725 @@
+use std::sync::Arc;
+
+use crate::{
+    buffer::UniformBuffer,
+    indirect::MeshArena,
+    light::Light,
+    mesh::{Instance, Vertex},
+    pipeline::{PipelineBuilder, PipelineCache},
+    renderer::{Gpu, InstanceBuffer, SceneMesh, Slots},
+    shader::{PipelineError, ShaderDefines, ShaderLibrary, ShaderReflection, Sources},
+    shader_type,
+};
+
+pub const MAX_SHADOW_CASCADES: usize = 4;
+pub const MAX_SPOT_SHADOWS: usize = 2;
+pub const MAX_POINT_SHADOWS: usize = 1;
+
+/// Point lights render one layer per cube face, in +X, -X, +Y, -Y, +Z, -Z order
+const CUBE_FACES: usize = 6;
+
+pub const SHADOW_LAYER_COUNT: usize =
+    MAX_SHADOW_CASCADES + MAX_SPOT_SHADOWS + MAX_POINT_SHADOWS * CUBE_FACES;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ShadowSettings {
+    pub enabled: bool,
+    /// Width and height of every shadow map layer, in texels
+    pub resolution: u32,
+    /// Number of cascades used by the first directional light, up to `MAX_SHADOW_CASCADES`
+    pub cascade_count: usize,
+    /// Blends between uniform (0.0) and logarithmic (1.0) cascade splits
+    pub cascade_split_lambda: f32,
+    /// Distance from the camera beyond which directional shadows are not drawn
+    pub max_distance: f32,
+    /// Constant offset subtracted from the receiver depth
+    pub depth_bias: f32,
+    /// Offset applied along the surface normal, scaled by the shadow map texel size
+    pub normal_bias: f32,
+    /// Percentage closer filtering kernel radius in texels. Zero takes a single sample.
+    pub pcf_radius: u32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            resolution: 1024,
+            cascade_count: MAX_SHADOW_CASCADES,
+            cascade_split_lambda: 0.75,
+            max_distance: 50.0,
+            depth_bias: 0.0005,
+            normal_bias: 1.5,
+            pcf_radius: 1,
+        }
+    }
+}
+
+/// The camera a frame is rendered from, used to fit the directional light cascades
+pub struct ShadowCamera {
+    pub view: nalgebra_glm::Mat4,
+    pub aspect_ratio: f32,
+    pub fov_y: f32,
+    pub z_near: f32,
+}
+
+/// Every shadow casting light renders into layers of one depth texture array.
+/// The first directional light gets the cascades, followed by spot lights
+/// and the six faces of each point light.
+pub struct ShadowMaps {
+    pub settings: ShadowSettings,
+    pub texture: wgpu::Texture,
+    /// Every layer of the shadow map, as sampled by the scene
+    pub view: wgpu::TextureView,
+    pub layer_views: Vec<wgpu::TextureView>,
+    pub pipeline: Arc<wgpu::RenderPipeline>,
+    pass_buffer: UniformBuffer<ShadowPassUniform>,
+    pub pass_bind_group_layout: wgpu::BindGroupLayout,
+    pub pass_bind_group: wgpu::BindGroup,
+    sampling_buffer: UniformBuffer<ShadowUniform>,
+    pub sampling_bind_group_layout: wgpu::BindGroupLayout,
+    pub sampling_bind_group: wgpu::BindGroup,
+    pub comparison_sampler: wgpu::Sampler,
+    /// The first shadow layer of each light, in the order lights were given to `update`
+    pub light_layers: Vec<Option<u32>>,
+    active_layers: Vec<bool>,
+    resolution: u32,
+}
+
+impl ShadowMaps {
+    /// The shadow shader along with every file it includes
+    pub const SHADERS: [&str; 2] = ["shadow.wgsl", "instancing.wgsl"];
+
+    /// Takes the shadow sampling bind group layout reflected from the scene shader
+    pub fn new(
+        gpu: &Gpu,
+        sampling_layout_entries: &[wgpu::BindGroupLayoutEntry],
+        shaders: &mut ShaderLibrary,
+        pipelines: &PipelineCache,
+    ) -> Self {
+        let settings = ShadowSettings::default();
+        let device = &gpu.device;
+
+        let texture = gpu.create_depth_texture_array(
+            settings.resolution,
+            settings.resolution,
+            SHADOW_LAYER_COUNT as u32,
+        );
+        let view = Self::create_view(&texture);
+        let layer_views = Self::create_layer_views(&texture);
+
+        let pass_buffer =
+            UniformBuffer::new_dynamic(device, "Shadow Pass Buffer", SHADOW_LAYER_COUNT);
+        let pass_layout_entries = shaders.build(device, "Shadow Reflection", |sources| {
+            Self::pass_bind_group_layout_entries(sources)
+        });
+        let pass_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &pass_layout_entries,
+                label: Some("shadow_pass_bind_group_layout"),
+            });
+        let pass_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pass_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: pass_buffer.binding(),
+            }],
+            label: Some("shadow_pass_bind_group"),
+        });
+
+        let sampling_buffer =
+            UniformBuffer::new(device, "Shadow Buffer", &ShadowUniform::default());
+        let comparison_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+        let sampling_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: sampling_layout_entries,
+                label: Some("shadow_bind_group_layout"),
+            });
+        let sampling_bind_group = Self::create_sampling_bind_group(
+            device,
+            &sampling_bind_group_layout,
+            &sampling_buffer,
+            &view,
+            &comparison_sampler,
+        );
+
+        let pipeline = shaders.build(device, "Shadow", |sources| {
+            Self::create_pipeline(device, &pass_bind_group_layout, sources, pipelines)
+        });
+
+        Self {
+            resolution: settings.resolution,
+            settings,
+            texture,
+            view,
+            layer_views,
+            pipeline,
+            pass_buffer,
+            pass_bind_group_layout,
+            pass_bind_group,
+            sampling_buffer,
+            sampling_bind_group_layout,
+            sampling_bind_group,
+            comparison_sampler,
+            light_layers: Vec::new(),
+            active_layers: vec![false; SHADOW_LAYER_COUNT],
+        }
+    }
+
+    /// Assigns shadow layers to lights and computes the light space matrix of every layer
+    pub fn update(&mut self, gpu: &Gpu, lights: &[Light], camera: &ShadowCamera) {
+        if self.settings.resolution != self.resolution {
+            self.resize(gpu);
+        }
+
+        let settings = self.settings;
+        let cascade_count = settings.cascade_count.clamp(1, MAX_SHADOW_CASCADES);
+        let mut uniform = ShadowUniform {
+            settings: [
+                settings.depth_bias,
+                settings.normal_bias,
+                settings.pcf_radius as f32,
+                1.0 / settings.resolution as f32,
+            ],
+            ..Default::default()
+        };
+
+        self.light_layers.clear();
+        self.active_layers
+            .iter_mut()
+            .for_each(|active| *active = false);
+        let mut has_directional = false;
+        let mut spot_count = 0;
+        let mut point_count = 0;
+
+        for light in lights.iter().take(crate::MAX_LIGHTS) {
+            let layer = match *light {
+                _ if !settings.enabled => None,
+                Light::Directional { direction, .. } if !has_directional => {
+                    has_directional = true;
+                    let splits = cascade_splits(
+                        camera.z_near,
+                        settings.max_distance,
+                        cascade_count,
+                        settings.cascade_split_lambda,
+                    );
+                    let mut near = camera.z_near;
+                    for (cascade, far) in splits.iter().enumerate() {
+                        uniform.layer_view_projections[cascade] = cascade_view_projection(
+                            camera,
+                            near,
+                            *far,
+                            &direction,
+                            settings.resolution,
+                        );
+                        uniform.cascade_splits[cascade] = *far;
+                        near = *far;
+                    }
+                    uniform.cascade_splits[cascade_count..]
+                        .iter_mut()
+                        .for_each(|split| *split = f32::MAX);
+                    Some(0)
+                }
+                Light::Spot {
+                    position,
+                    direction,
+                    range,
+                    outer_cone_angle,
+                    ..
+                } if spot_count < MAX_SPOT_SHADOWS => {
+                    let layer = MAX_SHADOW_CASCADES + spot_count;
+                    spot_count += 1;
+                    uniform.layer_view_projections[layer] =
+                        spot_view_projection(&position, &direction, range, outer_cone_angle);
+                    Some(layer as u32)
+                }
+                Light::Point {
+                    position, range, ..
+                } if point_count < MAX_POINT_SHADOWS => {
+                    let layer = MAX_SHADOW_CASCADES + MAX_SPOT_SHADOWS + point_count * CUBE_FACES;
+                    point_count += 1;
+                    uniform.layer_view_projections[layer..layer + CUBE_FACES]
+                        .copy_from_slice(&point_view_projections(&position, range));
+                    Some(layer as u32)
+                }
+                _ => None,
+            };
+
+            if let Some(layer) = layer {
+                let layer_count = match light {
+                    Light::Directional { .. } => cascade_count,
+                    Light::Spot { .. } => 1,
+                    Light::Point { .. } => CUBE_FACES,
+                };
+                let layer = layer as usize;
+                self.active_layers[layer..layer + layer_count]
+                    .iter_mut()
+                    .for_each(|active| *active = true);
+            }
+            self.light_layers.push(layer);
+        }
+
+        for (layer, view_projection) in uniform.layer_view_projections.iter().enumerate() {
+            self.pass_buffer.write_at(
+                &gpu.queue,
+                layer,
+                &ShadowPassUniform {
+                    view_projection: *view_projection,
+                },
+            );
+        }
+        self.sampling_buffer.write(&gpu.queue, &uniform);
+    }
+
+    /// Renders the depth of every mesh into each active shadow layer
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        meshes: &Slots<SceneMesh>,
+        arena: &MeshArena,
+        instances: &InstanceBuffer,
+    ) {
+        for (layer, view) in self.layer_views.iter().enumerate() {
+            if !self.active_layers[layer] {
+                continue;
+            }
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(
+                0,
+                &self.pass_bind_group,
+                &[self.pass_buffer.dynamic_offset(layer)],
+            );
+            render_pass.set_vertex_buffer(0, arena.vertex_buffer().slice(..));
+            render_pass.set_vertex_buffer(1, instances.buffer.slice(..));
+            render_pass.set_index_buffer(arena.index_buffer().slice(..), wgpu::IndexFormat::Uint32);
+            // Every instance is drawn, as those outside the view may still cast shadows into it
+            for level in meshes.iter().flat_map(|(_, mesh)| &mesh.levels) {
+                if level.instance_count == 0 {
+                    continue;
+                }
+                render_pass.draw_indexed(
+                    level.range.indices.clone(),
+                    level.range.base_vertex(),
+                    level.instance_range(),
+                );
+            }
+        }
+    }
+
+    fn resize(&mut self, gpu: &Gpu) {
+        self.resolution = self
+            .settings
+            .resolution
+            .clamp(1, gpu.device.limits().max_texture_dimension_2d);
+        self.settings.resolution = self.resolution;
+        self.texture = gpu.create_depth_texture_array(
+            self.resolution,
+            self.resolution,
+            SHADOW_LAYER_COUNT as u32,
+        );
+        self.view = Self::create_view(&self.texture);
+        self.layer_views = Self::create_layer_views(&self.texture);
+        self.sampling_bind_group = Self::create_sampling_bind_group(
+            &gpu.device,
+            &self.sampling_bind_group_layout,
+            &self.sampling_buffer,
+            &self.view,
+            &self.comparison_sampler,
+        );
+    }
+
+    fn create_view(texture: &wgpu::Texture) -> wgpu::TextureView {
+        texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Shadow Map View"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        })
+    }
+
+    fn create_layer_views(texture: &wgpu::Texture) -> Vec<wgpu::TextureView> {
+        (0..SHADOW_LAYER_COUNT as u32)
+            .map(|layer| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Shadow Layer View"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+
+    fn create_sampling_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        buffer: &UniformBuffer<ShadowUniform>,
+        view: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+            label: Some("shadow_bind_group"),
+        })
+    }
+
+    /// The view projection of the layer being rendered, reflected from the shadow shader.
+    /// Every layer reads its own slot of one buffer through a dynamic offset.
+    fn pass_bind_group_layout_entries(
+        sources: Sources,
+    ) -> Result<Vec<wgpu::BindGroupLayoutEntry>, PipelineError> {
+        let shader_source = sources.preprocess("shadow.wgsl", &ShaderDefines::new())?;
+        let mut entries = ShaderReflection::new(&shader_source)?.bind_group_layout_entries(0)?;
+        for entry in entries.iter_mut() {
+            if let wgpu::BindingType::Buffer {
+                has_dynamic_offset, ..
+            } = &mut entry.ty
+            {
+                *has_dynamic_offset = true;
+            }
+        }
+        Ok(entries)
+    }
+
+    pub fn create_pipeline(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        sources: Sources,
+        pipelines: &PipelineCache,
+    ) -> Result<Arc<wgpu::RenderPipeline>, PipelineError> {
+        let shader_source = sources.preprocess("shadow.wgsl", &ShaderDefines::new())?;
+        let vertex_attributes = Vertex::vertex_attributes();
+        let instance_attributes = Instance::vertex_attributes();
+        let buffers = [
+            Vertex::description(&vertex_attributes),
+            Instance::description(&instance_attributes),
+        ];
+        ShaderReflection::new(&shader_source)?.validate_vertex_buffers("vertex_main", &buffers)?;
+
+        PipelineBuilder::depth_only("Shadow", &shader_source)
+            .bind_group_layouts(&[bind_group_layout])
+            .vertex_buffers(&buffers)
+            .depth_format(wgpu::TextureFormat::Depth32Float)
+            .depth_test(wgpu::CompareFunction::LessEqual, true)
+            .depth_bias(wgpu::DepthBiasState {
+                constant: 2,
+                slope_scale: 2.0,
+                clamp: 0.0,
+            })
+            .build(device, pipelines)
+    }
+}
+
+const SHADOW_Z_NEAR: f32 = 0.05;
+
+/// Splits the view distance into cascades, returning the far distance of each
+fn cascade_splits(z_near: f32, z_far: f32, count: usize, lambda: f32) -> Vec<f32> {
+    (1..=count)
+        .map(|index| {
+            let fraction = index as f32 / count as f32;
+            let logarithmic = z_near * (z_far / z_near).powf(fraction);
+            let uniform = z_near + (z_far - z_near) * fraction;
+            lambda * logarithmic + (1.0 - lambda) * uniform
+        })
+        .collect()
+}
+
+/// Fits an orthographic projection around the bounding sphere of a slice of the
+/// camera frustum. Snapping to whole texels keeps the shadow edges from shimmering.
+fn cascade_view_projection(
+    camera: &ShadowCamera,
+    near: f32,
+    far: f32,
+    direction: &nalgebra_glm::Vec3,
+    resolution: u32,
+) -> nalgebra_glm::Mat4 {
+    let projection = nalgebra_glm::perspective_lh_zo(camera.aspect_ratio, camera.fov_y, near, far);
+    let inverse = nalgebra_glm::inverse(&(projection * camera.view));
+    let mut corners = Vec::with_capacity(8);
+    for x in [-1.0, 1.0] {
+        for y in [-1.0, 1.0] {
+            for z in [0.0, 1.0] {
+                let corner = inverse * nalgebra_glm::vec4(x, y, z, 1.0);
+                corners.push(corner.xyz() / corner.w);
+            }
+        }
+    }
+
+    let center = corners
+        .iter()
+        .fold(nalgebra_glm::Vec3::zeros(), |sum, corner| sum + corner)
+        / corners.len() as f32;
+    let radius = corners
+        .iter()
+        .map(|corner| nalgebra_glm::distance(corner, &center))
+        .fold(0.0_f32, f32::max)
+        .ceil();
+
+    let direction = direction.normalize();
+    let view = nalgebra_glm::look_at_lh(
+        &(center - direction * radius),
+        &center,
+        &up_vector(&direction),
+    );
+    // Casters behind the camera slice still need to reach the map
+    let depth_extension = radius * 4.0;
+    let projection = nalgebra_glm::ortho_lh_zo(
+        -radius,
+        radius,
+        -radius,
+        radius,
+        -depth_extension,
+        radius * 2.0,
+    );
+
+    let view_projection = projection * view;
+    let origin = view_projection * nalgebra_glm::vec4(0.0, 0.0, 0.0, 1.0);
+    let texel_scale = resolution as f32 / 2.0;
+    let snapped = nalgebra_glm::vec2(
+        (origin.x * texel_scale).round() / texel_scale,
+        (origin.y * texel_scale).round() / texel_scale,
+    );
+    let offset = nalgebra_glm::translation(&nalgebra_glm::vec3(
+        snapped.x - origin.x,
+        snapped.y - origin.y,
+        0.0,
+    ));
+    offset * view_projection
+}
+
+/// Perspective projection for a light that reaches `range`. The far plane is kept
+/// past the near plane, so lights with a tiny range still get a valid projection.
+fn light_projection(fov_y: f32, range: f32) -> nalgebra_glm::Mat4 {
+    nalgebra_glm::perspective_lh_zo(1.0, fov_y, SHADOW_Z_NEAR, range.max(SHADOW_Z_NEAR * 2.0))
+}
+
+fn spot_view_projection(
+    position: &nalgebra_glm::Vec3,
+    direction: &nalgebra_glm::Vec3,
+    range: f32,
+    outer_cone_angle: f32,
+) -> nalgebra_glm::Mat4 {
+    let projection = light_projection(
+        (outer_cone_angle * 2.0).clamp(0.01, std::f32::consts::PI - 0.01),
+        range,
+    );
+    let view = nalgebra_glm::look_at_lh(position, &(position + direction), &up_vector(direction));
+    projection * view
+}
+
+/// One view projection per cube face, in the order of `cube_face_directions`
+fn point_view_projections(
+    position: &nalgebra_glm::Vec3,
+    range: f32,
+) -> [nalgebra_glm::Mat4; CUBE_FACES] {
+    let projection = light_projection(std::f32::consts::FRAC_PI_2, range);
+    cube_face_directions().map(|direction| {
+        let view =
+            nalgebra_glm::look_at_lh(position, &(position + direction), &up_vector(&direction));
+        projection * view
+    })
+}
+
+fn cube_face_directions() -> [nalgebra_glm::Vec3; CUBE_FACES] {
+    [
+        nalgebra_glm::vec3(1.0, 0.0, 0.0),
+        nalgebra_glm::vec3(-1.0, 0.0, 0.0),
+        nalgebra_glm::vec3(0.0, 1.0, 0.0),
+        nalgebra_glm::vec3(0.0, -1.0, 0.0),
+        nalgebra_glm::vec3(0.0, 0.0, 1.0),
+        nalgebra_glm::vec3(0.0, 0.0, -1.0),
+    ]
+}
+
+fn up_vector(direction: &nalgebra_glm::Vec3) -> nalgebra_glm::Vec3 {
+    if direction.normalize().y.abs() > 0.99 {
+        nalgebra_glm::Vec3::z()
+    } else {
+        nalgebra_glm::Vec3::y()
+    }
+}
+
+shader_type! {
+    #[repr(C)]
+    #[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+    struct ShadowPassUniform {
+        view_projection: nalgebra_glm::Mat4,
+    }
+}
+
+shader_type! {
+    #[repr(C)]
+    #[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+    struct ShadowUniform {
+        layer_view_projections: [nalgebra_glm::Mat4; SHADOW_LAYER_COUNT],
+        /// The view distance at which each cascade ends
+        cascade_splits: [f32; 4],
+        /// x: depth bias, y: normal bias, z: pcf radius, w: texel size
+        settings: [f32; 4],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(
+        view_projection: &nalgebra_glm::Mat4,
+        point: nalgebra_glm::Vec3,
+    ) -> nalgebra_glm::Vec3 {
+        let clip = view_projection * nalgebra_glm::vec4(point.x, point.y, point.z, 1.0);
+        clip.xyz() / clip.w
+    }
+
+    fn is_inside_clip_volume(ndc: &nalgebra_glm::Vec3) -> bool {
+        ndc.x.abs() <= 1.0 && ndc.y.abs() <= 1.0 && (0.0..=1.0).contains(&ndc.z)
+    }
+
+    #[test]
+    fn pass_layout_is_reflected_with_a_dynamic_offset() {
+        let entries = ShadowMaps::pass_bind_group_layout_entries(Sources::Embedded).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            entries[0].ty,
+            wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: true,
+                min_binding_size: wgpu::BufferSize::new(
+                    std::mem::size_of::<ShadowPassUniform>() as _
+                ),
+            }
+        );
+        assert_eq!(entries[0].visibility, wgpu::ShaderStages::VERTEX);
+    }
+
+    #[test]
+    fn cascade_splits_end_at_the_far_distance() {
+        let splits = cascade_splits(0.1, 50.0, 4, 0.75);
+        assert_eq!(splits.len(), 4);
+        assert!(splits.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!((splits[3] - 50.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn cascade_split_lambda_blends_uniform_and_logarithmic() {
+        let uniform = cascade_splits(1.0, 100.0, 2, 0.0);
+        assert!((uniform[0] - 50.5).abs() < 1e-3);
+        let logarithmic = cascade_splits(1.0, 100.0, 2, 1.0);
+        assert!((logarithmic[0] - 10.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn cascade_contains_its_slice_of_the_view_frustum() {
+        let camera = ShadowCamera {
+            view: nalgebra_glm::look_at_lh(
+                &nalgebra_glm::vec3(0.0, 2.0, -5.0),
+                &nalgebra_glm::Vec3::zeros(),
+                &nalgebra_glm::Vec3::y(),
+            ),
+            aspect_ratio: 16.0 / 9.0,
+            fov_y: 80_f32.to_radians(),
+            z_near: 0.1,
+        };
+        let view_projection = cascade_view_projection(
+            &camera,
+            1.0,
+            10.0,
+            &nalgebra_glm::vec3(-0.3, -1.0, 0.2),
+            1024,
+        );
+
+        let projection =
+            nalgebra_glm::perspective_lh_zo(camera.aspect_ratio, camera.fov_y, 1.0, 10.0);
+        let inverse = nalgebra_glm::inverse(&(projection * camera.view));
+        for x in [-1.0, 1.0] {
+            for y in [-1.0, 1.0] {
+                for z in [0.0, 1.0] {
+                    let corner = inverse * nalgebra_glm::vec4(x, y, z, 1.0);
+                    let ndc = project(&view_projection, corner.xyz() / corner.w);
+                    assert!(
+                        is_inside_clip_volume(&ndc),
+                        "{ndc:?} is outside the cascade"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn spot_light_projects_points_along_its_direction() {
+        let position = nalgebra_glm::vec3(1.0, 4.0, 0.0);
+        let direction = nalgebra_glm::vec3(0.0, -1.0, 0.0);
+        let view_projection = spot_view_projection(&position, &direction, 10.0, 0.5);
+
+        let ndc = project(&view_projection, position + direction * 5.0);
+        assert!(ndc.x.abs() < 1e-4 && ndc.y.abs() < 1e-4);
+        assert!(is_inside_clip_volume(&ndc));
+        let beyond = project(&view_projection, position + direction * 11.0);
+        assert!(beyond.z > 1.0);
+    }
+
+    #[test]
+    fn point_light_faces_look_along_each_axis() {
+        let position = nalgebra_glm::vec3(0.0, 1.0, 2.0);
+        let view_projections = point_view_projections(&position, 5.0);
+        for (view_projection, direction) in view_projections.iter().zip(cube_face_directions()) {
+            let ndc = project(view_projection, position + direction * 2.0);
+            assert!(ndc.x.abs() < 1e-4 && ndc.y.abs() < 1e-4);
+            assert!(is_inside_clip_volume(&ndc));
+        }
+    }
+
+    #[test]
+    fn tiny_light_ranges_keep_a_valid_projection() {
+        for range in [0.0, SHADOW_Z_NEAR, 0.01] {
+            let view_projection = spot_view_projection(
+                &nalgebra_glm::Vec3::zeros(),
+                &nalgebra_glm::Vec3::z(),
+                range,
+                0.5,
+            );
+            let near = project(&view_projection, nalgebra_glm::vec3(0.0, 0.0, 0.06));
+            let far = project(&view_projection, nalgebra_glm::vec3(0.0, 0.0, 0.09));
+            assert!(near.z.is_finite() && far.z.is_finite());
+            assert!(near.z < far.z);
+        }
+    }
+}
@@ -0,0 +1,362 @@
+use std::{collections::HashMap, sync::Arc};
+
+use futures::{future::Shared, FutureExt};
+
+pub use pack::{Pack, ASSET_PACK};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VfsError {
+    /// No mount holds the path, so the next mount may be tried
+    NotFound {
+        path: String,
+    },
+    Read {
+        path: String,
+        message: String,
+    },
+    InvalidPack {
+        path: String,
+        message: String,
+    },
+}
+
+impl std::fmt::Display for VfsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound { path } => write!(f, "{path:?} was not found"),
+            Self::Read { path, message } => write!(f, "Failed to read {path:?}: {message}"),
+            Self::InvalidPack { path, message } => {
+                write!(f, "{path:?} is not a valid pack: {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for VfsError {}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub type VfsFuture<T> = futures::future::BoxFuture<'static, Result<T, VfsError>>;
+
+/// Fetches are not `Send` on the web, where reads run on the main thread
+#[cfg(target_arch = "wasm32")]
+pub type VfsFuture<T> = futures::future::LocalBoxFuture<'static, Result<T, VfsError>>;
+
+/// Mounts are read from the asset loading threads on native
+#[cfg(not(target_arch = "wasm32"))]
+pub trait MaybeSendSync: Send + Sync {}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<T: Send + Sync> MaybeSendSync for T {}
+
+#[cfg(target_arch = "wasm32")]
+pub trait MaybeSendSync {}
+
+#[cfg(target_arch = "wasm32")]
+impl<T> MaybeSendSync for T {}
+
+/// A source of files, attached to the `Vfs` at a mount point
+pub trait Mount: MaybeSendSync {
+    /// Reads a file by its '/' separated path relative to the mount point.
+    /// Fails with `VfsError::NotFound` if the mount does not hold it.
+    fn read(&self, path: &str) -> VfsFuture<Vec<u8>>;
+
+    /// The directory on disk the files are read from, watched for changes on native
+    #[cfg(not(target_arch = "wasm32"))]
+    fn directory(&self) -> Option<&std::path::Path> {
+        None
+    }
+}
+
+/// Files in a directory on native, or under a url relative to the page on the web
+pub struct DirectoryMount {
+    root: String,
+}
+
+impl DirectoryMount {
+    pub fn new(root: impl Into<String>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl Mount for DirectoryMount {
+    fn read(&self, path: &str) -> VfsFuture<Vec<u8>> {
+        let file = format!("{}/{path}", self.root);
+        let path = path.to_string();
+        Box::pin(async move { read_file(&file).await.map_err(|error| error.at(path)) })
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn directory(&self) -> Option<&std::path::Path> {
+        Some(std::path::Path::new(&self.root))
+    }
+}
+
+/// Files held in memory, such as assets embedded into the executable
+#[derive(Default)]
+pub struct MemoryMount {
+    files: HashMap<String, Arc<[u8]>>,
+}
+
+impl MemoryMount {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_file(mut self, path: impl Into<String>, contents: impl Into<Arc<[u8]>>) -> Self {
+        self.insert(path, contents);
+        self
+    }
+
+    pub fn insert(&mut self, path: impl Into<String>, contents: impl Into<Arc<[u8]>>) {
+        self.files.insert(path.into(), contents.into());
+    }
+}
+
+impl Mount for MemoryMount {
+    fn read(&self, path: &str) -> VfsFuture<Vec<u8>> {
+        let result = self
+            .files
+            .get(path)
+            .map(|contents| contents.to_vec())
+            .ok_or_else(|| VfsError::NotFound {
+                path: path.to_string(),
+            });
+        Box::pin(futures::future::ready(result))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+type PackFuture = futures::future::BoxFuture<'static, Arc<Result<Pack, VfsError>>>;
+
+#[cfg(target_arch = "wasm32")]
+type PackFuture = futures::future::LocalBoxFuture<'static, Arc<Result<Pack, VfsError>>>;
+
+/// The files of a pack, read in full by the first read from it
+pub struct PackMount {
+    pack: Shared<PackFuture>,
+}
+
+impl PackMount {
+    /// Reads the pack file at `path`, on disk on native and relative to the page on the web.
+    /// While the file is missing, the mount behaves as if it were empty.
+    pub fn open(path: impl Into<String>) -> Self {
+        let path = path.into();
+        let pack: PackFuture = Box::pin(async move {
+            let pack = match read_file(&path).await {
+                Ok(bytes) => Pack::parse(bytes).map_err(|message| VfsError::InvalidPack {
+                    path: path.clone(),
+                    message,
+                }),
+                Err(error) => Err(error.at(path.clone())),
+            };
+            if let Err(error @ VfsError::InvalidPack { .. }) = &pack {
+                log::error!("{error}");
+            }
+            Arc::new(pack)
+        });
+        Self {
+            pack: pack.shared(),
+        }
+    }
+
+    pub fn new(pack: Pack) -> Self {
+        let pack: PackFuture = Box::pin(futures::future::ready(Arc::new(Ok(pack))));
+        Self {
+            pack: pack.shared(),
+        }
+    }
+}
+
+impl Mount for PackMount {
+    fn read(&self, path: &str) -> VfsFuture<Vec<u8>> {
+        let pack = self.pack.clone();
+        let path = path.to_string();
+        Box::pin(async move {
+            match &*pack.await {
+                Ok(pack) => pack
+                    .get(&path)
+                    .map(<[u8]>::to_vec)
+                    .ok_or(VfsError::NotFound { path }),
+                // A pack that could not be read holds nothing
+                Err(VfsError::NotFound { .. } | VfsError::Read { .. }) => {
+                    Err(VfsError::NotFound { path })
+                }
+                Err(error) => Err(error.clone()),
+            }
+        })
+    }
+}
+
+/// Why reading a file failed, before the path it was read by is known
+enum FileError {
+    NotFound,
+    Other(String),
+}
+
+impl FileError {
+    fn at(self, path: String) -> VfsError {
+        match self {
+            Self::NotFound => VfsError::NotFound { path },
+            Self::Other(message) => VfsError::Read { path, message },
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn read_file(path: &str) -> Result<Vec<u8>, FileError> {
+    std::fs::read(path).map_err(|error| match error.kind() {
+        std::io::ErrorKind::NotFound => FileError::NotFound,
+        _ => FileError::Other(error.to_string()),
+    })
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn read_file(path: &str) -> Result<Vec<u8>, FileError> {
+    use wasm_bindgen::JsCast;
+    let describe = |error: wasm_bindgen::JsValue| FileError::Other(format!("{error:?}"));
+    let window =
+        web_sys::window().ok_or(FileError::Other("There is no window to fetch from".into()))?;
+    let response = wasm_bindgen_futures::JsFuture::from(window.fetch_with_str(path))
+        .await
+        .map_err(describe)?
+        .dyn_into::<web_sys::Response>()
+        .map_err(describe)?;
+    if response.status() == 404 {
+        return Err(FileError::NotFound);
+    }
+    if !response.ok() {
+        return Err(FileError::Other(format!(
+            "The server responded with {}",
+            response.status()
+        )));
+    }
+    let buffer = wasm_bindgen_futures::JsFuture::from(response.array_buffer().map_err(describe)?)
+        .await
+        .map_err(describe)?;
+    Ok(js_sys::Uint8Array::new(&buffer).to_vec())
+}
+
+/// Reads files from the mounts attached to it.
+/// Mounted later, a mount shadows the files of earlier mounts at the same paths.
+#[derive(Default)]
+pub struct Vfs {
+    mounts: Vec<(String, Box<dyn Mount>)>,
+}
+
+impl std::fmt::Debug for Vfs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Vfs")
+            .field(
+                "mount_points",
+                &self
+                    .mounts
+                    .iter()
+                    .map(|(point, _)| point)
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl Vfs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The directory at `root` mounted at the top
+    pub fn directory(root: impl Into<String>) -> Self {
+        Self::new().with_mount("", DirectoryMount::new(root))
+    }
+
+    pub fn with_mount(mut self, point: impl Into<String>, mount: impl Mount + 'static) -> Self {
+        self.mount(point, mount);
+        self
+    }
+
+    /// Attaches `mount` below `point`, a '/' separated path that is empty for the top
+    pub fn mount(&mut self, point: impl Into<String>, mount: impl Mount + 'static) {
+        let point = point.into().trim_matches('/').to_string();
+        self.mounts.push((point, Box::new(mount)));
+    }
+
+    /// Reads a file from the last mount holding it
+    pub fn read(&self, path: &str) -> VfsFuture<Vec<u8>> {
+        // Reads are started up front, but only run when awaited
+        let reads = self
+            .mounts
+            .iter()
+            .rev()
+            .filter_map(|(point, mount)| Some(mount.read(relative(point, path)?)))
+            .collect::<Vec<_>>();
+        let path = path.to_string();
+        Box::pin(async move {
+            for read in reads {
+                match read.await {
+                    Err(VfsError::NotFound { .. }) => continue,
+                    result => return result,
+                }
+            }
+            Err(VfsError::NotFound { path })
+        })
+    }
+
+    /// The directories on disk and the mount points they are attached to
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn directories(&self) -> impl Iterator<Item = (&str, &std::path::Path)> {
+        self.mounts
+            .iter()
+            .filter_map(|(point, mount)| Some((point.as_str(), mount.directory()?)))
+    }
+}
+
+/// The path below a mount point, if `path` lies below it
+fn relative<'a>(point: &str, path: &'a str) -> Option<&'a str> {
+    if point.is_empty() {
+        return Some(path);
+    }
+    path.strip_prefix(point)?.strip_prefix('/')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::block_on;
+
+    #[test]
+    fn later_mounts_shadow_earlier_ones() {
+        let vfs = Vfs::new()
+            .with_mount(
+                "",
+                MemoryMount::new()
+                    .with_file("a.txt", b"base".as_slice())
+                    .with_file("textures/b.txt", b"base".as_slice()),
+            )
+            .with_mount(
+                "textures/",
+                PackMount::new(
+                    Pack::parse(Pack::write([("b.txt".into(), b"pack".to_vec())])).unwrap(),
+                ),
+            );
+
+        assert_eq!(block_on(vfs.read("a.txt")).unwrap(), b"base");
+        assert_eq!(block_on(vfs.read("textures/b.txt")).unwrap(), b"pack");
+        assert_eq!(
+            block_on(vfs.read("textures/c.txt")),
+            Err(VfsError::NotFound {
+                path: "textures/c.txt".into()
+            })
+        );
+    }
+
+    #[test]
+    fn missing_packs_fall_through() {
+        let vfs = Vfs::new()
+            .with_mount(
+                "",
+                MemoryMount::new().with_file("a.txt", b"memory".as_slice()),
+            )
+            .with_mount("", PackMount::open("/nonexistent/assets.pak"));
+        assert_eq!(block_on(vfs.read("a.txt")).unwrap(), b"memory");
+    }
+}
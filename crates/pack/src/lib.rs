@@ -0,0 +1,195 @@
+//! Reads and writes asset packs. Kept apart from the engine,
+//! so `cargo xtask pack` builds without wgpu and egui.
+
+use std::{collections::HashMap, ops::Range};
+
+/// Where native builds read assets from, relative to the working directory,
+/// and the url web builds fetch them from, relative to the page
+pub const ASSET_DIRECTORY: &str = "assets";
+
+/// The archive `cargo xtask pack` writes, which web builds fetch all their assets from at once
+pub const ASSET_PACK: &str = "assets.pak";
+
+/// Identifies pack files, followed by the format version
+const PACK_MAGIC: &[u8; 4] = b"PAK\0";
+const PACK_VERSION: u32 = 1;
+
+/// The files of a pack, indexing into its bytes
+#[derive(Debug)]
+pub struct Pack {
+    bytes: Vec<u8>,
+    files: HashMap<String, Range<usize>>,
+}
+
+impl Pack {
+    /// Packs files into one archive. Files are stored in path order, so the same files
+    /// always give the same bytes.
+    pub fn write(files: impl IntoIterator<Item = (String, Vec<u8>)>) -> Vec<u8> {
+        let mut files = files.into_iter().collect::<Vec<_>>();
+        files.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let index_size = files
+            .iter()
+            .map(|(path, _)| 4 + path.len() + 8 + 8)
+            .sum::<usize>();
+        let mut offset = (PACK_MAGIC.len() + 4 + 4 + index_size) as u64;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(PACK_MAGIC);
+        bytes.extend_from_slice(&PACK_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(files.len() as u32).to_le_bytes());
+        for (path, contents) in &files {
+            bytes.extend_from_slice(&(path.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(path.as_bytes());
+            bytes.extend_from_slice(&offset.to_le_bytes());
+            bytes.extend_from_slice(&(contents.len() as u64).to_le_bytes());
+            offset += contents.len() as u64;
+        }
+        for (_, contents) in files {
+            bytes.extend(contents);
+        }
+        bytes
+    }
+
+    /// Packs every file below `directory`, by paths relative to it
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn write_directory(directory: &std::path::Path) -> std::io::Result<Vec<u8>> {
+        fn visit(
+            directory: &std::path::Path,
+            prefix: &str,
+            files: &mut Vec<(String, Vec<u8>)>,
+        ) -> std::io::Result<()> {
+            for entry in std::fs::read_dir(directory)? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let path = format!("{prefix}{name}");
+                if entry.file_type()?.is_dir() {
+                    visit(&entry.path(), &format!("{path}/"), files)?;
+                } else {
+                    files.push((path, std::fs::read(entry.path())?));
+                }
+            }
+            Ok(())
+        }
+
+        let mut files = Vec::new();
+        visit(directory, "", &mut files)?;
+        Ok(Self::write(files))
+    }
+
+    /// Reads the index of a pack. Offsets that do not fit the address space,
+    /// such as those of large packs on wasm32, are rejected like corrupt ones.
+    pub fn parse(bytes: Vec<u8>) -> Result<Self, String> {
+        let mut cursor = 0usize;
+        let mut take = |length: usize| {
+            let end = cursor
+                .checked_add(length)
+                .filter(|end| *end <= bytes.len())
+                .ok_or("The pack ends early")?;
+            let taken = &bytes[cursor..end];
+            cursor = end;
+            Ok::<_, String>(taken)
+        };
+        let to_usize = |value: u64| {
+            usize::try_from(value).map_err(|_| format!("The offset {value} does not fit in memory"))
+        };
+        let read_u32 = |bytes: &[u8]| u32::from_le_bytes(bytes.try_into().unwrap());
+        let read_u64 = |bytes: &[u8]| u64::from_le_bytes(bytes.try_into().unwrap());
+
+        if take(PACK_MAGIC.len())? != PACK_MAGIC {
+            return Err("The pack header is missing".into());
+        }
+        let version = read_u32(take(4)?);
+        if version != PACK_VERSION {
+            return Err(format!("Version {version} packs are not supported"));
+        }
+        let count = read_u32(take(4)?);
+        let mut files = HashMap::new();
+        for _ in 0..count {
+            let length = to_usize(read_u32(take(4)?).into())?;
+            let path = std::str::from_utf8(take(length)?)
+                .map_err(|error| error.to_string())?
+                .to_string();
+            let start = to_usize(read_u64(take(8)?))?;
+            let end = start
+                .checked_add(to_usize(read_u64(take(8)?))?)
+                .ok_or("A file lies past the end of the pack")?;
+            files.insert(path, start..end);
+        }
+        if files.values().any(|range| range.end > bytes.len()) {
+            return Err("A file lies past the end of the pack".into());
+        }
+        Ok(Self { bytes, files })
+    }
+
+    pub fn get(&self, path: &str) -> Option<&[u8]> {
+        self.files.get(path).map(|range| &self.bytes[range.clone()])
+    }
+
+    /// The paths of the packed files, in no particular order
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.files.keys().map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files() -> Vec<(String, Vec<u8>)> {
+        vec![
+            ("models/quad.obj".into(), b"v 0 0 0".to_vec()),
+            ("empty".into(), Vec::new()),
+            ("textures/red.png".into(), vec![255, 0, 0, 255]),
+        ]
+    }
+
+    #[test]
+    fn packs_round_trip() {
+        let bytes = Pack::write(files());
+        // The order files are given in does not change the pack
+        assert_eq!(bytes, Pack::write(files().into_iter().rev()));
+
+        let pack = Pack::parse(bytes).unwrap();
+        for (path, contents) in files() {
+            assert_eq!(pack.get(&path), Some(contents.as_slice()));
+        }
+        assert_eq!(pack.get("missing"), None);
+        assert_eq!(pack.paths().count(), 3);
+    }
+
+    #[test]
+    fn corrupt_packs_are_rejected() {
+        let bytes = Pack::write(files());
+        assert!(Pack::parse(bytes[..bytes.len() - 1].to_vec()).is_err());
+        assert!(Pack::parse(bytes[..20].to_vec()).is_err());
+        assert!(Pack::parse(b"PK\x03\x04".to_vec()).is_err());
+        assert!(Pack::parse(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn directories_are_packed_with_relative_paths() {
+        let directory = std::env::temp_dir().join(format!("pack_directory_{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("models")).unwrap();
+        std::fs::write(directory.join("models/quad.obj"), "v 0 0 0").unwrap();
+        std::fs::write(directory.join("empty"), "").unwrap();
+        let pack = Pack::write_directory(&directory);
+        let _ = std::fs::remove_dir_all(&directory);
+
+        let pack = Pack::parse(pack.unwrap()).unwrap();
+        assert_eq!(pack.get("models/quad.obj"), Some(b"v 0 0 0".as_slice()));
+        assert_eq!(pack.get("empty"), Some([].as_slice()));
+    }
+
+    #[test]
+    fn overflowing_offsets_are_rejected() {
+        let mut bytes = Pack::write([("a".into(), b"a".to_vec())]);
+        // The offset of the only file follows its one byte path
+        let offset = PACK_MAGIC.len() + 4 + 4 + 4 + 1;
+        bytes[offset..offset + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(Pack::parse(bytes.clone()).is_err());
+
+        bytes[offset..offset + 8].copy_from_slice(&1u64.to_le_bytes());
+        bytes[offset + 8..offset + 16].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(Pack::parse(bytes).is_err());
+    }
+}
@@ -0,0 +1,57 @@
+//! Project tasks, run with `cargo xtask <task>`
+
+use std::path::PathBuf;
+
+use pack::{Pack, ASSET_DIRECTORY, ASSET_PACK};
+
+const USAGE: &str = "Usage: cargo xtask pack [INPUT_DIRECTORY] [OUTPUT_FILE]
+
+Tasks:
+    pack    Packs an asset directory into one file that web builds fetch at once.
+            As a Trunk hook, it packs the app's assets into the staging directory.";
+
+fn main() {
+    let arguments = std::env::args().skip(1).collect::<Vec<_>>();
+    let result = match arguments.first().map(String::as_str) {
+        Some("pack") => pack(arguments.get(1).cloned(), arguments.get(2).cloned()),
+        _ => Err(USAGE.to_string()),
+    };
+    if let Err(error) = result {
+        eprintln!("{error}");
+        std::process::exit(1);
+    }
+}
+
+/// Trunk hooks are handed the app and staging directories through the environment
+fn trunk_directory(variable: &str) -> PathBuf {
+    std::env::var_os(variable)
+        .map(PathBuf::from)
+        .unwrap_or_default()
+}
+
+fn pack(input: Option<String>, output: Option<String>) -> Result<(), String> {
+    let input = input
+        .map(PathBuf::from)
+        .unwrap_or_else(|| trunk_directory("TRUNK_SOURCE_DIR").join(ASSET_DIRECTORY));
+    let output = output
+        .map(PathBuf::from)
+        .unwrap_or_else(|| trunk_directory("TRUNK_STAGING_DIR").join(ASSET_PACK));
+
+    // Apps without assets still ship a pack, so the page does not request a missing file
+    let pack = if input.is_dir() {
+        Pack::write_directory(&input)
+            .map_err(|error| format!("Failed to pack {}: {error}", input.display()))?
+    } else {
+        println!("{} does not exist, writing an empty pack", input.display());
+        Pack::write([])
+    };
+    std::fs::write(&output, &pack)
+        .map_err(|error| format!("Failed to write {}: {error}", output.display()))?;
+    println!(
+        "Packed {} into {} ({} bytes)",
+        input.display(),
+        output.display(),
+        pack.len()
+    );
+    Ok(())
+}